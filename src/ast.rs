@@ -21,7 +21,9 @@
 ///    patterns are _not sparse_ in the input.
 use crate::arena::{self, Arena};
 use crate::builtins::Function;
-use crate::common::{Either, FileSpec, Stage};
+use crate::common::{CheckedArith, Either, FileSpec, Stage};
+use crate::lexer::Loc;
+use crate::profile::{self, Region};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Unop {
@@ -40,6 +42,8 @@ static_map!(
 );
 
 pub struct FunDec<'a, 'b, I> {
+    // The location of the `function` keyword.
+    pub loc: Loc,
     pub name: I,
     pub args: Vec<I>,
     pub body: &'a Stmt<'a, 'b, I>,
@@ -74,11 +78,17 @@ pub struct Prog<'a, 'b, I> {
     pub endfile: arena::Vec<'a, &'a Stmt<'a, 'b, I>>,
     // END blocks
     pub end: arena::Vec<'a, &'a Stmt<'a, 'b, I>>,
-    // pattern actions
-    pub pats: arena::Vec<'a, (Pattern<'a, 'b, I>, Option<&'a Stmt<'a, 'b, I>>)>,
+    // pattern actions, along with the location of the pattern
+    pub pats: arena::Vec<'a, (Loc, Pattern<'a, 'b, I>, Option<&'a Stmt<'a, 'b, I>>)>,
     pub stage: Stage<()>,
     pub argv: Vec<&'b str>,
     pub parse_header: bool,
+    // for --profile
+    pub profile: bool,
+    // for -M
+    pub bignum: bool,
+    // for --checked-arith
+    pub checked_arith: Option<CheckedArith>,
 }
 
 fn parse_header<'a, 'b, I: From<&'b str> + Clone>(
@@ -129,6 +139,29 @@ fn parse_header<'a, 'b, I: From<&'b str> + Clone>(
     )));
 }
 
+// Add `region` to `regions`, returning a statement that marks the start of it.
+fn profile_mark<'a, 'b, I>(
+    arena: &'a Arena,
+    regions: &mut Vec<Region>,
+    region: Region,
+) -> &'a Stmt<'a, 'b, I> {
+    use {self::Expr::*, Stmt::*};
+    let id = arena.alloc(ILit(regions.len() as i64));
+    regions.push(region);
+    arena.alloc(Expr(arena.alloc(Call(
+        Either::Right(Function::ProfileMark),
+        arena.alloc_slice(&[id]),
+    ))))
+}
+
+// The location of a BEGIN or END block.
+fn block_loc<I>(stmt: &Stmt<I>) -> Option<Loc> {
+    match stmt {
+        Stmt::Located(loc, _) => Some(*loc),
+        _ => None,
+    }
+}
+
 impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
     pub(crate) fn from_stage(arena: &'a Arena, stage: Stage<()>) -> Self {
         Prog {
@@ -145,10 +178,19 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
             pats: arena.new_vec(),
             argv: Vec::new(),
             parse_header: false,
+            profile: false,
+            bignum: false,
+            checked_arith: None,
             stage,
         }
     }
-    pub(crate) fn desugar_stage(&self, arena: &'a Arena) -> Stage<&'a Stmt<'a, 'b, I>> {
+    /// When profiling, the regions of the program that are marked in the result are added to
+    /// `regions`.
+    pub(crate) fn desugar_stage(
+        &self,
+        arena: &'a Arena,
+        regions: &mut Vec<Region>,
+    ) -> Stage<&'a Stmt<'a, 'b, I>> {
         use {self::Binop::*, self::Expr::*, Stmt::*};
         let mut conds = 0;
 
@@ -210,7 +252,13 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
             }
         }
 
-        begin.extend(self.begin.iter().cloned());
+        for stmt in self.begin.iter().cloned() {
+            if self.profile {
+                let region = Region::new(profile::Kind::Begin, "BEGIN", block_loc(stmt));
+                begin.push(profile_mark(arena, regions, region));
+            }
+            begin.push(stmt);
+        }
 
         // Desugar patterns into if statements, with the usual desugaring for an empty action.
         let mut inner = arena.vec_with_capacity(10);
@@ -225,15 +273,28 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
             x: arena.alloc(Var("FNR".into())),
         }))));
         let init_len = inner.len();
-        for (pat, body) in self.pats.iter() {
-            let body = if let Some(body) = body {
+        for (i, (loc, pat, body)) in self.pats.iter().enumerate() {
+            let mut body: &'a Stmt<'a, 'b, I> = if let Some(body) = body {
                 body
             } else {
                 arena.alloc(Print(&[], None))
             };
+            if self.profile {
+                let name = format!("rule {}", i + 1);
+                if !matches!(pat, Pattern::Null) {
+                    let region = Region::new(profile::Kind::Pattern, name.as_str(), Some(*loc));
+                    inner.push(profile_mark(arena, regions, region));
+                }
+                let region = Region::new(profile::Kind::Action, name, Some(*loc));
+                body = arena.alloc(Block(
+                    arena.new_vec_from_slice(&[profile_mark(arena, regions, region), body]),
+                ));
+            }
             match pat {
                 Pattern::Null => inner.push(body),
-                Pattern::Bool(pat) => inner.push(arena.alloc(If(pat, body, None))),
+                Pattern::Bool(pat) => {
+                    inner.push(arena.alloc(Stmt::Located(*loc, arena.alloc(If(pat, body, None)))))
+                }
                 Pattern::Comma(l, r) => {
                     let mut block = arena.vec_with_capacity(2);
                     // Comma patterns run the corresponding action between pairs of lines matching
@@ -275,8 +336,14 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
                     //      if (Cond(0) == 2) EndCond(0); # _cond_0 = 0;
                     //      next;
                     //  }
-                    inner.push(arena.alloc(If(l, arena.alloc(StartCond(conds)), None)));
-                    inner.push(arena.alloc(If(r, arena.alloc(LastCond(conds)), None)));
+                    inner.push(arena.alloc(Stmt::Located(
+                        *loc,
+                        arena.alloc(If(l, arena.alloc(StartCond(conds)), None)),
+                    )));
+                    inner.push(arena.alloc(Stmt::Located(
+                        *loc,
+                        arena.alloc(If(r, arena.alloc(LastCond(conds)), None)),
+                    )));
                     block.push(arena.alloc(If(
                         arena.alloc(Binop(EQ, arena.alloc(Cond(conds)), arena.alloc(ILit(2)))),
                         arena.alloc(EndCond(conds)),
@@ -294,23 +361,51 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
         }
 
         if !self.end.is_empty() || !self.prepare.is_empty() || inner.len() > init_len {
+            // When profiling, reading each record (and anything else done between rules) is
+            // charged to a separate region.
+            let input_mark = if self.profile {
+                let region = Region::new(profile::Kind::Input, "input", None);
+                let mark = profile_mark(arena, regions, region);
+                inner.push(mark);
+                Some(mark)
+            } else {
+                None
+            };
             // Wrap the whole thing in a while((getline) > 0) { } statement.
-            let main_portion = arena.alloc(While(
+            let mut main_portion = arena.alloc(While(
                 /*is_toplevel=*/ true,
                 arena.alloc(Binop(GT, arena.alloc(ReadStdin), arena.alloc(ILit(0)))),
                 arena.alloc(Block(inner)),
             ));
+            if let Some(mark) = input_mark {
+                main_portion = arena.alloc(Block(arena.new_vec_from_slice(&[mark, main_portion])));
+            }
             main_loop = Some(if self.prepare.is_empty() {
                 main_portion
             } else {
-                let mut block = arena.vec_with_capacity(self.prepare.len() + 1);
+                let mut block = arena.vec_with_capacity(self.prepare.len() * 2 + 1);
                 block.push(main_portion);
-                block.extend(self.prepare.iter().cloned());
+                for stmt in self.prepare.iter().cloned() {
+                    if self.profile {
+                        let region =
+                            Region::new(profile::Kind::Prepare, "PREPARE", block_loc(stmt));
+                        block.push(profile_mark(arena, regions, region));
+                    }
+                    block.push(stmt);
+                }
                 arena.alloc(Block(block))
             });
         }
         if !self.end.is_empty() {
-            end = Some(arena.alloc(Block(self.end.clone())));
+            let mut block = arena.vec_with_capacity(self.end.len() * 2);
+            for stmt in self.end.iter().cloned() {
+                if self.profile {
+                    let region = Region::new(profile::Kind::End, "END", block_loc(stmt));
+                    block.push(profile_mark(arena, regions, region));
+                }
+                block.push(stmt);
+            }
+            end = Some(arena.alloc(Block(block)));
         }
         match self.stage {
             Stage::Main(_) => {
@@ -370,6 +465,9 @@ pub enum Expr<'a, 'b, I> {
     FLit(f64),
     StrLit(&'b [u8]),
     PatLit(&'b [u8]),
+    // A number under -M that an ILit cannot hold exactly, as it was written. It is used as a string
+    // holding the number, which is how bignum mode passes numbers around.
+    BigLit(&'b str),
     Unop(Unop, &'a Expr<'a, 'b, I>),
     Binop(Binop, &'a Expr<'a, 'b, I>, &'a Expr<'a, 'b, I>),
     Call(Either<I, Function>, &'a [&'a Expr<'a, 'b, I>]),
@@ -395,11 +493,25 @@ pub enum Expr<'a, 'b, I> {
     Getline {
         into: Option<&'a Expr<'a, 'b, I>>,
         from: Option<&'a Expr<'a, 'b, I>>,
-        is_file: bool,
+        src: GetlineSrc,
     },
     ReadStdin,
     // Used for comma patterns
     Cond(usize),
+    // An expression annotated with the location in the source where it begins. The parser only
+    // adds these around function calls, which are where most runtime errors come from.
+    Located(Loc, &'a Expr<'a, 'b, I>),
+}
+
+/// Where an explicit `getline` reads from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GetlineSrc {
+    /// `getline < file`, or standard input if there is no file.
+    File,
+    /// `cmd | getline`
+    Cmd,
+    /// `cmd |& getline`, reading the output of a coprocess.
+    Coproc,
 }
 
 #[derive(Debug, Clone)]
@@ -439,9 +551,18 @@ pub enum Stmt<'a, 'b, I> {
         &'a Stmt<'a, 'b, I>,
     ),
     ForEach(I, &'a Expr<'a, 'b, I>, &'a Stmt<'a, 'b, I>),
+    // A gawk-style switch statement. Case labels are literals; a `None` label is the default case.
+    // Control falls through from one case body to the next until it reaches a `break`.
+    Switch(
+        &'a Expr<'a, 'b, I>,
+        &'a [(Option<&'a Expr<'a, 'b, I>>, &'a Stmt<'a, 'b, I>)],
+    ),
     Break,
     Continue,
     Next,
     NextFile,
     Return(Option<&'a Expr<'a, 'b, I>>),
+    // A statement annotated with the location in the source where it begins. Errors raised while
+    // compiling or running the statement are reported at this location.
+    Located(Loc, &'a Stmt<'a, 'b, I>),
 }
@@ -9,7 +9,7 @@ pub struct CommentTag {
     pub description: Option<String>,
 }
 
-fn parse_comment_tags(awk_code: &str) -> Vec<CommentTag> {
+pub(crate) fn parse_comment_tags(awk_code: &str) -> Vec<CommentTag> {
     let mut tags: Vec<CommentTag> = vec![];
     for line in awk_code.lines() {
         if line.starts_with("# @") {
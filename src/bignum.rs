@@ -0,0 +1,384 @@
+//! Desugaring for `-M` (bignum) mode.
+//!
+//! gawk's `-M` computes with MPFR numbers. zawk instead keeps the numbers that arithmetic produces
+//! in strings holding their exact decimal value, and rewrites a program's arithmetic into calls to
+//! hidden builtins that work on those strings (see [crate::runtime::bignum]) before it is
+//! converted to a CFG:
+//!
+//! ```text
+//! x + y          bignum_add(x, y, PREC)        likewise -, *, /, % and ^
+//! -x             bignum_sub(0, x, PREC)
+//! x += y         x = bignum_add(x, y, PREC)
+//! x++            bignum_sub(x = bignum_add(x, 1, PREC), 1, PREC)
+//! int(x)         bignum_int(x, 0, PREC)
+//! x < y          bignum_cmp(x, y) < 0          likewise the other comparisons
+//! if (x) ...     if (bignum_cmp(x, 0) != 0)    and anywhere else a condition goes
+//! print x        print bignum_format(x, OFMT, PREC)
+//! x y            bignum_format(x, CONVFMT, PREC) bignum_format(y, CONVFMT, PREC)
+//! ```
+//!
+//! The values are ordinary strings, so every backend runs the result. Comparisons with a string
+//! constant stay string comparisons, builtins like `sqrt` still compute with doubles, and the
+//! counters that zawk maintains itself (`NR`, `NF`, ...) stay native integers.
+//!
+//! A compound assignment to `a[k]` or `$k` whose index has side effects, like `a[i++] += x`,
+//! evaluates the index once by saving it in a hidden global; a recursive call made while
+//! computing the right-hand side of the same assignment will clobber it.
+use std::fmt::Display;
+
+use crate::arena::Arena;
+use crate::ast::{Binop, Expr, Stmt, Unop};
+use crate::builtins::{BigOp, Function, Variable};
+use crate::common::Either;
+
+pub(crate) struct Bignum<'a> {
+    arena: &'a Arena,
+    keys: usize,
+}
+
+fn big_op(op: Binop) -> Option<BigOp> {
+    match op {
+        Binop::Plus => Some(BigOp::Add),
+        Binop::Minus => Some(BigOp::Sub),
+        Binop::Mult => Some(BigOp::Mul),
+        Binop::Div => Some(BigOp::Div),
+        Binop::Mod => Some(BigOp::Mod),
+        Binop::Pow => Some(BigOp::Pow),
+        Binop::Concat
+        | Binop::IsMatch
+        | Binop::LT
+        | Binop::GT
+        | Binop::LTE
+        | Binop::GTE
+        | Binop::EQ => None,
+    }
+}
+
+// Whether evaluating `e` twice is the same as evaluating it once.
+fn is_pure<I>(e: &Expr<'_, '_, I>) -> bool {
+    use Expr::*;
+    match e {
+        ILit(_) | FLit(_) | StrLit(_) | PatLit(_) | BigLit(_) | Var(_) | Cond(_) => true,
+        Unop(_, x) => is_pure(x),
+        Binop(_, x, y) | Index(x, y) => is_pure(x) && is_pure(y),
+        _ => false,
+    }
+}
+
+// Whether `e` is a string whatever values it works on, so that converting it to a string with
+// `OFMT` or `CONVFMT` has no effect.
+fn is_str<I>(e: &Expr<'_, '_, I>) -> bool {
+    matches!(
+        e,
+        Expr::StrLit(_) | Expr::Unop(Unop::Column, _) | Expr::Binop(Binop::Concat, ..)
+    )
+}
+
+// Whether `f` is the `int` builtin. The parser leaves the names of most builtins unresolved.
+fn is_int<I: Display>(f: &Either<I, Function>) -> bool {
+    match f {
+        Either::Left(name) => name.to_string() == "int",
+        Either::Right(f) => *f == Function::ToInt,
+    }
+}
+
+// Whether `e` names one of the variables that zawk updates itself, like NR.
+fn is_builtin_var<I: Display>(e: &Expr<'_, '_, I>) -> bool {
+    match e {
+        Expr::Var(v) => Variable::try_from(v.to_string().as_str()).is_ok(),
+        _ => false,
+    }
+}
+
+impl<'a> Bignum<'a> {
+    pub(crate) fn new(arena: &'a Arena) -> Self {
+        Bignum { arena, keys: 0 }
+    }
+
+    pub(crate) fn rewrite<'b, I: From<&'a str> + Clone + Display>(
+        &mut self,
+        s: &'a Stmt<'a, 'b, I>,
+    ) -> &'a Stmt<'a, 'b, I> {
+        let a = self.arena;
+        match s {
+            // The value of an increment used as a statement is not needed.
+            Stmt::Expr(Expr::Inc { is_inc, x, .. }) => {
+                a.alloc(Stmt::Expr(self.inc(*is_inc, /*is_post=*/ false, x)))
+            }
+            Stmt::Expr(e) => a.alloc(Stmt::Expr(self.expr(e))),
+            Stmt::Block(stmts) => {
+                let mut block = a.vec_with_capacity(stmts.len());
+                for s in stmts.iter() {
+                    block.push(self.rewrite(*s));
+                }
+                a.alloc(Stmt::Block(block))
+            }
+            Stmt::Print(args, out) => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|e| {
+                        let e = self.expr(e);
+                        self.to_str(e, "OFMT")
+                    })
+                    .collect();
+                let args = a.alloc_slice(&args);
+                let out = out.map(|(e, spec)| (self.expr(e), spec));
+                a.alloc(Stmt::Print(args, out))
+            }
+            Stmt::Printf(fmt, args, out) => {
+                let fmt = self.expr(fmt);
+                let args = self.exprs(args);
+                let out = out.map(|(e, spec)| (self.expr(e), spec));
+                a.alloc(Stmt::Printf(fmt, args, out))
+            }
+            Stmt::If(c, t, f) => {
+                let c = self.cond(c);
+                let t = self.rewrite(t);
+                let f = f.map(|f| self.rewrite(f));
+                a.alloc(Stmt::If(c, t, f))
+            }
+            Stmt::For(init, cond, update, body) => {
+                let init = init.map(|s| self.rewrite(s));
+                let cond = cond.map(|c| self.cond(c));
+                let update = update.map(|s| self.rewrite(s));
+                a.alloc(Stmt::For(init, cond, update, self.rewrite(body)))
+            }
+            Stmt::DoWhile(c, body) => a.alloc(Stmt::DoWhile(self.cond(c), self.rewrite(body))),
+            // The main loop's condition reads the next record.
+            Stmt::While(true, c, body) => a.alloc(Stmt::While(true, *c, self.rewrite(body))),
+            Stmt::While(false, c, body) => {
+                a.alloc(Stmt::While(false, self.cond(c), self.rewrite(body)))
+            }
+            Stmt::ForEach(v, arr, body) => {
+                a.alloc(Stmt::ForEach(v.clone(), self.expr(arr), self.rewrite(body)))
+            }
+            Stmt::Switch(e, cases) => {
+                let e = self.expr(e);
+                let cases: Vec<_> = cases.iter().map(|(l, b)| (*l, self.rewrite(*b))).collect();
+                a.alloc(Stmt::Switch(e, a.alloc_slice(&cases)))
+            }
+            Stmt::Return(Some(e)) => a.alloc(Stmt::Return(Some(self.expr(e)))),
+            Stmt::Located(loc, s) => a.alloc(Stmt::Located(*loc, self.rewrite(s))),
+            Stmt::StartCond(_)
+            | Stmt::EndCond(_)
+            | Stmt::LastCond(_)
+            | Stmt::Break
+            | Stmt::Continue
+            | Stmt::Next
+            | Stmt::NextFile
+            | Stmt::Return(None) => s,
+        }
+    }
+
+    fn exprs<'b, I: From<&'a str> + Clone + Display>(
+        &mut self,
+        es: &'a [&'a Expr<'a, 'b, I>],
+    ) -> &'a [&'a Expr<'a, 'b, I>] {
+        let es: Vec<_> = es.iter().map(|e| self.expr(e)).collect();
+        self.arena.alloc_slice(&es)
+    }
+
+    fn expr<'b, I: From<&'a str> + Clone + Display>(
+        &mut self,
+        e: &'a Expr<'a, 'b, I>,
+    ) -> &'a Expr<'a, 'b, I> {
+        let a = self.arena;
+        match e {
+            Expr::ILit(_)
+            | Expr::FLit(_)
+            | Expr::StrLit(_)
+            | Expr::PatLit(_)
+            | Expr::BigLit(_)
+            | Expr::Var(_)
+            | Expr::ReadStdin
+            | Expr::Cond(_) => e,
+            Expr::Unop(Unop::Neg, x) => {
+                let x = self.expr(x);
+                self.big(BigOp::Sub, a.alloc(Expr::ILit(0)), x)
+            }
+            Expr::Unop(Unop::Pos, x) => {
+                let x = self.expr(x);
+                self.big(BigOp::Add, x, a.alloc(Expr::ILit(0)))
+            }
+            Expr::Unop(Unop::Not, x) => a.alloc(Expr::Unop(Unop::Not, self.cond(x))),
+            Expr::Unop(op, x) => a.alloc(Expr::Unop(*op, self.expr(x))),
+            Expr::Binop(op, x, y) => {
+                let (x, y) = (self.expr(x), self.expr(y));
+                match (op, big_op(*op)) {
+                    (_, Some(op)) => self.big(op, x, y),
+                    (Binop::LT | Binop::GT | Binop::LTE | Binop::GTE | Binop::EQ, None)
+                        if !matches!(x, Expr::StrLit(_)) && !matches!(y, Expr::StrLit(_)) =>
+                    {
+                        let cmp = a.alloc(Expr::Call(
+                            Either::Right(Function::BignumCmp),
+                            a.alloc_slice(&[x, y]),
+                        ));
+                        a.alloc(Expr::Binop(*op, cmp, a.alloc(Expr::ILit(0))))
+                    }
+                    (Binop::Concat, None) => a.alloc(Expr::Binop(
+                        Binop::Concat,
+                        self.to_str(x, "CONVFMT"),
+                        self.to_str(y, "CONVFMT"),
+                    )),
+                    (_, None) => a.alloc(Expr::Binop(*op, x, y)),
+                }
+            }
+            Expr::Call(f, [x]) if is_int(f) => {
+                let x = self.expr(x);
+                self.big(BigOp::Int, x, a.alloc(Expr::ILit(0)))
+            }
+            Expr::Call(f, args) => a.alloc(Expr::Call(f.clone(), self.exprs(args))),
+            Expr::Index(m, k) => a.alloc(Expr::Index(self.expr(m), self.expr(k))),
+            Expr::Assign(x, y) => a.alloc(Expr::Assign(self.expr(x), self.expr(y))),
+            Expr::AssignOp(x, op, y) => {
+                let y = self.expr(y);
+                match big_op(*op) {
+                    Some(op) if !is_builtin_var(x) => {
+                        let (target, read) = self.lvalue(x);
+                        a.alloc(Expr::Assign(target, self.big(op, read, y)))
+                    }
+                    _ => a.alloc(Expr::AssignOp(self.expr(x), *op, y)),
+                }
+            }
+            Expr::Inc { is_inc, is_post, x } => self.inc(*is_inc, *is_post, x),
+            Expr::Located(l, x) => a.alloc(Expr::Located(*l, self.expr(x))),
+            Expr::And(x, y) => a.alloc(Expr::And(self.cond(x), self.cond(y))),
+            Expr::Or(x, y) => a.alloc(Expr::Or(self.cond(x), self.cond(y))),
+            Expr::ITE(c, t, f) => a.alloc(Expr::ITE(self.cond(c), self.expr(t), self.expr(f))),
+            Expr::Getline { into, from, src } => a.alloc(Expr::Getline {
+                into: into.map(|e| self.expr(e)),
+                from: from.map(|e| self.expr(e)),
+                src: *src,
+            }),
+        }
+    }
+
+    // Rewrite an expression whose truth value is used. Numbers are true when they are not zero,
+    // which for strings holding numbers is not the same as being nonempty.
+    fn cond<'b, I: From<&'a str> + Clone + Display>(
+        &mut self,
+        e: &'a Expr<'a, 'b, I>,
+    ) -> &'a Expr<'a, 'b, I> {
+        let a = self.arena;
+        match e {
+            // These are already 0 or 1, or have their own meaning as conditions.
+            Expr::ILit(_)
+            | Expr::FLit(_)
+            | Expr::StrLit(_)
+            | Expr::PatLit(_)
+            | Expr::ReadStdin
+            | Expr::Cond(_)
+            | Expr::Unop(Unop::Not, _)
+            | Expr::And(..)
+            | Expr::Or(..)
+            | Expr::Call(Either::Right(Function::Contains), _) => self.expr(e),
+            Expr::Binop(
+                Binop::LT | Binop::GT | Binop::LTE | Binop::GTE | Binop::EQ | Binop::IsMatch,
+                ..,
+            ) => self.expr(e),
+            Expr::ITE(c, t, f) => a.alloc(Expr::ITE(self.cond(c), self.cond(t), self.cond(f))),
+            _ => {
+                let cmp = a.alloc(Expr::Call(
+                    Either::Right(Function::BignumCmp),
+                    a.alloc_slice(&[self.expr(e), a.alloc(Expr::ILit(0))]),
+                ));
+                a.alloc(Expr::Unop(
+                    Unop::Not,
+                    a.alloc(Expr::Binop(Binop::EQ, cmp, a.alloc(Expr::ILit(0)))),
+                ))
+            }
+        }
+    }
+
+    fn inc<'b, I: From<&'a str> + Clone + Display>(
+        &mut self,
+        is_inc: bool,
+        is_post: bool,
+        x: &'a Expr<'a, 'b, I>,
+    ) -> &'a Expr<'a, 'b, I> {
+        let a = self.arena;
+        if is_builtin_var(x) {
+            return a.alloc(Expr::Inc {
+                is_inc,
+                is_post,
+                x: self.expr(x),
+            });
+        }
+        let (op, undo) = if is_inc {
+            (BigOp::Add, BigOp::Sub)
+        } else {
+            (BigOp::Sub, BigOp::Add)
+        };
+        let one = a.alloc(Expr::ILit(1));
+        let (target, read) = self.lvalue(x);
+        let updated = a.alloc(Expr::Assign(target, self.big(op, read, one)));
+        if is_post {
+            self.big(undo, updated, one)
+        } else {
+            updated
+        }
+    }
+
+    // Split the target of a compound assignment into the expression to assign to and the
+    // expression that reads its old value, such that the index is evaluated once between them.
+    // An indexed assignment evaluates its index before its right-hand side, while a field
+    // assignment evaluates it after.
+    fn lvalue<'b, I: From<&'a str> + Clone + Display>(
+        &mut self,
+        x: &'a Expr<'a, 'b, I>,
+    ) -> (&'a Expr<'a, 'b, I>, &'a Expr<'a, 'b, I>) {
+        let a = self.arena;
+        let x = self.expr(x);
+        match x {
+            Expr::Index(m, k) if !is_pure(k) => {
+                let key = self.hidden_key();
+                (
+                    a.alloc(Expr::Index(*m, a.alloc(Expr::Assign(key, *k)))),
+                    a.alloc(Expr::Index(*m, key)),
+                )
+            }
+            Expr::Unop(Unop::Column, k) if !is_pure(k) => {
+                let key = self.hidden_key();
+                (
+                    a.alloc(Expr::Unop(Unop::Column, key)),
+                    a.alloc(Expr::Unop(Unop::Column, a.alloc(Expr::Assign(key, *k)))),
+                )
+            }
+            _ => (x, x),
+        }
+    }
+
+    fn hidden_key<'b, I: From<&'a str>>(&mut self) -> &'a Expr<'a, 'b, I> {
+        let a = self.arena;
+        let name = a.alloc_str(&format!("--bignum-key-{}", self.keys));
+        self.keys += 1;
+        a.alloc(Expr::Var(name.into()))
+    }
+
+    // Convert the (already rewritten) expression `e` to a string with the format in the variable
+    // `fmt`.
+    fn to_str<'b, I: From<&'a str>>(
+        &self,
+        e: &'a Expr<'a, 'b, I>,
+        fmt: &'static str,
+    ) -> &'a Expr<'a, 'b, I> {
+        if is_str(e) {
+            return e;
+        }
+        self.big(BigOp::Format, e, self.arena.alloc(Expr::Var(fmt.into())))
+    }
+
+    fn big<'b, I: From<&'a str>>(
+        &self,
+        op: BigOp,
+        x: &'a Expr<'a, 'b, I>,
+        y: &'a Expr<'a, 'b, I>,
+    ) -> &'a Expr<'a, 'b, I> {
+        let a = self.arena;
+        let prec = a.alloc(Expr::Var("PREC".into()));
+        a.alloc(Expr::Call(
+            Either::Right(Function::Bignum(op)),
+            a.alloc_slice(&[x, y, prec]),
+        ))
+    }
+}
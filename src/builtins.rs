@@ -4,7 +4,7 @@ use crate::ast;
 use crate::common::Either;
 use crate::common::{NodeIx, Result};
 use crate::compile;
-use crate::runtime::{Int, IntMap, Str, StrMap};
+use crate::runtime::{printf, sorted_in::SortedIn, Float, Int, IntMap, Str, StrMap};
 use crate::types::{self, SmallVec};
 use smallvec::smallvec;
 
@@ -18,6 +18,12 @@ pub enum Function {
     Binop(ast::Binop),
     FloatFunc(FloatFunc),
     IntFunc(Bitwise),
+    // Arithmetic and comparisons in bignum mode (-M); see crate::bignum.
+    Bignum(BigOp),
+    BignumCmp,
+    // decimal_add, decimal_round and friends: (x, y, scale, rounding mode).
+    Decimal(DecimalOp),
+    DecimalCmp,
     Close,
     ReadErr,
     ReadErrCmd,
@@ -25,6 +31,8 @@ pub enum Function {
     ReadErrStdin,
     NextlineStdin,
     NextlineCmd,
+    ReadErrCoproc,
+    NextlineCoproc,
     ReadLineStdinFused,
     NextFile,
     Setcol,
@@ -193,9 +201,16 @@ pub enum Function {
     // For header-parsing logic
     UpdateUsedFields,
     SetFI,
+    // For --profile: marks the start of the given region of the program.
+    ProfileMark,
+    // For `switch` statements: the (1-based) index of the first of the newline-separated regex
+    // patterns in the second argument that matches the first, or 0 if none do.
+    MatchSet,
     ToUpper,
     ToLower,
     IncMap,
+    // For arrays of arrays: looks up (and inserts if necessary) the subarray `a[i]`.
+    Subarray,
     Exit,
 }
 
@@ -347,6 +362,74 @@ impl FloatFunc {
     }
 }
 
+/// The operations that bignum mode (-M) performs on exact decimal strings. `Int` truncates its
+/// first operand and ignores the second, and `Format` converts its first operand to a string with
+/// the format in its second.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BigOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Int,
+    Format,
+}
+
+impl BigOp {
+    pub fn func_name(&self) -> &'static str {
+        use BigOp::*;
+        match self {
+            Add => "bignum_add",
+            Sub => "bignum_sub",
+            Mul => "bignum_mul",
+            Div => "bignum_div",
+            Mod => "bignum_mod",
+            Pow => "bignum_pow",
+            Int => "bignum_int",
+            Format => "bignum_format",
+        }
+    }
+
+    /// The operation encoded by `code`, which is `op as i64` for some `op`. Compiled code passes
+    /// operations to the runtime this way.
+    pub fn from_code(code: i64) -> BigOp {
+        use BigOp::*;
+        [Add, Sub, Mul, Div, Mod, Pow, Int, Format][code as usize]
+    }
+}
+
+/// The `decimal_*` builtins, which do exact arithmetic on decimal strings outside of bignum mode.
+/// `Round` rounds its first operand and ignores the second.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecimalOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Round,
+}
+
+impl DecimalOp {
+    pub fn func_name(&self) -> &'static str {
+        use DecimalOp::*;
+        match self {
+            Add => "decimal_add",
+            Sub => "decimal_sub",
+            Mul => "decimal_mul",
+            Div => "decimal_div",
+            Round => "decimal_round",
+        }
+    }
+
+    /// The operation encoded by `code`, which is `op as i64` for some `op`.
+    pub fn from_code(code: i64) -> DecimalOp {
+        use DecimalOp::*;
+        [Add, Sub, Mul, Div, Round][code as usize]
+    }
+}
+
 // This map is used to look up functions that are called in the program source and determine if
 // they are builtin functions. Note that not all members of the Function enum are present here.
 // This includes only the "public" functions.
@@ -443,6 +526,12 @@ static_map!(
     ["to_csv", Function::ToCsv],
     ["min", Function::Min],
     ["max", Function::Max],
+    ["decimal_add", Function::Decimal(DecimalOp::Add)],
+    ["decimal_sub", Function::Decimal(DecimalOp::Sub)],
+    ["decimal_mul", Function::Decimal(DecimalOp::Mul)],
+    ["decimal_div", Function::Decimal(DecimalOp::Div)],
+    ["decimal_round", Function::Decimal(DecimalOp::Round)],
+    ["decimal_cmp", Function::DecimalCmp],
     // array underscore functions
     ["_max", Function::ArrayMax],
     ["_min", Function::ArrayMin],
@@ -564,6 +653,68 @@ impl<'a> IsSprintf for &'a str {
 }
 
 impl Function {
+    // Whether calling this function can raise a runtime error. Calls to functions that cannot do
+    // not need a statement (and source location) of their own.
+    pub(crate) fn may_fail(&self) -> bool {
+        use Function::*;
+        !matches!(
+            self,
+            Unop(_)
+                | Binop(_)
+                | FloatFunc(_)
+                | IntFunc(_)
+                | Length
+                | Strlen
+                | Substr
+                | SubstrIndex
+                | SubstrLastIndex
+                | CharAt
+                | ToUpper
+                | ToLower
+                | ToInt
+                | HexToInt
+                | Trim
+                | Capitalize
+                | UnCapitalize
+                | CamelCase
+                | KebabCase
+                | SnakeCase
+                | TitleCase
+                | PadLeft
+                | PadRight
+                | PadBoth
+                | StrCmp
+                | Repeat
+                | StartsWith
+                | EndsWith
+                | TextContains
+                | DefaultIfEmpty
+                | AppendIfMissing
+                | PrependIfMissing
+                | RemoveIfEnd
+                | RemoveIfBegin
+                | Quote
+                | DoubleQuote
+                | TypeOfVariable
+                | IsArray
+                | IsInt
+                | IsNum
+                | Min
+                | Max
+                | Rand
+                | Srand
+                | Systime
+                | Uuid
+                | Ulid
+                | Whoami
+                | Version
+                | Os
+                | OsFamily
+                | Arch
+                | GetEnv
+        )
+    }
+
     // feedback allows for certain functions to propagate type information back to their arguments.
     pub(crate) fn feedback(&self, args: &[NodeIx], res: NodeIx, ctx: &mut types::TypeContext) {
         use types::{BaseTy, Constraint, TVar::*};
@@ -572,10 +723,11 @@ impl Function {
         }
         match self {
             Function::Split => {
+                // Under --posix, the pieces are strnums, just like fields.
                 let arg1 = ctx.constant(
                     Map {
                         key: BaseTy::Int,
-                        val: BaseTy::Str,
+                        val: if ctx.posix { BaseTy::StrNum } else { BaseTy::Str },
                     }
                         .abs(),
                 );
@@ -606,6 +758,13 @@ impl Function {
                 ctx.nw.add_dep(v, arr, Constraint::ValIn(()));
                 ctx.nw.add_dep(arr, v, Constraint::Val(()));
             }
+            Function::Subarray => {
+                let arr = args[0];
+                let k = args[1];
+                let is_nested = ctx.constant(Some(NestedMap { key: None }));
+                ctx.nw.add_dep(is_nested, arr, Constraint::Flows(()));
+                ctx.nw.add_dep(k, arr, Constraint::KeyIn(()));
+            }
             // TODO: GenSub?
             Function::Sub | Function::GSub => {
                 let out_str = args[2];
@@ -645,6 +804,9 @@ impl Function {
         Ok(match self {
             FloatFunc(ff) => ff.sig(),
             IntFunc(bw) => bw.sig(),
+            Bignum(_) => (smallvec![Str; 3], Str),
+            BignumCmp | DecimalCmp | MatchSet => (smallvec![Str; 2], Int),
+            Decimal(_) => (smallvec![Str, Str, Int, Str], Str),
             Unop(Neg) | Unop(Pos) => match &incoming[0] {
                 Str | Float => (smallvec![Float], Float),
                 _ => (smallvec![Int], Int),
@@ -686,15 +848,28 @@ impl Function {
             }
             Binop(Pow) | Binop(Div) => (smallvec![Float;2], Float),
             Contains => match incoming[0] {
-                MapIntInt | MapIntStr | MapIntFloat => (smallvec![incoming[0], Int], Int),
-                MapStrInt | MapStrStr | MapStrFloat => (smallvec![incoming[0], Str], Int),
+                MapIntInt | MapIntStr | MapIntFloat | MapIntMap => {
+                    (smallvec![incoming[0], Int], Int)
+                }
+                MapStrInt | MapStrStr | MapStrFloat | MapStrMap => {
+                    (smallvec![incoming[0], Str], Int)
+                }
                 _ => return err!("invalid input spec for Contains: {:?}", incoming),
             },
             Delete => match incoming[0] {
-                MapIntInt | MapIntStr | MapIntFloat => (smallvec![incoming[0], Int], Int),
-                MapStrInt | MapStrStr | MapStrFloat => (smallvec![incoming[0], Str], Int),
+                MapIntInt | MapIntStr | MapIntFloat | MapIntMap => {
+                    (smallvec![incoming[0], Int], Int)
+                }
+                MapStrInt | MapStrStr | MapStrFloat | MapStrMap => {
+                    (smallvec![incoming[0], Str], Int)
+                }
                 _ => return err!("invalid input spec for Delete: {:?}", incoming),
             },
+            Subarray => match incoming[0] {
+                MapIntMap => (smallvec![incoming[0], Int], MapStrStr),
+                MapStrMap => (smallvec![incoming[0], Str], MapStrStr),
+                _ => return err!("invalid input spec for Subarray: {:?}", incoming),
+            },
             IncMap => {
                 let map = incoming[0];
                 if !map.is_array() {
@@ -703,12 +878,21 @@ impl Function {
                         map
                     );
                 }
+                if map.is_nested_array() {
+                    return err!("cannot increment an array of arrays: {:?}", map);
+                }
                 let val = map.val().unwrap();
-                let (args, res) = arith_sig(incoming[2], val);
-                (
-                    smallvec![incoming[0], incoming[0].key().unwrap(), args[0]],
-                    res,
-                )
+                let (by, res) = match (incoming[2], val) {
+                    // Maps with string values (e.g. `split` results and subarrays) store the
+                    // incremented number back as a string. The old value may not be an integer, so
+                    // always add in floating point.
+                    (_, Str) => (Float, Str),
+                    (by, val) => {
+                        let (args, res) = arith_sig(by, val);
+                        (args[0], res)
+                    }
+                };
+                (smallvec![incoming[0], incoming[0].key().unwrap(), by], res)
             }
             Clear => {
                 if incoming.len() == 1 && incoming[0].is_array() {
@@ -734,8 +918,8 @@ impl Function {
                     }
                 }
             }
-            NextlineCmd | Nextline => (smallvec![Str], Str),
-            ReadErrCmd | ReadErr => (smallvec![Str], Int),
+            NextlineCoproc | NextlineCmd | Nextline => (smallvec![Str], Str),
+            ReadErrCoproc | ReadErrCmd | ReadErr => (smallvec![Str], Int),
             UpdateUsedFields | NextFile | ReadLineStdinFused => (smallvec![], Int),
             NextlineStdin => (smallvec![], Str),
             ReadErrStdin => (smallvec![], Int),
@@ -833,6 +1017,7 @@ impl Function {
             IsNum => (smallvec![incoming[0]], Int),
             IsFormat => (smallvec![Str, Str], Int),
             IntMapJoin => (smallvec![incoming[0], Str], Str),
+            ArraySum if incoming[0] == MapIntStr => (smallvec![MapIntStr], Str),
             ArrayMax | ArrayMin | ArraySum | ArrayMean => {
                 if let MapIntInt = incoming[0] {
                     (smallvec![incoming[0]], Int)
@@ -842,7 +1027,7 @@ impl Function {
                     return err!("invalid input spec for array _max/_min: {:?}", incoming);
                 }
             }
-            Close => (smallvec![Str], Str),
+            Close => (smallvec![Str, Str], Str),
             Sub | GSub => (smallvec![Str, Str, Str], Int),
             GenSub => (smallvec![Str, Str, Str, Str], Str),
             ToUpper | ToLower | EscapeCSV | EscapeTSV => (smallvec![Str], Str),
@@ -862,6 +1047,7 @@ impl Function {
             JoinCols => (smallvec![Int, Int, Str], Str),
             JoinCSV | JoinTSV => (smallvec![Int, Int], Str),
             SetFI => (smallvec![Int, Int], Int),
+            ProfileMark => (smallvec![Int], Int),
         })
     }
 
@@ -870,14 +1056,18 @@ impl Function {
         Some(match self {
             FloatFunc(ff) => ff.arity(),
             IntFunc(bw) => bw.arity(),
+            Bignum(_) => 3,
+            BignumCmp | DecimalCmp | MatchSet => 2,
+            Decimal(_) => 4,
             UpdateUsedFields | Rand | Ulid | Tsid | LocalIp | Systime | ReseedRng | ReadErrStdin | NextlineStdin | NextFile
             | ReadLineStdinFused => 0,
             Whoami | Version | Os | OsFamily | Arch | Pwd | UserHome => 0,
-            Exit | ToUpper | ToLower | Clear | Srand | System | System2 | HexToInt | ToInt | EscapeCSV
-            | EscapeTSV | Close | Length | Strlen | ReadErr | ReadErrCmd | Nextline | NextlineCmd
+            Exit | ProfileMark | ToUpper | ToLower | Clear | Srand | System | System2 | HexToInt | ToInt | EscapeCSV
+            | EscapeTSV | Length | Strlen | ReadErr | ReadErrCmd | Nextline | NextlineCmd
+            | ReadErrCoproc | NextlineCoproc
             | Uuid | SnowFlake | Fend | Url | SemVer | Path | DataUrl | DateTime | Shlex | Tuple | Variant | Flags | ParseArray | Func | ToJson | FromJson | ToCsv | FromCsv | TypeOfVariable | IsArray | Unop(_) => 1,
-            SetFI | SubstrIndex | SubstrLastIndex | Match | Setcol | Binop(_) => 2,
-            JoinCSV | JoinTSV | Delete | Contains => 2,
+            SetFI | SubstrIndex | SubstrLastIndex | Match | Setcol | Close | Binop(_) => 2,
+            JoinCSV | JoinTSV | Delete | Contains | Subarray => 2,
             Eval => 2,
             DefaultIfEmpty => 2,
             JsonValue | JsonQuery | HtmlValue | HtmlQuery | XmlValue | XmlQuery => 2,
@@ -947,7 +1137,8 @@ impl Function {
         fn step_arith(x: &types::State, y: &types::State) -> types::State {
             use BaseTy::*;
             match (x, y) {
-                (Some(Scalar(Some(Str | Float))), _) | (_, Some(Scalar(Some(Str | Float)))) => {
+                (Some(Scalar(Some(Str | StrNum | Float))), _)
+                | (_, Some(Scalar(Some(Str | StrNum | Float)))) => {
                     Scalar(Float).abs()
                 }
                 (_, _) => Scalar(Int).abs(),
@@ -957,7 +1148,7 @@ impl Function {
             IntFunc(bw) => Ok(bw.ret_state()),
             FloatFunc(ff) => Ok(ff.ret_state()),
             Unop(Neg) | Unop(Pos) => match &args[0] {
-                Some(Scalar(Some(BaseTy::Str))) | Some(Scalar(Some(BaseTy::Float))) => {
+                Some(Scalar(Some(BaseTy::Str | BaseTy::StrNum | BaseTy::Float))) => {
                     Ok(Scalar(BaseTy::Float).abs())
                 }
                 x => Ok(*x),
@@ -970,7 +1161,7 @@ impl Function {
             Setcol => Ok(Scalar(BaseTy::Null).abs()),
             Clear | SubstrIndex | SubstrLastIndex | Srand | ReseedRng | Unop(Not) | Binop(IsMatch) | Binop(LT)
             | Binop(GT) | Binop(LTE) | Binop(GTE) | Binop(EQ) | Length | Strlen | Split | ReadErr
-            | ReadErrCmd | ReadErrStdin | Contains | Delete | Match | Sub | GSub | ToInt | Systime | Mktime | Duration
+            | ReadErrCmd | ReadErrCoproc | ReadErrStdin | Contains | Delete | Match | Sub | GSub | ToInt | Systime | Mktime | Duration
             | System | HexToInt | Asort | MkBool | SnowFlake => Ok(Scalar(BaseTy::Int).abs()),
             System2 => Ok(Map {
                 key: BaseTy::Str,
@@ -978,7 +1169,7 @@ impl Function {
             }.abs()),
             ToUpper | ToLower | JoinCSV | JoinTSV | Uuid | Ulid | Tsid | LocalIp | Strftime | Fend | Trim | Truncate | JoinCols
             | EscapeCSV | EscapeTSV | Escape
-            | Unop(Column) | Binop(Concat) | Nextline | NextlineCmd | NextlineStdin | GenSub | Substr | CharAt
+            | Unop(Column) | Binop(Concat) | Nextline | NextlineCmd | NextlineCoproc | NextlineStdin | GenSub | Substr | CharAt
             | Encode | Decode | Digest | Hmac | Jwt | ToJson | JsonValue | HtmlValue | XmlValue | ToCsv | TypeOfVariable | IntMapJoin => {
                 Ok(Scalar(BaseTy::Str).abs())
             }
@@ -1120,6 +1311,9 @@ impl Function {
                 }.abs())
             }
             PadLeft | PadRight | PadBoth => Ok(Scalar(BaseTy::Str).abs()),
+            ArraySum if matches!(args[0], Some(Map { key: Some(BaseTy::Int), val: Some(BaseTy::Str) })) => {
+                Ok(Scalar(BaseTy::Str).abs())
+            }
             ArrayMax | ArrayMin | ArraySum | ArrayMean => match &args[0] {
                 Some(Map {
                          key: Some(BaseTy::Int),
@@ -1135,9 +1329,17 @@ impl Function {
                 }
                 _ => { Ok(Scalar(BaseTy::Float).abs()) }
             },
-            StrCmp => Ok(Scalar(BaseTy::Int).abs()),
-            IncMap => Ok(step_arith(&types::val_of(&args[0])?, &args[2])),
-            Exit | SetFI | UpdateUsedFields | NextFile | ReadLineStdinFused | Close => Ok(None),
+            StrCmp | BignumCmp | DecimalCmp | MatchSet => Ok(Scalar(BaseTy::Int).abs()),
+            Bignum(_) | Decimal(_) => Ok(Scalar(BaseTy::Str).abs()),
+            IncMap => match types::val_of(&args[0])? {
+                Some(Scalar(Some(BaseTy::Str | BaseTy::StrNum))) => Ok(Scalar(BaseTy::Str).abs()),
+                val => Ok(step_arith(&val, &args[2])),
+            },
+            Subarray => Ok(Map {
+                key: BaseTy::Str,
+                val: BaseTy::Str,
+            }.abs()),
+            Exit | SetFI | UpdateUsedFields | ProfileMark | NextFile | ReadLineStdinFused | Close => Ok(None),
             KvGet => Ok(Scalar(BaseTy::Str).abs()),
             ReadAll => Ok(Scalar(BaseTy::Str).abs()),
             WriteAll => Ok(None),
@@ -1175,14 +1377,19 @@ pub(crate) enum Variable {
     ENVIRON = 14,
     PROCINFO = 15,
     CONVFMT = 16,
+    OFMT = 17,
+    FIELDWIDTHS = 18,
+    FPAT = 19,
+    IGNORECASE = 20,
+    RT = 21,
 }
 
 impl From<Variable> for compile::Ty {
     fn from(v: Variable) -> compile::Ty {
         use Variable::*;
         match v {
-            FS | OFS | ORS | RS | FILENAME | CONVFMT => compile::Ty::Str,
-            PID | ARGC | NF | NR | FNR | RSTART | RLENGTH => compile::Ty::Int,
+            FS | OFS | ORS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT => compile::Ty::Str,
+            PID | ARGC | NF | NR | FNR | RSTART | RLENGTH | IGNORECASE => compile::Ty::Int,
             ARGV => compile::Ty::MapIntStr,
             FI => compile::Ty::MapStrInt,
             ENVIRON => compile::Ty::MapStrStr,
@@ -1209,6 +1416,14 @@ pub(crate) struct Variables<'a> {
     pub environ: StrMap<'a, Str<'a>>,
     pub procinfo: StrMap<'a, Str<'a>>,
     pub convfmt: Str<'a>,
+    pub ofmt: Str<'a>,
+    // Whether `convfmt` and `ofmt` have their default value; kept up to date by `store_str`.
+    pub default_convfmt: bool,
+    pub default_ofmt: bool,
+    pub fieldwidths: Str<'a>,
+    pub fpat: Str<'a>,
+    pub ignorecase: Int,
+    pub rt: Str<'a>,
 }
 
 impl<'a> Default for Variables<'a> {
@@ -1224,7 +1439,14 @@ impl<'a> Default for Variables<'a> {
             fnr: 0,
             nf: 0,
             filename: Default::default(),
-            convfmt: "%.6g".into(),
+            convfmt: printf::DEFAULT_FLOAT_FMT.into(),
+            ofmt: printf::DEFAULT_FLOAT_FMT.into(),
+            default_convfmt: true,
+            default_ofmt: true,
+            fieldwidths: Default::default(),
+            fpat: "[^[:space:]]+".into(),
+            ignorecase: 0,
+            rt: Default::default(),
             rstart: 0,
             pid: 0,
             rlength: -1,
@@ -1272,6 +1494,29 @@ fn load_procinfo_variables<'a>() -> StrMap<'a, Str<'a>> {
 }
 
 impl<'a> Variables<'a> {
+    /// Convert a number to a string, as when it is concatenated or used as a map key.
+    pub fn convfmt(&self, f: Float) -> Str<'a> {
+        printf::format_float(f, &self.convfmt, self.default_convfmt)
+    }
+
+    /// `CONVFMT`, for numbers printed with `%s` by `printf`; `None` while it has its default value.
+    pub fn convfmt_spec(&self) -> Option<&Str<'a>> {
+        (!self.default_convfmt).then_some(&self.convfmt)
+    }
+
+    /// Convert a number to a string for `print`.
+    pub fn ofmt(&self, f: Float) -> Str<'a> {
+        printf::format_float(f, &self.ofmt, self.default_ofmt)
+    }
+
+    /// The order `for (k in m)` loops visit keys in, from `PROCINFO["sorted_in"]`.
+    pub fn sorted_in(&self) -> Option<SortedIn> {
+        let procinfo = self.procinfo.0.borrow();
+        procinfo
+            .get(&Str::from("sorted_in"))
+            .and_then(|order| order.with_bytes(SortedIn::parse))
+    }
+
     pub fn load_int(&self, var: Variable) -> Result<Int> {
         use Variable::*;
         Ok(match var {
@@ -1281,8 +1526,9 @@ impl<'a> Variables<'a> {
             FNR => self.fnr,
             RSTART => self.rstart,
             RLENGTH => self.rlength,
+            IGNORECASE => self.ignorecase,
             PID => self.pid,
-            FI | ORS | OFS | FS | RS | FILENAME | CONVFMT | ARGV | ENVIRON | PROCINFO => return err!("var {} not an int", var),
+            FI | ORS | OFS | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | ARGV | ENVIRON | PROCINFO => return err!("var {} not an int", var),
         })
     }
 
@@ -1295,8 +1541,9 @@ impl<'a> Variables<'a> {
             FNR => self.fnr = i,
             RSTART => self.rstart = i,
             RLENGTH => self.rlength = i,
+            IGNORECASE => self.ignorecase = i,
             PID => self.pid = i,
-            FI | ORS | OFS | FS | RS | FILENAME | CONVFMT | ARGV | ENVIRON | PROCINFO => return err!("var {} not an int", var),
+            FI | ORS | OFS | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | ARGV | ENVIRON | PROCINFO => return err!("var {} not an int", var),
         }
         Ok(())
     }
@@ -1310,7 +1557,11 @@ impl<'a> Variables<'a> {
             RS => self.rs.clone(),
             FILENAME => self.filename.clone(),
             CONVFMT => self.convfmt.clone(),
-            FI | PID | ARGC | ARGV | NF | NR | FNR | RSTART | RLENGTH | ENVIRON | PROCINFO => {
+            OFMT => self.ofmt.clone(),
+            FIELDWIDTHS => self.fieldwidths.clone(),
+            FPAT => self.fpat.clone(),
+            RT => self.rt.clone(),
+            FI | PID | ARGC | ARGV | NF | NR | FNR | RSTART | RLENGTH | IGNORECASE | ENVIRON | PROCINFO => {
                 return err!("var {} not a string", var);
             }
         })
@@ -1324,8 +1575,18 @@ impl<'a> Variables<'a> {
             ORS => self.ors = s,
             RS => self.rs = s,
             FILENAME => self.filename = s,
-            CONVFMT => self.convfmt = s,
-            FI | PID | ARGC | ARGV | NF | NR | FNR | RSTART | RLENGTH | ENVIRON | PROCINFO => {
+            CONVFMT => {
+                self.default_convfmt = printf::is_default_float_fmt(&s);
+                self.convfmt = s
+            }
+            OFMT => {
+                self.default_ofmt = printf::is_default_float_fmt(&s);
+                self.ofmt = s
+            }
+            FIELDWIDTHS => self.fieldwidths = s,
+            FPAT => self.fpat = s,
+            RT => self.rt = s,
+            FI | PID | ARGC | ARGV | NF | NR | FNR | RSTART | RLENGTH | IGNORECASE | ENVIRON | PROCINFO => {
                 return err!("var {} not a string", var);
             }
         };
@@ -1336,7 +1597,7 @@ impl<'a> Variables<'a> {
         use Variable::*;
         match var {
             ARGV => Ok(self.argv.clone()),
-            FI | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | RSTART | RLENGTH | ENVIRON | PROCINFO => {
+            FI | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | RSTART | RLENGTH | IGNORECASE | ENVIRON | PROCINFO => {
                 err!("var {} is not an int-keyed map", var)
             }
         }
@@ -1349,7 +1610,7 @@ impl<'a> Variables<'a> {
                 self.argv = m;
                 Ok(())
             }
-            FI | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | RSTART | RLENGTH | ENVIRON | PROCINFO => {
+            FI | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | RSTART | RLENGTH | IGNORECASE | ENVIRON | PROCINFO => {
                 err!("var {} is not an int-keyed map", var)
             }
         }
@@ -1359,8 +1620,8 @@ impl<'a> Variables<'a> {
         use Variable::*;
         match var {
             FI => Ok(self.fi.clone()),
-            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | RSTART | ENVIRON | PROCINFO
-            | RLENGTH => {
+            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | RSTART | ENVIRON | PROCINFO
+            | RLENGTH | IGNORECASE => {
                 err!("var {} is not a string-keyed map", var)
             }
         }
@@ -1373,8 +1634,8 @@ impl<'a> Variables<'a> {
                 self.fi = m;
                 Ok(())
             }
-            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | RSTART | ENVIRON | PROCINFO
-            | RLENGTH => {
+            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | RSTART | ENVIRON | PROCINFO
+            | RLENGTH | IGNORECASE => {
                 err!("var {} is not a string-keyed map", var)
             }
         }
@@ -1384,9 +1645,9 @@ impl<'a> Variables<'a> {
         use Variable::*;
         match var {
             ENVIRON => Ok(self.environ.clone()),
-            PROCINFO => Ok(self.environ.clone()),
-            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | RSTART | FI
-            | RLENGTH => {
+            PROCINFO => Ok(self.procinfo.clone()),
+            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | RSTART | FI
+            | RLENGTH | IGNORECASE => {
                 err!("var {} is not a string-keyed map", var)
             }
         }
@@ -1403,8 +1664,8 @@ impl<'a> Variables<'a> {
                 self.procinfo = m;
                 Ok(())
             }
-            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | RSTART | FI
-            | RLENGTH => {
+            ARGV | PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT | RSTART | FI
+            | RLENGTH | IGNORECASE => {
                 err!("var {} is not a string-keyed map", var)
             }
         }
@@ -1415,7 +1676,7 @@ impl Variable {
     pub(crate) fn ty(&self) -> types::TVar<types::BaseTy> {
         use Variable::*;
         match self {
-            PID | ARGC | NF | FNR | NR | RSTART | RLENGTH => {
+            PID | ARGC | NF | FNR | NR | RSTART | RLENGTH | IGNORECASE => {
                 types::TVar::Scalar(types::BaseTy::Int)
             }
             // NB: For full compliance, this may have to be Str -> Str
@@ -1452,7 +1713,7 @@ impl Variable {
                 key: types::BaseTy::Str,
                 val: types::BaseTy::Str,
             },
-            ORS | OFS | FS | RS | FILENAME | CONVFMT => types::TVar::Scalar(types::BaseTy::Str),
+            ORS | OFS | FS | RS | FILENAME | CONVFMT | OFMT | FIELDWIDTHS | FPAT | RT => types::TVar::Scalar(types::BaseTy::Str),
         }
     }
 }
@@ -1490,6 +1751,12 @@ impl TryFrom<usize> for Variable {
             13 => Ok(FI),
             14 => Ok(ENVIRON),
             15 => Ok(PROCINFO),
+            16 => Ok(CONVFMT),
+            17 => Ok(OFMT),
+            18 => Ok(FIELDWIDTHS),
+            19 => Ok(FPAT),
+            20 => Ok(IGNORECASE),
+            21 => Ok(RT),
             _ => Err(()),
         }
     }
@@ -1512,5 +1779,11 @@ static_map!(
     ["PID", Variable::PID],
     ["FI", Variable::FI],
     ["ENVIRON", Variable::ENVIRON],
-    ["PROCINFO", Variable::PROCINFO]
+    ["PROCINFO", Variable::PROCINFO],
+    ["CONVFMT", Variable::CONVFMT],
+    ["OFMT", Variable::OFMT],
+    ["FIELDWIDTHS", Variable::FIELDWIDTHS],
+    ["FPAT", Variable::FPAT],
+    ["IGNORECASE", Variable::IGNORECASE],
+    ["RT", Variable::RT]
 );
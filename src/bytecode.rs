@@ -2,13 +2,13 @@ use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::sync::Arc;
 
-use crate::builtins::{Bitwise, FloatFunc, Variable};
+use crate::ast::Binop;
+use crate::builtins::{BigOp, Bitwise, DecimalOp, FloatFunc, Variable};
 use crate::common::{FileSpec, NumTy};
 use crate::compile::{self, Ty};
 use crate::interp::{index, index_mut, Storage};
-use crate::runtime::{self, Float, Int, Str, UniqueStr};
+use crate::runtime::{self, Float, Int, RegexConst, Str, UniqueStr};
 
-use regex::bytes::Regex;
 pub(crate) use crate::interp::Interp;
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
@@ -71,6 +71,8 @@ pub(crate) enum Instr<'a> {
     // Conversions
     IntToStr(Reg<Str<'a>>, Reg<Int>),
     FloatToStr(Reg<Str<'a>>, Reg<Float>),
+    // Like FloatToStr, but using OFMT rather than CONVFMT; used for arguments to print.
+    FloatToStrOfmt(Reg<Str<'a>>, Reg<Float>),
     StrToInt(Reg<Int>, Reg<Str<'a>>),
     HexStrToInt(Reg<Int>, Reg<Str<'a>>),
     FloatToInt(Reg<Int>, Reg<Float>),
@@ -95,6 +97,10 @@ pub(crate) enum Instr<'a> {
     MinusInt(Reg<Int>, Reg<Int>, Reg<Int>),
     ModFloat(Reg<Float>, Reg<Float>, Reg<Float>),
     ModInt(Reg<Int>, Reg<Int>, Reg<Int>),
+    // Under --checked-arith: `+ - * %` on integers, failing on overflow, and `/ %` on floats,
+    // failing on a zero divisor.
+    CheckedInt(Binop, Reg<Int>, Reg<Int>, Reg<Int>),
+    CheckedFloat(Binop, Reg<Float>, Reg<Float>, Reg<Float>),
     Not(Reg<Int>, Reg<Int>),
     NotStr(Reg<Int>, Reg<Str<'a>>),
     NegInt(Reg<Int>, Reg<Int>),
@@ -103,6 +109,23 @@ pub(crate) enum Instr<'a> {
     Float2(FloatFunc, Reg<Float>, Reg<Float>, Reg<Float>),
     Int1(Bitwise, Reg<Int>, Reg<Int>),
     Int2(Bitwise, Reg<Int>, Reg<Int>, Reg<Int>),
+    Bignum(
+        BigOp,
+        Reg<Str<'a>>,
+        Reg<Str<'a>>,
+        Reg<Str<'a>>,
+        /* PREC */ Reg<Str<'a>>,
+    ),
+    BignumCmp(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    DecimalCmp(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    Decimal(
+        DecimalOp,
+        Reg<Str<'a>>,
+        Reg<Str<'a>>,
+        Reg<Str<'a>>,
+        /* scale */ Reg<Int>,
+        /* rounding mode */ Reg<Str<'a>>,
+    ),
     Rand(Reg<Float>),
     Srand(
         /* previous seed */ Reg<Int>,
@@ -114,9 +137,12 @@ pub(crate) enum Instr<'a> {
     Concat(Reg<Str<'a>>, Reg<Str<'a>>, Reg<Str<'a>>),
     StartsWithConst(Reg<Int>, Reg<Str<'a>>, Arc<[u8]>),
     IsMatch(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
-    IsMatchConst(Reg<Int>, Reg<Str<'a>>, Arc<Regex>),
+    // The index of the first of the newline-separated patterns in the second string matching the
+    // first, or 0. See `builtins::Function::MatchSet`.
+    MatchSet(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    IsMatchConst(Reg<Int>, Reg<Str<'a>>, Arc<RegexConst>),
     Match(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
-    MatchConst(Reg<Int>, Reg<Str<'a>>, Arc<Regex>),
+    MatchConst(Reg<Int>, Reg<Str<'a>>, Arc<RegexConst>),
     // index(s, t) returns index of substring t in s, 0 if it does not appear.
     SubstrIndex(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
     SubstrLastIndex(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
@@ -164,6 +190,10 @@ pub(crate) enum Instr<'a> {
     EQFloat(Reg<Int>, Reg<Float>, Reg<Float>),
     EQInt(Reg<Int>, Reg<Int>, Reg<Int>),
     EQStr(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    // Compare two strnums under `--posix`, yielding -1, 0 or 1.
+    StrNumCmp(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    // Compare a strnum with a number under `--posix`, yielding -1, 0 or 1.
+    StrNumCmpFloat(Reg<Int>, Reg<Str<'a>>, Reg<Float>),
 
     // Columns
     SetColumn(Reg<Int> /* dst column */, Reg<Str<'a>>),
@@ -190,6 +220,8 @@ pub(crate) enum Instr<'a> {
     // File reading.
     ReadErr(Reg<Int>, Reg<Str<'a>>, /*is_file=*/ bool),
     NextLine(Reg<Str<'a>>, Reg<Str<'a>>, /*is_file=*/ bool),
+    ReadErrCoproc(Reg<Int>, Reg<Str<'a>>),
+    NextLineCoproc(Reg<Str<'a>>, Reg<Str<'a>>),
     ReadErrStdin(Reg<Int>),
     NextLineStdin(Reg<Str<'a>>),
     // Fetches line directly into $0.
@@ -330,6 +362,7 @@ pub(crate) enum Instr<'a> {
     MapIntFloatMin(Reg<Float>, Reg<runtime::IntMap<Float>>),
     MapIntIntSum(Reg<Int>, Reg<runtime::IntMap<Int>>),
     MapIntFloatSum(Reg<Float>, Reg<runtime::IntMap<Float>>),
+    MapIntStrSum(Reg<Str<'a>>, Reg<runtime::IntMap<Str<'a>>>),
     MapIntIntMean(Reg<Int>, Reg<runtime::IntMap<Int>>),
     MapIntFloatMean(Reg<Float>, Reg<runtime::IntMap<Float>>),
     FromCsv(Reg<runtime::IntMap<Str<'a>>>, Reg<Str<'a>>),
@@ -373,6 +406,11 @@ pub(crate) enum Instr<'a> {
     // Set the corresponding index in the FI variable. This is equivalent of loading FI, but we
     // keep this as a separate instruction to make static analysis easier.
     SetFI(Reg<Int>, Reg<Int>),
+    // For --profile: start the given toplevel region of the program, or enter and exit a nested
+    // one (a function body or a call to a timed builtin).
+    ProfileMark(NumTy),
+    ProfileEnter(NumTy),
+    ProfileExit(),
 
     // Split
     SplitInt(
@@ -401,7 +439,7 @@ pub(crate) enum Instr<'a> {
         output: Option<(Reg<Str<'a>>, FileSpec)>,
         args: Vec<Reg<Str<'a>>>,
     },
-    Close(Reg<Str<'a>>),
+    Close(Reg<Str<'a>> /* file */, Reg<Str<'a>> /* how */),
     RunCmd(Reg<Int>, Reg<Str<'a>>),
     RunCmd2(Reg<runtime::StrMap<'a, Str<'a>>>, Reg<Str<'a>>),
     Exit(Reg<Int>),
@@ -588,6 +626,8 @@ impl_get!(runtime::IntMap<Str<'a>>, maps_int_str, MapIntStr, 'a);
 impl_get!(runtime::StrMap<'a, Float>, maps_str_float, MapStrFloat, 'a);
 impl_get!(runtime::StrMap<'a, Int>, maps_str_int, MapStrInt, 'a);
 impl_get!(runtime::StrMap<'a, Str<'a>>, maps_str_str, MapStrStr, 'a);
+impl_get!(runtime::IntMap<runtime::StrMap<'a, Str<'a>>>, maps_int_map, MapIntMap, 'a);
+impl_get!(runtime::StrMap<'a, runtime::StrMap<'a, Str<'a>>>, maps_str_map, MapStrMap, 'a);
 impl_get!(runtime::Iter<Int>, iters_int, IterInt);
 impl_get!(runtime::Iter<Str<'a>>, iters_str, IterStr, 'a);
 
@@ -603,7 +643,7 @@ impl<'a> Instr<'a> {
                 sr.accum(&mut f);
                 ir.accum(&mut f)
             }
-            FloatToStr(sr, fr) => {
+            FloatToStr(sr, fr) | FloatToStrOfmt(sr, fr) => {
                 sr.accum(&mut f);
                 fr.accum(&mut f);
             }
@@ -1093,6 +1133,10 @@ impl<'a> Instr<'a> {
                 dst.accum(&mut f);
                 arr.accum(&mut f);
             }
+            MapIntStrSum(dst, arr) => {
+                dst.accum(&mut f);
+                arr.accum(&mut f);
+            }
             MapIntIntMean(dst, arr) => {
                 dst.accum(&mut f);
                 arr.accum(&mut f);
@@ -1389,6 +1433,16 @@ impl<'a> Instr<'a> {
                 l.accum(&mut f);
                 r.accum(&mut f);
             }
+            CheckedInt(_, res, l, r) => {
+                res.accum(&mut f);
+                l.accum(&mut f);
+                r.accum(&mut f);
+            }
+            CheckedFloat(_, res, l, r) => {
+                res.accum(&mut f);
+                l.accum(&mut f);
+                r.accum(&mut f);
+            }
             Not(res, ir) => {
                 res.accum(&mut f);
                 ir.accum(&mut f)
@@ -1423,6 +1477,24 @@ impl<'a> Instr<'a> {
                 x.accum(&mut f);
                 y.accum(&mut f);
             }
+            Bignum(_, dst, x, y, prec) => {
+                dst.accum(&mut f);
+                x.accum(&mut f);
+                y.accum(&mut f);
+                prec.accum(&mut f);
+            }
+            BignumCmp(dst, x, y) | DecimalCmp(dst, x, y) => {
+                dst.accum(&mut f);
+                x.accum(&mut f);
+                y.accum(&mut f);
+            }
+            Decimal(_, dst, x, y, scale, mode) => {
+                dst.accum(&mut f);
+                x.accum(&mut f);
+                y.accum(&mut f);
+                scale.accum(&mut f);
+                mode.accum(&mut f);
+            }
             Rand(res) => res.accum(&mut f),
             Srand(res, seed) => {
                 res.accum(&mut f);
@@ -1443,7 +1515,7 @@ impl<'a> Instr<'a> {
                 l.accum(&mut f);
                 r.accum(&mut f);
             }
-            IsMatch(res, l, r) => {
+            IsMatch(res, l, r) | MatchSet(res, l, r) => {
                 res.accum(&mut f);
                 l.accum(&mut f);
                 r.accum(&mut f);
@@ -1582,6 +1654,16 @@ impl<'a> Instr<'a> {
                 l.accum(&mut f);
                 r.accum(&mut f);
             }
+            StrNumCmp(res, l, r) => {
+                res.accum(&mut f);
+                l.accum(&mut f);
+                r.accum(&mut f);
+            }
+            StrNumCmpFloat(res, l, r) => {
+                res.accum(&mut f);
+                l.accum(&mut f);
+                r.accum(&mut f);
+            }
             SetColumn(dst, src) => {
                 dst.accum(&mut f);
                 src.accum(&mut f)
@@ -1641,7 +1723,10 @@ impl<'a> Instr<'a> {
                     reg.accum(&mut f)
                 }
             }
-            Close(file) => file.accum(&mut f),
+            Close(file, how) => {
+                file.accum(&mut f);
+                how.accum(&mut f);
+            }
             RunCmd(dst, cmd) => {
                 dst.accum(&mut f);
                 cmd.accum(&mut f);
@@ -1756,6 +1841,14 @@ impl<'a> Instr<'a> {
                 dst.accum(&mut f);
                 file.accum(&mut f)
             }
+            ReadErrCoproc(dst, cmd) => {
+                dst.accum(&mut f);
+                cmd.accum(&mut f)
+            }
+            NextLineCoproc(dst, cmd) => {
+                dst.accum(&mut f);
+                cmd.accum(&mut f)
+            }
             ReadErrStdin(dst) => dst.accum(&mut f),
             NextLineStdin(dst) => dst.accum(&mut f),
             JmpIf(cond, _lbl) => cond.accum(&mut f),
@@ -1765,7 +1858,15 @@ impl<'a> Instr<'a> {
                 key.accum(&mut f);
                 val.accum(&mut f);
             }
-            UpdateUsedFields() | NextFile() | NextLineStdinFused() | Call(_) | Jmp(_) | Ret => {}
+            UpdateUsedFields()
+            | ProfileMark(_)
+            | ProfileEnter(_)
+            | ProfileExit()
+            | NextFile()
+            | NextLineStdinFused()
+            | Call(_)
+            | Jmp(_)
+            | Ret => {}
         }
     }
 }
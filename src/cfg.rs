@@ -1,8 +1,12 @@
 use crate::arena;
 use crate::ast::{self, Expr, Stmt, Unop};
+use crate::bignum::Bignum;
 use crate::builtins::{self, IsSprintf};
-use crate::common::{Either, FileSpec, Graph, NodeIx, NumTy, Result, Stage};
+use crate::common::{CheckedArith, Either, FileSpec, Graph, NodeIx, NumTy, Result, Stage};
 use crate::dom;
+use crate::lexer::Loc;
+use crate::profile::{self, Profile, Region};
+use crate::sort_loops::SortLoops;
 
 use hashbrown::{HashMap, HashSet};
 use petgraph::Direction;
@@ -46,9 +50,25 @@ impl<I> FunctionName<I> {
     }
 }
 
+/// Where a primitive statement came from: the start of the AWK statement that generated it, and of
+/// the call within that statement, if it was generated for one.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct SrcLoc {
+    pub stmt: Loc,
+    pub call: Option<Loc>,
+}
+
+impl SrcLoc {
+    /// The most precise location available, which is where errors are reported.
+    pub(crate) fn error(&self) -> Loc {
+        self.call.unwrap_or(self.stmt)
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct BasicBlock<'a> {
-    pub q: VecDeque<PrimStmt<'a>>,
+    // The statements in the block, along with their source location where known.
+    pub q: VecDeque<(PrimStmt<'a>, Option<SrcLoc>)>,
     pub sealed: bool,
 }
 
@@ -70,7 +90,7 @@ pub(crate) type Cfg<'a> = Graph<BasicBlock<'a>, Transition<'a>>;
 fn dbg_print(cfg: &Cfg, w: &mut impl io::Write) -> io::Result<()> {
     for (i, n) in cfg.raw_nodes().iter().enumerate() {
         writeln!(w, "{}:", i)?;
-        for s in n.weight.q.iter() {
+        for (s, _) in n.weight.q.iter() {
             writeln!(w, "\t{}", s)?;
         }
         let mut walker = cfg.neighbors(NodeIx::new(i)).detach();
@@ -289,6 +309,18 @@ pub(crate) struct ProgramContext<'a, I> {
     pub fold_regex_constants: bool,
     // Thread through information regarding header columns used.
     pub parse_header: bool,
+    // Infer strnums for `--posix`.
+    pub posix: bool,
+    // Set by `--checked-arith`.
+    pub checked_arith: Option<CheckedArith>,
+    // Set by `--ordered-maps`.
+    pub ordered_maps: bool,
+    // Set when there are no input files, so the main input is standard input.
+    pub stdin_is_input: bool,
+    // Set by `-M`.
+    pub bignum: bool,
+    // Set when running under `--profile`.
+    pub profile: Option<Profile>,
 }
 
 impl<'a, I> ProgramContext<'a, I> {
@@ -324,6 +356,9 @@ pub enum SepAssign<'a> {
     Potential {
         field_sep: Option<&'a [u8]>,
         record_sep: Option<&'a [u8]>,
+        // FIELDWIDTHS or FPAT may be assigned, so records must not be split into fields as they
+        // are read. The record separator is a single byte in this case.
+        split_lazily: bool,
     },
     Unsure,
 }
@@ -347,6 +382,10 @@ impl<'a, I> ProgramContext<'a, I>
     pub(crate) fn local_globals_ref(&self) -> &HashSet<NumTy> {
         &self.shared.local_globals
     }
+    /// Whether any function in the program assigns to the builtin variable `var`.
+    pub(crate) fn assigns_var(&self, var: builtins::Variable) -> bool {
+        self.funcs.iter().any(|f| f.vars.contains_key(&Some(var)))
+    }
 
     // We want to optimize scripts that never override FS after the start of the program. We do
     // this by collecting any builtin variable assignments (as well as getline and UDF calls)
@@ -417,9 +456,26 @@ impl<'a, I> ProgramContext<'a, I>
         if has_getline && (field_sep.is_some() || record_sep.is_some()) {
             return SepAssign::Unsure;
         }
+        let split_lazily = self.assigns_var(builtins::Variable::FIELDWIDTHS)
+            || self.assigns_var(builtins::Variable::FPAT);
+        // Records that are split lazily are read by splitting on a single-byte record separator;
+        // fall back to the regex splitter for anything else.
+        if split_lazily && record_sep.map_or(false, |rs| rs.len() != 1) {
+            return SepAssign::Unsure;
+        }
+        // The byte-oriented readers compare separators exactly; with IGNORECASE a separator
+        // containing letters has to go through the (case-insensitive) regex splitter.
+        let has_letters =
+            |sep: Option<&[u8]>| sep.map_or(false, |s| s.iter().any(u8::is_ascii_alphabetic));
+        if self.assigns_var(builtins::Variable::IGNORECASE)
+            && (has_letters(field_sep) || has_letters(record_sep))
+        {
+            return SepAssign::Unsure;
+        }
         SepAssign::Potential {
             field_sep,
             record_sep,
+            split_lazily,
         }
     }
 
@@ -445,9 +501,29 @@ impl<'a, I> ProgramContext<'a, I>
             max: 1, // 0 reserved for assigning to "unused" var for side-effecting operations
             conds: Default::default(),
             esc,
+            next_funcs: Default::default(),
+            next_flag: None,
+            checked_arith: p.checked_arith,
         };
         let mut func_table: HashMap<FunctionName<I>, NumTy> = Default::default();
         let mut funcs: Vec<Function<'a, I>> = Default::default();
+        // Loops ordered by a comparison function in `PROCINFO["sorted_in"]` are desugared before
+        // anything else; see the sort_loops module.
+        let mut sort_loops = SortLoops::new(arena, p)?;
+        // With -M, arithmetic is then rewritten to work on exact decimal strings; see the bignum
+        // module.
+        let mut bignum = p.bignum.then(|| Bignum::new(arena));
+        let bodies: Vec<_> = p
+            .decs
+            .iter()
+            .map(|fundec| {
+                let body = sort_loops.rewrite_function(fundec);
+                match &mut bignum {
+                    Some(bignum) => bignum.rewrite(body),
+                    None => body,
+                }
+            })
+            .collect();
         for fundec in p.decs.iter() {
             if func_table
                 .insert(
@@ -490,6 +566,45 @@ impl<'a, I> ProgramContext<'a, I>
             f.ret = ret;
             funcs.push(f);
         }
+
+        // Functions may call `next` or `nextfile`, so long as they are only called from the main
+        // loop. Compute the set of functions that can reach a `next` statement so that call
+        // sites know to check for one.
+        let mut may_next = Vec::with_capacity(p.decs.len());
+        let mut callees = Vec::with_capacity(p.decs.len());
+        for body in bodies.iter() {
+            let mut calls = Vec::new();
+            may_next.push(has_next(body, &mut calls));
+            callees.push(
+                calls
+                    .into_iter()
+                    .flat_map(|c| func_table.get(&FunctionName::Named(c)).cloned())
+                    .collect::<Vec<NumTy>>(),
+            );
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, cs) in callees.iter().enumerate() {
+                if !may_next[i] && cs.iter().any(|c| may_next[*c as usize]) {
+                    may_next[i] = true;
+                    changed = true;
+                }
+            }
+        }
+        shared.next_funcs = may_next
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b)
+            .map(|(i, _)| i as NumTy)
+            .collect();
+        if !shared.next_funcs.is_empty() {
+            // Pick an illegal frawk identifier, so the flag cannot collide with a user variable.
+            let flag = shared.fresh();
+            shared.hm.insert(I::from("--next"), flag);
+            shared.may_rename.push(flag);
+            shared.next_flag = Some(flag);
+        }
         // Now that we have all the functions in place, it's time to fill them up and convert them
         // to SSA.
         macro_rules! fill {
@@ -502,6 +617,7 @@ impl<'a, I> ProgramContext<'a, I>
                         f: &mut func,
                         func_table: &func_table,
                         parse_header: p.parse_header,
+                        arena,
                     }
                     .fill(s)?;
                     func_table.insert($name, offset as NumTy);
@@ -513,7 +629,7 @@ impl<'a, I> ProgramContext<'a, I>
             };
         }
 
-        for fundec in p.decs.iter() {
+        for (fundec, body) in p.decs.iter().zip(bodies) {
             let f = *func_table
                 .get_mut(&FunctionName::Named(fundec.name.clone()))
                 .unwrap();
@@ -522,12 +638,21 @@ impl<'a, I> ProgramContext<'a, I>
                 f: funcs.get_mut(f as usize).unwrap(),
                 func_table: &func_table,
                 parse_header: p.parse_header,
+                arena,
             }
-                .fill(fundec.body)?;
+                .fill(body)?;
         }
 
         // Bind the main function
-        let main_offset = match p.desugar_stage(arena) {
+        let mut regions = Vec::new();
+        let stage = p
+            .desugar_stage(arena, &mut regions)
+            .map(|s| sort_loops.rewrite(s))
+            .map(|s| match &mut bignum {
+                Some(bignum) => bignum.rewrite(s),
+                None => s,
+            });
+        let main_offset = match stage {
             Stage::Main(main_stmt) => {
                 Stage::Main(fill!(Some(main_stmt), FunctionName::MainLoop).unwrap())
             }
@@ -556,6 +681,19 @@ impl<'a, I> ProgramContext<'a, I>
             }
         };
 
+        let profile = if p.profile {
+            for fundec in p.decs.iter() {
+                regions.push(Region::new(
+                    profile::Kind::Function,
+                    fundec.name.to_string(),
+                    Some(fundec.loc),
+                ));
+            }
+            Some(Profile::new(regions))
+        } else {
+            None
+        };
+
         Ok(ProgramContext {
             shared,
             funcs,
@@ -563,6 +701,12 @@ impl<'a, I> ProgramContext<'a, I>
             allow_arbitrary_commands: false,
             fold_regex_constants: false,
             parse_header: p.parse_header,
+            posix: false,
+            checked_arith: p.checked_arith,
+            ordered_maps: false,
+            stdin_is_input: false,
+            bignum: p.bignum,
+            profile,
         })
     }
 }
@@ -572,6 +716,126 @@ struct View<'a, 'b, I> {
     f: &'a mut Function<'b, I>,
     func_table: &'a HashMap<FunctionName<I>, NumTy>,
     parse_header: bool,
+    // For strings built up while lowering statements, such as the patterns of a `switch`.
+    arena: &'b arena::Arena,
+}
+
+// Determine whether `stmt` contains a `next` or `nextfile` statement directly. The names of any
+// functions `stmt` calls are pushed onto `calls`, so the caller can close over the call graph.
+fn has_next<'a, 'b, I: Clone>(stmt: &Stmt<'a, 'b, I>, calls: &mut Vec<I>) -> bool {
+    fn expr_calls<'a, 'b, I: Clone>(e: &Expr<'a, 'b, I>, calls: &mut Vec<I>) {
+        use ast::Expr::*;
+        match e {
+            ILit(_) | FLit(_) | StrLit(_) | PatLit(_) | BigLit(_) | Var(_) | ReadStdin
+            | Cond(_) => {}
+            Unop(_, x) => expr_calls(x, calls),
+            Binop(_, x, y) | Index(x, y) | Assign(x, y) | AssignOp(x, _, y) | And(x, y)
+            | Or(x, y) => {
+                expr_calls(x, calls);
+                expr_calls(y, calls);
+            }
+            ITE(c, t, f) => {
+                expr_calls(c, calls);
+                expr_calls(t, calls);
+                expr_calls(f, calls);
+            }
+            Call(fname, args) => {
+                if let Either::Left(fname) = fname {
+                    calls.push(fname.clone());
+                }
+                for a in args.iter() {
+                    expr_calls(a, calls);
+                }
+            }
+            Inc { x, .. } | Located(_, x) => expr_calls(x, calls),
+            Getline { into, from, .. } => {
+                for x in into.iter().chain(from.iter()) {
+                    expr_calls(x, calls);
+                }
+            }
+        }
+    }
+    fn output_calls<'a, 'b, I: Clone>(
+        out: &Option<(&Expr<'a, 'b, I>, FileSpec)>,
+        calls: &mut Vec<I>,
+    ) {
+        if let Some((o, _)) = out {
+            expr_calls(o, calls);
+        }
+    }
+    match stmt {
+        Stmt::StartCond(_)
+        | Stmt::EndCond(_)
+        | Stmt::LastCond(_)
+        | Stmt::Break
+        | Stmt::Continue => false,
+        Stmt::Next | Stmt::NextFile => true,
+        Stmt::Expr(e) => {
+            expr_calls(e, calls);
+            false
+        }
+        Stmt::Return(e) => {
+            if let Some(e) = e {
+                expr_calls(e, calls);
+            }
+            false
+        }
+        Stmt::Block(stmts) => {
+            let mut res = false;
+            for s in stmts.iter() {
+                res |= has_next(s, calls);
+            }
+            res
+        }
+        Stmt::Print(args, out) => {
+            for a in args.iter() {
+                expr_calls(a, calls);
+            }
+            output_calls(out, calls);
+            false
+        }
+        Stmt::Printf(fmt, args, out) => {
+            expr_calls(fmt, calls);
+            for a in args.iter() {
+                expr_calls(a, calls);
+            }
+            output_calls(out, calls);
+            false
+        }
+        Stmt::If(cond, t, f) => {
+            expr_calls(cond, calls);
+            let mut res = has_next(t, calls);
+            if let Some(f) = f {
+                res |= has_next(f, calls);
+            }
+            res
+        }
+        Stmt::For(init, cond, update, body) => {
+            let mut res = false;
+            for s in init.iter().chain(update.iter()) {
+                res |= has_next(s, calls);
+            }
+            if let Some(c) = cond {
+                expr_calls(c, calls);
+            }
+            res | has_next(body, calls)
+        }
+        Stmt::DoWhile(cond, body)
+        | Stmt::While(_, cond, body)
+        | Stmt::ForEach(_, cond, body) => {
+            expr_calls(cond, calls);
+            has_next(body, calls)
+        }
+        Stmt::Switch(e, cases) => {
+            expr_calls(e, calls);
+            let mut res = false;
+            for (_, body) in cases.iter() {
+                res |= has_next(body, calls);
+            }
+            res
+        }
+        Stmt::Located(_, s) => has_next(s, calls),
+    }
 }
 
 #[derive(Debug)]
@@ -592,6 +856,14 @@ struct GlobalContext<I> {
     max: NumTy,
     conds: HashMap<usize, Ident>,
     esc: Escaper,
+    // User-defined functions that may execute `next` or `nextfile`, either directly or through
+    // one of their callees.
+    next_funcs: HashSet<NumTy>,
+    // A hidden global set when a user-defined function executes `next` or `nextfile`. Functions
+    // return as soon as they observe it set after a call, until control reaches the main loop.
+    next_flag: Option<Ident>,
+    // Set by `--checked-arith`, which needs `a[k] += x` to go through the usual `+`.
+    checked_arith: Option<CheckedArith>,
 }
 
 impl<I> GlobalContext<I> {
@@ -638,11 +910,17 @@ pub(crate) struct Function<'a, I> {
     // Stack of the entry and exit nodes for the loops within which the current statement is
     // nested.
     loop_ctx: SmallVec<(NodeIx, NodeIx)>,
+    // Stack of the exit nodes for the switch statements within which the current statement is
+    // nested, along with the depth of `loop_ctx` when the switch was entered. `break` exits the
+    // innermost switch if it is nested more deeply than the innermost loop.
+    switch_ctx: SmallVec<(usize, NodeIx)>,
     // Header node for the toplevel "pattern matching" loop of the AWK program. This is used to
     // implement the nonlocal continue of the `next` and `nextfile` statements.
     //
-    // NB: We only support doing this from main.
+    // NB: This is only set in main; other functions signal `next` via `GlobalContext::next_flag`.
     toplevel_header: Option<NodeIx>,
+    // The location of the statement currently being converted.
+    loc: Option<SrcLoc>,
 
     vars: VarAssigns<'a>,
 
@@ -668,7 +946,9 @@ impl<'a, I> Function<'a, I> {
             entry,
             exit,
             loop_ctx: Default::default(),
+            switch_ctx: Default::default(),
             toplevel_header: None,
+            loc: None,
             vars: Default::default(),
             dt: Default::default(),
             df: Default::default(),
@@ -896,7 +1176,6 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 } else {
                     current_open
                 };
-                // todo fix this bug with continue bug
                 let (h, b_start, _b_end, f) = self.make_loop(
                     body, *update, init_end, /*is_do*/ false, /*is_toplevel*/ false,
                 )?;
@@ -914,6 +1193,10 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
             While(is_toplevel, cond, body) => {
                 let (h, b_start, _b_end, f) =
                     self.make_loop(body, None, current_open, /*is_do*/ false, *is_toplevel)?;
+                if *is_toplevel {
+                    // Any END blocks following the main loop cannot jump back into it.
+                    self.f.toplevel_header = None;
+                }
                 let (h_end, cond_val) = self.convert_val(cond, h)?;
                 self.f
                     .cfg
@@ -938,7 +1221,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
             }
             ForEach(v, array, body) => {
                 let v_id = self.get_identifier(v);
-                let (next, array_val) = self.convert_val(array, current_open)?;
+                let (next, array_val) = self.convert_array(array, current_open)?;
                 current_open = next;
                 let array_iter =
                     self.to_val(PrimExpr::IterBegin(array_val.clone()), current_open)?;
@@ -977,6 +1260,108 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
 
                 footer
             }
+            Switch(e, cases) => {
+                let (next, subject) = self.convert_val(e, current_open)?;
+                current_open = next;
+                let footer = self.f.cfg.add_node(Default::default());
+
+                // Convert the case bodies. Each one falls through to the next.
+                self.f.switch_ctx.push((self.f.loop_ctx.len(), footer));
+                let mut bodies = Vec::with_capacity(cases.len());
+                for (_, body) in cases.iter() {
+                    bodies.push(self.standalone_block(body)?);
+                }
+                self.f.switch_ctx.pop().unwrap();
+                for (i, (_, end)) in bodies.iter().enumerate() {
+                    match bodies.get(i + 1) {
+                        Some((start, _)) => self.guarded_else(*end, *start),
+                        // Always give the footer an edge from the switch, even if the last case
+                        // ends with a `break` or `return`.
+                        None => {
+                            self.f.cfg.add_edge(*end, footer, Transition::null());
+                        }
+                    }
+                }
+
+                // Regex cases are matched all at once, with a RegexSet: `first` is the
+                // (1-based) index of the first regex case matching the subject, or 0. Reaching
+                // regex case k means no earlier case matched, so it matches iff `first` is k.
+                // Patterns are joined by newlines, so those containing one are matched alone.
+                let pats: Vec<&[u8]> = cases
+                    .iter()
+                    .filter_map(|(label, _)| match label {
+                        Some(ast::Expr::PatLit(pat)) => Some(*pat),
+                        _ => None,
+                    })
+                    .collect();
+                let first = if pats.len() > 1 && pats.iter().all(|p| !p.contains(&b'\n')) {
+                    let joined = self.arena.alloc_bytes(&pats.join(&b'\n'));
+                    Some(self.to_val(
+                        PrimExpr::CallBuiltin(
+                            builtins::Function::MatchSet,
+                            smallvec![subject.clone(), PrimVal::StrLit(joined)],
+                        ),
+                        current_open,
+                    )?)
+                } else {
+                    None
+                };
+
+                // Test each case label in order, jumping to the default case (or past the switch
+                // entirely) if none of them match.
+                let mut default = None;
+                let mut regex_ix = 0;
+                for ((label, _), (start, _)) in cases.iter().zip(bodies.iter()) {
+                    let label = match label {
+                        Some(label) => label,
+                        None if default.is_some() => {
+                            return err!("switch statement has more than one default case")
+                        }
+                        None => {
+                            default = Some(*start);
+                            continue;
+                        }
+                    };
+                    let (next, args, op) = match (label, &first) {
+                        (ast::Expr::PatLit(_), Some(first)) => {
+                            regex_ix += 1;
+                            (
+                                current_open,
+                                smallvec![first.clone(), PrimVal::ILit(regex_ix)],
+                                ast::Binop::EQ,
+                            )
+                        }
+                        (ast::Expr::PatLit(_), None) => {
+                            let (next, label_v) = self.convert_val(label, current_open)?;
+                            (
+                                next,
+                                smallvec![subject.clone(), label_v],
+                                ast::Binop::IsMatch,
+                            )
+                        }
+                        _ => {
+                            let (next, label_v) = self.convert_val(label, current_open)?;
+                            (next, smallvec![subject.clone(), label_v], ast::Binop::EQ)
+                        }
+                    };
+                    let matches = self.to_val(
+                        PrimExpr::CallBuiltin(builtins::Function::Binop(op), args),
+                        next,
+                    )?;
+                    let fallback = self.f.cfg.add_node(Default::default());
+                    self.f
+                        .cfg
+                        .add_edge(next, *start, Transition::new(matches));
+                    self.f.cfg.add_edge(next, fallback, Transition::null());
+                    current_open = fallback;
+                }
+                self.f.cfg.add_edge(
+                    current_open,
+                    default.unwrap_or(footer),
+                    Transition::null(),
+                );
+                footer
+            }
             Break => {
                 self.do_break_continue(current_open, /*is_break*/ true)?;
                 current_open
@@ -1006,6 +1391,15 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 self.seal(current_open);
                 current_open
             }
+            Located(loc, s) => {
+                let outer = self.f.loc.replace(SrcLoc {
+                    stmt: *loc,
+                    call: None,
+                });
+                let res = self.convert_stmt(s, current_open);
+                self.f.loc = outer;
+                res.map_err(|e| e.at(*loc))?
+            }
         })
     }
 
@@ -1039,6 +1433,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                     .convert_expr(&Binop(IsMatch, &Unop(Column, &ILit(0)), expr), current_open);
             }
             PatLit(s) | StrLit(s) => PrimExpr::Val(PrimVal::StrLit(s)),
+            BigLit(s) => PrimExpr::Val(PrimVal::StrLit(s.as_bytes())),
             Cond(cond) => {
                 let id = self.get_cond(*cond);
                 PrimExpr::Val(PrimVal::Var(id))
@@ -1100,11 +1495,33 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 }
             }
             Index(arr, ix) => {
-                let (next, arr_v) = self.convert_val_inner(arr, current_open, in_cond)?;
+                let (next, arr_v) = self.convert_array(arr, current_open)?;
                 let (next, ix_v) = self.convert_val_inner(ix, next, in_cond)?;
                 return Ok((next, PrimExpr::Index(arr_v, ix_v)));
             }
             Call(fname, args) => return self.call(current_open, fname, args),
+            Located(loc, x) => {
+                let stmt = self.f.loc.map_or(*loc, |l| l.stmt);
+                let outer = self.f.loc.replace(SrcLoc {
+                    stmt,
+                    call: Some(*loc),
+                });
+                let res = self.convert_expr_inner(x, current_open, in_cond).and_then(|res| {
+                    match res {
+                        // Evaluate a call that can fail in a statement of its own, so that
+                        // errors in it are reported at `loc` and not at the start of the enclosing
+                        // statement. Errors in a UDF are reported at the statement in its body.
+                        (next, e @ PrimExpr::CallBuiltin(f, _)) if f.may_fail() => {
+                            let v = self.fresh_local();
+                            self.add_stmt(next, PrimStmt::AsgnVar(v, e))?;
+                            Ok((next, PrimExpr::Val(PrimVal::Var(v))))
+                        }
+                        res => Ok(res),
+                    }
+                });
+                self.f.loc = outer;
+                return res.map_err(|e| e.at(*loc));
+            }
             Assign(Index(arr, ix), to) => {
                 return self.do_assign_index(
                     arr,
@@ -1115,7 +1532,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
             }
 
             AssignOp(Index(arr, ix), op, to) => {
-                if let ast::Binop::Plus = op {
+                if let (ast::Binop::Plus, None) = (op, self.ctx.checked_arith) {
                     // We don't need in_cond here, it would seem, because there aren't
                     // subexpressions which should be considered patterns.
                     return self.convert_expr(
@@ -1211,11 +1628,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                     current_open,
                 );
             }
-            Getline {
-                from,
-                into,
-                is_file,
-            } => {
+            Getline { from, into, src } => {
                 // break up getline calls to help the FS analysis out
                 let next = self.f.cfg.add_node(Default::default());
                 self.f.cfg.add_edge(current_open, next, Transition::null());
@@ -1237,10 +1650,14 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 //  getline var => getline var < stdin
                 //  getline => getline $0
                 use builtins::Function::{
-                    Nextline, NextlineCmd, NextlineStdin, ReadErr, ReadErrCmd, ReadErrStdin,
+                    Nextline, NextlineCmd, NextlineCoproc, NextlineStdin, ReadErr, ReadErrCmd,
+                    ReadErrCoproc, ReadErrStdin,
+                };
+                let (next_line, read_err) = match src {
+                    ast::GetlineSrc::File => (Nextline, ReadErr),
+                    ast::GetlineSrc::Cmd => (NextlineCmd, ReadErrCmd),
+                    ast::GetlineSrc::Coproc => (NextlineCoproc, ReadErrCoproc),
                 };
-                let next_line = if *is_file { Nextline } else { NextlineCmd };
-                let read_err = if *is_file { ReadErr } else { ReadErrCmd };
                 match (from, into) {
                     // an unadorned `getline` is uses the "fused" stdin construct, which in turn
                     // enables some optimizations.
@@ -1256,7 +1673,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                             &ast::Expr::Getline {
                                 from: *from,
                                 into: Some(&Unop(ast::Unop::Column, &ast::Expr::ILit(0))),
-                                is_file: *is_file,
+                                src: *src,
                             },
                             current_open,
                         );
@@ -1393,14 +1810,14 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
         ) -> Result<(NodeIx, PrimExpr<'b>)>,
         current_open: NodeIx,
     ) -> Result<(NodeIx, PrimExpr<'b>)> {
-        let (next, arr_e) = self.convert_expr(arr, current_open)?;
+        let (next, arr_v) = self.convert_array(arr, current_open)?;
 
         // Only assign to a new variable if we need to.
-        let arr_id = if let PrimExpr::Val(PrimVal::Var(id)) = arr_e {
+        let arr_id = if let PrimVal::Var(id) = arr_v {
             id
         } else {
             let arr_id = self.fresh_local();
-            self.add_stmt(next, PrimStmt::AsgnVar(arr_id, arr_e))?;
+            self.add_stmt(next, PrimStmt::AsgnVar(arr_id, PrimExpr::Val(arr_v)))?;
             arr_id
         };
 
@@ -1465,9 +1882,35 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
         Ok((next_open, self.to_val(e, next_open)?))
     }
 
+    // Converts an expression used in a position that requires an array (e.g. `a[i]` in `a[i][j]`
+    // or `for (k in a[i])`). Index expressions in this position are subarrays, which we lower to
+    // a `Subarray` call so that type inference knows `a` is an array of arrays.
+    fn convert_array<'c>(
+        &mut self,
+        expr: &'c Expr<'c, 'b, I>,
+        current_open: NodeIx,
+    ) -> Result<(NodeIx, PrimVal<'b>)> {
+        if let Expr::Index(arr, ix) = expr {
+            let (next, arr_v) = self.convert_array(arr, current_open)?;
+            let (next, ix_v) = self.convert_val(ix, next)?;
+            let sub = PrimExpr::CallBuiltin(builtins::Function::Subarray, smallvec![arr_v, ix_v]);
+            return Ok((next, self.to_val(sub, next)?));
+        }
+        self.convert_val(expr, current_open)
+    }
+
     // Handles "break", "continue" statements.
     fn do_break_continue(&mut self, current_open: NodeIx, is_break: bool) -> Result<()> {
         let name = if is_break { "break" } else { "continue" };
+        if is_break {
+            if let Some((depth, footer)) = self.f.switch_ctx.last().cloned() {
+                if depth == self.f.loop_ctx.len() {
+                    self.f.cfg.add_edge(current_open, footer, Transition::null());
+                    self.seal(current_open);
+                    return Ok(());
+                }
+            }
+        }
         if self.f.loop_ctx.len() == 1 && self.f.toplevel_header.is_some() {
             return err!("{} statement must be inside a loop", name);
         }
@@ -1511,15 +1954,77 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 .add_edge(current_open, header, Transition::null());
             self.seal(current_open);
             Ok(())
+        } else if let FunctionName::Named(_) = self.f.name {
+            // `next` inside of a function sets the pending flag and returns. Callers check the
+            // flag after the call returns; see `check_pending_next`.
+            if is_next_file {
+                self.add_stmt(
+                    current_open,
+                    PrimStmt::AsgnVar(
+                        Ident::unused(),
+                        PrimExpr::CallBuiltin(builtins::Function::NextFile, smallvec![]),
+                    ),
+                )?;
+            }
+            let flag = self
+                .ctx
+                .next_flag
+                .expect("functions containing `next` must allocate a flag");
+            self.add_stmt(
+                current_open,
+                PrimStmt::AsgnVar(flag, PrimExpr::Val(PrimVal::ILit(1))),
+            )?;
+            self.f
+                .cfg
+                .add_edge(current_open, self.f.exit, Transition::null());
+            self.seal(current_open);
+            Ok(())
         } else {
             err!(
-                "Cannot use `{}` from outside of the toplevel loop! \
-                 Note that frawk does not support `next` or `nextfile` from inside functions.",
+                "Cannot use `{}` from outside of the toplevel loop!",
                 if is_next_file { "nextfile" } else { "next" }
             )
         }
     }
 
+    // Called after a call to a function that may execute `next` or `nextfile`. If the function
+    // did so, we either return immediately (when inside another function) or clear the flag and
+    // jump to the header of the toplevel loop (when inside main).
+    fn check_pending_next(&mut self, current_open: NodeIx, fname: &I) -> Result<NodeIx> {
+        let flag = self
+            .ctx
+            .next_flag
+            .expect("functions containing `next` must allocate a flag");
+        let target = if let FunctionName::Named(_) = self.f.name {
+            None
+        } else if let Some(header) = self.f.toplevel_header {
+            Some(header)
+        } else {
+            return err!(
+                "function `{}` may call `next` or `nextfile`, so it can only be called from the \
+                 main loop (not from BEGIN or END)",
+                fname
+            );
+        };
+        let bail = self.f.cfg.add_node(Default::default());
+        let next = self.f.cfg.add_node(Default::default());
+        self.f
+            .cfg
+            .add_edge(current_open, bail, Transition::new(PrimVal::Var(flag)));
+        self.f.cfg.add_edge(current_open, next, Transition::null());
+        match target {
+            Some(header) => {
+                self.add_stmt(bail, PrimStmt::AsgnVar(flag, PrimExpr::Val(PrimVal::ILit(0))))?;
+                self.f.cfg.add_edge(bail, header, Transition::null());
+            }
+            None => {
+                self.f.cfg.add_edge(bail, self.f.exit, Transition::null());
+            }
+        }
+        self.seal(bail);
+        Ok(next)
+    }
+
     fn make_loop<'c>(
         &mut self,
         body: &'c Stmt<'c, 'b, I>,
@@ -1533,10 +2038,16 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
         NodeIx, // body footer
         NodeIx, // footer = next open
     )> {
-        // Create header and footer nodes.
+        // Create header and footer nodes. `continue` in a loop with an update jumps to a node
+        // running the update rather than straight to the header.
         let h = self.f.cfg.add_node(Default::default());
         let f = self.f.cfg.add_node(Default::default());
-        self.f.loop_ctx.push((h, f));
+        let cont = if update.is_some() {
+            self.f.cfg.add_node(Default::default())
+        } else {
+            h
+        };
+        self.f.loop_ctx.push((cont, f));
         if is_toplevel {
             self.f.toplevel_header = Some(h);
         }
@@ -1544,7 +2055,8 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
         // The body is a standalone graph.
         let (b_start, b_end) = if let Some(u) = update {
             let (start, mid) = self.standalone_block(body)?;
-            let end = self.convert_stmt(u, mid)?;
+            self.f.cfg.add_edge(mid, cont, Transition::null());
+            let end = self.convert_stmt(u, cont)?;
             (start, end)
         } else {
             self.standalone_block(body)?
@@ -1564,7 +2076,6 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
             // Current => Header => Body => Footer
             //             ^         |
             //             ^---------
-            self.f.cfg.add_edge(b_start, h, Transition::null());
             self.f.cfg.add_edge(current_open, h, Transition::null());
             self.f.cfg.add_edge(b_end, h, Transition::null());
         }
@@ -1611,10 +2122,25 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
             // that usage here.
             Either::Right(bi) => Either::Right(*bi),
         };
+        // Builtins that operate on arrays treat `a[i]` arguments as subarrays.
+        let array_arg = match bi {
+            Either::Right(
+                builtins::Function::Delete
+                | builtins::Function::Contains
+                | builtins::Function::Clear
+                | builtins::Function::IncMap,
+            ) => Some(0),
+            Either::Right(builtins::Function::Split) => Some(1),
+            _ => None,
+        };
         let mut prim_args = SmallVec::with_capacity(args.len());
         let mut open = current_open;
-        for a in args.iter() {
-            let (next, v) = self.convert_val(a, open)?;
+        for (i, a) in args.iter().enumerate() {
+            let (next, v) = if array_arg == Some(i) {
+                self.convert_array(a, open)?
+            } else {
+                self.convert_val(a, open)?
+            };
             open = next;
             prim_args.push(v);
         }
@@ -1630,7 +2156,17 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                         .entry(None)
                         .or_insert_with(Vec::new)
                         .push((current_open.index(), None));
-                    Ok((open, PrimExpr::CallUDF(*i, prim_args)))
+                    if self.ctx.next_funcs.contains(i) {
+                        let res = self.fresh_local();
+                        self.add_stmt(
+                            open,
+                            PrimStmt::AsgnVar(res, PrimExpr::CallUDF(*i, prim_args)),
+                        )?;
+                        let open = self.check_pending_next(open, &fname)?;
+                        Ok((open, PrimExpr::Val(PrimVal::Var(res))))
+                    } else {
+                        Ok((open, PrimExpr::CallUDF(*i, prim_args)))
+                    }
                 } else {
                     err!("Call to unknown function \"{}\"", fname)
                 };
@@ -1670,6 +2206,10 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 // Fill params if absent
                 let args_len = args.len();
                 match bi {
+                    // close(file) => close(file, "")
+                    builtins::Function::Close if args_len == 1 => {
+                        prim_args.push(PrimVal::StrLit(b""));
+                    }
                     // getenv(name) => getenv(name, default_value)
                     builtins::Function::GetEnv if args_len == 1 => {
                         prim_args.push(PrimVal::StrLit(b""));
@@ -1816,6 +2356,19 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                     builtins::Function::HttpPost if args_len == 2 => {
                         prim_args.push(PrimVal::Var(Ident::unused()));
                     }
+                    // decimal_round(x, scale) => decimal_round(x, "", scale, "");
+                    // decimal_add(x, y) => decimal_add(x, y, -1, "");
+                    builtins::Function::Decimal(op) if args_len >= 1 => {
+                        if op == builtins::DecimalOp::Round {
+                            prim_args.insert(1, PrimVal::StrLit(b""));
+                        }
+                        if prim_args.len() == 2 {
+                            prim_args.push(PrimVal::ILit(-1));
+                        }
+                        if prim_args.len() == 3 {
+                            prim_args.push(PrimVal::StrLit(b""));
+                        }
+                    }
                     _ => {}
                 }
 
@@ -1943,15 +2496,16 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
         if let PrimStmt::AsgnVar(ident, _) = stmt {
             self.record_ident(ident, at);
         }
+        let loc = self.f.loc;
         let bb = self.f.cfg.node_weight_mut(at).unwrap();
         if bb.sealed {
             return err!(
                 "appending to sealed basic block ({}). Last instr={:?}",
                 at.index(),
-                bb.q.back().unwrap()
+                bb.q.back().unwrap().0
             );
         }
-        bb.q.push_back(stmt);
+        bb.q.push_back((stmt, loc));
         Ok(())
     }
 
@@ -1994,12 +2548,12 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                                 .collect(),
                         );
                         let stmt = PrimStmt::AsgnVar(ident, phi);
-                        self.f
+                        let bb = self
+                            .f
                             .cfg
                             .node_weight_mut(d_ix)
-                            .expect("node in dominance frontier must be valid")
-                            .q
-                            .push_front(stmt);
+                            .expect("node in dominance frontier must be valid");
+                        bb.q.push_front((stmt, None));
                         phis.entry(ident).or_insert(HashSet::default()).insert(d_ix);
                         if !defsites.contains(&d_ix) {
                             worklist.insert(d_ix);
@@ -2054,7 +2608,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
 
             // First, go through all the statements and update the variables to the highest
             // subscript (second component in Ident).
-            for stmt in &mut f
+            for (stmt, _) in &mut f
                 .cfg
                 .node_weight_mut(cur)
                 .expect("rename must be passed valid node indices")
@@ -2108,7 +2662,7 @@ impl<'a, 'b, I: Hash + Eq + Clone + Default + std::fmt::Display + std::fmt::Debu
                 {
                     *sub = state[*low as usize].latest();
                 }
-                for stmt in &mut f.cfg.node_weight_mut(neigh).unwrap().q {
+                for (stmt, _) in &mut f.cfg.node_weight_mut(neigh).unwrap().q {
                     if let PrimStmt::AsgnVar(_, PrimExpr::Phi(ps)) = stmt {
                         for (pred, Ident { low, sub, .. }) in ps.iter_mut() {
                             if pred == &cur {
@@ -170,7 +170,8 @@ fn ty_to_param(ty: compile::Ty, ptr_ty: Type) -> Result<AbiParam> {
     let clif_ty = match ty {
         Null | Int => types::I64,
         Float => types::F64,
-        MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | Str => ptr_ty,
+        MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+        | MapStrMap | Str => ptr_ty,
         IterInt | IterStr => return err!("attempt to take iterator as parameter"),
         // We assume that null parameters are omitted from the argument list ahead of time
         // Null => return err!("attempt to take null as parameter"),
@@ -190,6 +191,7 @@ fn ty_to_clifty(ty: compile::Ty, ptr_ty: Type) -> Result<Type> {
         Str => Ok(types::I128),
         MapIntInt | MapIntFloat | MapIntStr => Ok(ptr_ty),
         MapStrInt | MapStrFloat | MapStrStr => Ok(ptr_ty),
+        MapIntMap | MapStrMap => Ok(ptr_ty),
         IterInt | IterStr => err!("taking type of an iterator"),
     }
 }
@@ -270,7 +272,7 @@ impl Generator {
             .shared
             .module
             .finalize_definitions()
-            .map_err(|err| CompileError(format!("{}", err)))?;
+            .map_err(|err| CompileError::new(format!("{}", err)))?;
         Ok(global)
     }
 
@@ -287,7 +289,7 @@ impl Generator {
             .shared
             .module
             .declare_function(name, Linkage::Export, &sig)
-            .map_err(|e| CompileError(format!("failed to declare main function: {}", e)))?;
+            .map_err(|e| CompileError::new(format!("failed to declare main function: {}", e)))?;
         let prelude = Prelude {
             sig,
             refs: smallvec![PLACEHOLDER],
@@ -350,7 +352,7 @@ impl Generator {
         self.shared
             .module
             .define_function(id, &mut self.cctx)
-            .map_err(|e| CompileError(e.to_string()))?;
+            .map_err(|e| CompileError::new(e.to_string()))?;
         self.shared.module.clear_context(&mut self.cctx);
         Ok(())
     }
@@ -465,7 +467,7 @@ impl Generator {
                 .shared
                 .module
                 .declare_function(name.as_str(), Linkage::Local, &sig)
-                .map_err(|e| CompileError(format!("cranelift module error: {}", e)))?;
+                .map_err(|e| CompileError::new(format!("cranelift module error: {}", e)))?;
 
             self.funcs.push(Some(Prelude {
                 sig,
@@ -654,6 +656,8 @@ impl<'a> View<'a> {
                     MapStrInt => external!(drop_strint),
                     MapStrFloat => external!(drop_strfloat),
                     MapStrStr => external!(drop_strstr),
+                    MapIntMap => external!(drop_intmap),
+                    MapStrMap => external!(drop_strmap),
                     Str => external!(drop_str),
                     _ => continue,
                 };
@@ -762,6 +766,19 @@ impl<'a> View<'a> {
             }
             // Phis are handled in predecessor blocks
             Phi(..) => Ok(()),
+            // Record the location in the runtime, where `fail!` can find it.
+            Loc(loc) => {
+                let rt = self.runtime_val();
+                let packed = self
+                    .builder
+                    .ins()
+                    .iconst(types::I64, loc.error().pack() as i64);
+                let offset = mem::offset_of!(intrinsics::Runtime<'static>, loc) as i32;
+                self.builder
+                    .ins()
+                    .store(MemFlags::trusted(), packed, rt, offset);
+                Ok(())
+            }
         }
     }
 
@@ -775,7 +792,8 @@ impl<'a> View<'a> {
                 let zero64 = self.builder.ins().iconst(types::I64, 0);
                 Ok(self.builder.ins().iconcat(zero64, zero64))
             }
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+            | MapStrMap => {
                 let alloc_fn = match ty {
                     MapIntInt => external!(alloc_intint),
                     MapIntFloat => external!(alloc_intfloat),
@@ -783,9 +801,12 @@ impl<'a> View<'a> {
                     MapStrInt => external!(alloc_strint),
                     MapStrFloat => external!(alloc_strfloat),
                     MapStrStr => external!(alloc_strstr),
+                    MapIntMap => external!(alloc_intmap),
+                    MapStrMap => external!(alloc_strmap),
                     _ => unreachable!(),
                 };
-                Ok(self.call_external(alloc_fn, &[]))
+                let rt = self.runtime_val();
+                Ok(self.call_external(alloc_fn, &[rt]))
             }
             IterInt | IterStr => err!("iterators do not have default values"),
         }
@@ -818,7 +839,8 @@ impl<'a> View<'a> {
                     self.builder.def_var(var, addr);
                     self.store_string(slot, default_v);
                 }
-                MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+                MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr
+                | MapIntMap | MapStrMap => {
                     self.builder.def_var(var, default_v);
                 }
                 IterInt | IterStr => return err!("attempting to default-initialize iterator type"),
@@ -845,7 +867,8 @@ impl<'a> View<'a> {
                 let ptr_ty = self.ptr_to(cl_ty);
                 self.builder.declare_var(next_var, ptr_ty);
             }
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+            | MapStrMap => {
                 self.builder.declare_var(next_var, cl_ty);
             }
             IterInt | IterStr => return err!("iterators cannot be declared"),
@@ -868,7 +891,7 @@ impl<'a> View<'a> {
                 Ok(IterState { bytes, cur, base })
             }
             Null | Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt
-            | MapStrFloat | MapStrStr => err!(
+            | MapStrFloat | MapStrStr | MapIntMap | MapStrMap => err!(
                 "attempting to declare iterator variable for non-iterator type: {:?}",
                 ty
             ),
@@ -881,7 +904,8 @@ impl<'a> View<'a> {
     fn ref_val(&mut self, ty: compile::Ty, v: Value) {
         use compile::Ty::*;
         let func = match ty {
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+            | MapStrMap => {
                 external!(ref_map)
             }
             Str => external!(ref_str),
@@ -902,6 +926,8 @@ impl<'a> View<'a> {
             MapStrInt => external!(drop_strint),
             MapStrFloat => external!(drop_strfloat),
             MapStrStr => external!(drop_strstr),
+            MapIntMap => external!(drop_intmap),
+            MapStrMap => external!(drop_strmap),
             Str => external!(drop_str),
             Null | Int | Float | IterInt | IterStr => return,
         };
@@ -1134,7 +1160,8 @@ impl<'a> View<'a> {
                 self.drop_val(Str, p);
                 self.builder.ins().store(MemFlags::trusted(), v, p, 0);
             }
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+            | MapStrMap => {
                 if let VarKind::Global = kind {
                     // Drop the value currently in the pointer
                     let p = self.builder.use_var(var);
@@ -1175,7 +1202,8 @@ impl<'a> View<'a> {
                 let loaded = self.builder.ins().load(str_ty, MemFlags::trusted(), src, 0);
                 self.bind_val_inner((dst, Str), loaded, skip_drop)?;
             }
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+            | MapStrMap => {
                 self.call_external_void(external!(ref_map), &[src]);
                 self.bind_val_inner((dst, ty), src, skip_drop)?;
             }
@@ -1281,7 +1309,7 @@ impl<'a> Backend for View<'a> {
             .shared
             .module
             .declare_function(name, Linkage::Import, cl_sig)
-            .map_err(|e| CompileError(format!("error declaring {} in module: {}", name, e,)))?;
+            .map_err(|e| CompileError::new(format!("error declaring {} in module: {}", name, e,)))?;
         self.shared.external_funcs.insert(addr, id);
         Ok(())
     }
@@ -1306,8 +1334,8 @@ impl<'a> CodeGenerator for View<'a> {
         let val = self.builder.use_var(var);
 
         match r.1 {
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | Int
-            | Float => {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+            | MapStrMap | Int | Float => {
                 if is_global {
                     let ty = self.get_ty(r.1);
                     Ok(self.builder.ins().load(ty, MemFlags::trusted(), val, 0))
@@ -1483,6 +1511,8 @@ impl<'a> CodeGenerator for View<'a> {
             MapStrInt => (external!(len_strint), external!(iter_strint)),
             MapStrStr => (external!(len_strstr), external!(iter_strstr)),
             MapStrFloat => (external!(len_strfloat), external!(iter_strfloat)),
+            MapIntMap => (external!(len_intmap), external!(iter_intmap)),
+            MapStrMap => (external!(len_strmap), external!(iter_strmap)),
             IterInt | IterStr | Int | Float | Str | Null => {
                 return err!("iterating over non-map type: {:?}", map.1)
             }
@@ -1490,7 +1520,8 @@ impl<'a> CodeGenerator for View<'a> {
         let key_ty = self.get_ty(dst.1.iter()?);
         let map = self.get_val(map)?;
         let IterState { bytes, cur, base } = self.get_iter(dst)?;
-        let ptr = self.call_external(begin_fn, &[map]);
+        let rt = self.runtime_val();
+        let ptr = self.call_external(begin_fn, &[rt, map]);
         let map_len = self.call_external(len_fn, &[map]);
         let total_bytes = self.mul_by_type_size(key_ty, map_len)?;
         let zero = self.const_int(0);
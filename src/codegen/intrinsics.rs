@@ -11,27 +11,31 @@ use crate::runtime::{
         batch::{ByteReader, CSVReader, WhitespaceOffsets},
         chunk::{ChunkProducer, OffsetChunk},
         regex::RegexSplitter,
+        RecordReader,
     },
-    string_util, ChainedReader, FileRead, Float, Int, IntMap, Line, LineReader, RegexCache, Str,
-    StrMap,
+    string_util, ChainedReader, FileRead, Float, Int, IntMap, Line, LineReader, RegexCache,
+    RegexConst, Str, StrMap,
 };
 use crate::{
-    builtins::Variable,
-    common::{CancelSignal, Cleanup, FileSpec, Notification, Result},
+    ast::Binop,
+    builtins::{BigOp, DecimalOp, Variable},
+    common::{CancelSignal, Cleanup, CompileError, FileSpec, Notification, Result},
     compile::Ty,
+    include::Source,
+    lexer::Loc,
     pushdown::FieldSet,
 };
 
 use libc::c_void;
 use paste::paste;
 use rand::{self, Rng};
-use regex::bytes::Regex;
 use smallvec;
 
 use std::convert::TryFrom;
 use std::io;
 use std::mem;
 use std::slice;
+use std::sync::OnceLock;
 use std::time::SystemTime;
 
 type SmallVec<T> = smallvec::SmallVec<[T; 4]>;
@@ -100,7 +104,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         drop_str_slow(str_ref_ty, int_ty);
         ref_map(map_ty);
         [ReadOnly] int_to_str(int_ty) -> str_ty;
-        [ReadOnly] float_to_str(float_ty) -> str_ty;
+        [ReadOnly] float_to_str(rt_ty, float_ty) -> str_ty;
+        [ReadOnly] float_to_str_ofmt(rt_ty, float_ty) -> str_ty;
         [ReadOnly] str_to_int(str_ref_ty) -> int_ty;
         [ReadOnly] hex_str_to_int(str_ref_ty) -> int_ty;
         [ReadOnly] str_to_float(str_ref_ty) -> float_ty;
@@ -109,11 +114,12 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         starts_with_const(str_ref_ty, rt_ty, int_ty) -> int_ty;
         concat(str_ref_ty, str_ref_ty) -> str_ty;
         [ReadOnly] match_pat(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
-        [ReadOnly] match_const_pat(str_ref_ty, rt_ty) -> int_ty;
+        [ReadOnly] match_set(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] match_const_pat(rt_ty, str_ref_ty, rt_ty) -> int_ty;
         [ReadOnly] match_pat_loc(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
         [ReadOnly] match_const_pat_loc(rt_ty, str_ref_ty, rt_ty) -> int_ty;
-        [ReadOnly] substr_index(str_ref_ty, str_ref_ty) -> int_ty;
-        [ReadOnly] substr_last_index(str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] substr_index(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] substr_last_index(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
         subst_first(rt_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> int_ty;
         subst_all(rt_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> int_ty;
         gen_subst(rt_ty, str_ref_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> str_ty;
@@ -144,15 +150,20 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         sprintf_impl(rt_ty, str_ref_ty, fmt_args_ty, fmt_tys_ty, int_ty) -> str_ty;
         printf_impl_file(rt_ty, str_ref_ty, fmt_args_ty, fmt_tys_ty, int_ty, str_ref_ty, int_ty);
         printf_impl_stdout(rt_ty, str_ref_ty, fmt_args_ty, fmt_tys_ty, int_ty);
-        close_file(rt_ty, str_ref_ty);
+        close_file(rt_ty, str_ref_ty, str_ref_ty);
         read_err(rt_ty, str_ref_ty, int_ty) -> int_ty;
         read_err_stdin(rt_ty) -> int_ty;
         next_line(rt_ty, str_ref_ty, int_ty) -> str_ty;
+        read_err_coproc(rt_ty, str_ref_ty) -> int_ty;
+        next_line_coproc(rt_ty, str_ref_ty) -> str_ty;
         next_line_stdin(rt_ty) -> str_ty;
         next_line_stdin_fused(rt_ty);
         next_file(rt_ty);
         update_used_fields(rt_ty);
         set_fi_entry(rt_ty, int_ty, int_ty);
+        profile_mark(rt_ty, int_ty);
+        profile_enter(rt_ty, int_ty);
+        profile_exit(rt_ty);
         uuid(str_ref_ty) -> str_ty;
         snowflake(int_ty) -> int_ty;
         ulid(rt_ty) -> str_ty;
@@ -207,6 +218,16 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         [ReadOnly] pad_right(str_ref_ty, int_ty, str_ref_ty) -> str_ty;
         [ReadOnly] pad_both(str_ref_ty, int_ty, str_ref_ty) -> str_ty;
         [ReadOnly] strcmp(str_ref_ty, str_ref_ty) -> int_ty;
+        bignum(rt_ty, int_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> str_ty;
+        [ReadOnly] bignum_cmp(str_ref_ty, str_ref_ty) -> int_ty;
+        decimal(rt_ty, int_ty, str_ref_ty, str_ref_ty, int_ty, str_ref_ty) -> str_ty;
+        decimal_cmp(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        checked_add(rt_ty, int_ty, int_ty) -> int_ty;
+        checked_sub(rt_ty, int_ty, int_ty) -> int_ty;
+        checked_mul(rt_ty, int_ty, int_ty) -> int_ty;
+        checked_mod(rt_ty, int_ty, int_ty) -> int_ty;
+        checked_div(rt_ty, float_ty, float_ty) -> float_ty;
+        checked_fmod(rt_ty, float_ty, float_ty) -> float_ty;
         [ReadOnly] encode(str_ref_ty, str_ref_ty) -> str_ty;
         [ReadOnly] decode(str_ref_ty, str_ref_ty) -> str_ty;
         [ReadOnly] escape(str_ref_ty, str_ref_ty) -> str_ty;
@@ -234,7 +255,7 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         [ReadOnly] rgb2hex(int_ty, int_ty, int_ty) -> str_ty;
         [ReadOnly] variant(str_ref_ty) -> map_ty;
         [ReadOnly] func(str_ref_ty) -> map_ty;
-        [ReadOnly] sqlite_query(str_ref_ty, str_ref_ty) -> map_ty;
+        sqlite_query(rt_ty, str_ref_ty, str_ref_ty) -> map_ty;
         [ReadOnly] sqlite_execute(str_ref_ty, str_ref_ty) -> int_ty;
         [ReadOnly] libsql_query(str_ref_ty, str_ref_ty) -> map_ty;
         [ReadOnly] libsql_execute(str_ref_ty, str_ref_ty) -> int_ty;
@@ -303,6 +324,7 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         [ReadOnly] map_int_float_min(map_ty) -> float_ty;
         [ReadOnly] map_int_int_sum(map_ty) -> int_ty;
         [ReadOnly] map_int_float_sum(map_ty) -> float_ty;
+        [ReadOnly] map_int_str_sum(map_ty) -> str_ty;
         [ReadOnly] map_int_int_mean(map_ty) -> int_ty;
         [ReadOnly] map_int_float_mean(map_ty) -> float_ty;
         [ReadOnly] from_csv(str_ref_ty) -> map_ty;
@@ -358,17 +380,19 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         [ReadOnly] load_var_strstrmap(rt_ty, str_ref_ty) -> map_ty;
         store_var_strstrmap(rt_ty, str_ref_ty, map_ty);
 
-        [ReadOnly] str_lt(str_ref_ty, str_ref_ty) -> int_ty;
-        [ReadOnly] str_gt(str_ref_ty, str_ref_ty) -> int_ty;
-        [ReadOnly] str_lte(str_ref_ty, str_ref_ty) -> int_ty;
-        [ReadOnly] str_gte(str_ref_ty, str_ref_ty) -> int_ty;
-        [ReadOnly] str_eq(str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] str_lt(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] str_gt(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] str_lte(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] str_gte(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] str_eq(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] strnum_cmp(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] strnum_cmp_float(rt_ty, str_ref_ty, float_ty) -> int_ty;
 
         drop_iter_int(iter_int_ty, int_ty);
         drop_iter_str(iter_str_ty, int_ty);
 
-        alloc_intint() -> map_ty;
-        iter_intint(map_ty) -> iter_int_ty;
+        alloc_intint(rt_ty) -> map_ty;
+        iter_intint(rt_ty, map_ty) -> iter_int_ty;
         [ReadOnly] len_intint(map_ty) -> int_ty;
         [ReadOnly] lookup_intint(map_ty, int_ty) -> int_ty;
         [ReadOnly] contains_intint(map_ty, int_ty) -> int_ty;
@@ -379,8 +403,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         inc_int_intint(map_ty, int_ty, int_ty) -> int_ty;
         inc_float_intint(map_ty, int_ty, float_ty) -> int_ty;
 
-        alloc_intfloat() -> map_ty;
-        iter_intfloat(map_ty) -> iter_int_ty;
+        alloc_intfloat(rt_ty) -> map_ty;
+        iter_intfloat(rt_ty, map_ty) -> iter_int_ty;
         [ReadOnly] len_intfloat(map_ty) -> int_ty;
         [ReadOnly] lookup_intfloat(map_ty, int_ty) -> float_ty;
         [ReadOnly] contains_intfloat(map_ty, int_ty) -> int_ty;
@@ -391,8 +415,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         inc_int_intfloat(map_ty, int_ty, int_ty) -> float_ty;
         inc_float_intfloat(map_ty, int_ty, float_ty) -> float_ty;
 
-        alloc_intstr() -> map_ty;
-        iter_intstr(map_ty) -> iter_int_ty;
+        alloc_intstr(rt_ty) -> map_ty;
+        iter_intstr(rt_ty, map_ty) -> iter_int_ty;
         [ReadOnly] len_intstr(map_ty) -> int_ty;
         [ReadOnly] lookup_intstr(map_ty, int_ty) -> str_ty;
         [ReadOnly] contains_intstr(map_ty, int_ty) -> int_ty;
@@ -403,8 +427,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         inc_int_intstr(map_ty, int_ty, int_ty) -> str_ty;
         inc_float_intstr(map_ty, int_ty, float_ty) -> str_ty;
 
-        alloc_strint() -> map_ty;
-        iter_strint(map_ty) -> iter_str_ty;
+        alloc_strint(rt_ty) -> map_ty;
+        iter_strint(rt_ty, map_ty) -> iter_str_ty;
         [ReadOnly] len_strint(map_ty) -> int_ty;
         [ReadOnly] lookup_strint(map_ty, str_ref_ty) -> int_ty;
         [ReadOnly] contains_strint(map_ty, str_ref_ty) -> int_ty;
@@ -415,8 +439,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         inc_int_strint(map_ty, str_ref_ty, int_ty) -> int_ty;
         inc_float_strint(map_ty, str_ref_ty, float_ty) -> int_ty;
 
-        alloc_strfloat() -> map_ty;
-        iter_strfloat(map_ty) -> iter_str_ty;
+        alloc_strfloat(rt_ty) -> map_ty;
+        iter_strfloat(rt_ty, map_ty) -> iter_str_ty;
         [ReadOnly] len_strfloat(map_ty) -> int_ty;
         [ReadOnly] lookup_strfloat(map_ty, str_ref_ty) -> float_ty;
         [ReadOnly] contains_strfloat(map_ty, str_ref_ty) -> int_ty;
@@ -427,8 +451,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         inc_int_strfloat(map_ty, str_ref_ty, int_ty) -> float_ty;
         inc_float_strfloat(map_ty, str_ref_ty, float_ty) -> float_ty;
 
-        alloc_strstr() -> map_ty;
-        iter_strstr(map_ty) -> iter_str_ty;
+        alloc_strstr(rt_ty) -> map_ty;
+        iter_strstr(rt_ty, map_ty) -> iter_str_ty;
         [ReadOnly] len_strstr(map_ty) -> int_ty;
         [ReadOnly] lookup_strstr(map_ty, str_ref_ty) -> str_ty;
         [ReadOnly] contains_strstr(map_ty, str_ref_ty) -> int_ty;
@@ -439,6 +463,26 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         inc_int_strstr(map_ty, str_ref_ty, int_ty) -> str_ty;
         inc_float_strstr(map_ty, str_ref_ty, float_ty) -> str_ty;
 
+        alloc_intmap(rt_ty) -> map_ty;
+        iter_intmap(rt_ty, map_ty) -> iter_int_ty;
+        [ReadOnly] len_intmap(map_ty) -> int_ty;
+        lookup_intmap(map_ty, int_ty) -> map_ty;
+        [ReadOnly] contains_intmap(map_ty, int_ty) -> int_ty;
+        insert_intmap(map_ty, int_ty, map_ty);
+        delete_intmap(map_ty, int_ty);
+        clear_intmap(map_ty);
+        drop_intmap(map_ty);
+
+        alloc_strmap(rt_ty) -> map_ty;
+        iter_strmap(rt_ty, map_ty) -> iter_str_ty;
+        [ReadOnly] len_strmap(map_ty) -> int_ty;
+        lookup_strmap(map_ty, str_ref_ty) -> map_ty;
+        [ReadOnly] contains_strmap(map_ty, str_ref_ty) -> int_ty;
+        insert_strmap(map_ty, str_ref_ty, map_ty);
+        delete_strmap(map_ty, str_ref_ty);
+        clear_strmap(map_ty);
+        drop_strmap(map_ty);
+
         load_slot_int(rt_ty, int_ty) -> int_ty;
         load_slot_float(rt_ty, int_ty) -> float_ty;
         load_slot_str(rt_ty, int_ty) -> str_ty;
@@ -448,6 +492,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         load_slot_strint(rt_ty, int_ty) -> map_ty;
         load_slot_strfloat(rt_ty, int_ty) -> map_ty;
         load_slot_strstr(rt_ty, int_ty) -> map_ty;
+        load_slot_intmap(rt_ty, int_ty) -> map_ty;
+        load_slot_strmap(rt_ty, int_ty) -> map_ty;
 
         store_slot_int(rt_ty, int_ty, int_ty);
         store_slot_float(rt_ty, int_ty, float_ty);
@@ -458,6 +504,8 @@ pub(crate) fn register_all(cg: &mut impl Backend) -> Result<()> {
         store_slot_strint(rt_ty, int_ty, map_ty);
         store_slot_strfloat(rt_ty, int_ty, map_ty);
         store_slot_strstr(rt_ty, int_ty, map_ty);
+        store_slot_intmap(rt_ty, int_ty, map_ty);
+        store_slot_strmap(rt_ty, int_ty, map_ty);
     }
     Ok(())
 }
@@ -466,17 +514,40 @@ macro_rules! fail {
     ($rt:expr, $($es:expr),+) => {{
         #[cfg(test)]
         {
+            let _ = &$rt;
             eprintln_ignore!("failure in runtime {}. Halting execution", format!($($es),*));
             panic!("failure in runtime")
         }
         #[cfg(not(test))]
         {
-            eprintln_ignore!("failure in runtime {}. Halting execution", format!($($es),*));
+            let loc = (*($rt as *mut Runtime)).loc;
+            eprintln_ignore!("{}", describe_failure(loc, format!($($es),*)));
             exit!($rt, 1)
         }
     }}
 }
 
+// The source of the running program, used to say where runtime errors happen.
+static SOURCE: OnceLock<Source> = OnceLock::new();
+
+/// Set the program source used to render runtime errors in compiled code.
+pub(crate) fn set_source(src: Source) {
+    let _ = SOURCE.set(src);
+}
+
+// Render a runtime error raised by the statement at the packed location `loc`, in the same format
+// as the interpreter where the location is known.
+#[allow(unused)]
+fn describe_failure(loc: u64, msg: String) -> String {
+    match (Loc::unpack(loc), SOURCE.get()) {
+        (Some(loc), Some(src)) => format!(
+            "fatal error during execution: {}",
+            src.render_error(&CompileError::new(msg.trim_start()).at(loc))
+        ),
+        _ => format!("failure in runtime {}. Halting execution", msg),
+    }
+}
+
 macro_rules! try_abort {
     ($rt:expr, $e:expr, $msg:expr) => {
         match $e {
@@ -538,6 +609,7 @@ macro_rules! with_input {
             $crate::codegen::intrinsics::InputData::V2($p) => $body,
             $crate::codegen::intrinsics::InputData::V3($p) => $body,
             $crate::codegen::intrinsics::InputData::V4($p) => $body,
+            $crate::codegen::intrinsics::InputData::V5($p) => $body,
         }
     };
 }
@@ -549,6 +621,7 @@ pub(crate) enum InputData {
     V2(InputTuple<ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk<WhitespaceOffsets>>>>>),
     V3(InputTuple<ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>>),
     V4(InputTuple<ChainedReader<RegexSplitter<Box<dyn io::Read + Send>>>>),
+    V5(InputTuple<RecordReader<ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>>>),
 }
 
 pub(crate) trait IntoRuntime {
@@ -571,15 +644,18 @@ macro_rules! impl_into_runtime {
                 named_columns: Option<Vec<&[u8]>>,
                 cancel_signal: CancelSignal,
             ) -> Runtime<'a> {
+                let core = crate::interp::Core::new(ff);
+                let channels = core.write_files.channels();
                 Runtime {
                     concurrent: false,
                     input_data: InputData::$var((
                         Default::default(),
-                        FileRead::new(self, used_fields.clone(), named_columns),
+                        FileRead::new(self, used_fields.clone(), named_columns, channels),
                     )),
-                    core: crate::interp::Core::new(ff),
+                    core,
                     cleanup: Cleanup::null(),
                     cancel_signal,
+                    loc: 0,
                 }
             }
         }
@@ -599,6 +675,10 @@ impl_into_runtime!(
 );
 impl_into_runtime!(ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>, V3);
 impl_into_runtime!(ChainedReader<RegexSplitter<Box<dyn io::Read + Send>>>, V4);
+impl_into_runtime!(
+    RecordReader<ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>>,
+    V5
+);
 
 pub(crate) struct Runtime<'a> {
     pub(crate) core: crate::interp::Core<'a>,
@@ -607,6 +687,9 @@ pub(crate) struct Runtime<'a> {
     pub(crate) concurrent: bool,
     pub(crate) cancel_signal: CancelSignal,
     pub(crate) cleanup: Cleanup<Self>,
+    // The location of the statement being executed, packed with `Loc::pack`, or 0 if unknown.
+    // Generated code stores to this directly.
+    pub(crate) loc: u64,
 }
 
 impl<'a> Runtime<'a> {
@@ -685,10 +768,8 @@ pub(crate) unsafe extern "C" fn next_line_stdin_fused(runtime: *mut c_void) {
     let changed = try_abort!(
         runtime,
         with_input!(&mut runtime.input_data, |(line, read_files)| {
-            runtime
-                .core
-                .regexes
-                .get_line_stdin_reuse(&runtime.core.vars.rs, read_files, line)
+            let (mut regexes, vars) = runtime.core.regexes_and_vars();
+            regexes.get_line_stdin_reuse(&vars.rs, read_files, line, &mut vars.rt)
         }),
         "unexpected error when reading line from stdin:"
     );
@@ -712,10 +793,8 @@ pub(crate) unsafe extern "C" fn next_line_stdin(runtime: *mut c_void) -> U128 {
     let (changed, res) = try_abort!(
         runtime,
         with_input!(&mut runtime.input_data, |(_, read_files)| {
-            runtime
-                .core
-                .regexes
-                .get_line_stdin(&runtime.core.vars.rs, read_files)
+            let (mut regexes, vars) = runtime.core.regexes_and_vars();
+            regexes.get_line_stdin(&vars.rs, read_files, &mut vars.rt)
         }),
         "unexpected error when reading line from stdin:"
     );
@@ -733,10 +812,33 @@ pub(crate) unsafe extern "C" fn next_line(
     let runtime = &mut *(runtime as *mut Runtime);
     let file = &*(file as *mut Str);
     let res = with_input!(&mut runtime.input_data, |(_, read_files)| {
-        runtime
-            .core
-            .regexes
-            .get_line(file, &runtime.core.vars.rs, read_files, is_file != 0)
+        let (mut regexes, vars) = runtime.core.regexes_and_vars();
+        regexes.get_line(file, &vars.rs, read_files, is_file != 0, &mut vars.rt)
+    });
+    match res {
+        Ok(res) => mem::transmute::<Str, U128>(res),
+        Err(_) => mem::transmute::<Str, U128>("".into()),
+    }
+}
+
+pub(crate) unsafe extern "C" fn read_err_coproc(runtime: *mut c_void, cmd: *mut c_void) -> Int {
+    let runtime = &mut *(runtime as *mut Runtime);
+    try_abort!(
+        runtime,
+        with_input!(&mut runtime.input_data, |(_, read_files)| {
+            let cmd = &*(cmd as *mut Str);
+            read_files.read_err_coproc(cmd)
+        }),
+        "unexpected error when reading error status of coprocess:"
+    )
+}
+
+pub(crate) unsafe extern "C" fn next_line_coproc(runtime: *mut c_void, cmd: *mut c_void) -> U128 {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let cmd = &*(cmd as *mut Str);
+    let res = with_input!(&mut runtime.input_data, |(_, read_files)| {
+        let (mut regexes, vars) = runtime.core.regexes_and_vars();
+        regexes.get_line_coproc(cmd, &vars.rs, read_files, &mut vars.rt)
     });
     match res {
         Ok(res) => mem::transmute::<Str, U128>(res),
@@ -752,6 +854,27 @@ pub(crate) unsafe extern "C" fn update_used_fields(runtime: *mut c_void) {
     });
 }
 
+pub(crate) unsafe extern "C" fn profile_mark(runtime: *mut c_void, id: Int) {
+    let runtime = &mut *(runtime as *mut Runtime);
+    if let Some(profile) = &mut runtime.core.profile {
+        profile.mark(id as u32);
+    }
+}
+
+pub(crate) unsafe extern "C" fn profile_enter(runtime: *mut c_void, id: Int) {
+    let runtime = &mut *(runtime as *mut Runtime);
+    if let Some(profile) = &mut runtime.core.profile {
+        profile.enter(id as u32);
+    }
+}
+
+pub(crate) unsafe extern "C" fn profile_exit(runtime: *mut c_void) {
+    let runtime = &mut *(runtime as *mut Runtime);
+    if let Some(profile) = &mut runtime.core.profile {
+        profile.exit();
+    }
+}
+
 pub(crate) unsafe extern "C" fn set_fi_entry(runtime: *mut c_void, key: Int, val: Int) {
     let rt = &mut *(runtime as *mut Runtime);
     let fi = &rt.core.vars.fi;
@@ -768,19 +891,14 @@ pub(crate) unsafe extern "C" fn split_str(
     let runtime = &mut *(runtime as *mut Runtime);
     let into_arr = mem::transmute::<*mut c_void, StrMap<Str>>(into_arr);
     let to_split = &*(to_split as *mut Str);
-    let mut pat = &*(pat as *mut Str);
-    if pat.is_empty() {
-        pat = &runtime.core.vars.fs;
-    }
-    if let Err(e) = runtime
-        .core
-        .regexes
-        .split_regex_strmap(pat, to_split, &into_arr)
-    {
+    let pat = &*(pat as *mut Str);
+    let (mut regexes, vars) = runtime.core.regexes_and_vars();
+    let pat = if pat.is_empty() { &vars.fs } else { pat };
+    if let Err(e) = regexes.split_regex_strmap(pat, to_split, &into_arr) {
         fail!(runtime, "failed to split string: {}", e);
     }
     let res = into_arr.len() as Int;
-    mem::forget((into_arr, to_split, pat));
+    mem::forget((into_arr, to_split));
     res
 }
 
@@ -793,31 +911,22 @@ pub(crate) unsafe extern "C" fn split_int(
     let runtime = &mut *(runtime as *mut Runtime);
     let into_arr = mem::transmute::<*mut c_void, IntMap<Str>>(into_arr);
     let to_split = &*(to_split as *mut Str);
-    let mut pat = &*(pat as *mut Str);
-    if pat.is_empty() {
-        pat = &runtime.core.vars.fs;
-    }
-    if let Err(e) = runtime
-        .core
-        .regexes
-        .split_regex_intmap(pat, to_split, &into_arr)
-    {
+    let pat = &*(pat as *mut Str);
+    let (mut regexes, vars) = runtime.core.regexes_and_vars();
+    let pat = if pat.is_empty() { &vars.fs } else { pat };
+    if let Err(e) = regexes.split_regex_intmap(pat, to_split, &into_arr) {
         fail!(runtime, "failed to split string: {}", e);
     }
     let res = into_arr.len() as Int;
-    mem::forget((into_arr, to_split, pat));
+    mem::forget((into_arr, to_split));
     res
 }
 
 pub(crate) unsafe extern "C" fn get_col(runtime: *mut c_void, col: Int) -> U128 {
     let runtime = &mut *(runtime as *mut Runtime);
     let col_str = with_input!(&mut runtime.input_data, |(line, _)| {
-        line.get_col(
-            col,
-            &runtime.core.vars.fs,
-            &runtime.core.vars.ofs,
-            &mut runtime.core.regexes,
-        )
+        let (mut regexes, vars) = runtime.core.regexes_and_vars();
+        line.get_col(col, &vars.fs, &vars.ofs, &mut regexes)
     });
     let res = match col_str {
         Ok(s) => s,
@@ -832,11 +941,8 @@ pub(crate) unsafe extern "C" fn join_csv(runtime: *mut c_void, start: Int, end:
     let res = try_abort!(
         runtime,
         with_input!(&mut runtime.input_data, |(line, _)| {
-            let nf = try_abort!(
-                runtime,
-                line.nf(&runtime.core.vars.fs, &mut runtime.core.regexes),
-                "nf:"
-            );
+            let (mut regexes, vars) = runtime.core.regexes_and_vars();
+            let nf = try_abort!(runtime, line.nf(&vars.fs, &mut regexes), "nf:");
             line.join_cols(start, end, &sep, nf, |s| runtime::escape_csv(&s))
         }),
         "join_csv:"
@@ -1156,6 +1262,83 @@ pub(crate) unsafe extern "C" fn strcmp(text1: *mut U128, text2: *mut U128) -> In
     string_util::strcmp(text1.as_str(), text2.as_str())
 }
 
+pub(crate) unsafe extern "C" fn bignum(
+    runtime: *mut c_void,
+    op: Int,
+    x: *mut U128,
+    y: *mut U128,
+    prec: *mut U128,
+) -> U128 {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let x = &*(x as *mut Str);
+    let y = &*(y as *mut Str);
+    let prec = &*(prec as *mut Str);
+    let res = x.with_bytes(|x| {
+        y.with_bytes(|y| {
+            prec.with_bytes(|prec| runtime::bignum::arith(BigOp::from_code(op), x, y, prec))
+        })
+    });
+    let res = try_abort!(runtime, res);
+    mem::transmute::<Str, U128>(Str::from(res))
+}
+
+pub(crate) unsafe extern "C" fn bignum_cmp(x: *mut U128, y: *mut U128) -> Int {
+    let x = &*(x as *mut Str);
+    let y = &*(y as *mut Str);
+    x.with_bytes(|x| y.with_bytes(|y| runtime::bignum::cmp(x, y)))
+}
+
+pub(crate) unsafe extern "C" fn decimal(
+    runtime: *mut c_void,
+    op: Int,
+    x: *mut U128,
+    y: *mut U128,
+    scale: Int,
+    mode: *mut U128,
+) -> U128 {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let x = &*(x as *mut Str);
+    let y = &*(y as *mut Str);
+    let mode = &*(mode as *mut Str);
+    let res = math_util::decimal(
+        DecimalOp::from_code(op),
+        x.as_str(),
+        y.as_str(),
+        scale,
+        mode.as_str(),
+    );
+    let res = try_abort!(runtime, res);
+    mem::transmute::<Str, U128>(Str::from(res))
+}
+
+pub(crate) unsafe extern "C" fn decimal_cmp(
+    runtime: *mut c_void,
+    x: *mut U128,
+    y: *mut U128,
+) -> Int {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let x = &*(x as *mut Str);
+    let y = &*(y as *mut Str);
+    let res = x.with_bytes(|x| y.with_bytes(|y| math_util::decimal_cmp(x, y)));
+    try_abort!(runtime, res)
+}
+
+macro_rules! checked_arith {
+    ($name:ident, $ty:ty, $checked:ident, $op:expr) => {
+        pub(crate) unsafe extern "C" fn $name(runtime: *mut c_void, l: $ty, r: $ty) -> $ty {
+            let runtime = &mut *(runtime as *mut Runtime);
+            try_abort!(runtime, math_util::$checked($op, l, r))
+        }
+    };
+}
+
+checked_arith!(checked_add, Int, checked_int, Binop::Plus);
+checked_arith!(checked_sub, Int, checked_int, Binop::Minus);
+checked_arith!(checked_mul, Int, checked_int, Binop::Mult);
+checked_arith!(checked_mod, Int, checked_int, Binop::Mod);
+checked_arith!(checked_div, Float, checked_float, Binop::Div);
+checked_arith!(checked_fmod, Float, checked_float, Binop::Mod);
+
 pub(crate) unsafe extern "C" fn mask(text: *mut U128) -> U128 {
     let text = &*(text as *mut Str);
     let res = text.mask();
@@ -1402,11 +1585,8 @@ pub(crate) unsafe extern "C" fn join_tsv(runtime: *mut c_void, start: Int, end:
     let res = try_abort!(
         runtime,
         with_input!(&mut runtime.input_data, |(line, _)| {
-            let nf = try_abort!(
-                runtime,
-                line.nf(&runtime.core.vars.fs, &mut runtime.core.regexes),
-                "nf:"
-            );
+            let (mut regexes, vars) = runtime.core.regexes_and_vars();
+            let nf = try_abort!(runtime, line.nf(&vars.fs, &mut regexes), "nf:");
             line.join_cols(start, end, &sep, nf, |s| runtime::escape_tsv(&s))
         }),
         "join_tsv:"
@@ -1424,11 +1604,8 @@ pub(crate) unsafe extern "C" fn join_cols(
     let res = try_abort!(
         runtime,
         with_input!(&mut runtime.input_data, |(line, _)| {
-            let nf = try_abort!(
-                runtime,
-                line.nf(&runtime.core.vars.fs, &mut runtime.core.regexes),
-                "nf:"
-            );
+            let (mut regexes, vars) = runtime.core.regexes_and_vars();
+            let nf = try_abort!(runtime, line.nf(&vars.fs, &mut regexes), "nf:");
             line.join_cols(start, end, &*(sep as *mut Str), nf, |s| s)
         }),
         "join_cols:"
@@ -1661,10 +1838,17 @@ pub(crate) unsafe extern "C" fn func(text: *mut U128) -> *mut c_void {
     mem::transmute::<IntMap<Str>, *mut c_void>(res)
 }
 
-pub(crate) unsafe extern "C" fn sqlite_query(db_path: *mut U128, sql: *mut U128) -> *mut c_void {
+pub(crate) unsafe extern "C" fn sqlite_query(
+    runtime: *mut c_void,
+    db_path: *mut U128,
+    sql: *mut U128,
+) -> *mut c_void {
     let db_path = &*(db_path as *mut Str);
     let sql = &*(sql as *mut Str);
-    let res = runtime::sqlite::sqlite_query(db_path.as_str(), sql.as_str());
+    let res = try_abort!(
+        runtime,
+        runtime::sqlite::sqlite_query(db_path.as_str(), sql.as_str())
+    );
     mem::transmute::<IntMap<Str>, *mut c_void>(res)
 }
 
@@ -1985,6 +2169,13 @@ pub(crate) unsafe extern "C" fn map_int_float_sum(arr: *mut c_void) -> Float {
     result
 }
 
+pub(crate) unsafe extern "C" fn map_int_str_sum(arr: *mut c_void) -> U128 {
+    let arr = mem::transmute::<*mut c_void, IntMap<Str>>(arr);
+    let result = math_util::map_int_str_sum(&arr);
+    mem::forget(arr);
+    mem::transmute::<Str, U128>(Str::from(result))
+}
+
 pub(crate) unsafe extern "C" fn map_int_int_mean(arr: *mut c_void) -> Int {
     let arr = mem::transmute::<*mut c_void, IntMap<Int>>(arr);
     let result = math_util::map_int_int_mean(&arr);
@@ -2107,12 +2298,10 @@ pub(crate) unsafe extern "C" fn s3_put(
 pub(crate) unsafe extern "C" fn set_col(runtime: *mut c_void, col: Int, s: *mut c_void) {
     let runtime = &mut *(runtime as *mut Runtime);
     let s = &*(s as *mut Str);
-    if let Err(e) = with_input!(&mut runtime.input_data, |(line, _)| line.set_col(
-        col,
-        s,
-        &runtime.core.vars.ofs,
-        &mut runtime.core.regexes,
-    )) {
+    if let Err(e) = with_input!(&mut runtime.input_data, |(line, _)| {
+        let (mut regexes, vars) = runtime.core.regexes_and_vars();
+        line.set_col(col, s, &vars.ofs, &mut regexes)
+    }) {
         fail!(runtime, "set_col: {}", e);
     }
 }
@@ -2146,6 +2335,21 @@ pub(crate) unsafe extern "C" fn concat(s1: *mut c_void, s2: *mut c_void) -> U128
     mem::transmute::<Str, U128>(res)
 }
 
+pub(crate) unsafe extern "C" fn match_set(
+    runtime: *mut c_void,
+    s: *mut c_void,
+    pats: *mut c_void,
+) -> Int {
+    let runtime = runtime as *mut Runtime;
+    let s = &*(s as *mut Str);
+    let pats = &*(pats as *mut Str);
+    try_abort!(
+        runtime,
+        (*runtime).core.match_regex_set(s, pats),
+        "match_set:"
+    )
+}
+
 pub(crate) unsafe extern "C" fn match_pat(
     runtime: *mut c_void,
     s: *mut c_void,
@@ -2162,10 +2366,16 @@ pub(crate) unsafe extern "C" fn match_pat(
     res as Int
 }
 
-pub(crate) unsafe extern "C" fn match_const_pat(s: *mut c_void, pat: *mut c_void) -> Int {
+pub(crate) unsafe extern "C" fn match_const_pat(
+    runtime: *mut c_void,
+    s: *mut c_void,
+    pat: *mut c_void,
+) -> Int {
+    let runtime = &*(runtime as *mut Runtime);
     let s = &*(s as *mut Str);
-    let pat = &*(pat as *const Regex);
-    RegexCache::regex_const_match(pat, s) as Int
+    let pat = &*(pat as *const RegexConst);
+    let ignore_case = runtime.core.ignore_case();
+    RegexCache::regex_const_match(pat.get(ignore_case), s) as Int
 }
 
 pub(crate) unsafe extern "C" fn match_pat_loc(
@@ -2191,7 +2401,7 @@ pub(crate) unsafe extern "C" fn match_const_pat_loc(
 ) -> Int {
     let runtime = runtime as *mut Runtime;
     let s = &*(s as *mut Str);
-    let pat = &*(pat as *const Regex);
+    let pat = &*(pat as *const RegexConst);
     try_abort!(
         runtime,
         (*runtime).core.match_const_regex(s, pat),
@@ -2199,16 +2409,28 @@ pub(crate) unsafe extern "C" fn match_const_pat_loc(
     )
 }
 
-pub(crate) unsafe extern "C" fn substr_index(s: *mut U128, t: *mut U128) -> Int {
+pub(crate) unsafe extern "C" fn substr_index(
+    runtime: *mut c_void,
+    s: *mut U128,
+    t: *mut U128,
+) -> Int {
+    let runtime = &*(runtime as *mut Runtime);
     let s = &*(s as *mut Str);
     let t = &*(t as *mut Str);
-    runtime::string_search::index_substr(/*needle*/ t, /*haystack*/ s)
+    let ignore_case = runtime.core.ignore_case();
+    runtime::string_search::index_substr(/*needle*/ t, /*haystack*/ s, ignore_case)
 }
 
-pub(crate) unsafe extern "C" fn substr_last_index(s: *mut U128, t: *mut U128) -> Int {
+pub(crate) unsafe extern "C" fn substr_last_index(
+    runtime: *mut c_void,
+    s: *mut U128,
+    t: *mut U128,
+) -> Int {
+    let runtime = &*(runtime as *mut Runtime);
     let s = &*(s as *mut Str);
     let t = &*(t as *mut Str);
-    runtime::string_search::last_index_substr(/*needle*/ t, /*haystack*/ s)
+    let ignore_case = runtime.core.ignore_case();
+    runtime::string_search::last_index_substr(/*needle*/ t, /*haystack*/ s, ignore_case)
 }
 
 pub(crate) unsafe extern "C" fn subst_first(
@@ -2225,7 +2447,7 @@ pub(crate) unsafe extern "C" fn subst_first(
         runtime,
         runtime
             .core
-            .regexes
+            .regexes()
             .with_regex(pat, |re| in_s.subst_first(re, s))
     );
     *in_s = subbed;
@@ -2246,7 +2468,7 @@ pub(crate) unsafe extern "C" fn subst_all(
         runtime,
         runtime
             .core
-            .regexes
+            .regexes()
             .with_regex(pat, |re| in_s.subst_all(re, s))
     );
     *in_s = subbed;
@@ -2269,7 +2491,7 @@ pub(crate) unsafe extern "C" fn gen_subst(
         runtime,
         runtime
             .core
-            .regexes
+            .regexes()
             .with_regex(pat, |re| in_s.gen_subst_dynamic(re, s, how))
     );
     mem::transmute::<Str, U128>(subbed)
@@ -2355,8 +2577,14 @@ pub(crate) unsafe extern "C" fn int_to_str(i: Int) -> U128 {
     mem::transmute::<Str, U128>(runtime::convert::<Int, Str>(i))
 }
 
-pub(crate) unsafe extern "C" fn float_to_str(f: Float) -> U128 {
-    mem::transmute::<Str, U128>(runtime::convert::<Float, Str>(f))
+pub(crate) unsafe extern "C" fn float_to_str(rt: *mut c_void, f: Float) -> U128 {
+    let runtime = &mut *(rt as *mut Runtime);
+    mem::transmute::<Str, U128>(runtime.core.vars.convfmt(f))
+}
+
+pub(crate) unsafe extern "C" fn float_to_str_ofmt(rt: *mut c_void, f: Float) -> U128 {
+    let runtime = &mut *(rt as *mut Runtime);
+    mem::transmute::<Str, U128>(runtime.core.vars.ofmt(f))
 }
 
 pub(crate) unsafe extern "C" fn str_to_int(s: *mut c_void) -> Int {
@@ -2388,7 +2616,7 @@ pub(crate) unsafe extern "C" fn store_var_str(rt: *mut c_void, var: usize, s: *m
     let runtime = &mut *(rt as *mut Runtime);
     if let Ok(var) = Variable::try_from(var) {
         let s = (*(s as *mut Str)).clone();
-        try_abort!(runtime, runtime.core.vars.store_str(var, s))
+        try_abort!(runtime, runtime.core.store_var_str(var, s))
     } else {
         fail!(runtime, "invalid variable code={}", var)
     }
@@ -2398,9 +2626,10 @@ pub(crate) unsafe extern "C" fn load_var_int(rt: *mut c_void, var: usize) -> Int
     let runtime = &mut *(rt as *mut Runtime);
     if let Ok(var) = Variable::try_from(var) {
         if let Variable::NF = var {
-            runtime.core.vars.nf = match with_input!(&mut runtime.input_data, |(line, _)| line
-                .nf(&runtime.core.vars.fs, &mut runtime.core.regexes))
-            {
+            runtime.core.vars.nf = match with_input!(&mut runtime.input_data, |(line, _)| {
+                let (mut regexes, vars) = runtime.core.regexes_and_vars();
+                line.nf(&vars.fs, &mut regexes)
+            }) {
                 Ok(nf) => nf as Int,
                 Err(e) => fail!(runtime, "nf: {}", e),
             };
@@ -2414,7 +2643,7 @@ pub(crate) unsafe extern "C" fn load_var_int(rt: *mut c_void, var: usize) -> Int
 pub(crate) unsafe extern "C" fn store_var_int(rt: *mut c_void, var: usize, i: Int) {
     let runtime = &mut *(rt as *mut Runtime);
     if let Ok(var) = Variable::try_from(var) {
-        try_abort!(runtime, runtime.core.vars.store_int(var, i));
+        try_abort!(runtime, runtime.core.store_var_int(var, i));
     } else {
         fail!(runtime, "invalid variable code={}", var)
     }
@@ -2485,21 +2714,46 @@ pub(crate) unsafe extern "C" fn store_var_strstrmap(rt: *mut c_void, var: usize,
 }
 
 macro_rules! str_compare_inner {
-    ($name:ident, $op:tt) => {
-        pub(crate) unsafe extern "C" fn $name(s1: *mut c_void, s2: *mut c_void) -> Int {
+    ($name:ident, $op:ident) => {
+        pub(crate) unsafe extern "C" fn $name(
+            runtime: *mut c_void,
+            s1: *mut c_void,
+            s2: *mut c_void,
+        ) -> Int {
+            let runtime = &*(runtime as *mut Runtime);
             let s1 = &*(s1 as *mut Str);
             let s2 = &*(s2 as *mut Str);
-            let res = s1.with_bytes(|bs1| s2.with_bytes(|bs2| bs1 $op bs2)) as Int;
-            res
+            runtime::str_cmp(s1, s2, runtime.core.ignore_case()).$op() as Int
         }
-    }
+    };
 }
 macro_rules! str_compare {
-    ($($name:ident ($op:tt);)*) => { $( str_compare_inner!($name, $op); )* };
+    ($($name:ident ($op:ident);)*) => { $( str_compare_inner!($name, $op); )* };
 }
 
 str_compare! {
-    str_lt(<); str_gt(>); str_lte(<=); str_gte(>=); str_eq(==);
+    str_lt(is_lt); str_gt(is_gt); str_lte(is_le); str_gte(is_ge); str_eq(is_eq);
+}
+
+pub(crate) unsafe extern "C" fn strnum_cmp(
+    runtime: *mut c_void,
+    s1: *mut c_void,
+    s2: *mut c_void,
+) -> Int {
+    let runtime = &*(runtime as *mut Runtime);
+    let s1 = &*(s1 as *mut Str);
+    let s2 = &*(s2 as *mut Str);
+    runtime::strnum_cmp(s1, s2, runtime.core.ignore_case())
+}
+
+pub(crate) unsafe extern "C" fn strnum_cmp_float(
+    runtime: *mut c_void,
+    s: *mut c_void,
+    f: Float,
+) -> Int {
+    let runtime = &*(runtime as *mut Runtime);
+    let s = &*(s as *mut Str);
+    runtime::strnum_cmp_float(s, f, &runtime.core.vars, runtime.core.ignore_case())
 }
 
 pub(crate) unsafe extern "C" fn drop_iter_int(iter: *mut Int, len: usize) {
@@ -2512,7 +2766,7 @@ pub(crate) unsafe extern "C" fn drop_iter_str(iter: *mut U128, len: usize) {
 }
 
 unsafe fn wrap_args<'a>(
-    _rt: &mut Runtime<'a>,
+    rt: &mut Runtime<'a>,
     args: *mut usize,
     tys: *mut u32,
     num_args: Int,
@@ -2525,7 +2779,7 @@ unsafe fn wrap_args<'a>(
             ty
         } else {
             fail!(
-                _rt,
+                rt,
                 "invalid type code passed to printf_impl_file: {}",
                 ty_code
             )
@@ -2533,10 +2787,11 @@ unsafe fn wrap_args<'a>(
         let typed_arg: FormatArg = match ty {
             Ty::Int => mem::transmute::<usize, Int>(arg).into(),
             Ty::Float => Float::from_bits(arg as u64).into(),
+            Ty::Str if rt.core.bignum => FormatArg::Big(mem::transmute::<usize, &Str>(arg).clone()),
             Ty::Str => mem::transmute::<usize, &Str>(arg).clone().into(),
             Ty::Null => FormatArg::Null,
             _ => fail!(
-                _rt,
+                rt,
                 "invalid format arg {:?} (this should have been caught earlier)",
                 ty
             ),
@@ -2550,7 +2805,10 @@ pub(crate) unsafe extern "C" fn print_all_stdout(rt: *mut c_void, args: *mut usi
     let args_wrapped: &[&Str] =
         slice::from_raw_parts(args as *const usize as *const &Str, num_args as usize);
     let rt = rt as *mut Runtime;
-    try_silent_abort!(rt, (*rt).core.write_files.write_all(args_wrapped, None))
+    try_silent_abort!(
+        rt,
+        try_abort!(rt, (*rt).core.write_files.write_all(args_wrapped, None))
+    )
 }
 
 pub(crate) unsafe extern "C" fn print_all_file(
@@ -2570,10 +2828,13 @@ pub(crate) unsafe extern "C" fn print_all_file(
 
     try_silent_abort!(
         rt,
-        (*rt)
-            .core
-            .write_files
-            .write_all(args_wrapped, output_wrapped)
+        try_abort!(
+            rt,
+            (*rt)
+                .core
+                .write_files
+                .write_all(args_wrapped, output_wrapped)
+        )
     )
 }
 
@@ -2592,12 +2853,17 @@ pub(crate) unsafe extern "C" fn printf_impl_file(
     ));
     let format_args = wrap_args(&mut *(rt as *mut _), args, tys, num_args);
     let rt = rt as *mut Runtime;
-    try_abort!(
+    try_silent_abort!(
         rt,
-        (*rt)
-            .core
-            .write_files
-            .printf(output_wrapped, &*(spec as *mut Str), &format_args[..],)
+        try_abort!(
+            rt,
+            (*rt).core.write_files.printf(
+                output_wrapped,
+                &*(spec as *mut Str),
+                &format_args[..],
+                (*rt).core.vars.convfmt_spec(),
+            )
+        )
     )
 }
 
@@ -2613,7 +2879,8 @@ pub(crate) unsafe extern "C" fn sprintf_impl(
     let rt = &mut *(rt as *mut _);
     let format_args = wrap_args(rt, args, tys, num_args);
     let spec = &*(spec as *mut Str);
-    if let Err(e) = spec.with_bytes(|bs| printf(&mut buf, bs, &format_args[..])) {
+    let convfmt = rt.core.vars.convfmt_spec();
+    if let Err(e) = spec.with_bytes(|bs| printf(&mut buf, bs, &format_args[..], convfmt)) {
         fail!(rt, "unexpected failure during sprintf: {}", e);
     }
     mem::transmute::<Str, U128>(buf.into_str())
@@ -2627,21 +2894,29 @@ pub(crate) unsafe extern "C" fn printf_impl_stdout(
     num_args: Int,
 ) {
     let format_args = wrap_args(&mut *(rt as *mut _), args, tys, num_args);
-    let res = (*(rt as *mut Runtime)).core.write_files.printf(
-        None,
-        &*(spec as *mut Str),
-        &format_args[..],
+    let rt = rt as *mut Runtime;
+    let res = try_abort!(
+        rt,
+        (*rt).core.write_files.printf(
+            None,
+            &*(spec as *mut Str),
+            &format_args[..],
+            (*rt).core.vars.convfmt_spec(),
+        )
     );
     if res.is_err() {
         exit!(rt);
     }
 }
 
-pub(crate) unsafe extern "C" fn close_file(rt: *mut c_void, file: *mut U128) {
+pub(crate) unsafe extern "C" fn close_file(rt: *mut c_void, file: *mut U128, how: *mut U128) {
     let rt = &mut *(rt as *mut Runtime);
     let file = &*(file as *mut Str);
-    with_input!(&mut rt.input_data, |(_, read_files)| read_files.close(file));
-    try_abort!(rt, rt.core.write_files.close(file));
+    let how = &*(how as *mut Str);
+    let res = with_input!(&mut rt.input_data, |(_, read_files)| {
+        runtime::close_file(&mut rt.core.write_files, read_files, file, how)
+    });
+    try_abort!(rt, res);
 }
 
 pub(crate) unsafe extern "C" fn _frawk_cos(f: Float) -> Float {
@@ -2700,7 +2975,7 @@ pub(crate) unsafe extern "C" fn _frawk_fprem(x: Float, y: Float) -> Float {
     x % y
 }
 
-// And now for the shenanigans for implementing map operations. There are 48 functions here; we
+// And now for the shenanigans for implementing map operations. There are 66 functions here; we
 // have a bunch of macros to handle type-specific operations. Note: we initially had a trait for
 // these operations:
 //   pub trait InTy {
@@ -2749,6 +3024,9 @@ macro_rules! convert_in {
     (Float, $e:expr) => {
         $e
     };
+    (Map, $e:expr) => {
+        mem::transmute::<&*mut c_void, &runtime::SharedMap<_, _>>($e)
+    };
 }
 
 macro_rules! convert_in_val {
@@ -2782,18 +3060,48 @@ macro_rules! convert_out {
     }};
 }
 
+// The values of an array of arrays. `Map` doubles as the value "type" passed to the macros above.
+type Map<'a> = runtime::StrMap<'a, Str<'a>>;
+
 macro_rules! map_impl {
     ($ty:ident, $k:tt, $v:tt) => {
+        map_impl!(@base $ty, $k, $v);
+        paste! {
+            pub(crate) unsafe extern "C" fn [<inc_int_ $ty>](map: *mut c_void, k: in_ty!($k), by: Int) -> out_ty!($v) {
+                debug_assert!(!map.is_null());
+                let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
+                let key = convert_in!($k, &k);
+                let res = map.inc_int(key, by);
+                mem::forget(map);
+                convert_out!($v, res)
+            }
+
+            pub(crate) unsafe extern "C" fn [<inc_float_ $ty>](map: *mut c_void, k: in_ty!($k), by: Float) -> out_ty!($v) {
+                debug_assert!(!map.is_null());
+                let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
+                let key = convert_in!($k, &k);
+                let res = map.inc_float(key, by);
+                mem::forget(map);
+                convert_out!($v, res)
+            }
+        }
+    };
+    // Operations shared by all maps; arrays of arrays do not support increments.
+    (@base $ty:ident, $k:tt, $v:tt) => {
         paste! {
-            pub(crate) unsafe extern "C" fn [< alloc_ $ty >]() -> *mut c_void {
-                let res: runtime::SharedMap<$k, $v> = Default::default();
+            pub(crate) unsafe extern "C" fn [< alloc_ $ty >](runtime: *mut c_void) -> *mut c_void {
+                let runtime = &*(runtime as *mut Runtime);
+                let res = runtime::SharedMap::<$k, $v>::new(runtime.core.ordered_maps);
                 mem::transmute::<runtime::SharedMap<$k, $v>, *mut c_void>(res)
             }
 
-            pub(crate) unsafe extern "C" fn [< iter_ $ty >](map: *mut c_void) -> iter_ty!($k) {
+            pub(crate) unsafe extern "C" fn [< iter_ $ty >](runtime: *mut c_void, map: *mut c_void) -> iter_ty!($k) {
                 debug_assert!(!map.is_null());
+                let runtime = &*(runtime as *mut Runtime);
+                let order = runtime.core.vars.sorted_in();
+                let ignore_case = runtime.core.ignore_case();
                 let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
-                let iter: Vec<_> = map.to_vec();
+                let iter: Vec<_> = map.sorted_keys(order, ignore_case);
                 mem::forget(map);
                 let b = iter.into_boxed_slice();
                 Box::into_raw(b) as _
@@ -2854,24 +3162,6 @@ macro_rules! map_impl {
                 debug_assert!(!map.is_null());
                 drop_map_generic::<$k, $v>(map)
             }
-
-            pub(crate) unsafe extern "C" fn [<inc_int_ $ty>](map: *mut c_void, k: in_ty!($k), by: Int) -> out_ty!($v) {
-                debug_assert!(!map.is_null());
-                let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
-                let key = convert_in!($k, &k);
-                let res = map.inc_int(key, by);
-                mem::forget(map);
-                convert_out!($v, res)
-            }
-
-            pub(crate) unsafe extern "C" fn [<inc_float_ $ty>](map: *mut c_void, k: in_ty!($k), by: Float) -> out_ty!($v) {
-                debug_assert!(!map.is_null());
-                let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
-                let key = convert_in!($k, &k);
-                let res = map.inc_float(key, by);
-                mem::forget(map);
-                convert_out!($v, res)
-            }
         }
     };
 }
@@ -2882,6 +3172,8 @@ map_impl!(intstr, Int, Str);
 map_impl!(strint, Str, Int);
 map_impl!(strfloat, Str, Float);
 map_impl!(strstr, Str, Str);
+map_impl!(@base intmap, Int, Map);
+map_impl!(@base strmap, Str, Map);
 
 macro_rules! slot_impl {
     ($name:ident, $ty:tt) => {
@@ -2910,3 +3202,5 @@ slot_impl!(intstr, Map);
 slot_impl!(strint, Map);
 slot_impl!(strfloat, Map);
 slot_impl!(strstr, Map);
+slot_impl!(intmap, Map);
+slot_impl!(strmap, Map);
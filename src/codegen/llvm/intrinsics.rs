@@ -49,6 +49,8 @@ impl IntrinsicMap {
             MapStrInt => Some(self.get(external!(drop_strint))),
             MapStrFloat => Some(self.get(external!(drop_strfloat))),
             MapStrStr => Some(self.get(external!(drop_strstr))),
+            MapIntMap => Some(self.get(external!(drop_intmap))),
+            MapStrMap => Some(self.get(external!(drop_strmap))),
             _ => None,
         }
     }
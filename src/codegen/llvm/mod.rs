@@ -155,7 +155,8 @@ impl<'a> CodeGenerator for View<'a> {
                 let param = LLVMGetParam(self.f.val, *ix as libc::c_uint);
                 let new_global = to;
                 match val.1 {
-                    MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat => {
+                    MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat
+                    | MapIntMap | MapStrMap => {
                         let prev_global = LLVMBuildLoad(self.f.builder, param, c_str!(""));
                         self.drop_val(prev_global, val.1);
                         LLVMBuildStore(self.f.builder, new_global, param);
@@ -177,7 +178,8 @@ impl<'a> CodeGenerator for View<'a> {
                 self.f.locals[&val]
             );
             match val.1 {
-                MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat => {
+                MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat
+                | MapIntMap | MapStrMap => {
                     // alloca only fails with an iterator or null type; but we have checked the type
                     // already.
                     let loc = self.alloca(val.1).unwrap();
@@ -492,10 +494,13 @@ impl<'a> CodeGenerator for View<'a> {
                 MapStrInt => (intrinsic!(len_strint), intrinsic!(iter_strint)),
                 MapStrStr => (intrinsic!(len_strstr), intrinsic!(iter_strstr)),
                 MapStrFloat => (intrinsic!(len_strfloat), intrinsic!(iter_strfloat)),
+                MapIntMap => (intrinsic!(len_intmap), intrinsic!(iter_intmap)),
+                MapStrMap => (intrinsic!(len_strmap), intrinsic!(iter_strmap)),
                 _ => return err!("iterating over non-map type: {:?}", map.1),
             };
 
-            let iter_ptr = self.call(begin_fn, &mut [arrv]);
+            let rt = self.runtime_val();
+            let iter_ptr = self.call(begin_fn, &mut [rt, arrv]);
             let cur_index = self.alloca(Ty::Int)?;
 
             let ty = self.tmap.get_ty(Ty::Int);
@@ -644,6 +649,7 @@ impl<'a, 'b> Drop for Generator<'a, 'b> {
 unsafe fn alloc_local(
     builder: LLVMBuilderRef,
     ty: Ty,
+    rt: LLVMValueRef,
     tmap: &TypeMap,
     intrinsics: &IntrinsicMap,
 ) -> Result<LLVMValueRef> {
@@ -659,7 +665,8 @@ unsafe fn alloc_local(
             LLVMBuildStore(builder, v, v_loc);
             v_loc
         }
-        MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat => {
+        MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat | MapIntMap
+        | MapStrMap => {
             let func = match ty {
                 MapIntInt => intrinsic!(alloc_intint),
                 MapIntFloat => intrinsic!(alloc_intfloat),
@@ -667,14 +674,17 @@ unsafe fn alloc_local(
                 MapStrInt => intrinsic!(alloc_strint),
                 MapStrFloat => intrinsic!(alloc_strfloat),
                 MapStrStr => intrinsic!(alloc_strstr),
+                MapIntMap => intrinsic!(alloc_intmap),
+                MapStrMap => intrinsic!(alloc_strmap),
                 _ => unreachable!(),
             };
             let map_ty = tmap.get_ty(ty);
+            let mut args = [rt];
             let v = LLVMBuildCall(
                 builder,
                 intrinsics.get(func),
-                ptr::null_mut(),
-                0,
+                args.as_mut_ptr(),
+                1,
                 c_str!(""),
             );
             let v_loc = LLVMBuildAlloca(builder, map_ty, c_str!(""));
@@ -859,6 +869,8 @@ impl<'a, 'b> Generator<'a, 'b> {
         self.type_map.init(Ty::MapStrInt, make(voidptr));
         self.type_map.init(Ty::MapStrFloat, make(voidptr));
         self.type_map.init(Ty::MapStrStr, make(voidptr));
+        self.type_map.init(Ty::MapIntMap, make(voidptr));
+        self.type_map.init(Ty::MapStrMap, make(voidptr));
         // NB: iterators do not have types of their own, and we should never ask for their types.
         // See the IterState type and its uses for more info.
         self.type_map.init(Ty::IterInt, TypeRef::null());
@@ -946,8 +958,13 @@ impl<'a, 'b> Generator<'a, 'b> {
         }
     }
 
-    unsafe fn alloc_local(&self, builder: LLVMBuilderRef, ty: Ty) -> Result<LLVMValueRef> {
-        alloc_local(builder, ty, &self.type_map, &self.intrinsics)
+    unsafe fn alloc_local(
+        &self,
+        builder: LLVMBuilderRef,
+        ty: Ty,
+        rt: LLVMValueRef,
+    ) -> Result<LLVMValueRef> {
+        alloc_local(builder, ty, rt, &self.type_map, &self.intrinsics)
     }
 
     unsafe fn gen_main_function(
@@ -973,7 +990,7 @@ impl<'a, 'b> Generator<'a, 'b> {
         let mut args: SmallVec<_> = smallvec![ptr::null_mut(); main_info.num_args];
         let mut to_drop = SmallVec::with_capacity(args.len());
         for ((_reg, ty), arg_ix) in main_info.globals.iter() {
-            let local = self.alloc_local(builder, *ty)?;
+            let local = self.alloc_local(builder, *ty, LLVMGetParam(decl, 0))?;
             let param = if ty.is_array() || matches!(ty, Ty::Str) {
                 // Already a pointer; we're good to go!
                 to_drop.push((local, *ty));
@@ -1092,12 +1109,16 @@ impl<'a, 'b> Generator<'a, 'b> {
             .enumerate()
             .collect();
         let arg_set: HashSet<_> = enum_args.iter().map(|(_, x)| *x).collect();
+        let rt = LLVMGetParam(
+            self.funcs[func_id].val,
+            self.decls[func_id].num_args as libc::c_uint - 1,
+        );
         for (local, (reg, ty)) in frame.locals.iter() {
             // implicitly-declared locals are just the ones with a subscript of 0.
             // Args are handled separately, skip them for now.
             if local.sub == 0 && !arg_set.contains(&(*reg, *ty)) {
                 // For maps, we need these to go in entry
-                let val = self.alloc_local(entry_builder, *ty)?;
+                let val = self.alloc_local(entry_builder, *ty, rt)?;
                 self.funcs[func_id].locals.insert((*reg, *ty), val);
             }
         }
@@ -1144,7 +1165,7 @@ impl<'a, 'b> Generator<'a, 'b> {
                         match hl {
                             Ret(_, _) => exits.push((i, j)),
                             Phi(_, ty, _) if ty != &Ty::Null => phis.push((i, j)),
-                            Phi(_, _, _) | DropIter(_, _) | Call { .. } => {}
+                            Phi(_, _, _) | DropIter(_, _) | Call { .. } | Loc(_) => {}
                         }
                     }
                 }
@@ -1190,10 +1211,12 @@ impl<'a, 'b> Generator<'a, 'b> {
                 // function. It probably means this function is "void"; but because you can assign
                 // to the result of a void function we need to allocate something here.
                 let ty = var.1;
+                let rt = view.runtime_val();
                 mem::swap(view.intrinsics, &mut placeholder_intrinsics);
                 let val = alloc_local(
                     view.entry_builder,
                     ty,
+                    rt,
                     &self.type_map,
                     &placeholder_intrinsics,
                 )?;
@@ -1285,7 +1308,8 @@ impl<'a> View<'a> {
                 let str_ty = self.tmap.get_ty(local.1);
                 LLVMConstInt(str_ty, 0, /*sign_extend=*/ 0)
             } else {
-                alloc_local(self.f.builder, local.1, self.tmap, self.intrinsics)?
+                let rt = self.runtime_val();
+                alloc_local(self.f.builder, local.1, rt, self.tmap, self.intrinsics)?
             };
             self.bind_val(local, v)?;
             Ok(v)
@@ -1380,10 +1404,13 @@ impl<'a> View<'a> {
             Ty::MapStrInt => intrinsic!(alloc_strint),
             Ty::MapStrFloat => intrinsic!(alloc_strfloat),
             Ty::MapStrStr => intrinsic!(alloc_strstr),
+            Ty::MapIntMap => intrinsic!(alloc_intmap),
+            Ty::MapStrMap => intrinsic!(alloc_strmap),
         };
         let llty = self.tmap.get_ty(ty);
         let res = LLVMBuildAlloca(self.entry_builder, llty, c_str!(""));
-        let v = self.call_at(self.entry_builder, alloc_fn, &mut []);
+        let rt = self.runtime_val();
+        let v = self.call_at(self.entry_builder, alloc_fn, &mut [rt]);
         LLVMBuildStore(self.entry_builder, v, res);
         Ok(res)
     }
@@ -1530,6 +1557,9 @@ impl<'a> View<'a> {
             }
             // Returns are handled elsewhere
             Ret(_reg, _ty) => {}
+            // The LLVM backend does not record source locations, so its runtime errors do not say
+            // where in the program they happened.
+            Loc(_) => {}
             DropIter(reg, ty) => {
                 let drop_fn = match ty {
                     Ty::IterInt => intrinsic!(drop_iter_int),
@@ -3,16 +3,15 @@
 //!
 //! The module root contains code that is shared by the cranelift and LLVM backends.
 use crate::{
-    builtins,
+    ast, builtins,
     bytecode::{self, Accum},
     common::{CancelSignal, Cleanup, FileSpec, NumTy, Result, Stage},
     compile,
+    profile::Profile,
     pushdown::FieldSet,
-    runtime::{self, UniqueStr},
+    runtime::{self, RegexConst, UniqueStr},
 };
 
-use regex::bytes::Regex;
-
 use std::marker::PhantomData;
 use std::mem;
 use std::sync::Arc;
@@ -144,6 +143,10 @@ pub(crate) unsafe fn run_main<R, FF, J>(
     ff: FF,
     used_fields: &FieldSet,
     named_columns: Option<Vec<&[u8]>>,
+    profile: Option<Profile>,
+    ordered_maps: bool,
+    stdin_is_input: bool,
+    bignum: bool,
     num_workers: usize,
     cancel_signal: CancelSignal,
 ) -> Result<()>
@@ -153,6 +156,14 @@ where
     J: Jit,
 {
     let mut rt = stdin.into_runtime(ff, used_fields, named_columns, cancel_signal.clone());
+    rt.core.profile = profile.map(Box::new);
+    rt.core.ordered_maps = ordered_maps;
+    if stdin_is_input {
+        with_input!(&mut rt.input_data, |(_, read_files)| {
+            read_files.share_stdin()
+        });
+    }
+    rt.core.bignum = bignum;
     let main = jit.main_functions()?;
     match main {
         Stage::Main(m) => {
@@ -171,9 +182,13 @@ where
                 let reads = read_files.try_resize(num_workers.saturating_sub(1));
                 if num_workers <= 1 || reads.is_empty() || main_loop.is_none() {
                     // execute serially.
-                    for main in begin.into_iter().chain(main_loop).chain(end) {
+                    for main in begin.into_iter().chain(main_loop) {
                         main.invoke(&mut rt);
                     }
+                    if let Some(end) = end {
+                        rt.core.sort_slots();
+                        end.invoke(&mut rt);
+                    }
                     return Ok(());
                 }
                 #[cfg(not(debug_assertions))]
@@ -223,6 +238,7 @@ where
                                             sender.send(rt.core.extract_result(0)).unwrap();
                                         }),
                                         cancel_signal,
+                                        loc: 0,
                                     };
                                     main_loop_fn.invoke(&mut runtime);
                                 }
@@ -240,9 +256,7 @@ where
                         rt.core.vars.pid = 0;
 
                         with_input!(&mut rt.input_data, |(_, read_files)| {
-                            while let Ok(res) = receiver.recv() {
-                                rt.core.combine(res);
-                            }
+                            rt.core.combine_all(receiver.iter().collect());
                             if let Some(rc) = cancel_signal.get_code() {
                                 mem::drop(rt);
                                 std::process::exit(rc);
@@ -267,7 +281,7 @@ where
 /// Handles to ensure the liveness of rust objects passed by pointer into generated code.
 #[derive(Default)]
 pub(crate) struct Handles {
-    res: Vec<Arc<Regex>>,
+    res: Vec<Arc<RegexConst>>,
     slices: Vec<Arc<[u8]>>,
 }
 
@@ -316,7 +330,7 @@ pub(crate) trait CodeGenerator: Backend {
     // const_{re,slice} take an `Arc` so that it can store a pointer to `c` and ensure references
     // to `c` will live as long as the generated code.
 
-    fn const_re(&mut self, pat: Arc<Regex>) -> Self::Val {
+    fn const_re(&mut self, pat: Arc<RegexConst>) -> Self::Val {
         let res = self.const_ptr(&*pat);
         self.handles().res.push(pat);
         res
@@ -395,6 +409,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => intrinsic!(load_slot_strint),
             MapStrFloat => intrinsic!(load_slot_strfloat),
             MapStrStr => intrinsic!(load_slot_strstr),
+            MapIntMap => intrinsic!(load_slot_intmap),
+            MapStrMap => intrinsic!(load_slot_strmap),
             _ => unreachable!(),
         };
         let rt = self.runtime_val();
@@ -418,6 +434,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => external!(store_slot_strint),
             MapStrFloat => external!(store_slot_strfloat),
             MapStrStr => external!(store_slot_strstr),
+            MapIntMap => external!(store_slot_intmap),
+            MapStrMap => external!(store_slot_strmap),
             _ => unreachable!(),
         };
         let rt = self.runtime_val();
@@ -440,6 +458,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => intrinsic!(lookup_strint),
             MapStrFloat => intrinsic!(lookup_strfloat),
             MapStrStr => intrinsic!(lookup_strstr),
+            MapIntMap => intrinsic!(lookup_intmap),
+            MapStrMap => intrinsic!(lookup_strmap),
             ty => return err!("non-map type: {:?}", ty),
         };
         let mapv = self.get_val(map)?;
@@ -462,6 +482,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => external!(delete_strint),
             MapStrFloat => external!(delete_strfloat),
             MapStrStr => external!(delete_strstr),
+            MapIntMap => external!(delete_intmap),
+            MapStrMap => external!(delete_strmap),
             ty => return err!("non-map type: {:?}", ty),
         };
         let mapv = self.get_val(map)?;
@@ -482,6 +504,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => external!(clear_strint),
             MapStrFloat => external!(clear_strfloat),
             MapStrStr => external!(clear_strstr),
+            MapIntMap => external!(clear_intmap),
+            MapStrMap => external!(clear_strmap),
             ty => return err!("non-map type: {:?}", ty),
         };
         let mapv = self.get_val(map)?;
@@ -502,6 +526,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => intrinsic!(contains_strint),
             MapStrFloat => intrinsic!(contains_strfloat),
             MapStrStr => intrinsic!(contains_strstr),
+            MapIntMap => intrinsic!(contains_intmap),
+            MapStrMap => intrinsic!(contains_strmap),
             ty => return err!("non-map type: {:?}", ty),
         };
         let mapv = self.get_val(map)?;
@@ -521,6 +547,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => intrinsic!(len_strint),
             MapStrFloat => intrinsic!(len_strfloat),
             MapStrStr => intrinsic!(len_strstr),
+            MapIntMap => intrinsic!(len_intmap),
+            MapStrMap => intrinsic!(len_strmap),
             ty => return err!("non-map type: {:?}", ty),
         };
         let mapv = self.get_val(map)?;
@@ -542,6 +570,8 @@ pub(crate) trait CodeGenerator: Backend {
             MapStrInt => external!(insert_strint),
             MapStrFloat => external!(insert_strfloat),
             MapStrStr => external!(insert_strstr),
+            MapIntMap => external!(insert_intmap),
+            MapStrMap => external!(insert_strmap),
             ty => return err!("non-map type: {:?}", ty),
         };
         let mapv = self.get_val(map)?;
@@ -586,6 +616,15 @@ pub(crate) trait CodeGenerator: Backend {
         self.bind_val(dst.reflect(), res)
     }
 
+    /// Like `binop`, but for [`Op`]s that also take the runtime as their first argument.
+    fn rt_binop(&mut self, op: Op, dst: &impl Accum, l: &impl Accum, r: &impl Accum) -> Result<()> {
+        let rt = self.runtime_val();
+        let lv = self.get_val(l.reflect())?;
+        let rv = self.get_val(r.reflect())?;
+        let res = self.call_intrinsic(op, &mut [rt, lv, rv])?;
+        self.bind_val(dst.reflect(), res)
+    }
+
     /// Wraps `call_intrinsic` for [`Op`]s that have one argument and return a value.
     fn unop(&mut self, op: Op, dst: &impl Accum, x: &impl Accum) -> Result<()> {
         let xv = self.get_val(x.reflect())?;
@@ -609,7 +648,18 @@ pub(crate) trait CodeGenerator: Backend {
                 self.bind_val(fr.reflect(), fv)
             }
             IntToStr(sr, ir) => self.unop(intrinsic!(int_to_str), sr, ir),
-            FloatToStr(sr, fr) => self.unop(intrinsic!(float_to_str), sr, fr),
+            FloatToStr(sr, fr) => {
+                let rt = self.runtime_val();
+                let fv = self.get_val(fr.reflect())?;
+                let res = self.call_intrinsic(intrinsic!(float_to_str), &mut [rt, fv])?;
+                self.bind_val(sr.reflect(), res)
+            }
+            FloatToStrOfmt(sr, fr) => {
+                let rt = self.runtime_val();
+                let fv = self.get_val(fr.reflect())?;
+                let res = self.call_intrinsic(intrinsic!(float_to_str_ofmt), &mut [rt, fv])?;
+                self.bind_val(sr.reflect(), res)
+            }
             StrToInt(ir, sr) => self.unop(intrinsic!(str_to_int), ir, sr),
             HexStrToInt(ir, sr) => self.unop(intrinsic!(hex_str_to_int), ir, sr),
             StrToFloat(fr, sr) => self.unop(intrinsic!(str_to_float), fr, sr),
@@ -627,6 +677,22 @@ pub(crate) trait CodeGenerator: Backend {
             ModFloat(res, l, r) => self.binop(op(Arith::Mod, true), res, l, r),
             Div(res, l, r) => self.binop(Op::Div, res, l, r),
             Pow(res, l, r) => self.binop(Op::Pow, res, l, r),
+            CheckedInt(op, res, l, r) => {
+                let f = match op {
+                    ast::Binop::Plus => intrinsic!(checked_add),
+                    ast::Binop::Minus => intrinsic!(checked_sub),
+                    ast::Binop::Mult => intrinsic!(checked_mul),
+                    _ => intrinsic!(checked_mod),
+                };
+                self.rt_binop(f, res, l, r)
+            }
+            CheckedFloat(op, res, l, r) => {
+                let f = match op {
+                    ast::Binop::Div => intrinsic!(checked_div),
+                    _ => intrinsic!(checked_fmod),
+                };
+                self.rt_binop(f, res, l, r)
+            }
             Not(res, ir) => {
                 let iv = self.get_val(ir.reflect())?;
                 let zero = self.const_int(0);
@@ -696,6 +762,13 @@ pub(crate) trait CodeGenerator: Backend {
                 let res = self.call_intrinsic(intrinsic!(match_pat), &mut [rt, lv, rv])?;
                 self.bind_val(dst.reflect(), res)
             }
+            MatchSet(dst, l, r) => {
+                let lv = self.get_val(l.reflect())?;
+                let rv = self.get_val(r.reflect())?;
+                let rt = self.runtime_val();
+                let res = self.call_intrinsic(intrinsic!(match_set), &mut [rt, lv, rv])?;
+                self.bind_val(dst.reflect(), res)
+            }
             MatchConst(res, src, pat) => {
                 let rt = self.runtime_val();
                 let srcv = self.get_val(src.reflect())?;
@@ -705,13 +778,15 @@ pub(crate) trait CodeGenerator: Backend {
                 self.bind_val(res.reflect(), resv)
             }
             IsMatchConst(res, src, pat) => {
+                let rt = self.runtime_val();
                 let srcv = self.get_val(src.reflect())?;
                 let patv = self.const_re(pat.clone());
-                let resv = self.call_intrinsic(intrinsic!(match_const_pat), &mut [srcv, patv])?;
+                let resv =
+                    self.call_intrinsic(intrinsic!(match_const_pat), &mut [rt, srcv, patv])?;
                 self.bind_val(res.reflect(), resv)
             }
-            SubstrIndex(dst, s, t) => self.binop(intrinsic!(substr_index), dst, s, t),
-            SubstrLastIndex(dst, s, t) => self.binop(intrinsic!(substr_last_index), dst, s, t),
+            SubstrIndex(dst, s, t) => self.rt_binop(intrinsic!(substr_index), dst, s, t),
+            SubstrLastIndex(dst, s, t) => self.rt_binop(intrinsic!(substr_last_index), dst, s, t),
             LenStr(dst, x) => self.unop(intrinsic!(len_str), dst, x),
             Strlen(dst, x) => self.unop(intrinsic!(str_len), dst, x),
             Sub(res, pat, s, in_s) => {
@@ -775,11 +850,13 @@ pub(crate) trait CodeGenerator: Backend {
             LTEFloat(res, l, r) => self.binop(cmp(Cmp::Lte, true), res, l, r),
             GTEFloat(res, l, r) => self.binop(cmp(Cmp::Gte, true), res, l, r),
             EQFloat(res, l, r) => self.binop(cmp(Cmp::Eq, true), res, l, r),
-            LTStr(res, l, r) => self.binop(intrinsic!(str_lt), res, l, r),
-            GTStr(res, l, r) => self.binop(intrinsic!(str_gt), res, l, r),
-            LTEStr(res, l, r) => self.binop(intrinsic!(str_lte), res, l, r),
-            GTEStr(res, l, r) => self.binop(intrinsic!(str_gte), res, l, r),
-            EQStr(res, l, r) => self.binop(intrinsic!(str_eq), res, l, r),
+            LTStr(res, l, r) => self.rt_binop(intrinsic!(str_lt), res, l, r),
+            GTStr(res, l, r) => self.rt_binop(intrinsic!(str_gt), res, l, r),
+            LTEStr(res, l, r) => self.rt_binop(intrinsic!(str_lte), res, l, r),
+            GTEStr(res, l, r) => self.rt_binop(intrinsic!(str_gte), res, l, r),
+            EQStr(res, l, r) => self.rt_binop(intrinsic!(str_eq), res, l, r),
+            StrNumCmp(res, l, r) => self.rt_binop(intrinsic!(strnum_cmp), res, l, r),
+            StrNumCmpFloat(res, l, r) => self.rt_binop(intrinsic!(strnum_cmp_float), res, l, r),
             SetColumn(dst, src) => {
                 let rt = self.runtime_val();
                 let srcv = self.get_val(src.reflect())?;
@@ -1063,7 +1140,8 @@ pub(crate) trait CodeGenerator: Backend {
             SqliteQuery(dst,db_path,sql) => {
                 let db_path = self.get_val(db_path.reflect())?;
                 let sql = self.get_val(sql.reflect())?;
-                let resv = self.call_intrinsic(intrinsic!(sqlite_query), &mut [db_path, sql])?;
+                let rt = self.runtime_val();
+                let resv = self.call_intrinsic(intrinsic!(sqlite_query), &mut [rt, db_path, sql])?;
                 self.bind_val(dst.reflect(), resv)
             }
             SqliteExecute(dst,db_path,sql) => {
@@ -1249,6 +1327,7 @@ pub(crate) trait CodeGenerator: Backend {
             MapIntFloatMin(dst,arr) => self.unop(intrinsic!(map_int_float_min), dst, arr),
             MapIntIntSum(dst,arr) => self.unop(intrinsic!(map_int_int_sum), dst, arr),
             MapIntFloatSum(dst,arr) => self.unop(intrinsic!(map_int_float_sum), dst, arr),
+            MapIntStrSum(dst,arr) => self.unop(intrinsic!(map_int_str_sum), dst, arr),
             MapIntIntMean(dst,arr) => self.unop(intrinsic!(map_int_int_mean), dst, arr),
             MapIntFloatMean(dst,arr) => self.unop(intrinsic!(map_int_float_mean), dst, arr),
             FromCsv(dst,src) => self.unop(intrinsic!(from_csv), dst, src),
@@ -1353,6 +1432,38 @@ pub(crate) trait CodeGenerator: Backend {
                 let resv = self.call_intrinsic(intrinsic!(pad_both), &mut [text, len, pad])?;
                 self.bind_val(dst.reflect(),resv)
             }
+            Bignum(op, dst, x, y, prec) => {
+                let rt = self.runtime_val();
+                let opv = self.const_int(*op as i64);
+                let x = self.get_val(x.reflect())?;
+                let y = self.get_val(y.reflect())?;
+                let prec = self.get_val(prec.reflect())?;
+                let resv = self.call_intrinsic(intrinsic!(bignum), &mut [rt, opv, x, y, prec])?;
+                self.bind_val(dst.reflect(), resv)
+            }
+            BignumCmp(dst, x, y) => {
+                let x = self.get_val(x.reflect())?;
+                let y = self.get_val(y.reflect())?;
+                let resv = self.call_intrinsic(intrinsic!(bignum_cmp), &mut [x, y])?;
+                self.bind_val(dst.reflect(), resv)
+            }
+            DecimalCmp(dst, x, y) => {
+                let rt = self.runtime_val();
+                let x = self.get_val(x.reflect())?;
+                let y = self.get_val(y.reflect())?;
+                let resv = self.call_intrinsic(intrinsic!(decimal_cmp), &mut [rt, x, y])?;
+                self.bind_val(dst.reflect(), resv)
+            }
+            Decimal(op, dst, x, y, scale, mode) => {
+                let rt = self.runtime_val();
+                let opv = self.const_int(*op as i64);
+                let x = self.get_val(x.reflect())?;
+                let y = self.get_val(y.reflect())?;
+                let scale = self.get_val(scale.reflect())?;
+                let mode = self.get_val(mode.reflect())?;
+                let resv = self.call_intrinsic(intrinsic!(decimal), &mut [rt, opv, x, y, scale, mode])?;
+                self.bind_val(dst.reflect(), resv)
+            }
             StrCmp(dst,text1, text2) => {
                 let text1 = self.get_val(text1.reflect())?;
                 let text2 = self.get_val(text2.reflect())?;
@@ -1578,10 +1689,11 @@ pub(crate) trait CodeGenerator: Backend {
             Printf { output, fmt, args } => self.printf(output, fmt, &args[..]),
             Sprintf { dst, fmt, args } => self.sprintf(dst, fmt, &args[..]),
             PrintAll { output, args } => self.print_all(output, &args[..]),
-            Close(file) => {
+            Close(file, how) => {
                 let rt = self.runtime_val();
                 let filev = self.get_val(file.reflect())?;
-                self.call_void(external!(close_file), &mut [rt, filev])?;
+                let howv = self.get_val(how.reflect())?;
+                self.call_void(external!(close_file), &mut [rt, filev, howv])?;
                 Ok(())
             }
             RunCmd(dst, cmd) => self.unop(intrinsic!(run_cmd), dst, cmd),
@@ -1607,6 +1719,18 @@ pub(crate) trait CodeGenerator: Backend {
                     self.call_intrinsic(intrinsic!(next_line), &mut [rt, filev, is_filev])?;
                 self.bind_val(dst.reflect(), resv)
             }
+            ReadErrCoproc(dst, cmd) => {
+                let rt = self.runtime_val();
+                let cmdv = self.get_val(cmd.reflect())?;
+                let resv = self.call_intrinsic(intrinsic!(read_err_coproc), &mut [rt, cmdv])?;
+                self.bind_val(dst.reflect(), resv)
+            }
+            NextLineCoproc(dst, cmd) => {
+                let rt = self.runtime_val();
+                let cmdv = self.get_val(cmd.reflect())?;
+                let resv = self.call_intrinsic(intrinsic!(next_line_coproc), &mut [rt, cmdv])?;
+                self.bind_val(dst.reflect(), resv)
+            }
             ReadErrStdin(dst) => {
                 let rt = self.runtime_val();
                 let resv = self.call_intrinsic(intrinsic!(read_err_stdin), &mut [rt])?;
@@ -1642,6 +1766,23 @@ pub(crate) trait CodeGenerator: Backend {
                 self.call_void(external!(set_fi_entry), &mut [rt, keyv, valv])?;
                 Ok(())
             }
+            ProfileMark(id) => {
+                let rt = self.runtime_val();
+                let idv = self.const_int(*id as i64);
+                self.call_void(external!(profile_mark), &mut [rt, idv])?;
+                Ok(())
+            }
+            ProfileEnter(id) => {
+                let rt = self.runtime_val();
+                let idv = self.const_int(*id as i64);
+                self.call_void(external!(profile_enter), &mut [rt, idv])?;
+                Ok(())
+            }
+            ProfileExit() => {
+                let rt = self.runtime_val();
+                self.call_void(external!(profile_exit), &mut [rt])?;
+                Ok(())
+            }
             Lookup {
                 map_ty,
                 dst,
@@ -8,6 +8,8 @@ use std::sync::{
     Arc, Condvar, Mutex,
 };
 
+use crate::lexer::Loc;
+
 pub(crate) type NumTy = u32;
 pub(crate) type NodeIx = petgraph::graph::NodeIndex<NumTy>;
 pub(crate) type Graph<V, E> = petgraph::Graph<V, E, petgraph::Directed, NumTy>;
@@ -174,23 +176,47 @@ where
     }
 }
 
+/// An error encountered while compiling or running a program. The location, if present, points
+/// at the start of the statement in the (expanded) program text that produced the error.
 #[derive(Debug, Clone)]
-pub struct CompileError(pub String);
+pub struct CompileError(pub String, pub Option<Loc>);
+
+impl CompileError {
+    pub fn new(msg: impl Into<String>) -> CompileError {
+        CompileError(msg.into(), None)
+    }
+
+    /// Attach `loc` to the error, unless a more precise location has already been attached.
+    pub fn at(mut self, loc: Loc) -> CompileError {
+        self.1.get_or_insert(loc);
+        self
+    }
+
+    pub(crate) fn at_opt(self, loc: Option<Loc>) -> CompileError {
+        match loc {
+            Some(loc) => self.at(loc),
+            None => self,
+        }
+    }
+}
 
 impl std::fmt::Display for CompileError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match &self.1 {
+            Some(loc) => write!(f, "{}: {}", loc, self.0),
+            None => write!(f, "{}", self.0),
+        }
     }
 }
 
 macro_rules! err_raw {
     ($head:expr) => {
-        $crate::common::CompileError(
+        $crate::common::CompileError::new(
                 format!(concat!("[", file!(), ":", line!(), ":", column!(), "] ", $head))
         )
     };
     ($head:expr, $($t:expr),+) => {
-        $crate::common::CompileError(
+        $crate::common::CompileError::new(
                 format!(concat!("[", file!(), ":", line!(), ":", column!(), "] ", $head), $($t),*)
         )
     };
@@ -395,6 +421,8 @@ pub enum FileSpec {
     #[default]
     Append = 1,
     Cmd = 2,
+    // A coprocess, written with `|&`.
+    Coproc = 3,
 }
 
 #[derive(Debug)]
@@ -415,12 +443,23 @@ impl std::convert::TryFrom<i64> for FileSpec {
             Ok(FileSpec::Append)
         } else if i == FileSpec::Cmd as i64 {
             Ok(FileSpec::Cmd)
+        } else if i == FileSpec::Coproc as i64 {
+            Ok(FileSpec::Coproc)
         } else {
             Err(InvalidFileSpec)
         }
     }
 }
 
+/// What `--checked-arith` does about integer arithmetic that would overflow.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheckedArith {
+    /// Stop with a runtime error naming the operation. Division by zero is an error as well.
+    Error,
+    /// Do `+`, `-`, `*` and `%` in floating point, as awks without integers do.
+    Float,
+}
+
 pub(crate) fn traverse<T>(o: Option<Result<T>>) -> Result<Option<T>> {
     match o {
         Some(e) => Ok(Some(e?)),
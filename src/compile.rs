@@ -1,27 +1,34 @@
 use crate::builtins;
 use crate::bytecode;
-use crate::cfg::{self, is_unused, Function, Ident, PrimExpr, PrimStmt, PrimVal, ProgramContext};
+use crate::cfg::{
+    self, is_unused, Function, Ident, PrimExpr, PrimStmt, PrimVal, ProgramContext, SrcLoc,
+};
 use crate::codegen;
 #[cfg(feature = "llvm_backend")]
 use crate::codegen::llvm;
 use crate::common::{
-    CancelSignal, CompileError, Either, Graph, NodeIx, NumTy, Result, Stage, WorkList,
+    CancelSignal, CheckedArith, CompileError, Either, Graph, NodeIx, NumTy, Result, Stage,
+    WorkList,
 };
 use crate::cross_stage;
+use crate::debugger::{self, Debugger};
 use crate::input_taint::TaintedStringAnalysis;
+use crate::lexer::Loc;
+use crate::profile::{self, Profile};
 use crate::pushdown::{FieldSet, UsedFieldAnalysis};
-use crate::runtime::{self, Str};
+use crate::runtime::{self, RegexConst, Str};
 use crate::string_constants::{self, StringConstantAnalysis};
 use crate::types;
 
 use hashbrown::{hash_map::Entry, HashMap, HashSet};
-use regex::bytes::Regex;
 use smallvec::smallvec;
 
 use std::collections::VecDeque;
 use std::mem;
 use std::sync::Arc;
-use crate::compile::Ty::{MapIntFloat, MapIntInt, MapIntStr, MapStrFloat, MapStrInt, MapStrStr};
+use crate::compile::Ty::{
+    MapIntFloat, MapIntInt, MapIntMap, MapIntStr, MapStrFloat, MapStrInt, MapStrMap, MapStrStr,
+};
 
 pub(crate) const UNUSED: u32 = u32::MAX;
 pub(crate) const NULL_REG: u32 = UNUSED - 1;
@@ -37,10 +44,13 @@ pub(crate) enum Ty {
     MapStrInt = 6,
     MapStrFloat = 7,
     MapStrStr = 8,
-    IterInt = 9,
-    IterStr = 10,
+    // Arrays of arrays. The inner arrays always have type MapStrStr.
+    MapIntMap = 9,
+    MapStrMap = 10,
+    IterInt = 11,
+    IterStr = 12,
     #[default]
-    Null = 11,
+    Null = 13,
 }
 
 pub(crate) const NUM_TYPES: usize = Ty::Null as usize + 1;
@@ -59,9 +69,11 @@ impl std::convert::TryFrom<u32> for Ty {
             6 => MapStrInt,
             7 => MapStrFloat,
             8 => MapStrStr,
-            9 => IterInt,
-            10 => IterStr,
-            11 => Null,
+            9 => MapIntMap,
+            10 => MapStrMap,
+            11 => IterInt,
+            12 => IterStr,
+            13 => Null,
             _ => return Err(()),
         })
     }
@@ -75,8 +87,8 @@ impl Ty {
     pub(crate) fn key_iter(self) -> Result<Ty> {
         use Ty::*;
         match self {
-            MapIntInt | MapIntFloat | MapIntStr => Ok(IterInt),
-            MapStrInt | MapStrFloat | MapStrStr => Ok(IterStr),
+            MapIntInt | MapIntFloat | MapIntStr | MapIntMap => Ok(IterInt),
+            MapStrInt | MapStrFloat | MapStrStr | MapStrMap => Ok(IterStr),
             Null | Int | Float | Str | IterInt | IterStr => {
                 err!("attempt to get iterator from non-map type: {:?}", self)
             }
@@ -89,7 +101,7 @@ impl Ty {
             IterInt => Ok(Int),
             IterStr => Ok(Str),
             Null | Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt
-            | MapStrFloat | MapStrStr => {
+            | MapStrFloat | MapStrStr | MapIntMap | MapStrMap => {
                 err!("attempt to get element of non-iterator type: {:?}", self)
             }
         }
@@ -98,16 +110,22 @@ impl Ty {
     pub(crate) fn is_array(self) -> bool {
         use Ty::*;
         match self {
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => true,
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr
+            | MapIntMap | MapStrMap => true,
             Null | Int | Float | Str | IterInt | IterStr => false,
         }
     }
 
+    /// Whether this is an array whose values are themselves arrays.
+    pub(crate) fn is_nested_array(self) -> bool {
+        matches!(self, Ty::MapIntMap | Ty::MapStrMap)
+    }
+
     pub(crate) fn key(self) -> Result<Ty> {
         use Ty::*;
         match self {
-            MapIntInt | MapIntFloat | MapIntStr => Ok(Int),
-            MapStrInt | MapStrFloat | MapStrStr => Ok(Str),
+            MapIntInt | MapIntFloat | MapIntStr | MapIntMap => Ok(Int),
+            MapStrInt | MapStrFloat | MapStrStr | MapStrMap => Ok(Str),
             Null | Int | Float | Str | IterInt | IterStr => {
                 err!("attempt to get key of non-map type: {:?}", self)
             }
@@ -120,6 +138,7 @@ impl Ty {
             MapStrInt | MapIntInt => Ok(Int),
             MapStrFloat | MapIntFloat => Ok(Float),
             MapStrStr | MapIntStr => Ok(Str),
+            MapIntMap | MapStrMap => Ok(MapStrStr),
             Null | Int | Float | Str | IterInt | IterStr => {
                 err!("attempt to get val of non-map type: {:?}", self)
             }
@@ -161,7 +180,24 @@ pub(crate) fn bytecode<'a, LR: runtime::LineReader>(
     Typer::init_from_ctx(ctx)?.to_interp(reader, ff, num_workers)
 }
 
-#[cfg(test)]
+/// Like `bytecode`, but the program runs serially under `debugger`.
+pub(crate) fn bytecode_debug<'a, LR: runtime::LineReader>(
+    ctx: &mut cfg::ProgramContext<'a, &'a str>,
+    reader: LR,
+    ff: impl runtime::writers::FileFactory,
+    mut debugger: Debugger,
+) -> Result<bytecode::Interp<'a, LR>> {
+    let mut typer = Typer::init_from_ctx(ctx)?;
+    // The debugger can print any field, not just the ones the program uses.
+    typer.used_fields = FieldSet::all();
+    debugger.set_symbols(typer.symbols(ctx));
+    let mut interp = typer.to_interp(reader, ff, /*num_workers=*/ 1)?;
+    interp.set_debugger(debugger);
+    Ok(interp)
+}
+
+/// Type-check `ctx` (which also runs taint analysis, unless it is disabled) without generating
+/// any code.
 pub(crate) fn context_compiles<'a>(ctx: &mut cfg::ProgramContext<'a, &'a str>) -> Result<()> {
     Typer::init_from_ctx(ctx)?;
     Ok(())
@@ -210,6 +246,10 @@ pub(crate) fn run_llvm<'a>(
     let mut typer = Typer::init_from_ctx(ctx)?;
     let used_fields = typer.used_fields.clone();
     let named_cols = typer.named_columns.take();
+    let profile = typer.profile.take();
+    let ordered_maps = typer.ordered_maps;
+    let stdin_is_input = typer.stdin_is_input;
+    let bignum = typer.bignum;
     unsafe {
         let gen = Generator::init(&mut typer, cfg)?;
         codegen::run_main(
@@ -218,6 +258,10 @@ pub(crate) fn run_llvm<'a>(
             ff,
             &used_fields,
             named_cols,
+            profile,
+            ordered_maps,
+            stdin_is_input,
+            bignum,
             cfg.num_workers,
             cancel_signal,
         )
@@ -235,6 +279,10 @@ pub(crate) fn run_cranelift<'a>(
     let mut typer = Typer::init_from_ctx(ctx)?;
     let used_fields = typer.used_fields.clone();
     let named_cols = typer.named_columns.take();
+    let profile = typer.profile.take();
+    let ordered_maps = typer.ordered_maps;
+    let stdin_is_input = typer.stdin_is_input;
+    let bignum = typer.bignum;
     unsafe {
         let gen = Generator::init(&mut typer, cfg)?;
         codegen::run_main(
@@ -243,6 +291,10 @@ pub(crate) fn run_cranelift<'a>(
             ff,
             &used_fields,
             named_cols,
+            profile,
+            ordered_maps,
+            stdin_is_input,
+            bignum,
             cfg.num_workers,
             cancel_signal,
         )
@@ -265,12 +317,17 @@ pub(crate) enum HighLevel {
     Ret(NumTy, Ty),
     Phi(NumTy, Ty, SmallVec<(NodeIx /*pred*/, NumTy /*register*/)>),
     DropIter(NumTy, Ty),
+    // Marks the start of the instructions generated for a statement at the given source location.
+    // Phis always precede any `Loc` in a basic block.
+    Loc(SrcLoc),
 }
 
 #[derive(Default)]
 struct Registers {
     stats: RegStatuses,
     globals: HashMap<Ident, (u32, Ty)>,
+    // Globals holding strnums, under `--posix`.
+    strnums: HashSet<Ident>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -366,6 +423,25 @@ pub(crate) struct Typer<'a> {
     // variables in the LLVM backend. It is computed lazily because these are not needed for
     // serial, bytecode-only scripts.
     global_refs: Option<Vec<HashSet<(NumTy, Ty)>>>,
+
+    // Set when running under `--profile`. Moved into the runtime once code is generated.
+    profile: Option<Profile>,
+
+    // Set by `--checked-arith`.
+    checked_arith: Option<CheckedArith>,
+
+    // Set by `--ordered-maps`.
+    ordered_maps: bool,
+
+    // Set when the main input is standard input.
+    stdin_is_input: bool,
+
+    // Set by `-M`.
+    bignum: bool,
+
+    // Set if the program assigns IGNORECASE, in which case folded regex constants also carry a
+    // case-insensitive copy.
+    ignore_case: bool,
 }
 
 #[derive(Default)]
@@ -401,6 +477,8 @@ pub(crate) struct Frame<'a> {
     entry: NodeIx,
     exit: NodeIx,
     pub locals: HashMap<Ident, (u32, Ty)>,
+    // Locals holding strnums, under `--posix`.
+    strnums: HashSet<Ident>,
     pub arg_regs: SmallVec<NumTy>,
     pub cfg: Cfg<'a>,
     pub is_called: bool,
@@ -448,6 +526,11 @@ struct View<'a, 'b> {
     // The current basic block being filled; It'll be swapped into `frame.cfg` as we translate a
     // given function cfg.
     stream: &'b mut Node<'a>,
+    // When profiling, the profile, along with the region for the function being translated (if
+    // it is a user-defined function).
+    profile: Option<&'b mut Profile>,
+    profile_region: Option<NumTy>,
+    checked_arith: Option<CheckedArith>,
 }
 
 fn pop_var(instrs: &mut Vec<LL>, reg: NumTy, ty: Ty) -> Result<()> {
@@ -456,7 +539,7 @@ fn pop_var(instrs: &mut Vec<LL>, reg: NumTy, ty: Ty) -> Result<()> {
         Null => return Ok(()),
         IterInt | IterStr => return err!("invalid argument type: {:?}", ty),
         Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat
-        | MapStrStr => LL::Pop(ty, reg),
+        | MapStrStr | MapIntMap | MapStrMap => LL::Pop(ty, reg),
     });
     Ok(())
 }
@@ -466,7 +549,7 @@ fn push_var(instrs: &mut Vec<LL>, reg: NumTy, ty: Ty) -> Result<()> {
     match ty {
         Null => Ok(()),
         Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat
-        | MapStrStr => {
+        | MapStrStr | MapIntMap | MapStrMap => {
             instrs.push(LL::Push(ty, reg));
             Ok(())
         }
@@ -477,9 +560,8 @@ fn push_var(instrs: &mut Vec<LL>, reg: NumTy, ty: Ty) -> Result<()> {
 fn alloc_local<'a>(dst_reg: NumTy, dst_ty: Ty) -> Option<LL<'a>> {
     use Ty::*;
     match dst_ty {
-        MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
-            Some(LL::AllocMap(dst_ty, dst_reg))
-        }
+        MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr | MapIntMap
+        | MapStrMap => Some(LL::AllocMap(dst_ty, dst_reg)),
         _ => None,
     }
 }
@@ -493,12 +575,20 @@ fn mov<'a>(dst_reg: u32, src_reg: u32, ty: Ty) -> Result<Option<LL<'a>>> {
         Null => return Ok(None),
         IterInt | IterStr => return err!("attempt to move values of type {:?}", ty),
         Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat
-        | MapStrStr => LL::Mov(ty, dst_reg, src_reg),
+        | MapStrStr | MapIntMap | MapStrMap => LL::Mov(ty, dst_reg, src_reg),
     };
 
     Ok(Some(res))
 }
 
+// The location of the statement that generated the instruction at `ix`, if it has one.
+fn stmt_loc(insts: &VecDeque<Instr>, ix: usize) -> Option<Loc> {
+    insts.iter().take(ix).rev().find_map(|inst| match inst {
+        Either::Right(HighLevel::Loc(loc)) => Some(loc.error()),
+        _ => None,
+    })
+}
+
 fn accum(inst: &Instr, mut f: impl FnMut(NumTy, Ty)) {
     use {Either::*, HighLevel::*};
     match inst {
@@ -515,6 +605,7 @@ fn accum(inst: &Instr, mut f: impl FnMut(NumTy, Ty)) {
             }
         }
         Right(Ret(reg, ty)) | Right(Phi(reg, ty, _)) | Right(DropIter(reg, ty)) => f(*reg, *ty),
+        Right(HighLevel::Loc(_)) => {}
     }
 }
 
@@ -530,10 +621,11 @@ impl<'a> Typer<'a> {
         ff: impl runtime::writers::FileFactory,
         num_workers: usize,
     ) -> Result<bytecode::Interp<'a, LR>> {
-        let instrs = self.to_bytecode()?;
+        let (instrs, locs) = self.to_bytecode()?;
         let cols = self.named_columns.take();
-        Ok(bytecode::Interp::new(
+        let mut interp = bytecode::Interp::new(
             instrs,
+            locs,
             self.stage(),
             num_workers,
             |ty| self.regs.stats.count(ty) as usize,
@@ -541,13 +633,72 @@ impl<'a> Typer<'a> {
             ff,
             &self.used_fields,
             cols,
-        ))
+        );
+        interp.core.profile = self.profile.take().map(Box::new);
+        interp.core.bignum = self.bignum;
+        if self.ordered_maps {
+            interp.set_ordered_maps();
+        }
+        if self.stdin_is_input {
+            interp.share_stdin();
+        }
+        Ok(interp)
     }
 
-    // At initialization time, we generate Either<LL, HL>, this function lowers the HL into LL.
-    #[allow(clippy::wrong_self_convention)]
-    fn to_bytecode(&mut self) -> Result<Vec<Vec<LL<'a>>>> {
+    // The names of the variables held in each register, for the debugger. Only named variables are
+    // included: globals, function parameters, and globals referenced only from the main program
+    // (which are SSA-converted along with the locals of the main function).
+    fn symbols(&self, pc: &ProgramContext<'a, &'a str>) -> debugger::Symbols {
+        let names = pc._invert_ident();
+        let named = |ty: Ty| ty != Ty::Null && !ty.is_iter();
+        let globals = self
+            .regs
+            .globals
+            .iter()
+            .filter(|(_, (_, ty))| named(*ty))
+            .filter_map(|(id, (reg, ty))| Some((names.get(&id._base())?.to_string(), *reg, *ty)))
+            .collect();
+        let funcs = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let func = &pc.funcs[frame.src_function as usize];
+                let mut locals = Vec::new();
+                for (id, (reg, ty)) in frame.locals.iter() {
+                    if !named(*ty) {
+                        continue;
+                    }
+                    if let Some(name) = names.get(&id._base()) {
+                        locals.push(debugger::Local {
+                            name: name.to_string(),
+                            param: false,
+                            sub: id.sub,
+                            reg: *reg,
+                            ty: *ty,
+                        });
+                    } else if let Some(arg) = func.args.iter().find(|a| a.id.low == id.low) {
+                        locals.push(debugger::Local {
+                            name: arg.name.to_string(),
+                            param: true,
+                            sub: id.sub,
+                            reg: *reg,
+                            ty: *ty,
+                        });
+                    }
+                }
+                debugger::FuncSymbols::new(func.name.to_string(), locals)
+            })
+            .collect();
+        debugger::Symbols { funcs, globals }
+    }
+
+    // At initialization time, we generate Either<LL, HL>, this function lowers the HL into LL. For
+    // each function, we also return the source location (if any) of the instructions starting at a
+    // given offset, sorted by offset.
+    #[allow(clippy::wrong_self_convention, clippy::type_complexity)]
+    fn to_bytecode(&mut self) -> Result<(Vec<Vec<LL<'a>>>, Vec<Vec<(usize, Option<SrcLoc>)>>)> {
         let mut res = vec![vec![]; self.frames.len()];
+        let mut res_locs = vec![vec![]; self.frames.len()];
         let ret_regs: Vec<_> = (0..self.frames.len())
             .map(|i| {
                 let ret_ty = self.func_info[i].ret_ty;
@@ -565,6 +716,7 @@ impl<'a> Typer<'a> {
                 continue;
             }
             let instrs = &mut res[i];
+            let locs = &mut res_locs[i];
             bb_map.clear();
             bb_map.reserve(frame.cfg.node_count());
             jmps.clear();
@@ -600,6 +752,10 @@ impl<'a> Typer<'a> {
 
             for (j, n) in frame.cfg.raw_nodes().iter().enumerate() {
                 bb_map.push(instrs.len());
+                // Basic blocks do not inherit the location of the block laid out before them.
+                if let Some((_, Some(_))) = locs.last() {
+                    locs.push((instrs.len(), None));
+                }
                 use HighLevel::*;
                 for stmt in &n.weight.insts {
                     match stmt {
@@ -661,6 +817,9 @@ impl<'a> Typer<'a> {
                         Either::Right(Phi(_, _, _)) => {}
                         // we do not explicitly drop iterators in the bytecode interpreter.
                         Either::Right(DropIter(_, _)) => {}
+                        Either::Right(HighLevel::Loc(loc)) => {
+                            locs.push((instrs.len(), Some(*loc)))
+                        }
                     }
                 }
 
@@ -709,7 +868,7 @@ impl<'a> Typer<'a> {
                 }
             }
         }
-        Ok(res)
+        Ok((res, res_locs))
     }
 
     fn init_from_ctx(pc: &mut ProgramContext<'a, &'a str>) -> Result<Typer<'a>> {
@@ -720,6 +879,7 @@ impl<'a> Typer<'a> {
         if !pc.allow_arbitrary_commands {
             gen.taint_analysis = Some(Default::default());
         }
+        gen.ignore_case = pc.assigns_var(builtins::Variable::IGNORECASE);
         if pc.fold_regex_constants || pc.parse_header {
             gen.string_constants = Some(StringConstantAnalysis::from_config(
                 string_constants::Config {
@@ -728,7 +888,16 @@ impl<'a> Typer<'a> {
                 },
             ));
         }
-        let types::TypeInfo { var_tys, func_tys } = types::get_types(pc)?;
+        gen.profile = pc.profile.take();
+        gen.checked_arith = pc.checked_arith;
+        gen.ordered_maps = pc.ordered_maps;
+        gen.stdin_is_input = pc.stdin_is_input;
+        gen.bignum = pc.bignum;
+        let types::TypeInfo {
+            var_tys,
+            func_tys,
+            strnums,
+        } = types::get_types(pc)?;
         let local_globals = pc.local_globals();
         macro_rules! init_entry {
             ($v:expr, $func_id:expr, $args:expr) => {
@@ -784,6 +953,13 @@ impl<'a> Typer<'a> {
                 );
             }
         }
+        for (id, func_id, args) in strnums.iter() {
+            if id.is_global(&local_globals) {
+                gen.regs.strnums.insert(*id);
+            } else if let Some(frame) = gen.id_map.get(&(*func_id, args.clone())) {
+                gen.frames[*frame as usize].strnums.insert(*id);
+            }
+        }
         gen.main_offset = pc
             .main_stage()
             .map_ref(|o| gen.id_map[&(*o as NumTy, Default::default())] as usize);
@@ -791,6 +967,10 @@ impl<'a> Typer<'a> {
         for frame in gen.frames.iter_mut() {
             let src_func = frame.src_function as usize;
             let mut stream = Default::default();
+            let profile_region = match (&gen.profile, &pc.funcs[src_func].name) {
+                (Some(p), cfg::FunctionName::Named(name)) => p.function_region(name),
+                _ => None,
+            };
             View {
                 frame,
                 regs: &mut gen.regs,
@@ -800,6 +980,9 @@ impl<'a> Typer<'a> {
                 local_globals: &gen.local_globals,
                 func_info: &gen.func_info,
                 stream: &mut stream,
+                profile: gen.profile.as_mut(),
+                profile_region,
+                checked_arith: gen.checked_arith,
             }
                 .process_function(&pc.funcs[src_func])?;
         }
@@ -855,11 +1038,17 @@ impl<'a> Typer<'a> {
                     if strs.len() != 1 {
                         continue;
                     }
+                    let loc = stmt_loc(
+                        &self.frames[frame].cfg.node_weight(NodeIx::new(bb)).unwrap().insts,
+                        stmt,
+                    );
                     let text = std::str::from_utf8(strs[0]).map_err(|e| {
-                        CompileError(format!("regex patterns must be valid UTF-8: {}", e))
+                        CompileError::new(format!("regex patterns must be valid UTF-8: {}", e))
+                            .at_opt(loc)
                     })?;
-                    let re = Arc::new(Regex::new(text).map_err(|err| {
-                        CompileError(format!("regex parse error during compilation: {}", err))
+                    let re = Arc::new(RegexConst::new(text, self.ignore_case).map_err(|err| {
+                        CompileError::new(format!("regex parse error during compilation: {}", err))
+                            .at_opt(loc)
                     })?);
                     // TODO: finish up
                     let inst = self.frames[frame]
@@ -871,7 +1060,14 @@ impl<'a> Typer<'a> {
                         .unwrap();
                     let new_inst: Instr = match inst {
                         Either::Left(LL::IsMatch(dst, s, _)) => {
-                            if let Some(bs) = extract_anchored_literal(text) {
+                            // A literal prefix is compared byte for byte, so it cannot honor
+                            // IGNORECASE.
+                            let prefix = if self.ignore_case {
+                                None
+                            } else {
+                                extract_anchored_literal(text)
+                            };
+                            if let Some(bs) = prefix {
                                 Either::Left(LL::StartsWithConst(*dst, *s, bs))
                             } else {
                                 Either::Left(LL::IsMatchConst(*dst, *s, re))
@@ -1031,8 +1227,18 @@ impl<'a, 'b> View<'a, 'b> {
         }
         // Fill them in.
         for (i, n) in func.cfg.raw_nodes().iter().enumerate() {
-            for stmt in n.weight.q.iter() {
-                self.stmt(stmt)?;
+            if let (Some(id), true) = (self.profile_region, NodeIx::new(i) == func.entry) {
+                self.pushl(LL::ProfileEnter(id));
+            }
+            let mut cur_loc = None;
+            for (stmt, loc) in n.weight.q.iter() {
+                if let Some(l) = *loc {
+                    if cur_loc != Some(l) {
+                        cur_loc = Some(l);
+                        self.pushr(HighLevel::Loc(l));
+                    }
+                }
+                self.stmt(stmt).map_err(|e| e.at_opt(loc.map(|l| l.error())))?;
             }
             let ix = NodeIx::new(i);
             let mut branches: SmallVec<petgraph::graph::EdgeIndex> = Default::default();
@@ -1135,6 +1341,14 @@ impl<'a, 'b> View<'a, 'b> {
         Ok((reg, ty))
     }
 
+    // Whether `v` holds a strnum: a string compared as a number when it looks like one.
+    fn is_strnum(&self, v: &PrimVal<'a>) -> bool {
+        match v {
+            PrimVal::Var(id) => self.frame.strnums.contains(id) || self.regs.strnums.contains(id),
+            _ => false,
+        }
+    }
+
     fn get_reg_status(&mut self, v: &PrimVal<'a>) -> Result<(u32, Ty, RegStatus)> {
         use RegStatus::*;
         match v {
@@ -1282,8 +1496,8 @@ impl<'a, 'b> View<'a, 'b> {
         // Emit the corresponding instruction.
         use Ty::*;
         match arr_ty {
-            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => self
-                .pushl(LL::Lookup {
+            MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr
+            | MapIntMap | MapStrMap => self.pushl(LL::Lookup {
                     map_ty: arr_ty,
                     dst: load_reg,
                     map: arr_reg,
@@ -1307,6 +1521,16 @@ impl<'a, 'b> View<'a, 'b> {
         use crate::ast::{Binop::*, Unop::*};
         use builtins::Function::*;
 
+        if let ProfileMark = bf {
+            return match args.first() {
+                Some(PrimVal::ILit(id)) => {
+                    self.pushl(LL::ProfileMark(*id as NumTy));
+                    Ok(())
+                }
+                _ => err!("profile marks must name a constant region, got {:?}", args),
+            };
+        }
+
         // Compile the argument values
         let mut args_regs = cfg::SmallVec::with_capacity(args.len());
         let mut args_tys = cfg::SmallVec::with_capacity(args.len());
@@ -1318,7 +1542,15 @@ impl<'a, 'b> View<'a, 'b> {
 
         // Now, perform any necessary conversions if input types do not match the argument types.
         let mut conv_regs: cfg::SmallVec<_> = smallvec![UNUSED; args.len()];
-        let (conv_tys, res_ty) = bf.type_sig(&args_tys[..])?;
+        let (conv_tys, res_ty) = match bf.type_sig(&args_tys[..])? {
+            // Under --checked-arith=float, integer operands are converted before the operation.
+            (_, Ty::Int)
+                if self.checked_arith == Some(CheckedArith::Float) && types::is_int_arith(bf) =>
+            {
+                (smallvec![Ty::Float; 2], Ty::Float)
+            }
+            sig => sig,
+        };
 
         for (areg, (aty, (creg, cty))) in args_regs.iter().cloned().zip(
             args_tys
@@ -1368,6 +1600,16 @@ impl<'a, 'b> View<'a, 'b> {
         //    There are more sophisticated ways to handle this (i.e. reusing "placeholder
         //    registers" within a function), but for now we are keeping things simple.
 
+        // When profiling, time calls to the more expensive builtins separately from the code
+        // calling them. This excludes the time taken to evaluate the arguments.
+        let timed = match &mut self.profile {
+            Some(p) if profile::timed(bf) => Some(p.builtin_region(bf)),
+            _ => None,
+        };
+        if let Some(id) = timed {
+            self.pushl(LL::ProfileEnter(id));
+        }
+
         match bf {
             Unop(Column) => self.pushl(LL::GetColumn(res_reg.into(), conv_regs[0].into())),
             Unop(Not) => self.pushl(if conv_tys[0] == Ty::Str {
@@ -1376,12 +1618,51 @@ impl<'a, 'b> View<'a, 'b> {
                 debug_assert_eq!(conv_tys[0], Ty::Int);
                 LL::Not(res_reg.into(), conv_regs[0].into())
             }),
+            // Negating Int::MIN overflows too: check it as 0 - x.
+            Unop(Neg)
+                if self.checked_arith == Some(CheckedArith::Error) && conv_tys[0] == Ty::Int =>
+            {
+                if res_reg != UNUSED {
+                    let zero_reg = self.regs.stats.reg_of_ty(Ty::Int);
+                    self.pushl(LL::StoreConstInt(zero_reg.into(), 0));
+                    self.pushl(LL::CheckedInt(
+                        Minus,
+                        res_reg.into(),
+                        zero_reg.into(),
+                        conv_regs[0].into(),
+                    ))
+                }
+            }
             Unop(Neg) => self.pushl(if conv_tys[0] == Ty::Float {
                 LL::NegFloat(res_reg.into(), conv_regs[0].into())
             } else {
                 LL::NegInt(res_reg.into(), conv_regs[0].into())
             }),
             Unop(Pos) => self.mov(res_reg, conv_regs[0], conv_tys[0])?,
+            Binop(op @ (Plus | Minus | Mult | Mod))
+                if self.checked_arith == Some(CheckedArith::Error) && conv_tys[0] == Ty::Int =>
+            {
+                if res_reg != UNUSED {
+                    self.pushl(LL::CheckedInt(
+                        *op,
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
+            Binop(op @ (Div | Mod))
+                if self.checked_arith == Some(CheckedArith::Error) && conv_tys[0] == Ty::Float =>
+            {
+                if res_reg != UNUSED {
+                    self.pushl(LL::CheckedFloat(
+                        *op,
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
             Binop(Plus) => gen_op!(Plus, [Float, AddFloat], [Int, AddInt]),
             Binop(Minus) => gen_op!(Minus, [Float, MinusFloat], [Int, MinusInt]),
             Binop(Mult) => gen_op!(Minus, [Float, MulFloat], [Int, MulInt]),
@@ -1390,6 +1671,68 @@ impl<'a, 'b> View<'a, 'b> {
             Binop(Mod) => gen_op!(Mod, [Float, ModFloat], [Int, ModInt]),
             Binop(Concat) => gen_op!(Concat, [Str, Concat]),
             Binop(IsMatch) => gen_op!(IsMatch, [Str, IsMatch]),
+            // Under --posix, comparisons between strnums check whether both sides look like numbers
+            // at runtime.
+            Binop(op @ (LT | GT | LTE | GTE | EQ))
+                if conv_tys[0] == Ty::Str && self.is_strnum(&args[0]) && self.is_strnum(&args[1]) =>
+            {
+                if res_reg != UNUSED {
+                    let cmp_reg = self.regs.stats.reg_of_ty(Ty::Int);
+                    let zero_reg = self.regs.stats.reg_of_ty(Ty::Int);
+                    self.pushl(LL::StrNumCmp(cmp_reg.into(), conv_regs[0].into(), conv_regs[1].into()));
+                    self.pushl(LL::StoreConstInt(zero_reg.into(), 0));
+                    let (res, cmp, zero) = (res_reg.into(), cmp_reg.into(), zero_reg.into());
+                    self.pushl(match op {
+                        LT => LL::LTInt(res, cmp, zero),
+                        GT => LL::GTInt(res, cmp, zero),
+                        LTE => LL::LTEInt(res, cmp, zero),
+                        GTE => LL::GTEInt(res, cmp, zero),
+                        _ => LL::EQInt(res, cmp, zero),
+                    });
+                }
+            }
+            // As are comparisons between a strnum and a number, which compare as strings when the
+            // strnum does not look like a number.
+            Binop(op @ (LT | GT | LTE | GTE | EQ))
+                if conv_tys[0] == Ty::Float
+                    && (self.is_strnum(&args[0]) || self.is_strnum(&args[1])) =>
+            {
+                if res_reg != UNUSED {
+                    // Put the strnum on the left, flipping the comparison if it was on the right.
+                    let (op, s, n) = if self.is_strnum(&args[0]) {
+                        (*op, &args[0], conv_regs[1])
+                    } else {
+                        let flipped = match op {
+                            LT => GT,
+                            GT => LT,
+                            LTE => GTE,
+                            GTE => LTE,
+                            _ => EQ,
+                        };
+                        (flipped, &args[1], conv_regs[0])
+                    };
+                    let (s_reg, s_ty) = self.get_reg(s)?;
+                    let str_reg = if s_ty == Ty::Str {
+                        s_reg
+                    } else {
+                        let str_reg = self.regs.stats.reg_of_ty(Ty::Str);
+                        self.convert(str_reg, Ty::Str, s_reg, s_ty)?;
+                        str_reg
+                    };
+                    let cmp_reg = self.regs.stats.reg_of_ty(Ty::Int);
+                    let zero_reg = self.regs.stats.reg_of_ty(Ty::Int);
+                    self.pushl(LL::StrNumCmpFloat(cmp_reg.into(), str_reg.into(), n.into()));
+                    self.pushl(LL::StoreConstInt(zero_reg.into(), 0));
+                    let (res, cmp, zero) = (res_reg.into(), cmp_reg.into(), zero_reg.into());
+                    self.pushl(match op {
+                        LT => LL::LTInt(res, cmp, zero),
+                        GT => LL::GTInt(res, cmp, zero),
+                        LTE => LL::LTEInt(res, cmp, zero),
+                        GTE => LL::GTEInt(res, cmp, zero),
+                        _ => LL::EQInt(res, cmp, zero),
+                    });
+                }
+            }
             Binop(LT) => gen_op!(LT, [Float, LTFloat], [Int, LTInt], [Str, LTStr]),
             Binop(GT) => gen_op!(GT, [Float, GTFloat], [Int, GTInt], [Str, GTStr]),
             Binop(LTE) => gen_op!(LTE, [Float, LTEFloat], [Int, LTEInt], [Str, LTEStr]),
@@ -1413,6 +1756,58 @@ impl<'a, 'b> View<'a, 'b> {
                     }
                 }
             }
+            Bignum(op) => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::Bignum(
+                        *op,
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                        conv_regs[2].into(),
+                    ))
+                }
+            }
+            BignumCmp => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::BignumCmp(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
+            MatchSet => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::MatchSet(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
+            DecimalCmp => {
+                // Malformed operands are an error, so this runs even if the result is unused.
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
+                }
+                self.pushl(LL::DecimalCmp(
+                    res_reg.into(),
+                    conv_regs[0].into(),
+                    conv_regs[1].into(),
+                ))
+            }
+            Decimal(op) => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::Decimal(
+                        *op,
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                        conv_regs[2].into(),
+                        conv_regs[3].into(),
+                    ))
+                }
+            }
             Match => gen_op!(Match, [Str, Match]),
             SubstrIndex => gen_op!(SubstrIndex, [Str, SubstrIndex]),
             SubstrLastIndex => gen_op!(SubstrLastIndex, [Str, SubstrLastIndex]),
@@ -1424,7 +1819,9 @@ impl<'a, 'b> View<'a, 'b> {
                         | Ty::MapIntFloat
                         | Ty::MapStrInt
                         | Ty::MapStrStr
-                        | Ty::MapStrFloat => self.pushl(LL::Contains {
+                        | Ty::MapStrFloat
+                        | Ty::MapIntMap
+                        | Ty::MapStrMap => self.pushl(LL::Contains {
                             map_ty: conv_tys[0],
                             dst: res_reg,
                             map: conv_regs[0],
@@ -1436,8 +1833,24 @@ impl<'a, 'b> View<'a, 'b> {
                     }
                 }
             }
+            Subarray => {
+                // Like any other lookup, this inserts an (empty) subarray if one is not present.
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
+                }
+                if !conv_tys[0].is_nested_array() {
+                    return err!("unexpected non-nested map type for Subarray: {:?}", conv_tys[0]);
+                }
+                self.pushl(LL::Lookup {
+                    map_ty: conv_tys[0],
+                    dst: res_reg,
+                    map: conv_regs[0],
+                    key: conv_regs[1],
+                })
+            }
             UpdateUsedFields => self.pushl(LL::UpdateUsedFields()),
             SetFI => self.pushl(LL::SetFI(conv_regs[0].into(), conv_regs[1].into())),
+            ProfileMark => unreachable!("profile marks are handled above"),
             System => {
                 if res_reg == UNUSED {
                     res_reg = self.regs.stats.reg_of_ty(res_ty);
@@ -1479,6 +1892,14 @@ impl<'a, 'b> View<'a, 'b> {
                 conv_regs[0].into(),
                 /*is_file=*/ false,
             )),
+            ReadErrCoproc => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::ReadErrCoproc(res_reg.into(), conv_regs[0].into()))
+                }
+            }
+            NextlineCoproc => {
+                self.pushl(LL::NextLineCoproc(res_reg.into(), conv_regs[0].into()))
+            }
             ReadErrStdin => {
                 if res_reg != UNUSED {
                     self.pushl(LL::ReadErrStdin(res_reg.into()))
@@ -1638,7 +2059,9 @@ impl<'a, 'b> View<'a, 'b> {
                         | Ty::MapIntFloat
                         | Ty::MapStrInt
                         | Ty::MapStrStr
-                        | Ty::MapStrFloat => LL::Len {
+                        | Ty::MapStrFloat
+                        | Ty::MapIntMap
+                        | Ty::MapStrMap => LL::Len {
                             map_ty: conv_tys[0],
                             map: conv_regs[0],
                             dst: res_reg,
@@ -1654,7 +2077,9 @@ impl<'a, 'b> View<'a, 'b> {
                 | Ty::MapIntFloat
                 | Ty::MapStrInt
                 | Ty::MapStrStr
-                | Ty::MapStrFloat => self.pushl(LL::Delete {
+                | Ty::MapStrFloat
+                | Ty::MapIntMap
+                | Ty::MapStrMap => self.pushl(LL::Delete {
                     map_ty: conv_tys[0],
                     map: conv_regs[0],
                     key: conv_regs[1],
@@ -1666,6 +2091,7 @@ impl<'a, 'b> View<'a, 'b> {
                     res_reg = self.regs.stats.reg_of_ty(res_ty);
                 }
                 if !conv_tys[0].is_array()
+                    || conv_tys[0].is_nested_array()
                     || conv_tys[0].val()? != res_ty
                     || conv_tys[0].key()? != conv_tys[1]
                 {
@@ -1709,7 +2135,7 @@ impl<'a, 'b> View<'a, 'b> {
                 }
             }
             Close => {
-                self.pushl(LL::Close(conv_regs[0].into()));
+                self.pushl(LL::Close(conv_regs[0].into(), conv_regs[1].into()));
                 assert_eq!(res_ty, Ty::Str);
                 if res_reg != UNUSED {
                     self.pushl(LL::StoreConstStr(res_reg.into(), Default::default()));
@@ -2617,7 +3043,8 @@ impl<'a, 'b> View<'a, 'b> {
             TypeOfVariable => {
                 if res_reg != UNUSED {
                     match conv_tys[0] {
-                        MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+                        MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr
+                        | MapIntMap | MapStrMap => {
                             self.pushl(LL::TypeOfArray(
                                 res_reg.into(),
                             ))
@@ -2759,9 +3186,12 @@ impl<'a, 'b> View<'a, 'b> {
                         Ty::MapIntFloat => {
                             self.pushl(LL::MapIntFloatSum(res_reg.into(), conv_regs[0].into()))
                         }
+                        Ty::MapIntStr => {
+                            self.pushl(LL::MapIntStrSum(res_reg.into(), conv_regs[0].into()))
+                        }
                         _ => {
                             return err!(
-                                "_sum only support IntIntMap/IntFloatMap called with malformed types: {:?} => {:?}",
+                                "_sum only support IntIntMap/IntFloatMap/IntStrMap called with malformed types: {:?} => {:?}",
                                 &conv_tys[..],
                                 dst_ty
                              );
@@ -2860,6 +3290,9 @@ impl<'a, 'b> View<'a, 'b> {
                 }
             }
         };
+        if timed.is_some() {
+            self.pushl(LL::ProfileExit());
+        }
         self.convert(dst_reg, dst_ty, res_reg, res_ty)
     }
 
@@ -2985,7 +3418,8 @@ impl<'a, 'b> View<'a, 'b> {
                 }
                 use Ty::*;
                 match arr_ty {
-                    MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr => {
+                    MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat | MapStrStr
+                    | MapIntMap | MapStrMap => {
                         self.pushl(LL::IterBegin {
                             map_ty: arr_ty,
                             dst: dst_reg,
@@ -3071,6 +3505,13 @@ impl<'a, 'b> View<'a, 'b> {
                             val: v_reg,
                         })
                     }
+                    MapIntMap | MapStrMap => {
+                        return err!(
+                            "in stmt {:?} attempting to assign to an array of arrays {:?}",
+                            stmt,
+                            a_ty
+                        );
+                    }
                     Null | Int | Float | Str | IterInt | IterStr => {
                         return err!(
                             "in stmt {:?} computed type is non-map type {:?}",
@@ -3101,6 +3542,9 @@ impl<'a, 'b> View<'a, 'b> {
                 let (mut v_reg, v_ty) = self.get_reg(v)?;
                 let ret_ty = self.func_info[self.frame.cur_ident as usize].ret_ty;
                 v_reg = self.ensure_ty(v_reg, v_ty, ret_ty)?;
+                if self.profile_region.is_some() {
+                    self.pushl(LL::ProfileExit());
+                }
                 self.pushr(HighLevel::Ret(v_reg, ret_ty));
                 self.stream.exit = true;
             }
@@ -3109,6 +3553,13 @@ impl<'a, 'b> View<'a, 'b> {
                 let mut arg_regs = Vec::with_capacity(args.len());
                 for a in args {
                     let (a_reg, a_ty) = self.get_reg(a)?;
+                    if a_ty == Ty::Float && a_reg != UNUSED {
+                        // print formats numbers with OFMT rather than CONVFMT.
+                        let s_reg = self.regs.stats.reg_of_ty(Ty::Str);
+                        self.pushl(LL::FloatToStrOfmt(s_reg.into(), a_reg.into()));
+                        arg_regs.push(s_reg.into());
+                        continue;
+                    }
                     arg_regs.push(self.ensure_ty(a_reg, a_ty, Ty::Str)?.into());
                 }
                 let out_reg = if let Some((out, append)) = out {
@@ -20,7 +20,7 @@ pub(crate) fn load_slot_instr<'a>(reg: NumTy, ty: Ty, slot: usize) -> Result<Opt
     use Ty::*;
     match ty {
         Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat
-        | MapStrStr => Ok(Some(LL::LoadSlot {
+        | MapStrStr | MapIntMap | MapStrMap => Ok(Some(LL::LoadSlot {
             ty,
             dst: reg,
             slot: slot as _,
@@ -34,7 +34,7 @@ pub(crate) fn store_slot_instr<'a>(reg: NumTy, ty: Ty, slot: usize) -> Result<Op
     use Ty::*;
     match ty {
         Int | Float | Str | MapIntInt | MapIntFloat | MapIntStr | MapStrInt | MapStrFloat
-        | MapStrStr => Ok(Some(LL::StoreSlot {
+        | MapStrStr | MapIntMap | MapStrMap => Ok(Some(LL::StoreSlot {
             ty,
             src: reg,
             slot: slot as _,
@@ -181,7 +181,7 @@ pub(crate) mod boilerplate {
                     f(Key::Reg(*reg, *ty), Some(Key::Reg(*pred_reg, *ty)));
                 }
             }
-            DropIter(..) => {}
+            DropIter(..) | Loc(_) => {}
         }
     }
 
@@ -394,6 +394,9 @@ pub(crate) mod boilerplate {
             MapIntFloatSum(dst, arr) => {
                 f(dst.into(), Some(arr.into()));
             }
+            MapIntStrSum(dst, arr) => {
+                f(dst.into(), Some(arr.into()));
+            }
             MapIntIntMean(dst, arr) => {
                 f(dst.into(), Some(arr.into()));
             }
@@ -482,6 +485,21 @@ pub(crate) mod boilerplate {
                 f(dst.into(), Some(text1.into()));
                 f(dst.into(), Some(text2.into()));
             }
+            Bignum(_, dst, x, y, prec) => {
+                f(dst.into(), Some(x.into()));
+                f(dst.into(), Some(y.into()));
+                f(dst.into(), Some(prec.into()));
+            }
+            BignumCmp(dst, x, y) | DecimalCmp(dst, x, y) => {
+                f(dst.into(), Some(x.into()));
+                f(dst.into(), Some(y.into()));
+            }
+            Decimal(_, dst, x, y, scale, mode) => {
+                f(dst.into(), Some(x.into()));
+                f(dst.into(), Some(y.into()));
+                f(dst.into(), Some(scale.into()));
+                f(dst.into(), Some(mode.into()));
+            }
             Mask(dst, text) => {
                 f(dst.into(), Some(text.into()));
             }
@@ -663,7 +681,7 @@ pub(crate) mod boilerplate {
                 f(dst.into(), Some(text.into()));
             }
             IntToFloat(dst, src) => f(dst.into(), Some(src.into())),
-            FloatToStr(dst, src) => f(dst.into(), Some(src.into())),
+            FloatToStr(dst, src) | FloatToStrOfmt(dst, src) => f(dst.into(), Some(src.into())),
             FloatToInt(dst, src) => f(dst.into(), Some(src.into())),
             StrToFloat(dst, src) => f(dst.into(), Some(src.into())),
             Strlen(dst, s) => f(dst.into(), Some(s.into())),
@@ -681,6 +699,7 @@ pub(crate) mod boilerplate {
             | MulInt(dst, x, y)
             | MinusInt(dst, x, y)
             | ModInt(dst, x, y)
+            | CheckedInt(_, dst, x, y)
             | Int2(_, dst, x, y) => {
                 f(dst.into(), Some(x.into()));
                 f(dst.into(), Some(y.into()));
@@ -691,6 +710,7 @@ pub(crate) mod boilerplate {
             | ModFloat(dst, x, y)
             | Div(dst, x, y)
             | Pow(dst, x, y)
+            | CheckedFloat(_, dst, x, y)
             | Float2(_, dst, x, y) => {
                 f(dst.into(), Some(x.into()));
                 f(dst.into(), Some(y.into()));
@@ -714,7 +734,7 @@ pub(crate) mod boilerplate {
             // user-input. That is certainly true today, but any kind of dynamic simplification or
             // inlining could change that.
             MatchConst(dst, x, _) | IsMatchConst(dst, x, _) => f(dst.into(), Some(x.into())),
-            IsMatch(dst, x, y) | Match(dst, x, y)
+            IsMatch(dst, x, y) | MatchSet(dst, x, y) | Match(dst, x, y)
             | SubstrIndex(dst, x, y) | SubstrLastIndex(dst, x, y) => {
                 f(dst.into(), Some(x.into()));
                 f(dst.into(), Some(y.into()));
@@ -768,7 +788,12 @@ pub(crate) mod boilerplate {
             | GTStr(dst, x, y)
             | LTEStr(dst, x, y)
             | GTEStr(dst, x, y)
-            | EQStr(dst, x, y) => {
+            | EQStr(dst, x, y)
+            | StrNumCmp(dst, x, y) => {
+                f(dst.into(), Some(x.into()));
+                f(dst.into(), Some(y.into()));
+            }
+            StrNumCmpFloat(dst, x, y) => {
                 f(dst.into(), Some(x.into()));
                 f(dst.into(), Some(y.into()));
             }
@@ -787,6 +812,8 @@ pub(crate) mod boilerplate {
             }
             ReadErr(dst, _cmd, _) => f(dst.into(), None),
             NextLine(dst, _cmd, _) => f(dst.into(), None),
+            ReadErrCoproc(dst, _cmd) => f(dst.into(), None),
+            NextLineCoproc(dst, _cmd) => f(dst.into(), None),
             ReadErrStdin(dst) => f(dst.into(), None),
             NextLineStdin(dst) => f(dst.into(), None),
             SplitInt(dst1, src1, dst2, src2) => {
@@ -821,7 +848,17 @@ pub(crate) mod boilerplate {
                 f(Key::MapKey(*map, *map_ty), Some(Key::Reg(*key, map_ty.key().unwrap())));
                 // a null value will be inserted as a value into the map
                 f(Key::MapVal(*map, *map_ty), None);
-                f(Key::Reg(*dst, map_ty.val().unwrap()), Some(Key::MapVal(*map, *map_ty)))
+                let val_ty = map_ty.val().unwrap();
+                if map_ty.is_nested_array() {
+                    // The result aliases the inner array; treat it like a `Mov` to and from the
+                    // values of the outer array.
+                    f(Key::MapKey(*dst, val_ty), Some(Key::MapVal(*map, *map_ty)));
+                    f(Key::MapVal(*dst, val_ty), Some(Key::MapVal(*map, *map_ty)));
+                    f(Key::MapVal(*map, *map_ty), Some(Key::MapKey(*dst, val_ty)));
+                    f(Key::MapVal(*map, *map_ty), Some(Key::MapVal(*dst, val_ty)));
+                } else {
+                    f(Key::Reg(*dst, val_ty), Some(Key::MapVal(*map, *map_ty)))
+                }
             }
             Len { map_ty, dst, map } => f(Key::Reg(*dst, Ty::Int), Some(Key::Reg(*map, *map_ty))),
             Store { map_ty, map, key, val } => {
@@ -883,6 +920,9 @@ pub(crate) mod boilerplate {
             | Clear { .. }
             | UpdateUsedFields()
             | SetFI(..)
+            | ProfileMark(_)
+            | ProfileEnter(_)
+            | ProfileExit()
             | PrintAll { .. }
             | Contains { .. } // 0 or 1
             | IterHasNext { .. }
@@ -895,7 +935,7 @@ pub(crate) mod boilerplate {
             | Call(_)
             | Ret
             | Printf { .. }
-            | Close(_)
+            | Close(_, _)
             | NextLineStdinFused()
             | NextFile()
             | SetColumn(_, _)
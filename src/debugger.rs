@@ -0,0 +1,919 @@
+//! An interactive step debugger for the bytecode interpreter, enabled with `--debug`.
+//!
+//! The interpreter calls `Debugger::before` ahead of every instruction. The compiler records the
+//! source location of the statement that generated each run of instructions (`Interp::locs`), so an
+//! instruction starts a statement if it is the first one generated for a new statement location.
+//! Calls and returns show up as changes in the depth of the interpreter's call stack.
+//!
+//! Function parameters, and globals that only the main program refers to, are in SSA form: one AWK
+//! variable is spread over several registers, one per assignment. Registers are never reused, so
+//! the debugger notes which registers each instruction touches and takes the most recently touched
+//! register of a variable to hold its current value. Instructions list their destination first, and
+//! any other register of the variable that they read holds the current value too, so this is exact
+//! for straight-line code. Where control flow merges, the value is right but may come from the
+//! register of a different merge point.
+use std::hash::Hash;
+use std::io::{BufRead, Write};
+
+use hashbrown::HashMap;
+use smallvec::SmallVec;
+
+use crate::builtins::Variable;
+use crate::bytecode::{Get, Instr, Reg};
+use crate::common::{NumTy, Result};
+use crate::compile::Ty;
+use crate::include::Source;
+use crate::interp::Interp;
+use crate::lexer::Loc;
+use crate::runtime::{self, Float, Int, IntMap, Line, LineReader, SharedMap, Str, StrMap};
+
+const HELP: &str = "\
+step, s            run to the next statement, stepping into function calls
+next, n            run to the next statement, stepping over function calls
+finish             run until the current function returns
+continue, c        run until a breakpoint or watchpoint is hit
+break, b [WHERE]   stop at WHERE: LINE, FILE:LINE or a function name (default: the current line)
+watch, w VAR       stop after a statement changes the value of VAR
+delete, d [N]      delete breakpoint or watchpoint N (default: all of them)
+info, i            list breakpoints and watchpoints
+print, p ITEM...   print variables, fields ($0, $1, ...) and builtin variables (NF, NR, ...)
+globals            print all global variables
+locals             print the parameters of the current function
+record             print $0, NF, NR, FNR and FILENAME
+backtrace, bt      print the active function calls
+list, l [LINE]     print the source around the current statement, or around LINE
+help, h            print this message
+quit, q            stop the program (as does the end of input)
+An empty line repeats the last step, next, finish or continue.";
+
+/// A named variable in a function, as reported by the compiler.
+pub(crate) struct Local {
+    pub name: String,
+    /// Whether this is a function parameter, rather than a global that only the main program
+    /// refers to.
+    pub param: bool,
+    /// The SSA subscript of this version of the variable.
+    pub sub: NumTy,
+    pub reg: NumTy,
+    pub ty: Ty,
+}
+
+/// The named variables of a program, and the registers holding them.
+#[derive(Default)]
+pub(crate) struct Symbols {
+    /// Indexed by (bytecode-level) function.
+    pub funcs: Vec<FuncSymbols>,
+    pub globals: Vec<(String, NumTy, Ty)>,
+}
+
+pub(crate) struct FuncSymbols {
+    name: String,
+    vars: Vec<Var>,
+    // The variable, and the version of it, held in each register.
+    regs: HashMap<(NumTy, Ty), (usize, usize)>,
+}
+
+struct Var {
+    name: String,
+    param: bool,
+    // The register holding each version of the variable, in the order they were introduced.
+    versions: Vec<(NumTy, Ty)>,
+}
+
+impl FuncSymbols {
+    pub(crate) fn new(name: String, mut locals: Vec<Local>) -> FuncSymbols {
+        locals.sort_by(|a, b| (&a.name, a.sub).cmp(&(&b.name, b.sub)));
+        let mut vars: Vec<Var> = Vec::new();
+        let mut regs = HashMap::new();
+        for local in locals {
+            if !matches!(vars.last(), Some(v) if v.name == local.name) {
+                vars.push(Var {
+                    name: local.name,
+                    param: local.param,
+                    versions: Vec::new(),
+                });
+            }
+            let ix = vars.len() - 1;
+            let var = &mut vars[ix];
+            regs.insert((local.reg, local.ty), (ix, var.versions.len()));
+            var.versions.push((local.reg, local.ty));
+        }
+        FuncSymbols { name, vars, regs }
+    }
+}
+
+// How far to run before stopping again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    // Stop at the next statement.
+    Step,
+    // Stop at the next statement at or above the given call depth.
+    Next(usize),
+    // Stop at the next statement above the given call depth.
+    Finish(usize),
+    // Only stop at breakpoints and watchpoints.
+    Continue,
+}
+
+enum Point {
+    // A line of the expanded program.
+    Line(usize),
+    Function(String),
+    Watch { name: String, last: Option<String> },
+}
+
+struct Frame {
+    func: usize,
+    // The current version of each variable in `FuncSymbols::vars`.
+    current: Vec<usize>,
+    // The location of the code most recently entered in this frame.
+    last: Option<Loc>,
+    // The statement most recently started in this frame.
+    stmt: Option<Loc>,
+}
+
+enum Reply {
+    Show(String),
+    Resume(Mode),
+    Quit,
+}
+
+type Reported<T> = std::result::Result<T, String>;
+
+pub(crate) struct Debugger {
+    src: Source,
+    syms: Symbols,
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    mode: Mode,
+    points: Vec<(usize, Point)>,
+    next_point: usize,
+    frames: Vec<Frame>,
+    last_command: String,
+    started: bool,
+    quit: bool,
+}
+
+impl Debugger {
+    /// A debugger for the program `src`, reading commands from `input` and writing to `output`. It
+    /// stops before the first statement.
+    pub(crate) fn new(
+        src: Source,
+        input: Box<dyn BufRead + Send>,
+        output: Box<dyn Write + Send>,
+    ) -> Debugger {
+        Debugger {
+            src,
+            syms: Default::default(),
+            input,
+            output,
+            mode: Mode::Step,
+            points: Vec::new(),
+            next_point: 1,
+            frames: Vec::new(),
+            last_command: String::new(),
+            started: false,
+            quit: false,
+        }
+    }
+
+    pub(crate) fn set_symbols(&mut self, syms: Symbols) {
+        self.syms = syms;
+    }
+
+    /// Called before the interpreter executes instruction `ix` of function `func`. Returns an exit
+    /// code if the program should stop.
+    pub(crate) fn before<LR: LineReader>(
+        &mut self,
+        interp: &mut Interp<'_, LR>,
+        (func, ix): (usize, usize),
+    ) -> Result<Option<i32>> {
+        if self.quit {
+            return Ok(Some(0));
+        }
+        let depth = interp.stack.len();
+        self.frames.truncate(depth + 1);
+        if matches!(self.frames.get(depth), Some(f) if f.func != func) {
+            self.frames.pop();
+        }
+        while self.frames.len() <= depth {
+            let f = interp
+                .stack
+                .get(self.frames.len())
+                .map_or(func, |(f, _)| *f);
+            self.frames.push(Frame {
+                func: f,
+                current: vec![0; self.syms.funcs[f].vars.len()],
+                last: None,
+                stmt: None,
+            });
+        }
+
+        let instr = &interp.instrs()[func][ix];
+        // Pushes and pops save and restore variables around calls without changing their values.
+        if !matches!(instr, Instr::Push(..) | Instr::Pop(..)) {
+            let syms = &self.syms.funcs[func];
+            let frame = &mut self.frames[depth];
+            let mut touched = SmallVec::<[(NumTy, Ty); 4]>::new();
+            instr.accum(|reg, ty| touched.push((reg, ty)));
+            // Visit destinations last, so that they count as the most recently touched.
+            for reg in touched.iter().rev() {
+                if let Some((var, version)) = syms.regs.get(reg) {
+                    frame.current[*var] = *version;
+                }
+            }
+        }
+
+        let locs = &interp.locs[func];
+        let cur = match locs.partition_point(|(off, _)| *off <= ix) {
+            n if n > 0 && locs[n - 1].0 == ix => locs[n - 1].1.map(|l| l.stmt),
+            _ => return Ok(None),
+        };
+        let frame = &mut self.frames[depth];
+        if cur == frame.last {
+            // Statements spanning several basic blocks have their location recorded for each.
+            return Ok(None);
+        }
+        let prev = std::mem::replace(&mut frame.last, cur);
+        match cur {
+            Some(loc) => {
+                let first = frame.stmt.replace(loc).is_none();
+                self.statement(interp, loc, prev, first)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Decide whether to stop at the statement starting at `loc`. `prev` is the location of the code
+    // that ran before it in the same frame, and `first` is set for the first statement of a call.
+    fn statement<LR: LineReader>(
+        &mut self,
+        interp: &mut Interp<'_, LR>,
+        loc: Loc,
+        prev: Option<Loc>,
+        first: bool,
+    ) -> Result<Option<i32>> {
+        let depth = self.frames.len() - 1;
+        let func = &self.syms.funcs[self.frames[depth].func].name;
+        let mut reasons = Vec::new();
+        for i in 0..self.points.len() {
+            let (id, point) = &self.points[i];
+            match point {
+                Point::Line(line) => {
+                    if loc.line == *line && prev.map(|p| p.line) != Some(*line) {
+                        reasons.push(format!("Breakpoint {}", id));
+                    }
+                }
+                Point::Function(name) => {
+                    if first && name == func {
+                        reasons.push(format!("Breakpoint {}", id));
+                    }
+                }
+                Point::Watch { name, .. } => {
+                    let id = *id;
+                    let name = name.clone();
+                    let cur = self.value(interp, &name).ok();
+                    if let Point::Watch { last, .. } = &mut self.points[i].1 {
+                        if let (Some(old), Some(new)) = (last.as_ref(), cur.as_ref()) {
+                            if old != new {
+                                reasons.push(format!(
+                                    "Watchpoint {}: {} changed from {} to {}",
+                                    id, name, old, new
+                                ));
+                            }
+                        }
+                        if cur.is_some() {
+                            *last = cur;
+                        }
+                    }
+                }
+            }
+        }
+        let stop = !reasons.is_empty()
+            || match self.mode {
+                Mode::Step => true,
+                Mode::Next(d) => depth <= d,
+                Mode::Finish(d) => depth < d,
+                Mode::Continue => false,
+            };
+        if !stop {
+            return Ok(None);
+        }
+        // Show anything the program printed so far before prompting.
+        let _ = interp.core.write_files.flush_stdout();
+        if !self.started {
+            self.started = true;
+            self.say("zawk debugger, type help for a list of commands");
+        }
+        for reason in reasons {
+            self.say(reason);
+        }
+        let here = self.describe(loc);
+        self.say(here);
+        self.prompt(interp)
+    }
+
+    fn prompt<LR: LineReader>(&mut self, interp: &mut Interp<'_, LR>) -> Result<Option<i32>> {
+        loop {
+            let _ = write!(self.output, "(zawk) ");
+            let _ = self.output.flush();
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) => {
+                    self.say("");
+                    self.quit = true;
+                    return Ok(Some(0));
+                }
+                Ok(_) => {}
+                Err(e) => return err!("failed to read debugger command: {}", e),
+            }
+            let mut line = line.trim().to_string();
+            if line.is_empty() {
+                line = self.last_command.clone();
+            }
+            let (cmd, arg) = line
+                .split_once(char::is_whitespace)
+                .unwrap_or((line.as_str(), ""));
+            match self.command(interp, cmd, arg.trim()) {
+                Ok(Reply::Show(msg)) => self.say(msg),
+                Ok(Reply::Resume(mode)) => {
+                    self.last_command = line.clone();
+                    self.mode = mode;
+                    return Ok(None);
+                }
+                Ok(Reply::Quit) => {
+                    self.quit = true;
+                    return Ok(Some(0));
+                }
+                Err(msg) => self.say(msg),
+            }
+        }
+    }
+
+    fn command<LR: LineReader>(
+        &mut self,
+        interp: &mut Interp<'_, LR>,
+        cmd: &str,
+        arg: &str,
+    ) -> Reported<Reply> {
+        let depth = self.frames.len() - 1;
+        Ok(match cmd {
+            "" => Reply::Show(String::new()),
+            "s" | "step" => Reply::Resume(Mode::Step),
+            "n" | "next" => Reply::Resume(Mode::Next(depth)),
+            "finish" => {
+                if depth == 0 {
+                    return Err(String::from("not inside a function call"));
+                }
+                Reply::Resume(Mode::Finish(depth))
+            }
+            "c" | "continue" => Reply::Resume(Mode::Continue),
+            "b" | "break" => Reply::Show(self.add_breakpoint(interp, arg)?),
+            "w" | "watch" => Reply::Show(self.add_watchpoint(interp, arg)?),
+            "d" | "delete" => Reply::Show(self.delete(arg)?),
+            "i" | "info" => Reply::Show(self.info()),
+            "p" | "print" => {
+                if arg.is_empty() {
+                    return Err(String::from("usage: print ITEM..."));
+                }
+                let mut lines = Vec::new();
+                for item in arg.split_whitespace() {
+                    lines.push(format!("{} = {}", item, self.value(interp, item)?));
+                }
+                Reply::Show(lines.join("\n"))
+            }
+            "globals" => Reply::Show(self.globals(interp)),
+            "locals" => Reply::Show(self.locals(interp)),
+            "record" => {
+                let mut lines = Vec::new();
+                for item in ["$0", "NF", "NR", "FNR", "FILENAME"] {
+                    lines.push(format!("{} = {}", item, self.value(interp, item)?));
+                }
+                Reply::Show(lines.join("\n"))
+            }
+            "bt" | "backtrace" => Reply::Show(self.backtrace()),
+            "l" | "list" => Reply::Show(self.list(arg)?),
+            "h" | "help" => Reply::Show(String::from(HELP)),
+            "q" | "quit" => Reply::Quit,
+            _ => {
+                return Err(format!(
+                    "unknown command {}, type help for a list of commands",
+                    cmd
+                ))
+            }
+        })
+    }
+
+    fn say(&mut self, msg: impl std::fmt::Display) {
+        let _ = writeln!(self.output, "{}", msg);
+    }
+
+    // `func at file:line` followed by the source line.
+    fn describe(&self, loc: Loc) -> String {
+        let func = &self.syms.funcs[self.frames[self.frames.len() - 1].func].name;
+        let (file, line) = self.src.locate(loc.line);
+        let text = self.src.text.lines().nth(loc.line).unwrap_or("");
+        format!("{} at {}:{}\n{}\t{}", func, file, line + 1, line + 1, text)
+    }
+
+    // Parse LINE (in the main file) or FILE:LINE into a line of the expanded program. Returns None
+    // if `spec` is not of that form.
+    fn line(&self, spec: &str) -> Option<Reported<usize>> {
+        let (file, line) = spec
+            .rsplit_once(':')
+            .unwrap_or((self.src.main_file(), spec));
+        let n: usize = line.parse().ok()?;
+        Some(
+            n.checked_sub(1)
+                .and_then(|l| self.src.expanded_line(file, l))
+                .ok_or_else(|| format!("no line {} in {}", n, file)),
+        )
+    }
+
+    fn add_breakpoint<LR: LineReader>(
+        &mut self,
+        interp: &Interp<'_, LR>,
+        arg: &str,
+    ) -> Reported<String> {
+        let point = if arg.is_empty() {
+            match self.frames.last().and_then(|f| f.stmt) {
+                Some(loc) => Point::Line(loc.line),
+                None => return Err(String::from("no current statement")),
+            }
+        } else if let Some(line) = self.line(arg) {
+            Point::Line(line?)
+        } else if self.syms.funcs.iter().any(|f| f.name == arg) {
+            Point::Function(arg.to_string())
+        } else {
+            return Err(format!("no function named {}", arg));
+        };
+        let desc = match &point {
+            Point::Line(line) => {
+                let starts = interp
+                    .locs
+                    .iter()
+                    .flatten()
+                    .any(|(_, loc)| loc.map(|l| l.stmt.line) == Some(*line));
+                if !starts {
+                    return Err(format!("no statement starts on line {}", arg));
+                }
+                let (file, l) = self.src.locate(*line);
+                format!("{}:{}", file, l + 1)
+            }
+            _ => format!("function {}", arg),
+        };
+        Ok(format!("Breakpoint {} at {}", self.add(point), desc))
+    }
+
+    fn add_watchpoint<LR: LineReader>(
+        &mut self,
+        interp: &mut Interp<'_, LR>,
+        name: &str,
+    ) -> Reported<String> {
+        if name.is_empty() {
+            return Err(String::from("usage: watch VAR"));
+        }
+        let known = self.syms.globals.iter().any(|(n, ..)| n == name)
+            || self
+                .syms
+                .funcs
+                .iter()
+                .any(|f| f.vars.iter().any(|v| v.name == name));
+        if !known {
+            return Err(format!("no variable named {}", name));
+        }
+        let last = self.value(interp, name).ok();
+        let id = self.add(Point::Watch {
+            name: name.to_string(),
+            last,
+        });
+        Ok(format!("Watchpoint {}: {}", id, name))
+    }
+
+    fn add(&mut self, point: Point) -> usize {
+        let id = self.next_point;
+        self.next_point += 1;
+        self.points.push((id, point));
+        id
+    }
+
+    fn delete(&mut self, arg: &str) -> Reported<String> {
+        if arg.is_empty() {
+            self.points.clear();
+            return Ok(String::from("Deleted all breakpoints and watchpoints"));
+        }
+        let id: usize = arg
+            .parse()
+            .map_err(|_| format!("invalid breakpoint number {}", arg))?;
+        let before = self.points.len();
+        self.points.retain(|(i, _)| *i != id);
+        if self.points.len() == before {
+            return Err(format!("no breakpoint number {}", id));
+        }
+        Ok(format!("Deleted {}", id))
+    }
+
+    fn info(&self) -> String {
+        if self.points.is_empty() {
+            return String::from("No breakpoints or watchpoints.");
+        }
+        let lines: Vec<String> = self
+            .points
+            .iter()
+            .map(|(id, point)| match point {
+                Point::Line(line) => {
+                    let (file, l) = self.src.locate(*line);
+                    format!("{}\tbreakpoint at {}:{}", id, file, l + 1)
+                }
+                Point::Function(name) => format!("{}\tbreakpoint at function {}", id, name),
+                Point::Watch { name, .. } => format!("{}\twatchpoint on {}", id, name),
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    fn backtrace(&self) -> String {
+        let lines: Vec<String> = self
+            .frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, frame)| {
+                let name = &self.syms.funcs[frame.func].name;
+                match frame.stmt {
+                    Some(loc) => {
+                        let (file, line) = self.src.locate(loc.line);
+                        format!("#{}  {} at {}:{}", i, name, file, line + 1)
+                    }
+                    None => format!("#{}  {}", i, name),
+                }
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    fn list(&self, arg: &str) -> Reported<String> {
+        let cur = self.frames.last().and_then(|f| f.stmt).map(|loc| loc.line);
+        let center = if arg.is_empty() {
+            cur.ok_or_else(|| String::from("no current statement"))?
+        } else {
+            self.line(arg)
+                .unwrap_or_else(|| Err(format!("invalid line {}", arg)))?
+        };
+        let lines: Vec<String> = self
+            .src
+            .text
+            .lines()
+            .enumerate()
+            .skip(center.saturating_sub(5))
+            .take(11)
+            .map(|(i, text)| {
+                let marker = if Some(i) == cur { "=>" } else { "" };
+                format!("{:>2} {:>4}\t{}", marker, self.src.locate(i).1 + 1, text)
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn globals<LR: LineReader>(&self, interp: &Interp<'_, LR>) -> String {
+        let mut vars: Vec<(&str, String)> = self
+            .syms
+            .globals
+            .iter()
+            .map(|(name, reg, ty)| (name.as_str(), render(interp, *reg, *ty)))
+            .collect();
+        if let Some(main) = self.frames.first() {
+            vars.extend(self.vars(interp, main, false));
+        }
+        if vars.is_empty() {
+            return String::from("No globals.");
+        }
+        vars.sort();
+        let lines: Vec<String> = vars
+            .into_iter()
+            .map(|(name, val)| format!("{} = {}", name, val))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn locals<LR: LineReader>(&self, interp: &Interp<'_, LR>) -> String {
+        let vars = match self.frames.last() {
+            Some(frame) => self.vars(interp, frame, true),
+            None => Vec::new(),
+        };
+        if vars.is_empty() {
+            return String::from("No locals.");
+        }
+        let lines: Vec<String> = vars
+            .into_iter()
+            .map(|(name, val)| format!("{} = {}", name, val))
+            .collect();
+        lines.join("\n")
+    }
+
+    // The current values of the parameters (or the other variables) of `frame`.
+    fn vars<LR: LineReader>(
+        &self,
+        interp: &Interp<'_, LR>,
+        frame: &Frame,
+        params: bool,
+    ) -> Vec<(&str, String)> {
+        let syms = &self.syms.funcs[frame.func];
+        syms.vars
+            .iter()
+            .zip(frame.current.iter())
+            .filter(|(var, _)| var.param == params)
+            .map(|(var, cur)| {
+                let (reg, ty) = var.versions[*cur];
+                (var.name.as_str(), render(interp, reg, ty))
+            })
+            .collect()
+    }
+
+    // Look up `name` in `frame`, returning the register holding its current value.
+    fn lookup(&self, frame: &Frame, name: &str, param: bool) -> Option<(NumTy, Ty)> {
+        let syms = &self.syms.funcs[frame.func];
+        let (var, cur) = syms
+            .vars
+            .iter()
+            .zip(frame.current.iter())
+            .find(|(var, _)| var.name == name && var.param == param)?;
+        Some(var.versions[*cur])
+    }
+
+    // The value of a variable, field or builtin variable.
+    fn value<LR: LineReader>(&self, interp: &mut Interp<'_, LR>, name: &str) -> Reported<String> {
+        if let Some(col) = name.strip_prefix('$') {
+            let col: Int = col.parse().map_err(|_| format!("invalid field {}", name))?;
+            let (mut regexes, vars) = interp.core.regexes_and_vars();
+            return interp
+                .line
+                .get_col(col, &vars.fs, &vars.ofs, &mut regexes)
+                .map(|s| s.show())
+                .map_err(|e| e.to_string());
+        }
+        if let Ok(var) = Variable::try_from(name) {
+            return builtin(interp, var).map_err(|e| e.to_string());
+        }
+        // Parameters of the current function shadow globals.
+        let found = self
+            .frames
+            .last()
+            .and_then(|f| self.lookup(f, name, true))
+            .or_else(|| {
+                self.syms
+                    .globals
+                    .iter()
+                    .find(|(n, ..)| n == name)
+                    .map(|(_, reg, ty)| (*reg, *ty))
+            })
+            .or_else(|| self.lookup(self.frames.first()?, name, false));
+        match found {
+            Some((reg, ty)) => Ok(render(interp, reg, ty)),
+            None => Err(format!("no variable named {} in the current scope", name)),
+        }
+    }
+}
+
+fn builtin<LR: LineReader>(interp: &mut Interp<'_, LR>, var: Variable) -> Result<String> {
+    if let Variable::NF = var {
+        let (mut regexes, vars) = interp.core.regexes_and_vars();
+        let nf = interp.line.nf(&vars.fs, &mut regexes)?;
+        return Ok(nf.to_string());
+    }
+    let vars = &interp.core.vars;
+    if let Ok(i) = vars.load_int(var) {
+        return Ok(i.show());
+    }
+    if let Ok(s) = vars.load_str(var) {
+        return Ok(s.show());
+    }
+    if let Ok(m) = vars.load_intmap(var) {
+        return Ok(m.show());
+    }
+    if let Ok(m) = vars.load_strmap(var) {
+        return Ok(m.show());
+    }
+    Ok(vars.load_strstrmap(var)?.show())
+}
+
+fn render<'a, LR: LineReader>(interp: &Interp<'a, LR>, reg: NumTy, ty: Ty) -> String {
+    macro_rules! show {
+        ($t:ty) => {
+            interp.get(Reg::<$t>::from(reg)).show()
+        };
+    }
+    match ty {
+        Ty::Int => show!(Int),
+        Ty::Float => show!(Float),
+        Ty::Str => show!(Str<'a>),
+        Ty::MapIntInt => show!(IntMap<Int>),
+        Ty::MapIntFloat => show!(IntMap<Float>),
+        Ty::MapIntStr => show!(IntMap<Str<'a>>),
+        Ty::MapStrInt => show!(StrMap<'a, Int>),
+        Ty::MapStrFloat => show!(StrMap<'a, Float>),
+        Ty::MapStrStr => show!(StrMap<'a, Str<'a>>),
+        Ty::MapIntMap => show!(IntMap<StrMap<'a, Str<'a>>>),
+        Ty::MapStrMap => show!(StrMap<'a, StrMap<'a, Str<'a>>>),
+        Ty::IterInt | Ty::IterStr | Ty::Null => String::from("\"\""),
+    }
+}
+
+// Runtime values as the debugger prints them: strings are quoted, so that they can be told apart
+// from numbers, and arrays are printed in order of their keys.
+trait Show {
+    fn show(&self) -> String;
+}
+
+impl Show for Int {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Show for Float {
+    fn show(&self) -> String {
+        runtime::convert::<Float, Str>(*self).to_string()
+    }
+}
+
+impl<'a> Show for Str<'a> {
+    fn show(&self) -> String {
+        format!("{:?}", self.to_string())
+    }
+}
+
+impl<K: Show + Hash + Eq, V: Show> Show for SharedMap<K, V> {
+    fn show(&self) -> String {
+        let mut entries: Vec<(String, String)> =
+            self.iter(|it| it.map(|(k, v)| (k.show(), v.show())).collect());
+        entries.sort_by(
+            |(a, _), (b, _)| match (a.parse::<Int>(), b.parse::<Int>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            },
+        );
+        let entries: Vec<String> = entries
+            .into_iter()
+            .map(|(k, v)| format!("{}: {}", k, v))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+    use crate::ast;
+    use crate::cfg::{self, Escaper};
+    use crate::common::Stage;
+    use crate::compile;
+    use crate::lexer;
+    use crate::parsing::syntax::ProgParser;
+    use crate::runtime::splitter::regex::RegexSplitter;
+    use crate::runtime::writers::testing::FakeFs;
+    use crate::runtime::CHUNK_SIZE;
+    use std::io::{self, Cursor};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const PROG: &str = r#"function add(a, b) {
+    return a + b
+}
+{
+    sum = add(sum, $2)
+    n++
+}
+END {
+    print sum, n
+}
+"#;
+
+    const INPUT: &str = "a 1\nb 2\nc 3\n";
+
+    // Run PROG on INPUT under the debugger, returning the debugger's output and the program's.
+    fn debug(commands: &str) -> (String, String) {
+        let src = Source::from_text(PROG).unwrap();
+        let a = Arena::default();
+        let text = a.alloc_str(src.text.as_str());
+        let mut buf = Vec::new();
+        let mut prog = ast::Prog::from_stage(&a, Stage::Main(()));
+        ProgParser::new()
+            .parse(&a, &mut buf, &mut prog, lexer::Tokenizer::new(text))
+            .unwrap();
+        let mut ctx =
+            cfg::ProgramContext::from_prog(&a, a.alloc(prog), Escaper::default()).unwrap();
+        let out = Output::default();
+        let debugger = Debugger::new(
+            src,
+            Box::new(Cursor::new(commands.to_string())),
+            Box::new(out.clone()),
+        );
+        let fs = FakeFs::default();
+        let reader = RegexSplitter::new(
+            Cursor::new(INPUT.to_string()),
+            CHUNK_SIZE,
+            "-",
+            /*check_utf8=*/ false,
+        );
+        let mut interp = compile::bytecode_debug(&mut ctx, reader, fs.clone(), debugger).unwrap();
+        assert_eq!(interp.run().unwrap(), 0);
+        drop(interp);
+        let printed = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        (printed, String::from_utf8(fs.stdout.read_data()).unwrap())
+    }
+
+    #[test]
+    fn stepping() {
+        let (out, stdout) = debug("record\nstep\nlocals\nbt\nfinish\nprint n $1\nnext\n\nquit\n");
+        assert!(
+            out.contains("<main> at <command line>:5\n5\t    sum = add(sum, $2)"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("$0 = \"a 1\"\nNF = 2\nNR = 1\nFNR = 1"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("add at <command line>:2\n2\t    return a + b"),
+            "{}",
+            out
+        );
+        assert!(out.contains("b = \"1\""), "{}", out);
+        assert!(
+            out.contains("#0  add at <command line>:2\n#1  <main> at <command line>:5"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("<main> at <command line>:6\n6\t    n++"),
+            "{}",
+            out
+        );
+        assert!(out.contains("n = 0\n$1 = \"a\""), "{}", out);
+        // `next` steps over the call on the second record, and the empty line repeats it.
+        assert_eq!(out.matches("add at").count(), 2, "{}", out);
+        // Quitting stops the program before the END block runs.
+        assert_eq!(stdout, "");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (out, stdout) =
+            debug("break add\nbreak 9\ninfo\ncontinue\ndelete 1\ncontinue\nprint sum n\nc\n");
+        assert!(out.contains("Breakpoint 1 at function add"), "{}", out);
+        assert!(out.contains("Breakpoint 2 at <command line>:9"), "{}", out);
+        assert!(out.contains("1\tbreakpoint at function add\n2\tbreakpoint at <command line>:9"));
+        assert!(
+            out.contains("Breakpoint 1\nadd at <command line>:2"),
+            "{}",
+            out
+        );
+        assert_eq!(out.matches("Breakpoint 1\n").count(), 1, "{}", out);
+        assert!(
+            out.contains("Breakpoint 2\n<main> at <command line>:9\n9\t    print sum, n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("sum = 6\nn = 3"), "{}", out);
+        assert_eq!(stdout, "6 3\n");
+        let (out, _) = debug("break 3\nbreak nope\nbreak 42\nc\n");
+        assert!(out.contains("no statement starts on line 3"), "{}", out);
+        assert!(out.contains("no function named nope"), "{}", out);
+        assert!(out.contains("no line 42 in <command line>"), "{}", out);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let (out, stdout) = debug("watch sum\nwatch nope\ncontinue\nglobals\ncontinue\nq\n");
+        assert!(out.contains("Watchpoint 1: sum\n"), "{}", out);
+        assert!(out.contains("no variable named nope"), "{}", out);
+        assert!(
+            out.contains("Watchpoint 1: sum changed from 0 to 1\n<main> at <command line>:6"),
+            "{}",
+            out
+        );
+        assert!(out.contains("n = 0\nsum = 1"), "{}", out);
+        assert!(
+            out.contains("Watchpoint 1: sum changed from 1 to 3"),
+            "{}",
+            out
+        );
+        assert_eq!(stdout, "");
+    }
+}
@@ -27,7 +27,7 @@ impl<'a> Display for Transition<'a> {
 
 impl<'a> Display for BasicBlock<'a> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        for i in &self.q {
+        for (i, _) in &self.q {
             writeln!(f, "{}", i)?;
         }
         Ok(())
@@ -57,6 +57,7 @@ impl<'a> Display for PrimStmt<'a> {
                         FileSpec::Trunc => ">",
                         FileSpec::Append => ">>",
                         FileSpec::Cmd => "|",
+                        FileSpec::Coproc => "|&",
                     };
                     write!(f, " {} {}", out, redirect)?;
                 }
@@ -77,6 +78,7 @@ impl<'a> Display for PrimStmt<'a> {
                         FileSpec::Trunc => ">",
                         FileSpec::Append => ">>",
                         FileSpec::Cmd => "|",
+                        FileSpec::Coproc => "|&",
                     };
                     write!(f, " {} {}", out, redirect)?;
                 }
@@ -152,10 +154,16 @@ impl Display for Function {
             Binop(b) => write!(f, "{}", b),
             FloatFunc(ff) => write!(f, "{}", ff.func_name()),
             IntFunc(bw) => write!(f, "{}", bw.func_name()),
+            Bignum(op) => write!(f, "{}", op.func_name()),
+            BignumCmp => write!(f, "bignum_cmp"),
+            DecimalCmp => write!(f, "decimal_cmp"),
+            Decimal(op) => write!(f, "{}", op.func_name()),
             ReadErr => write!(f, "hasline"),
             ReadErrCmd => write!(f, "hasline(cmd)"),
             Nextline => write!(f, "nextline"),
             NextlineCmd => write!(f, "nextline(cmd)"),
+            ReadErrCoproc => write!(f, "hasline(coproc)"),
+            NextlineCoproc => write!(f, "nextline(coproc)"),
             ReadErrStdin => write!(f, "hasline(stdin)"),
             NextlineStdin => write!(f, "nextline(stdin)"),
             ReadLineStdinFused => write!(f, "stdin-fused"),
@@ -326,9 +334,12 @@ impl Display for Function {
             System2 => write!(f, "system2"),
             UpdateUsedFields => write!(f, "update_used_fields"),
             SetFI => write!(f, "set-FI"),
+            ProfileMark => write!(f, "profile_mark"),
+            MatchSet => write!(f, "match_set"),
             ToLower => write!(f, "tolower"),
             ToUpper => write!(f, "toupper"),
             IncMap => write!(f, "inc_map"),
+            Subarray => write!(f, "subarray"),
             Exit => write!(f, "exit"),
         }
     }
@@ -358,6 +369,11 @@ impl Display for Variable {
                 ENVIRON => "ENVIRON",
                 PROCINFO => "PROCINFO",
                 CONVFMT => "CONVFMT",
+                OFMT => "OFMT",
+                FIELDWIDTHS => "FIELDWIDTHS",
+                FPAT => "FPAT",
+                IGNORECASE => "IGNORECASE",
+                RT => "RT",
             }
         )
     }
@@ -441,6 +457,9 @@ impl<'a> Display for lexer::Tok<'a> {
             PrintfLP => "printf(",
             While => "while",
             Do => "do",
+            Switch => "switch",
+            Case => "case",
+            DefaultCase => "default",
 
             // { }
             LBrace => "{",
@@ -454,6 +473,7 @@ impl<'a> Display for lexer::Tok<'a> {
 
             Getline => "getline",
             Pipe => "|",
+            Coproc => "|&",
             Assign => "=",
             Add => "+",
             AddAssign => "+=",
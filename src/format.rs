@@ -0,0 +1,1073 @@
+//! A source formatter for AWK programs, used by `zawk fmt`.
+//!
+//! Programs are parsed into an `ast::Prog` and printed back out with four-space indentation, one
+//! statement per line, braces around every block and single spaces around binary operators.
+//! Parentheses are only printed where the grammar requires them, and the parser's desugarings are
+//! undone where they would otherwise leak into the output (`a != b` is parsed as `!(a == b)`,
+//! `(i, j) in a` as a lookup of `i SUBSEP j`, and so on). Literals keep their original spelling.
+//!
+//! Comments are not part of the AST, so they are collected from the token stream and re-attached
+//! as the program is printed: a comment on a line of its own is printed before the statement that
+//! follows it, and a comment after some code stays at the end of that line. `@include` directives
+//! are carried along in the same way. Single blank lines between statements are preserved.
+use crate::arena::Arena;
+use crate::ast::{Binop, Expr, FunDec, GetlineSrc, Pattern, Prog, Stmt, Unop};
+use crate::builtins::Function;
+use crate::common::{CompileError, Either, FileSpec, Result, Stage};
+use crate::include::{self, Source};
+use crate::lexer::{self, Loc, Tok};
+use crate::parsing::syntax::ProgParser;
+use crate::runtime::{hextoi, strtod, strtoi};
+
+const INDENT: &str = "    ";
+
+// Binding strength of each level of the expression grammar, from `Expr` down to `BaseTerm`. An
+// expression printed in a position that requires a higher level is parenthesized.
+const EXPR: u8 = 0;
+const ASGN: u8 = 1;
+const TERN: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 4;
+const IN: u8 = 5;
+const MATCH: u8 = 6;
+const CMP: u8 = 7;
+const ADD: u8 = 8;
+const MUL: u8 = 9;
+const POW: u8 = 10;
+const UNOP: u8 = 11;
+const INC: u8 = 12;
+const CAT: u8 = 13;
+const FIELD: u8 = 14;
+const BASE: u8 = 15;
+
+type Pos = (usize, usize);
+
+fn pos(loc: Loc) -> Pos {
+    (loc.line, loc.col)
+}
+
+/// Format the AWK program `text`. `name` is used when reporting parse errors.
+pub fn format_source(name: &str, text: &str) -> Result<String> {
+    // Include directives are not part of the grammar: blank them out before parsing and print
+    // them back out like comments.
+    let mut directives = Vec::new();
+    let mut stripped = String::with_capacity(text.len());
+    for (i, line) in text.lines().enumerate() {
+        if include::is_include(line) {
+            directives.push((i, line.trim()));
+        } else {
+            stripped.push_str(line);
+        }
+        stripped.push('\n');
+    }
+    let arena = Arena::default();
+    let mut buf = Vec::new();
+    let mut prog = Prog::from_stage(&arena, Stage::Main(()));
+    let lexer = lexer::Tokenizer::new(stripped.as_str());
+    if let Err(e) = ProgParser::new().parse(&arena, &mut buf, &mut prog, lexer) {
+        return Err(CompileError::new(
+            Source::unexpanded(name, &stripped).parse_error(e),
+        ));
+    }
+    let mut printer = Printer::new(text, &stripped, directives);
+    printer.prog(&prog);
+    Ok(printer.finish())
+}
+
+struct Comment<'s> {
+    pos: Pos,
+    text: &'s str,
+    // Whether the comment is the only thing on its line.
+    own_line: bool,
+    // Index into `Printer::toks` of the first token after the comment.
+    next_tok: usize,
+}
+
+// A token other than a newline or semicolon.
+struct Token {
+    pos: Pos,
+    lbrace: bool,
+    rbrace: bool,
+    // The brace nesting depth just before the token.
+    depth: usize,
+}
+
+// An open block in the output.
+struct Frame {
+    // The brace depth of the block's statements if it was braced in the source.
+    expect: usize,
+    // The brace depth of the block's statements, once one has been printed.
+    depth: Option<usize>,
+}
+
+enum Item<'p, 'a, 'b> {
+    Special(&'static str, &'a Stmt<'a, 'b, &'b str>),
+    Function(&'p FunDec<'a, 'b, &'b str>),
+    Pattern(
+        &'p Pattern<'a, 'b, &'b str>,
+        Option<&'a Stmt<'a, 'b, &'b str>>,
+    ),
+}
+
+struct Printer<'s> {
+    src_lines: Vec<&'s str>,
+    toks: Vec<Token>,
+    comments: Vec<Comment<'s>>,
+    next_comment: usize,
+    // Literal tokens, in source order. The printer visits literals in the same order, which lets
+    // it reuse their original spelling.
+    lits: Vec<Tok<'s>>,
+    next_lit: usize,
+    // Scratch space for re-parsing literal tokens.
+    arena: Arena,
+    buf: Vec<u8>,
+
+    out: Vec<String>,
+    line: String,
+    indent: usize,
+    // The position of the last statement or closing brace printed.
+    last: Pos,
+    // The index in `out` of the last line of code, which comments that follow code on the same
+    // source line are appended to.
+    trailing: Option<usize>,
+    frames: Vec<Frame>,
+}
+
+impl<'s> Printer<'s> {
+    fn new(text: &'s str, stripped: &'s str, directives: Vec<(usize, &'s str)>) -> Printer<'s> {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(stripped.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let offset = |loc: Loc| line_starts[loc.line] + loc.col;
+        let mut toks = Vec::new();
+        let mut lits = Vec::new();
+        let mut comments = Vec::new();
+        let mut depth = 0;
+        let mut prev_end = 0;
+        let mut scan_gap = |start: usize, end: usize, toks: &Vec<Token>| {
+            let mut cur = start;
+            while let Some(i) = stripped[cur..end].find('#') {
+                let at = cur + i;
+                let len = stripped[at..end].find('\n').unwrap_or(end - at);
+                let line = line_starts.partition_point(|s| *s <= at) - 1;
+                comments.push(Comment {
+                    pos: (line, at - line_starts[line]),
+                    text: stripped[at..at + len].trim_end(),
+                    own_line: stripped[line_starts[line]..at].trim().is_empty(),
+                    next_tok: toks.len(),
+                });
+                cur = at + len;
+            }
+        };
+        for (l, tok, r) in lexer::Tokenizer::new(stripped).map_while(|t| t.ok()) {
+            scan_gap(prev_end, offset(l), &toks);
+            prev_end = offset(r);
+            match tok {
+                Tok::Newline | Tok::Semi => continue,
+                Tok::ILit(_) | Tok::HexLit(_) | Tok::FLit(_) | Tok::StrLit(_) | Tok::PatLit(_) => {
+                    lits.push(tok.clone())
+                }
+                _ => {}
+            }
+            let (lbrace, rbrace) = (tok == Tok::LBrace, tok == Tok::RBrace);
+            toks.push(Token {
+                pos: pos(l),
+                lbrace,
+                rbrace,
+                depth,
+            });
+            if lbrace {
+                depth += 1;
+            } else if rbrace {
+                depth = depth.saturating_sub(1);
+            }
+        }
+        scan_gap(prev_end, stripped.len(), &toks);
+        for (line, text) in directives {
+            comments.push(Comment {
+                pos: (line, 0),
+                text,
+                own_line: true,
+                next_tok: toks.partition_point(|t| t.pos < (line, 0)),
+            });
+        }
+        comments.sort_by_key(|c| c.pos);
+        Printer {
+            src_lines: text.lines().collect(),
+            toks,
+            comments,
+            next_comment: 0,
+            lits,
+            next_lit: 0,
+            arena: Arena::default(),
+            buf: Vec::new(),
+            out: Vec::new(),
+            line: String::new(),
+            indent: 0,
+            last: (0, 0),
+            trailing: None,
+            frames: Vec::new(),
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.commit();
+        self.flush_comments((usize::MAX, usize::MAX));
+        let mut res = self.out.join("\n");
+        if !res.is_empty() {
+            res.push('\n');
+        }
+        res
+    }
+
+    // Output
+
+    fn push(&mut self, s: &str) {
+        if self.line.is_empty() {
+            for _ in 0..self.indent {
+                self.line.push_str(INDENT);
+            }
+        }
+        self.line.push_str(s);
+    }
+
+    fn commit(&mut self) {
+        if !self.line.is_empty() {
+            self.out.push(std::mem::take(&mut self.line));
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.is_empty() {
+            self.commit();
+            self.trailing = Some(self.out.len() - 1);
+        }
+    }
+
+    fn line_of(&mut self, s: &str) {
+        self.push(s);
+        self.end_line();
+    }
+
+    fn blank_before(&mut self, line: usize) {
+        let blank = line > 0
+            && self
+                .src_lines
+                .get(line - 1)
+                .is_some_and(|l| l.trim().is_empty());
+        match self.out.last() {
+            Some(prev) if blank && !prev.is_empty() && !prev.ends_with('{') => {
+                self.out.push(String::new())
+            }
+            _ => {}
+        }
+    }
+
+    // Comments
+
+    fn comment(&mut self) {
+        let c = &self.comments[self.next_comment];
+        let (line, text, own_line) = (c.pos.0, c.text, c.own_line);
+        self.next_comment += 1;
+        match self.trailing {
+            Some(i) if !own_line => {
+                let prev = &mut self.out[i];
+                prev.push(' ');
+                prev.push_str(text);
+            }
+            _ => {
+                self.blank_before(line);
+                self.push(text);
+                self.commit();
+                self.trailing = None;
+            }
+        }
+    }
+
+    fn flush_comments(&mut self, before: Pos) {
+        while matches!(self.comments.get(self.next_comment), Some(c) if c.pos < before) {
+            self.comment();
+        }
+    }
+
+    fn depth_after(&self, at: Pos) -> usize {
+        let i = self.toks.partition_point(|t| t.pos < at);
+        match self.toks.get(i) {
+            Some(t) if t.pos == at && t.rbrace => t.depth - 1,
+            Some(t) => t.depth,
+            None => 0,
+        }
+    }
+
+    // Called before printing a statement or toplevel item that begins at `loc`.
+    fn located(&mut self, loc: Loc) {
+        let at = pos(loc);
+        self.flush_comments(at);
+        if loc.line > self.last.0 {
+            self.blank_before(loc.line);
+        }
+        self.last = at;
+        let depth = self.depth_after(at);
+        if let Some(frame) = self.frames.last_mut() {
+            frame.depth.get_or_insert(depth);
+        }
+    }
+
+    fn open_block(&mut self) {
+        let expect = self.depth_after(self.last) + 1;
+        self.frames.push(Frame {
+            expect,
+            depth: None,
+        });
+        self.indent += 1;
+    }
+
+    // Print any comments between the last statement of a block and its closing brace.
+    fn close_block(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        let start = self.toks.partition_point(|t| t.pos <= self.last);
+        let close = match frame.depth {
+            Some(depth) if depth == frame.expect => (start..self.toks.len())
+                .find(|i| self.toks[*i].rbrace && self.toks[*i].depth == depth),
+            // An empty block; look for a `{}`.
+            None => (start..self.toks.len())
+                .find(|i| self.toks[*i].lbrace || self.toks[*i].rbrace)
+                .filter(|i| self.toks[*i].lbrace && self.toks[*i].depth + 1 == frame.expect)
+                .map(|i| i + 1)
+                .filter(|i| self.toks.get(*i).is_some_and(|t| t.rbrace)),
+            // The block was not braced in the source.
+            Some(_) => None,
+        };
+        if let Some(close) = close {
+            while matches!(self.comments.get(self.next_comment), Some(c) if c.next_tok <= close) {
+                self.comment();
+            }
+            self.last = self.toks[close].pos;
+        }
+        self.indent -= 1;
+    }
+
+    // Print `header {`, the statements in `body` and the closing brace. The line is left open so
+    // that callers can append `else` or `while`.
+    fn body(&mut self, header: &str, body: &Stmt<'_, '_, &str>) {
+        if !header.is_empty() {
+            self.push(header);
+        }
+        self.push(if self.line.is_empty() { "{" } else { " {" });
+        self.end_line();
+        self.open_block();
+        self.stmts(body);
+        self.close_block();
+        self.push("}");
+    }
+
+    fn stmts(&mut self, s: &Stmt<'_, '_, &str>) {
+        match s {
+            Stmt::Block(stmts) | Stmt::Located(_, Stmt::Block(stmts)) => {
+                for s in stmts.iter() {
+                    self.stmt(s)
+                }
+            }
+            s => self.stmt(s),
+        }
+    }
+
+    // Toplevel
+
+    fn prog<'a, 'b>(&mut self, prog: &Prog<'a, 'b, &'b str>) {
+        let mut items = Vec::new();
+        for (name, blocks) in [
+            ("BEGIN", &prog.begin),
+            ("PREPARE", &prog.prepare),
+            ("BEGINFILE", &prog.beginfile),
+            ("ENDFILE", &prog.endfile),
+            ("END", &prog.end),
+        ] {
+            for block in blocks.iter() {
+                let (loc, block) = match block {
+                    Stmt::Located(loc, block) => (*loc, *block),
+                    block => (Loc::default(), *block),
+                };
+                items.push((loc, Item::Special(name, block)));
+            }
+        }
+        for dec in prog.decs.iter() {
+            items.push((dec.loc, Item::Function(dec)));
+        }
+        for (loc, pat, body) in prog.pats.iter() {
+            items.push((*loc, Item::Pattern(pat, *body)));
+        }
+        items.sort_by_key(|(loc, _)| pos(*loc));
+        for (loc, item) in items {
+            self.located(loc);
+            match item {
+                Item::Special(name, body) => self.body(name, body),
+                Item::Function(dec) => {
+                    let header = format!("function {}({})", dec.name, dec.args.join(", "));
+                    self.body(&header, dec.body)
+                }
+                Item::Pattern(pat, body) => {
+                    let header = match pat {
+                        Pattern::Null => String::new(),
+                        Pattern::Bool(e) => self.expr(e, EXPR),
+                        Pattern::Comma(l, r) => {
+                            let l = self.expr(l, BASE);
+                            format!("{}, {}", l, self.expr(r, BASE))
+                        }
+                    };
+                    match body {
+                        Some(body) => self.body(&header, body),
+                        None => self.push(&header),
+                    }
+                }
+            }
+            self.end_line();
+        }
+    }
+
+    // Statements
+
+    fn stmt(&mut self, s: &Stmt<'_, '_, &str>) {
+        match s {
+            Stmt::Located(loc, s) => {
+                self.located(*loc);
+                self.stmt(s)
+            }
+            Stmt::Block(_) => {
+                self.body("", s);
+                self.end_line();
+            }
+            Stmt::Expr(e) => {
+                let e = self.expr_stmt(e);
+                self.line_of(&e)
+            }
+            Stmt::Print(args, out) => {
+                let mut res = String::from("print");
+                for (i, arg) in args.iter().enumerate() {
+                    res.push_str(if i == 0 { " " } else { ", " });
+                    res.push_str(&self.expr(arg, ADD));
+                }
+                self.redirect(&mut res, out);
+                self.line_of(&res)
+            }
+            Stmt::Printf(spec, args, out) => {
+                let mut res = format!("printf {}", self.expr(spec, ADD));
+                for arg in args.iter() {
+                    res.push_str(", ");
+                    res.push_str(&self.expr(arg, ADD));
+                }
+                self.redirect(&mut res, out);
+                self.line_of(&res)
+            }
+            Stmt::If(cond, then, els) => {
+                self.if_stmt(cond, then, *els);
+                self.end_line();
+            }
+            Stmt::For(init, cond, update, body) => {
+                let init = match init {
+                    Some(Stmt::Expr(e)) => self.for_init(e),
+                    _ => String::new(),
+                };
+                let cond = match cond {
+                    Some(e) => format!(" {}", self.expr(e, EXPR)),
+                    None => String::new(),
+                };
+                let update = match update {
+                    Some(Stmt::Expr(e)) => format!(" {}", self.expr(e, EXPR)),
+                    _ => String::new(),
+                };
+                self.body(&format!("for ({};{};{})", init, cond, update), body);
+                self.end_line();
+            }
+            Stmt::DoWhile(cond, body) => {
+                self.body("do", body);
+                let cond = self.expr(cond, EXPR);
+                self.line_of(&format!(" while ({})", cond));
+            }
+            Stmt::While(_, cond, body) => {
+                let header = format!("while ({})", self.expr(cond, EXPR));
+                self.body(&header, body);
+                self.end_line();
+            }
+            Stmt::ForEach(v, arr, body) => {
+                let header = format!("for ({} in {})", v, self.expr(arr, EXPR));
+                self.body(&header, body);
+                self.end_line();
+            }
+            Stmt::Switch(e, cases) => {
+                let header = format!("switch ({}) {{", self.expr(e, EXPR));
+                self.line_of(&header);
+                self.open_block();
+                for (label, body) in cases.iter() {
+                    let label = match label {
+                        Some(l) => format!("case {}:", self.expr(l, EXPR)),
+                        None => String::from("default:"),
+                    };
+                    self.line_of(&label);
+                    self.indent += 1;
+                    self.stmts(body);
+                    self.indent -= 1;
+                }
+                self.close_block();
+                self.line_of("}");
+            }
+            Stmt::Break => self.line_of("break"),
+            Stmt::Continue => self.line_of("continue"),
+            Stmt::Next => self.line_of("next"),
+            Stmt::NextFile => self.line_of("nextfile"),
+            Stmt::Return(None) => self.line_of("return"),
+            Stmt::Return(Some(e)) => {
+                let e = self.expr(e, EXPR);
+                self.line_of(&format!("return {}", e))
+            }
+            Stmt::StartCond(_) | Stmt::EndCond(_) | Stmt::LastCond(_) => {
+                unreachable!("range pattern state is only introduced by desugaring")
+            }
+        }
+    }
+
+    fn if_stmt(
+        &mut self,
+        cond: &Expr<'_, '_, &str>,
+        then: &Stmt<'_, '_, &str>,
+        els: Option<&Stmt<'_, '_, &str>>,
+    ) {
+        let header = format!("if ({})", self.expr(cond, EXPR));
+        self.body(&header, then);
+        match els {
+            Some(Stmt::If(cond, then, els)) | Some(Stmt::Located(_, Stmt::If(cond, then, els))) => {
+                self.push(" else ");
+                self.if_stmt(cond, then, *els)
+            }
+            Some(els) => {
+                self.push(" else");
+                self.body("", els)
+            }
+            None => {}
+        }
+    }
+
+    fn redirect(&mut self, res: &mut String, out: &Option<(&Expr<'_, '_, &str>, FileSpec)>) {
+        if let Some((e, spec)) = out {
+            let op = match spec {
+                FileSpec::Trunc => ">",
+                FileSpec::Append => ">>",
+                FileSpec::Cmd => "|",
+                FileSpec::Coproc => "|&",
+            };
+            // Other awks disagree on how much of an unparenthesized target to take.
+            res.push_str(&format!(" {} {}", op, self.expr(e, FIELD)));
+        }
+    }
+
+    // The initializer of a `for` loop may not contain a bare `in`: it would be parsed as a
+    // `for (x in arr)` loop.
+    fn for_init(&mut self, e: &Expr<'_, '_, &str>) -> String {
+        let res = self.expr(e, EXPR);
+        if !matches!(e, Expr::Assign(..) | Expr::AssignOp(..)) && has_in(e) {
+            format!("({})", res)
+        } else {
+            res
+        }
+    }
+
+    fn expr_stmt(&mut self, e: &Expr<'_, '_, &str>) -> String {
+        match e {
+            Expr::Call(Either::Right(Function::Delete), [arr, key]) => {
+                let arr = self.expr(arr, BASE);
+                format!("delete {}[{}]", arr, self.subscript(key))
+            }
+            Expr::Call(Either::Right(Function::Clear), [arr]) => {
+                format!("delete {}", self.expr(arr, BASE))
+            }
+            // `exit` is parsed as `exit 0`.
+            Expr::Call(Either::Right(Function::Exit), [Expr::ILit(0)]) if !self.zero_lit_next() => {
+                String::from("exit")
+            }
+            Expr::Call(Either::Right(Function::Exit), [code]) => {
+                format!("exit {}", self.expr(code, ADD))
+            }
+            e => self.expr(e, EXPR),
+        }
+    }
+
+    fn zero_lit_next(&self) -> bool {
+        matches!(self.lits.get(self.next_lit), Some(Tok::ILit(s)) if strtoi(s.as_bytes()) == 0)
+    }
+
+    // Expressions
+
+    fn expr(&mut self, e: &Expr<'_, '_, &str>, min: u8) -> String {
+        let (res, prec) = self.expr_prec(e);
+        if prec < min {
+            format!("({})", res)
+        } else {
+            res
+        }
+    }
+
+    fn list(&mut self, es: &[&Expr<'_, '_, &str>], min: u8) -> String {
+        let mut res = String::new();
+        for (i, e) in es.iter().enumerate() {
+            if i > 0 {
+                res.push_str(", ");
+            }
+            res.push_str(&self.expr(e, min));
+        }
+        res
+    }
+
+    fn subscript(&mut self, e: &Expr<'_, '_, &str>) -> String {
+        match subsep_list(e) {
+            Some(keys) => self.list(&keys, MATCH),
+            None => self.expr(e, EXPR),
+        }
+    }
+
+    fn expr_prec(&mut self, e: &Expr<'_, '_, &str>) -> (String, u8) {
+        match e {
+            Expr::ILit(n) => self.int_lit(*n),
+            Expr::FLit(f) => self.float_lit(*f),
+            Expr::StrLit(s) => (self.str_lit(s), BASE),
+            Expr::BigLit(s) => (s.to_string(), BASE),
+            Expr::PatLit(s) => (self.pat_lit(s), BASE),
+            Expr::Var(v) => (v.to_string(), BASE),
+            Expr::Located(_, x) => self.expr_prec(x),
+            Expr::Index(arr, ix) => {
+                let arr = self.expr(arr, BASE);
+                (format!("{}[{}]", arr, self.subscript(ix)), BASE)
+            }
+            Expr::Call(Either::Right(Function::Contains), [arr, key]) => {
+                let key = match subsep_list(key) {
+                    Some(keys) => format!("({})", self.list(&keys, MATCH)),
+                    None => self.expr(key, MATCH),
+                };
+                (format!("{} in {}", key, self.expr(arr, MATCH)), IN)
+            }
+            Expr::Call(f, args) => {
+                let name = match f {
+                    Either::Left(name) => name.to_string(),
+                    Either::Right(f) => f.to_string(),
+                };
+                (format!("{}({})", name, self.list(args, EXPR)), BASE)
+            }
+            Expr::Unop(Unop::Not, Expr::Binop(Binop::EQ, l, r)) => {
+                let l = self.expr(l, ADD);
+                (format!("{} != {}", l, self.expr(r, CMP)), CMP)
+            }
+            Expr::Unop(Unop::Not, Expr::Binop(Binop::IsMatch, l, r)) => {
+                let l = self.expr(l, MATCH);
+                (format!("{} !~ {}", l, self.expr(r, CMP)), MATCH)
+            }
+            Expr::Unop(Unop::Column, x) => (format!("${}", self.expr(x, BASE)), FIELD),
+            Expr::Unop(op, x) => {
+                let op = op.to_string();
+                let x = self.expr(x, INC);
+                // Keep `- --x` from being lexed as a decrement.
+                let sep = if x.starts_with(op.as_str()) { " " } else { "" };
+                (format!("{}{}{}", op, sep, x), UNOP)
+            }
+            Expr::Binop(Binop::Concat, l, r) => {
+                let l = self.expr(l, CAT);
+                let mut r = self.expr(r, FIELD);
+                // `x /re/` would be lexed as a division.
+                if r.starts_with('/') {
+                    r = format!("({})", r);
+                }
+                (format!("{} {}", l, r), CAT)
+            }
+            Expr::Binop(op, l, r) => {
+                let (prec, lmin, rmin) = match op {
+                    Binop::IsMatch => (MATCH, MATCH, CMP),
+                    Binop::LT | Binop::GT | Binop::LTE | Binop::GTE | Binop::EQ => (CMP, ADD, CMP),
+                    Binop::Plus | Binop::Minus => (ADD, ADD, MUL),
+                    Binop::Mult | Binop::Div | Binop::Mod => (MUL, MUL, POW),
+                    // The grammar reads `-x ^ 2` as `(-x) ^ 2`, unlike other awks.
+                    Binop::Pow => (POW, INC, POW),
+                    Binop::Concat => unreachable!(),
+                };
+                let mut l = self.expr(l, lmin);
+                // `x++ / y` would be lexed as the start of a regex.
+                if *op == Binop::Div && (l.ends_with("++") || l.ends_with("--")) {
+                    l = format!("({})", l);
+                }
+                (format!("{} {} {}", l, op, self.expr(r, rmin)), prec)
+            }
+            Expr::Assign(l, r) => {
+                let l = self.expr(l, IN);
+                (format!("{} = {}", l, self.expr(r, ASGN)), ASGN)
+            }
+            Expr::AssignOp(l, op, r) => {
+                let l = self.expr(l, IN);
+                (format!("{} {}= {}", l, op, self.expr(r, ASGN)), ASGN)
+            }
+            Expr::And(l, r) => {
+                let l = self.expr(l, IN);
+                (format!("{} && {}", l, self.expr(r, AND)), AND)
+            }
+            Expr::Or(l, r) => {
+                let l = self.expr(l, AND);
+                (format!("{} || {}", l, self.expr(r, OR)), OR)
+            }
+            Expr::ITE(c, t, f) => {
+                let c = self.expr(c, OR);
+                let t = self.expr(t, TERN);
+                (format!("{} ? {} : {}", c, t, self.expr(f, TERN)), TERN)
+            }
+            Expr::Inc { is_inc, is_post, x } => {
+                let op = if *is_inc { "++" } else { "--" };
+                let x = self.expr(x, CAT);
+                let res = if *is_post {
+                    format!("{}{}", x, op)
+                } else {
+                    format!("{}{}", op, x)
+                };
+                (res, INC)
+            }
+            Expr::Getline {
+                into,
+                from,
+                src: GetlineSrc::File,
+            } => {
+                let mut res = String::from("getline");
+                if let Some(into) = into {
+                    res.push(' ');
+                    res.push_str(&self.expr(into, BASE));
+                }
+                if let Some(from) = from {
+                    res.push_str(" < ");
+                    res.push_str(&self.expr(from, FIELD));
+                }
+                (res, EXPR)
+            }
+            Expr::Getline {
+                into,
+                from,
+                src: src @ (GetlineSrc::Cmd | GetlineSrc::Coproc),
+            } => {
+                let pipe = match src {
+                    GetlineSrc::Coproc => "|&",
+                    _ => "|",
+                };
+                let mut res = match from {
+                    Some(cmd) => format!("{} {} getline", self.expr(cmd, FIELD), pipe),
+                    None => String::from("getline"),
+                };
+                if let Some(into) = into {
+                    res.push(' ');
+                    res.push_str(&self.expr(into, BASE));
+                }
+                (res, EXPR)
+            }
+            Expr::ReadStdin => (String::from("getline"), EXPR),
+            Expr::Cond(_) => unreachable!("range pattern state is only introduced by desugaring"),
+        }
+    }
+
+    // Literals. Where the next literal token in the source has the value being printed, its
+    // spelling is reused; otherwise the value is printed in a canonical form.
+
+    fn int_lit(&mut self, n: i64) -> (String, u8) {
+        let mag = n.unsigned_abs();
+        let spelled = match self.lits.get(self.next_lit) {
+            Some(Tok::ILit(s)) if strtoi(s.as_bytes()).unsigned_abs() == mag => Some(*s),
+            Some(Tok::HexLit(s)) if hextoi(s.as_bytes()).unsigned_abs() == mag => Some(*s),
+            _ => None,
+        };
+        let text = match spelled {
+            Some(s) => {
+                self.next_lit += 1;
+                s.to_string()
+            }
+            None => mag.to_string(),
+        };
+        // Negative literals only appear as case labels.
+        if n < 0 {
+            (format!("-{}", text), UNOP)
+        } else {
+            (text, BASE)
+        }
+    }
+
+    fn float_lit(&mut self, f: f64) -> (String, u8) {
+        let mag = f.abs();
+        let text = match self.lits.get(self.next_lit) {
+            Some(Tok::FLit(s)) if strtod(s.as_bytes()) == mag => {
+                self.next_lit += 1;
+                s.to_string()
+            }
+            // Debug formatting always includes a decimal point or an exponent.
+            _ => format!("{:?}", mag),
+        };
+        if f.is_sign_negative() {
+            (format!("-{}", text), UNOP)
+        } else {
+            (text, BASE)
+        }
+    }
+
+    fn str_lit(&mut self, bs: &[u8]) -> String {
+        match self.lits.get(self.next_lit) {
+            Some(Tok::StrLit(s))
+                if lexer::parse_string_literal(s, &self.arena, &mut self.buf) == bs =>
+            {
+                self.next_lit += 1;
+                format!("\"{}\"", s)
+            }
+            _ => escape_string(bs),
+        }
+    }
+
+    fn pat_lit(&mut self, bs: &[u8]) -> String {
+        match self.lits.get(self.next_lit) {
+            Some(Tok::PatLit(s))
+                if lexer::parse_regex_literal(s, &self.arena, &mut self.buf) == bs =>
+            {
+                self.next_lit += 1;
+                format!("/{}/", s)
+            }
+            _ => escape_regex(bs),
+        }
+    }
+}
+
+// Multi-dimensional subscripts `a[i, j]` are parsed as `a[i SUBSEP j]`; recover the list of
+// subscripts.
+fn subsep_list<'a, 'b>(e: &Expr<'a, 'b, &'b str>) -> Option<Vec<&'a Expr<'a, 'b, &'b str>>> {
+    match e {
+        Expr::Binop(Binop::Concat, Expr::Binop(Binop::Concat, l, Expr::Var(sep)), r)
+            if *sep == "SUBSEP" =>
+        {
+            let mut res = subsep_list(l).unwrap_or_else(|| vec![*l]);
+            res.push(*r);
+            Some(res)
+        }
+        _ => None,
+    }
+}
+
+fn has_in(e: &Expr<'_, '_, &str>) -> bool {
+    match e {
+        Expr::Call(Either::Right(Function::Contains), _) => true,
+        Expr::Unop(_, x) | Expr::Inc { x, .. } | Expr::Located(_, x) => has_in(x),
+        Expr::Binop(_, l, r)
+        | Expr::Assign(l, r)
+        | Expr::AssignOp(l, _, r)
+        | Expr::And(l, r)
+        | Expr::Or(l, r) => has_in(l) || has_in(r),
+        Expr::ITE(c, t, f) => has_in(c) || has_in(t) || has_in(f),
+        Expr::Getline { into, from, .. } => [into, from]
+            .iter()
+            .any(|e| matches!(e, Some(e) if has_in(e))),
+        // Subscripts and arguments are already delimited.
+        Expr::Index(..)
+        | Expr::Call(..)
+        | Expr::ILit(_)
+        | Expr::FLit(_)
+        | Expr::StrLit(_)
+        | Expr::PatLit(_)
+        | Expr::BigLit(_)
+        | Expr::Var(_)
+        | Expr::ReadStdin
+        | Expr::Cond(_) => false,
+    }
+}
+
+fn escape_string(bs: &[u8]) -> String {
+    fn push(res: &mut String, c: char) {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c if c.is_ascii_control() => res.push_str(&format!("\\{:03o}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    let mut res = String::from("\"");
+    match std::str::from_utf8(bs) {
+        Ok(s) => s.chars().for_each(|c| push(&mut res, c)),
+        Err(_) => {
+            for b in bs {
+                if b.is_ascii() {
+                    push(&mut res, *b as char)
+                } else {
+                    res.push_str(&format!("\\{:03o}", b))
+                }
+            }
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn escape_regex(bs: &[u8]) -> String {
+    // The parser keeps escape sequences other than `\/` as-is.
+    let s = String::from_utf8_lossy(bs);
+    let mut res = String::from("/");
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                res.push('\\');
+                res.extend(chars.next());
+            }
+            '/' => res.push_str("\\/"),
+            c => res.push(c),
+        }
+    }
+    res.push('/');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(text: &str) -> String {
+        let res = format_source("test.awk", text).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(
+            format_source("test.awk", &res).unwrap(),
+            res,
+            "formatting is not idempotent"
+        );
+        res
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(
+            fmt("BEGIN{x=1;y=2}\n$1>10{print $1,$2 > \"out\"; n++}\nEND{if(n)print n;else{print \"none\"}}"),
+            r#"BEGIN {
+    x = 1
+    y = 2
+}
+$1 > 10 {
+    print $1, $2 > "out"
+    n++
+}
+END {
+    if (n) {
+        print n
+    } else {
+        print "none"
+    }
+}
+"#
+        );
+        assert_eq!(
+            fmt("function f(a,b){for(i=0;i<a;i++)s+=b;return s}\n/x/,/y/\n{ while ((getline line < FILENAME) > 0) c++; }"),
+            r#"function f(a, b) {
+    for (i = 0; i < a; i++) {
+        s += b
+    }
+    return s
+}
+/x/, /y/
+{
+    while ((getline line < FILENAME) > 0) {
+        c++
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn desugared_syntax() {
+        assert_eq!(
+            fmt("{ if ((i,j) in a && $1 !~ /x\\/y/ && x != 0x1F) delete a[i,j]; delete b; exit }"),
+            r#"{
+    if ((i, j) in a && $1 !~ /x\/y/ && x != 0x1F) {
+        delete a[i, j]
+    }
+    delete b
+    exit
+}
+"#
+        );
+        assert_eq!(
+            fmt(
+                "{ x = (a + b) * c ^ (d ^ e) - (f - g); y = -(-z); print (a > b), \"\\t1e3\" 1e3 }"
+            ),
+            r#"{
+    x = (a + b) * c ^ d ^ e - (f - g)
+    y = -(-z)
+    print (a > b), "\t1e3" 1e3
+}
+"#
+        );
+        assert_eq!(
+            fmt("BEGIN { switch (x) { case -1: exit 0\n case \"a\": break\n default: print } }"),
+            r#"BEGIN {
+    switch (x) {
+        case -1:
+            exit 0
+        case "a":
+            break
+        default:
+            print
+    }
+}
+"#
+        );
+        assert_eq!(
+            fmt("BEGIN { print 1 |& \"cat\"; close(\"cat\", \"to\"); \"cat\" |& getline x }"),
+            r#"BEGIN {
+    print 1 |& "cat"
+    close("cat", "to")
+    "cat" |& getline x
+}
+"#
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let src = r#"#!/usr/bin/env zawk -f
+
+# @desc this is a demo awk
+# @var email user email
+@include "lib/strings"
+
+BEGIN { # setup
+    # the total
+    total = 0   # starts at zero
+
+
+    for (k in a) { n++ } # count
+    # trailing
+}
+# the end
+"#;
+        assert_eq!(
+            fmt(src),
+            r#"#!/usr/bin/env zawk -f
+
+# @desc this is a demo awk
+# @var email user email
+@include "lib/strings"
+
+BEGIN { # setup
+    # the total
+    total = 0 # starts at zero
+
+    for (k in a) {
+        n++
+    } # count
+    # trailing
+}
+# the end
+"#
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let err = format_source("bad.awk", "BEGIN {\n  x = = 1\n}\n")
+            .err()
+            .unwrap();
+        assert!(err.0.starts_with("bad.awk:2:"), "{}", err);
+    }
+}
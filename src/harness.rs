@@ -9,7 +9,7 @@ use crate::{
     ast,
     cfg::{self, Escaper},
     codegen::{self, intrinsics::IntoRuntime},
-    common::{ExecutionStrategy, Result},
+    common::{CheckedArith, ExecutionStrategy, Result},
     compile, lexer,
     parsing::syntax,
     pushdown::FieldSet,
@@ -18,6 +18,7 @@ use crate::{
         splitter::{
             batch::{ByteReader, CSVReader, InputFormat},
             regex::RegexSplitter,
+            RecordReader,
         },
         writers::testing::FakeFs,
         ChainedReader,
@@ -57,11 +58,15 @@ macro_rules! with_reader {
             cfg::SepAssign::Potential {
                 field_sep,
                 record_sep,
+                split_lazily,
             } => {
                 // TODO: unify this code with the code in main.
                 let field_sep = field_sep.unwrap_or(b" ");
                 let record_sep = record_sep.unwrap_or(b"\n");
-                if field_sep.len() == 1 && record_sep.len() == 1 {
+                if split_lazily {
+                    let $id = simulate_stdin_records(record_sep[0], $inp);
+                    $body
+                } else if field_sep.len() == 1 && record_sep.len() == 1 {
                     if field_sep == b" " && record_sep == b"\n" {
                         let $id = simulate_stdin_whitespace($inp);
                         $body
@@ -94,6 +99,21 @@ fn simulate_stdin_singlechar(
     )
 }
 
+fn simulate_stdin_records(
+    record_sep: u8,
+    inp: impl Into<String>,
+) -> impl IntoRuntime + runtime::LineReader {
+    RecordReader::new(ByteReader::new(
+        split_stdin(inp.into()),
+        record_sep,
+        record_sep,
+        runtime::CHUNK_SIZE,
+        /*check_utf8=*/ true,
+        ExecutionStrategy::Serial,
+        Default::default(),
+    ))
+}
+
 fn simulate_stdin_whitespace(inp: impl Into<String>) -> impl IntoRuntime + runtime::LineReader {
     ByteReader::new_whitespace(
         split_stdin(inp.into()),
@@ -311,16 +331,160 @@ pub(crate) fn used_fields(prog: &str) -> Result<FieldSet> {
     compile::used_fields(&mut ctx)
 }
 
+/// Run `prog` under `--posix`, first with the bytecode interpreter and then with Cranelift,
+/// returning the output of each.
+pub(crate) fn run_posix(prog: &str, stdin: &str) -> Result<(String, String)> {
+    let a = Arena::default();
+    let stmt = parse_program(prog, &a, Escaper::Identity, ExecutionStrategy::Serial)?;
+    let (interp_fs, cranelift_fs) = run_with_fake_fs(&a, stmt, stdin, |ctx| ctx.posix = true)?;
+    Ok((stdout_of(interp_fs)?, stdout_of(cranelift_fs)?))
+}
+
+/// Run `prog` with `--ordered-maps` on the bytecode interpreter and then with Cranelift, returning
+/// the output of each. `stdin` is read as CSV, as in `test_program_parallel!`, so that `strat` can
+/// split it into files for up to `num_workers` threads.
+pub(crate) fn run_ordered_maps(
+    prog: &str,
+    stdin: &str,
+    strat: ExecutionStrategy,
+    num_workers: usize,
+) -> Result<(String, String)> {
+    let a = Arena::default();
+    let esc = Escaper::Identity;
+    let stmt = parse_program(prog, &a, esc, strat)?;
+    let mut ctx = cfg::ProgramContext::from_prog(&a, stmt, esc)?;
+    ctx.ordered_maps = true;
+    let interp_fs = FakeFs::default();
+    let stdin_csv = || simulate_stdin_csv(InputFormat::CSV, stdin, strat);
+    compile::bytecode(
+        &mut ctx,
+        stdin_csv(),
+        interp_fs.clone(),
+        num_workers,
+    )?
+    .run()?;
+    let mut ctx = cfg::ProgramContext::from_prog(&a, stmt, esc)?;
+    ctx.ordered_maps = true;
+    let cranelift_fs = FakeFs::default();
+    compile::run_cranelift(
+        &mut ctx,
+        stdin_csv(),
+        cranelift_fs.clone(),
+        codegen::Config {
+            opt_level: CODEGEN_CONFIG.opt_level,
+            num_workers,
+        },
+        Default::default(),
+    )?;
+    Ok((stdout_of(interp_fs)?, stdout_of(cranelift_fs)?))
+}
+
+fn stdout_of(fs: FakeFs) -> Result<String> {
+    match String::from_utf8(fs.stdout.read_data()) {
+        Ok(s) => Ok(s),
+        Err(e) => err!("program produced invalid UTF-8: {}", e),
+    }
+}
+
+// Run `stmt` with the bytecode interpreter and then with Cranelift, returning the output of each.
+// An error from the interpreter is returned before Cranelift runs, as compiled code aborts on
+// runtime errors.
+fn run_interp_and_cranelift<'a>(
+    a: &'a Arena,
+    stmt: Prog<'a>,
+    stdin: &str,
+) -> Result<(String, String)> {
+    let (interp_fs, cranelift_fs) = run_with_fake_fs(a, stmt, stdin, |_| {})?;
+    Ok((stdout_of(interp_fs)?, stdout_of(cranelift_fs)?))
+}
+
+// Like `run_interp_and_cranelift`, but returns the files each run wrote to. `setup` sets the
+// options of each run (say, `--posix`) on the program before it is compiled.
+fn run_with_fake_fs<'a>(
+    a: &'a Arena,
+    stmt: Prog<'a>,
+    stdin: &str,
+    setup: impl Fn(&mut cfg::ProgramContext<'a, &'a str>),
+) -> Result<(FakeFs, FakeFs)> {
+    let esc = Escaper::Identity;
+    let mut ctx = cfg::ProgramContext::from_prog(a, stmt, esc)?;
+    setup(&mut ctx);
+    let interp_fs = FakeFs::default();
+    compile::bytecode(&mut ctx, simulate_stdin_regex(stdin), interp_fs.clone(), 1)?.run()?;
+    let mut ctx = cfg::ProgramContext::from_prog(a, stmt, esc)?;
+    setup(&mut ctx);
+    let cranelift_fs = FakeFs::default();
+    compile::run_cranelift(
+        &mut ctx,
+        simulate_stdin_regex(stdin),
+        cranelift_fs.clone(),
+        CODEGEN_CONFIG,
+        Default::default(),
+    )?;
+    Ok((interp_fs, cranelift_fs))
+}
+
+/// Run `prog` with the bytecode interpreter and then with Cranelift, returning the files that
+/// each run wrote to (standard output and named files, including `/dev/fd/N`).
+pub(crate) fn run_files(prog: &str, stdin: &str) -> Result<(FakeFs, FakeFs)> {
+    let a = Arena::default();
+    let stmt = parse_program(prog, &a, Escaper::Identity, ExecutionStrategy::Serial)?;
+    run_with_fake_fs(&a, stmt, stdin, |_| {})
+}
+
+/// Run `prog` with the bytecode interpreter and then with Cranelift, returning the output of each.
+/// `stdin` stands in for standard input, as it does when no input files are given.
+pub(crate) fn run_stdin_input(prog: &str, stdin: &str) -> Result<(String, String)> {
+    let a = Arena::default();
+    let stmt = parse_program(prog, &a, Escaper::Identity, ExecutionStrategy::Serial)?;
+    let (interp_fs, cranelift_fs) =
+        run_with_fake_fs(&a, stmt, stdin, |ctx| ctx.stdin_is_input = true)?;
+    Ok((stdout_of(interp_fs)?, stdout_of(cranelift_fs)?))
+}
+
+/// Run `prog` in bignum mode (-M), first with the bytecode interpreter and then with Cranelift,
+/// returning the output of each.
+pub(crate) fn run_bignum(prog: &str, stdin: &str) -> Result<(String, String)> {
+    let a = Arena::default();
+    let program = parse_ast(prog, &a, Escaper::Identity, ExecutionStrategy::Serial, true)?;
+    let stmt = &*a.alloc(program);
+    run_interp_and_cranelift(&a, stmt, stdin)
+}
+
+/// Like `run_bignum`, but under `--checked-arith` instead of -M.
+pub(crate) fn run_checked_arith(
+    prog: &str,
+    mode: CheckedArith,
+    stdin: &str,
+) -> Result<(String, String)> {
+    let a = Arena::default();
+    let mut program = parse_ast(prog, &a, Escaper::Identity, ExecutionStrategy::Serial, false)?;
+    program.checked_arith = Some(mode);
+    run_interp_and_cranelift(&a, a.alloc(program), stdin)
+}
+
 pub(crate) fn parse_program<'a>(
     prog: &str,
     a: &'a Arena,
     esc: Escaper,
     strat: ExecutionStrategy,
 ) -> Result<Prog<'a>> {
+    Ok(a.alloc(parse_ast(prog, a, esc, strat, false)?))
+}
+
+// Parse `prog`, in bignum mode (-M) if `bignum` is set.
+fn parse_ast<'a>(
+    prog: &str,
+    a: &'a Arena,
+    esc: Escaper,
+    strat: ExecutionStrategy,
+    bignum: bool,
+) -> Result<ast::Prog<'a, 'a, &'a str>> {
     let prog = a.alloc_str(prog);
     let lexer = lexer::Tokenizer::new(prog);
     let mut buf = Vec::new();
     let mut program = ast::Prog::from_stage(a, strat.stage());
+    program.bignum = bignum;
     let parser = syntax::ProgParser::new();
     match parser.parse(a, &mut buf, &mut program, lexer) {
         Ok(()) => {
@@ -329,7 +493,7 @@ pub(crate) fn parse_program<'a>(
                 Escaper::TSV => program.output_sep = Some(b"\t"),
                 Escaper::Identity => {}
             };
-            Ok(a.alloc(program))
+            Ok(program)
         }
         Err(e) => {
             let mut ix = 0;
@@ -395,7 +559,9 @@ pub(crate) fn run_prog<'a>(
         writeln!(&mut instrs_buf, "\nCFG:").unwrap();
         ctx.dbg_print(&mut instrs_buf).unwrap();
         writeln!(&mut instrs_buf).unwrap();
-        let types::TypeInfo { var_tys, func_tys } = get_types(&ctx)?;
+        let types::TypeInfo {
+            var_tys, func_tys, ..
+        } = get_types(&ctx)?;
         // ident_map : Ident -> &str (but only has globals)
         // ts: Ident -> Type
         //
@@ -1086,6 +1252,85 @@ depth 0: k=1 v=99
     // Just want to make sure this compiles
     test_program!(map_clear_degenerate, r#"BEGIN { delete m; }"#, "");
 
+    test_program!(
+        nested_map_count,
+        r#"{ a[$1][$2]++ }
+        END { print a["x"]["y"], a["x"]["z"], a["w"]["y"], length(a), length(a["x"]) }"#,
+        "2 1 1 2 2\n",
+        @input "x y\nx z\nx y\nw y",
+        @types [a :: MapStrMap]
+    );
+
+    test_program!(
+        nested_map_iter,
+        r#"BEGIN {
+            a[1]["x"] = 3;
+            a[1]["y"] = 4;
+            for (k in a[1]) s += a[1][k];
+            print s, isarray(a[1]), isarray(a[1]["x"]), ("x" in a[1]), ("z" in a[1])
+        }"#,
+        "7 1 0 1 0\n",
+        @input "",
+        @types [a :: MapIntMap]
+    );
+
+    test_program!(
+        nested_map_first_write,
+        r#"{ a[$1][$2] = $3 }
+        END { print length(a), length(a["x"]), a["x"]["y"], a["x"]["z"], a["w"]["y"] }"#,
+        "2 2 1 2 3\n",
+        @input "x y 1\nx z 2\nw y 3"
+    );
+
+    test_program!(
+        nested_map_delete,
+        r#"BEGIN {
+            a["p"]["q"] = 1;
+            a["r"]["s"] = 2;
+            delete a["p"]["q"];
+            print length(a), length(a["p"]);
+            delete a["p"];
+            print length(a), ("p" in a)
+        }"#,
+        "2 0\n1 0\n"
+    );
+
+    test_program!(
+        inc_str_map,
+        r#"BEGIN { split("a 2", s); s[1]++; s[2] += 0.5; print s[1], s[2] }"#,
+        "1 2.5\n"
+    );
+
+    test_program!(
+        inc_flat_str_map,
+        r#"BEGIN {
+            m["a"] = "2.5"; m["a"]++
+            m["b"] = "x"; m["b"] += 2
+            m["c"] = "7"; x = (m["c"] += 1)
+            n["a"] = 1; n["a"] += 2
+            print m["a"], m["b"], m["c"], x + 1, n["a"], length(m), length(n)
+        }"#,
+        "3.5 2 8 9 3 3 1\n"
+    );
+
+    test_program!(
+        inc_subarray,
+        r#"BEGIN { a[1]["x"] = "1.5"; a[1]["x"]++; a[1]["y"] += 2; print a[1]["x"], a[1]["y"] }"#,
+        "2.5 2\n"
+    );
+
+    #[test]
+    fn nested_map_type_errors() {
+        for prog in [
+            r#"BEGIN { a[1]["x"] = 1; a[1]++ }"#,
+            r#"BEGIN { a[1]["x"] = 1; a[2] = 3 }"#,
+            r#"BEGIN { a[1]["x"] = 1; x = a[1]; print x }"#,
+            r#"BEGIN { a[1]["x"] = 1; a[1]["x"]["y"] = 2 }"#,
+        ] {
+            assert!(program_compiles(prog, false).is_err(), "{}", prog);
+        }
+    }
+
     test_program!(
         function_keyword_overlap,
         r#"
@@ -1270,6 +1515,536 @@ and this!
 this as well"#
     );
 
+    test_program!(
+        next_in_function,
+        r#"
+        function skip_if_odd(n) { if (n % 2) next; return n }
+        function check(n) { return skip_if_odd(n) * 10 }
+        { x = check($1); print x }
+        "#,
+        "20\n40\n",
+        @input "1\n2\n3\n4"
+    );
+
+    test_program!(
+        nextfile_in_function,
+        r#"
+        function skip_file() { nextfile; }
+        FNR == 2 { skip_file() }
+        { print FILENAME, $0 }
+        "#,
+        "fake_stdin_0 a\nfake_stdin_1 c\n",
+        @input "a\nb<<<FILE BREAK>>>c\nd"
+    );
+
+    #[test]
+    fn next_in_function_from_begin() {
+        let prog = r#"function f() { next; } BEGIN { f(); } { print; }"#;
+        assert!(program_compiles(prog, false).is_err());
+        let prog = r#"function f() { next; } function g() { f(); } END { g(); }"#;
+        assert!(program_compiles(prog, false).is_err());
+    }
+
+    #[test]
+    fn compile_error_location() {
+        let prog = "BEGIN {\n  x = 1\n  break\n}";
+        match program_compiles(prog, false) {
+            Ok(()) => panic!("expected break outside of a loop to fail"),
+            Err(e) => assert_eq!(e.1.map(|loc| (loc.line, loc.col)), Some((2, 2)), "{}", e),
+        }
+    }
+
+    #[test]
+    fn runtime_error_location() {
+        let a = Arena::default();
+        let prog = "BEGIN {\n  x = \"(\"\n  if (\"a\" ~ x) print \"matched\"\n}";
+        match run_program(
+            &a,
+            prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        ) {
+            Ok(_) => panic!("expected an invalid regex to fail"),
+            Err(e) => assert_eq!(e.1.map(|loc| (loc.line, loc.col)), Some((2, 2)), "{}", e),
+        };
+    }
+
+    #[test]
+    fn runtime_error_call_location() {
+        // Errors in a call are reported where the call is, even inside a longer statement.
+        let a = Arena::default();
+        let prog = "BEGIN {\n  x = \"(\"\n  print \"a\",\n    match(\"a\", x)\n}";
+        match run_program(
+            &a,
+            prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        ) {
+            Ok(_) => panic!("expected an invalid regex to fail"),
+            Err(e) => assert_eq!(e.1.map(|loc| (loc.line, loc.col)), Some((3, 4)), "{}", e),
+        };
+    }
+
+    #[test]
+    fn posix_strnum_comparisons() {
+        let prog = r#"{ print ($1 < $2), ($1 == $2); split($0, a); print (a[1] < a[2]) }"#;
+        let (interp, cranelift) = run_posix(prog, "10 9\n1e1 10.0\nabc abd\n").unwrap();
+        assert_eq!(interp, "0 0\n0\n0 1\n0\n1 0\n1\n");
+        assert_eq!(cranelift, interp);
+        let a = Arena::default();
+        let (out, _, _) = run_program(
+            &a,
+            prog,
+            "10 9\n",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        assert_eq!(out, "1 0\n1\n");
+        let prog = r#"{ if (NR > 1) x = 1; print "[" x "]"; }"#;
+        let (interp, cranelift) = run_posix(prog, "a\nb\n").unwrap();
+        assert_eq!(interp, "[]\n[1]\n");
+        assert_eq!(cranelift, interp);
+        // Comparing with a number compares as strings when the field does not look like a number,
+        // with the number converted using CONVFMT.
+        let prog = r#"{ x = 10; print ($1 < 10), (10 > $1), ($1 == 10), ($1 >= x), ($1 < 1.5) }"#;
+        let (interp, cranelift) = run_posix(prog, "abc\n9\n1e1\n1.50\n+\n").unwrap();
+        assert_eq!(interp, "0 0 0 1 0\n1 1 0 0 0\n0 0 1 1 0\n1 1 0 0 0\n1 1 0 0 1\n");
+        assert_eq!(cranelift, interp);
+    }
+
+    #[test]
+    fn ordered_maps() {
+        let prog = r#"BEGIN {
+            for (i = 5; i >= 1; i--) { m["k" i] = i; a[i] = i * 10 }
+            delete m["k4"]; delete a[4]; a[0] = 0;
+            for (k in m) printf "%s ", k;
+            print "";
+            print to_json(m);
+            print _join(a, ",");
+            print to_csv(a);
+        }"#;
+        let expected = "k5 k3 k2 k1 \n{\"k5\":5,\"k3\":3,\"k2\":2,\"k1\":1}\n50,30,20,10,0\n50,30,20,10,0\n";
+        let (interp, cranelift) = run_ordered_maps(prog, "", ExecutionStrategy::Serial, 1).unwrap();
+        assert_eq!(interp, expected);
+        assert_eq!(cranelift, expected);
+
+        let prog = r#"BEGIN {
+            m["b"] = "x"; m["a"] = "y"; m["c"] = "z"; delete m["a"]
+            s[3] = "c"; s[1] = "a"; s[2] = "b,c"
+            print to_json(m);
+            print _join(s, ";");
+            print to_csv(s);
+        }"#;
+        let expected = "{\"b\":\"x\",\"c\":\"z\"}\nc;a;b,c\nc,a,\"b,c\"\n";
+        let (interp, cranelift) = run_ordered_maps(prog, "", ExecutionStrategy::Serial, 1).unwrap();
+        assert_eq!(interp, expected);
+        assert_eq!(cranelift, expected);
+
+        let prog = r#"{ a[$1][$2] = NR } END { for (i in a) for (j in a[i]) print i, j, a[i][j]; }"#;
+        let expected = "y b 1\ny a 3\nx a 2\n";
+        let (interp, cranelift) =
+            run_ordered_maps(prog, "y,b\nx,a\ny,a\n", ExecutionStrategy::Serial, 1).unwrap();
+        assert_eq!(interp, expected);
+        assert_eq!(cranelift, expected);
+
+        // With -pr, each worker's arrays are combined and their keys sorted, however the input is
+        // divided, and also when the script runs on one thread.
+        let prog = r#"{ m[$1] = $2; n[$1]++ } END { for (k in m) printf "%s ", k; print to_json(m), to_json(n) }"#;
+        let expected = "a b c d {\"a\":\"3\",\"b\":\"2\",\"c\":\"1\",\"d\":\"4\"} {\"a\":1,\"b\":1,\"c\":1,\"d\":1}\n";
+        for num_workers in [1, 3] {
+            let (interp, cranelift) = run_ordered_maps(
+                prog,
+                "c,1<<<FILE BREAK>>>b,2<<<FILE BREAK>>>a,3<<<FILE BREAK>>>d,4\n",
+                ExecutionStrategy::ShardPerFile,
+                num_workers,
+            )
+            .unwrap();
+            assert_eq!(interp, expected, "{} workers", num_workers);
+            assert_eq!(cranelift, expected, "{} workers", num_workers);
+        }
+    }
+
+    #[test]
+    fn bignum_mode() {
+        let prog = r#"{ sum += $1 }
+        END {
+            print sum, 0.1 + 0.2, 2 ^ 70, 1 / 3, -sum % 7
+            printf "%.1f %f %s\n", sum, 1 / 4, (1 / 3) ""
+            PREC = 24; OFMT = "%.10g"; print 2 / 3
+            x = 2 ^ 64; x++
+            printf "%d %025d %.2f %s\n", x, -x, 2.675 + 0, (sum > 9223372036854775807)
+            if (0.1 + 0.2 == 0.3) print "exact"
+            y = 5; z = 1 - 1
+            if (!z) print "zero", int(-3.7), y++, y
+        }"#;
+        let (interp, clif) =
+            run_bignum(prog, "9223372036854775807\n9223372036854775807\n0.10\n").unwrap();
+        let expected = "1.84467e+19 0.3 1180591620717411303424 0.333333 -0.1
+18446744073709551614.1 0.250000 0.333333
+0.66666667
+18446744073709551617 -000018446744073709551617 2.68 1
+exact
+zero -3 5 6
+";
+        assert_eq!(interp, expected);
+        assert_eq!(clif, expected);
+    }
+
+    #[test]
+    fn bignum_literals() {
+        // Literals keep all of their digits.
+        let prog = r#"BEGIN {
+            y = 12345678901234567890123; x = 0.12345678901234567890123
+            print y, y % 7, -y, y + 1, (1e400 > 1e399), (y > 12345678901234567890122)
+            OFMT = "%.30g"; print x * 1
+        }"#;
+        let (interp, clif) = run_bignum(prog, "").unwrap();
+        let expected = "12345678901234567890123 3 -12345678901234567890123 12345678901234567890124 1 1
+0.12345678901234567890123
+";
+        assert_eq!(interp, expected);
+        assert_eq!(clif, expected);
+    }
+
+    #[test]
+    fn checked_arith() {
+        let prog = r#"BEGIN {
+            x = 9223372036854775806; x++
+            print x, -7 % 3, 7 / 2, x - 1
+        }"#;
+        let expected = "9223372036854775807 -1 3.5 9223372036854775806\n".to_string();
+        let out = run_checked_arith(prog, CheckedArith::Error, "").unwrap();
+        assert_eq!(out, (expected.clone(), expected));
+
+        // These stop at the first error, from the interpreter; the compiled backends end the
+        // process instead, so tests/runtime_errors.rs checks these on each backend.
+        for (prog, msg) in [
+            (
+                "BEGIN { x = 9223372036854775807; print x + 1 }",
+                "integer overflow in 9223372036854775807 + 1",
+            ),
+            (
+                "BEGIN { x = 3037000499; print x * x * 2 }",
+                "integer overflow in 9223372030926249001 * 2",
+            ),
+            (
+                "BEGIN { a[1] = 9223372036854775807; a[1] += 1 }",
+                "integer overflow in 9223372036854775807 + 1",
+            ),
+            (
+                "BEGIN { x = -9223372036854775807; x--; print -x }",
+                "integer overflow in 0 - -9223372036854775808",
+            ),
+            ("BEGIN { x = 0; print 1 / x }", "division by zero in 1 / 0"),
+            ("BEGIN { x = 0; print 5 % x }", "division by zero in 5 % 0"),
+        ] {
+            let err = run_checked_arith(prog, CheckedArith::Error, "").unwrap_err();
+            assert!(err.0.contains(msg), "{}: {}", prog, err.0);
+        }
+
+        let prog = r#"BEGIN {
+            x = 9223372036854775807; y = x * 4
+            z = 3; z++
+            print (y > x), y / x, 7 % 2, z
+        }"#;
+        let expected = "1 4 1 4\n".to_string();
+        let out = run_checked_arith(prog, CheckedArith::Float, "").unwrap();
+        assert_eq!(out, (expected.clone(), expected));
+    }
+
+    // Coprocesses are shared by every interpreter in the process, keyed by their command, so the
+    // backends run one after the other here rather than as separate tests.
+    #[test]
+    fn coprocess() {
+        let prog = r#"BEGIN {
+            print "hello" |& "cat";
+            "cat" |& getline x;
+            print "cat:", x;
+            close("cat");
+            cmd = "sort";
+            print "b" |& cmd; print "a" |& cmd;
+            close(cmd, "to");
+            while ((cmd |& getline line) > 0) print "sorted:", line;
+            close(cmd);
+        }"#;
+        let expected = "cat: hello\nsorted: a\nsorted: b\n";
+        let a = Arena::default();
+        let (out, _, _) = run_program(
+            &a,
+            prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        assert_eq!(out, expected);
+        let out = run_cranelift(prog, "", Escaper::Identity, None, ExecutionStrategy::Serial);
+        assert_eq!(out.unwrap(), expected);
+    }
+
+    #[test]
+    fn special_files() {
+        let prog = r#"BEGIN {
+            print "a";
+            print "b" > "/dev/stdout";
+            print "oops" > "/dev/stderr";
+            print "c" > "-";
+            printf "%s\n", "d" > "/dev/fd/1";
+            close("/dev/stdout");
+            print "e";
+        }"#;
+        let (interp, cranelift) = run_files(prog, "").unwrap();
+        for fs in [interp, cranelift] {
+            assert_eq!(fs.stdout.read_data(), b"a\nb\nc\nd\ne\n");
+            let stderr = fs
+                .get_handle("/dev/fd/2")
+                .expect("nothing written to /dev/stderr");
+            assert_eq!(stderr.read_data(), b"oops\n");
+        }
+    }
+
+    #[test]
+    fn getline_shares_stdin() {
+        // `-` and `/dev/stdin` pick up where the main input left off.
+        let prog = r#"BEGIN { getline x < "-" }
+        NR == 1 {
+            getline y < "/dev/stdin";
+            print x, $1, y;
+        }
+        END { print NR }"#;
+        let (interp, cranelift) = run_stdin_input(prog, "a\nb\nc\nd\n").unwrap();
+        assert_eq!(interp, "a b c\n2\n");
+        assert_eq!(cranelift, interp);
+    }
+
+    // A line-based service on a local port: it answers each line with the line in upper case,
+    // until the client closes its end of the connection.
+    fn upcase_server() -> (u16, std::thread::JoinHandle<()>) {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut out = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                writeln!(out, "{}", line.unwrap().to_uppercase()).unwrap();
+            }
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn network_files() {
+        let prog = |port: u16| {
+            format!(
+                r#"BEGIN {{
+                    s = "/inet/tcp/0/127.0.0.1/{}";
+                    print "hello" |& s; s |& getline x; print x;
+                    print "bye" > s; getline y < s; print y;
+                    close(s);
+                }}"#,
+                port
+            )
+        };
+        let expected = "HELLO\nBYE\n";
+        let (port, server) = upcase_server();
+        let a = Arena::default();
+        let (out, _, _) = run_program(
+            &a,
+            &prog(port),
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        assert_eq!(out, expected);
+        server.join().unwrap();
+        let (port, server) = upcase_server();
+        let out = run_cranelift(
+            &prog(port),
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        );
+        assert_eq!(out.unwrap(), expected);
+        server.join().unwrap();
+
+        let listener = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let prog = format!(
+            r#"BEGIN {{ s = "/inet/udp/0/127.0.0.1/{}"; print "ping" > s; close(s) }}"#,
+            port
+        );
+        let out = run_cranelift(
+            &prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        );
+        assert_eq!(out.unwrap(), "");
+        let mut buf = [0; 16];
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping\n");
+
+        // Listening on the address in the name.
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let client = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+                // Keep sending until the program has bound the port and received a datagram.
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let _ = socket.send_to(b"pong\n", ("127.0.0.1", port));
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                }
+            })
+        };
+        let prog = format!(
+            r#"BEGIN {{ s = "/inet/udp/{}/127.0.0.1/0"; s |& getline x; print x }}"#,
+            port
+        );
+        let a = Arena::default();
+        let res = run_program(
+            &a,
+            &prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        );
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        client.join().unwrap();
+        assert_eq!(res.unwrap().0, "pong\n");
+
+        // Connecting from a chosen local port.
+        let lport = free_port();
+        let (port, server) = upcase_server();
+        let prog = format!(
+            r#"BEGIN {{ s = "/inet/tcp/{}/127.0.0.1/{}"; print "hi" |& s; s |& getline x; print x; close(s) }}"#,
+            lport, port
+        );
+        let a = Arena::default();
+        let (out, _, _) = run_program(
+            &a,
+            &prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        assert_eq!(out, "HI\n");
+        server.join().unwrap();
+    }
+
+    // A local TCP port that nothing is listening on.
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn network_file_refused() {
+        let prog = format!(
+            r#"BEGIN {{ print "x" > "/inet/tcp/0/127.0.0.1/{}"; print "after" }}"#,
+            free_port()
+        );
+        let a = Arena::default();
+        let err = run_program(
+            &a,
+            &prog,
+            "",
+            Escaper::Identity,
+            None,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap_err();
+        assert!(err.0.contains("failed to open network file"), "{}", err.0);
+    }
+
+    test_program!(
+        switch_basic,
+        r#"{
+            switch ($1) {
+            case "apple":
+                print "fruit"; break
+            case /^[0-9]+$/:
+                print "number"; break
+            case "carrot":
+            case "leek":
+                print "vegetable"
+                break
+            default:
+                print "unknown"
+            }
+        }"#,
+        "fruit\nnumber\nvegetable\nvegetable\nunknown\n",
+        @input "apple\n123\ncarrot\nleek\nrock"
+    );
+
+    test_program!(
+        switch_fallthrough,
+        r#"BEGIN {
+            for (i = 1; i <= 3; i++) {
+                switch (i) {
+                case 1: printf "one "
+                case 2: printf "two "; break
+                case -1: print "negative"
+                default: printf "other "; continue
+                }
+                printf "| "
+            }
+            print ""
+        }"#,
+        "one two | two | other \n"
+    );
+
+    test_program!(
+        switch_regex_cases,
+        r#"function kind(x) {
+            switch (x) {
+            case /^[0-9]+$/: return "number"
+            case "abc": return "literal"
+            case /^[a-z]+$/: return "word"
+            case /^a/: return "prefix"
+            default: return "other"
+            }
+        }
+        { print kind($1) }
+        END { IGNORECASE = 1; print kind("XYZ"), kind("A1") }"#,
+        "number\nliteral\nword\nprefix\nother\nword prefix\n",
+        @input "123\nabc\nabd\na1\n?"
+    );
+
+    test_program!(
+        for_continue,
+        r#"BEGIN {
+            for (i = 0; i < 5; i++) { if (i % 2) continue; printf "%d ", i }
+            print ""
+            for (i = 10; i > 0; i -= 3) { if (i == 7) continue; printf "%d ", i }
+            print ""
+        }"#,
+        "0 2 4 \n10 4 1 \n"
+    );
+
     test_program!(
         basic_match_loc,
         r#"BEGIN {
@@ -1470,6 +2245,192 @@ function do_something(v) {
         @input "hello\n"
     );
 
+    test_program!(
+        convfmt_ofmt,
+        r#"BEGIN {
+    x = 3.14159265
+    print x, x ""
+    OFMT = "%.2f"
+    print x, x "", 17.0
+    CONVFMT = "%.3f"
+    print x, x ""
+}"#,
+        "3.14159265 3.14159265\n3.14 3.14159265 17\n3.14 3.142\n"
+    );
+
+    test_program!(
+        convfmt_printf,
+        r#"BEGIN {
+    x = 3.14159265
+    printf "%s %d\n", x, x
+    CONVFMT = "%.2f"
+    printf "%s %d %s %s\n", x, x, 17.0, 2^63
+    print sprintf("%s", x)
+}"#,
+        "3.14159265 3\n3.14 3 17 9223372036854775808.00\n3.14\n"
+    );
+
+    test_program!(
+        fieldwidths_basic,
+        r#"BEGIN { FIELDWIDTHS = "3 2 *" } { print $1 "|" $2 "|" $3, NF }"#,
+        "abc|de|fgh 3\nxy|| 1\n",
+        @input "abcdefgh\nxy\n"
+    );
+
+    test_program!(
+        fieldwidths_projection,
+        r#"BEGIN { FIELDWIDTHS = "1:2 3" } { print $2 }"#,
+        "def\nmno\n",
+        @input "abcdefg\njklmnop\n"
+    );
+
+    test_program!(
+        fpat_fields,
+        r#"BEGIN { FPAT = "(\"[^\"]*\")|([^,]*)" } { print NF, $2; $1 = "x"; print }"#,
+        "3 \"b,c\"\nx \"b,c\" \n2 \nx \n",
+        @input "a,\"b,c\",\nd,\n"
+    );
+
+    test_program!(
+        fieldwidths_then_fs,
+        r#"BEGIN { FIELDWIDTHS = "2" } { print $1; FS = "," }"#,
+        "ab\nabcd\n",
+        @input "abcd,ef\nabcd,ef\n"
+    );
+
+    test_program!(
+        ignorecase_regex,
+        r#"BEGIN { IGNORECASE = 1 } /foo/ { n++ } $0 ~ "BAR" { m++ }
+        END { s = "HellO WOrld"; gsub(/o/, "0", s); print n, m, s, match("xABC", /abc/), RSTART, index("Hello", "LL") }"#,
+        "2 2 Hell0 W0rld 2 2 3\n",
+        @input "FOO\nbar\nfoo bar\n"
+    );
+
+    test_program!(
+        ignorecase_fs_compare,
+        r#"BEGIN { IGNORECASE = 1; FS = "x" } { s = "b"; print $2, ($1 == "A"), ($2 < "C"), (s < "C") }"#,
+        "b 1 1 1\n",
+        @input "aXb\n"
+    );
+
+    test_program!(
+        ignorecase_toggle,
+        r#"{ IGNORECASE = NR % 2; print ($0 ~ /a/), index($0, "a") }"#,
+        "1 1\n0 0\n",
+        @input "A\nA\n"
+    );
+
+    test_program!(
+        ignorecase_unicode,
+        r#"BEGIN { IGNORECASE = 1; print ("ÉTÉ" == "été"), ("été" ~ /ÉTÉ/), ("Ab" ~ /^a/), index("aÉTÉ", "été"), ("b" < "Á") }"#,
+        "1 1 1 2 1\n"
+    );
+
+    test_program!(
+        paragraph_mode,
+        r#"BEGIN { RS = "" } { print NF, $1, $NF; n++ } END { print n }"#,
+        "3 a c\n2 d e\n2\n",
+        @input "\n\na b\nc\n\n\nd e\n"
+    );
+
+    test_program!(
+        paragraph_mode_single_char_fs,
+        r#"BEGIN { RS = ""; FS = "," } { print NF, $2 }"#,
+        "3 b\n1 \n",
+        @input "a,b\nc\n\nd"
+    );
+
+    test_program!(
+        regex_rs_rt,
+        r#"BEGIN { RS = "[0-9]+" } { print $0 "|" RT }"#,
+        "ab|12\ncd|345\nef|\n",
+        @input "ab12cd345ef"
+    );
+
+    test_program!(
+        default_rs_rt,
+        r#"{ print RT }"#,
+        "\n\n\n\n\n",
+        @input "a\nb\nc"
+    );
+
+    test_program!(
+        sorted_in_builtin,
+        r#"BEGIN {
+    m["b"] = 2; m["a"] = 3; m["c"] = 1
+    PROCINFO["sorted_in"] = "@ind_str_asc"
+    for (k in m) printf "%s", k
+    PROCINFO["sorted_in"] = "@val_num_asc"
+    for (k in m) printf "%s", k
+    print ""
+}"#,
+        "abccba\n"
+    );
+
+    test_program!(
+        sorted_in_function,
+        r#"function by_len(i1, v1, i2, v2) { return length(i2) - length(i1) }
+BEGIN {
+    m["aaa"] = 1; m["b"] = 2; m["cc"] = 3
+    PROCINFO["sorted_in"] = "by_len"
+    for (k in m) print k
+}"#,
+        "aaa\ncc\nb\n"
+    );
+
+    test_program!(
+        sorted_in_function_local_array,
+        r#"function rev(i1, v1, i2, v2) { return i2 - i1 }
+function show(a,   k, s) { for (k in a) { if (k == 5) continue; s = s k " " } return s }
+BEGIN {
+    for (i = 1; i <= 10; i++) m[i] = i
+    PROCINFO["sorted_in"] = "rev"
+    print show(m)
+}"#,
+        "10 9 8 7 6 4 3 2 1 \n"
+    );
+
+    test_program!(
+        nested_loops,
+        r#"BEGIN {
+    for (i = 1; i <= 2; i++) {
+        for (j = 1; j <= 2; j++) printf "%d%d ", i, j
+    }
+    while (k < 2) {
+        for (j = 0; j < 2; j++) printf "%d%d ", k, j
+        k++
+    }
+    print ""
+}"#,
+        "11 12 21 22 00 01 10 11 \n"
+    );
+
+    #[test]
+    fn sorted_in_unknown_function() {
+        let prog = "BEGIN {\n  PROCINFO[\"sorted_in\"] = \"by_len\"\n}";
+        match program_compiles(prog, false) {
+            Ok(()) => panic!("expected an unknown sorted_in order to fail"),
+            Err(e) => {
+                assert!(e.0.contains("\"by_len\""), "{}", e);
+                assert_eq!(e.1.map(|loc| (loc.line, loc.col)), Some((1, 2)), "{}", e);
+            }
+        }
+    }
+
+    test_program!(
+        decimal_builtins,
+        r#"{ a[NR] = $1 }
+END {
+    print _sum(a)
+    print decimal_add("1.10", "2.20"), decimal_sub("0.3", "0.1"), decimal_mul("19.99", "3")
+    print decimal_mul("2.665", "1", 2), decimal_mul("2.665", "1", 2, "half_up")
+    print decimal_div("10.00", "3"), decimal_div("1", "3", 4), decimal_round("2.5"), decimal_round("2.5", 0, "half_up"), decimal_round("-1.005", 2, "half_up")
+    print decimal_cmp("0.30", "0.3")
+}"#,
+        "20.29\n3.30 0.2 59.97\n2.66 2.67\n3.33 0.3333 2 3 -1.01\n0\n",
+        @input "19.99\n0.10\n0.20\n"
+    );
+
     // TODO test more operators, consider more edge cases around functions
 }
 
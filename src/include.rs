@@ -0,0 +1,489 @@
+//! Support for the `@include` directive.
+//!
+//! Includes are expanded textually before the program is parsed: a line of the form
+//! `@include "lib/strings.awk"` is replaced by the contents of the named file. A relative path is
+//! resolved against the directory of the including file (or the current directory for programs
+//! passed on the command line), then each directory in `ZAWK_PATH`, and finally the user library
+//! directory `~/.awk/lib`. Each file is included at most once, and including a file that is
+//! already being expanded is an error.
+//!
+//! The expanded program keeps track of where each of its lines came from, so that errors can be
+//! reported in terms of the original files.
+use hashbrown::HashSet;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::{CompileError, Result};
+use crate::lexer::{self, Tok};
+
+/// The environment variable holding additional directories to search for included files.
+pub const SEARCH_PATH_VAR: &str = "ZAWK_PATH";
+
+/// The name given to programs passed directly on the command line.
+pub const COMMAND_LINE: &str = "<command line>";
+
+lazy_static! {
+    static ref INCLUDE: Regex = Regex::new(r#"^\s*@include\s+"([^"]+)"\s*;?\s*(#.*)?$"#).unwrap();
+}
+
+/// Whether `line` is an `@include` directive (well-formed or not).
+pub fn is_include(line: &str) -> bool {
+    line.trim_start().starts_with("@include")
+}
+
+// A run of lines in the expanded program that come from a single file.
+#[derive(Clone)]
+struct Segment {
+    // The first line of the run in the expanded program.
+    start: usize,
+    // Index into `Source::files`.
+    file: usize,
+    // The line in `file` corresponding to `start`.
+    line: usize,
+}
+
+/// A program with all of its includes expanded.
+#[derive(Clone)]
+pub struct Source {
+    pub text: String,
+    files: Vec<String>,
+    segments: Vec<Segment>,
+}
+
+impl Source {
+    /// Load a program from the given files or URLs, in order. As in gawk, a file given more than
+    /// once is loaded each time; only `@include` skips files that have already been loaded.
+    pub fn from_files(names: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Source> {
+        let mut loader = Loader::default();
+        for name in names {
+            let name = name.as_ref();
+            let key = if is_url(name) {
+                name.to_string()
+            } else {
+                canonical(Path::new(name)).unwrap_or_else(|| name.to_string())
+            };
+            loader.seen.insert(key.clone());
+            let text = read_source(name)?;
+            loader.expand(key, &text)?;
+        }
+        Ok(loader.finish())
+    }
+
+    /// Load a program passed as a string on the command line.
+    pub fn from_text(text: &str) -> Result<Source> {
+        let mut loader = Loader::default();
+        loader.expand(COMMAND_LINE.to_string(), text)?;
+        Ok(loader.finish())
+    }
+
+    /// Load a program from the (possibly unsaved) contents of the file `name`, expanding its
+    /// includes relative to that file. Files included by URL are read with `fetch`.
+    pub fn from_buffer(name: &str, text: &str, fetch: Fetch<'_>) -> Result<Source> {
+        let mut loader = Loader {
+            fetch: Some(fetch),
+            ..Loader::default()
+        };
+        let key = canonical(Path::new(name)).unwrap_or_else(|| name.to_string());
+        loader.seen.insert(key.clone());
+        loader.expand(key, text)?;
+        Ok(loader.finish())
+    }
+
+    /// Wrap the contents of a single file without expanding its includes.
+    pub fn unexpanded(name: &str, text: &str) -> Source {
+        Source {
+            text: text.to_string(),
+            files: vec![name.to_string()],
+            segments: vec![Segment {
+                start: 0,
+                file: 0,
+                line: 0,
+            }],
+        }
+    }
+
+    /// The name of the first file making up the program.
+    pub fn main_file(&self) -> &str {
+        self.files.first().map_or(COMMAND_LINE, String::as_str)
+    }
+
+    /// Map a (0-indexed) line of the expanded program back to the file it came from and the
+    /// (0-indexed) line within that file.
+    pub fn locate(&self, line: usize) -> (&str, usize) {
+        let ix = match self.segments.binary_search_by_key(&line, |s| s.start) {
+            Ok(ix) => ix,
+            Err(0) => return (COMMAND_LINE, line),
+            Err(ix) => ix - 1,
+        };
+        let seg = &self.segments[ix];
+        (self.files[seg.file].as_str(), seg.line + (line - seg.start))
+    }
+
+    /// The inverse of `locate`: map a (0-indexed) line of `file` to the line of the expanded
+    /// program it ended up on. `file` may be a trailing part of the file's path, such as its name.
+    pub fn expanded_line(&self, file: &str, line: usize) -> Option<usize> {
+        let total = self.text.lines().count();
+        self.segments.iter().enumerate().find_map(|(i, seg)| {
+            let name = self.files[seg.file].as_str();
+            let matches = name == file
+                || (name.ends_with(file) && name[..name.len() - file.len()].ends_with('/'));
+            let end = self.segments.get(i + 1).map_or(total, |next| next.start);
+            let ix = seg.start + line.checked_sub(seg.line)?;
+            (matches && ix < end).then_some(ix)
+        })
+    }
+
+    /// Render `loc` as `file:line:column`.
+    pub fn describe(&self, loc: lexer::Loc) -> String {
+        let (file, line) = self.locate(loc.line);
+        format!("{}:{}:{}", file, line + 1, loc.col + 1)
+    }
+
+    /// Render a parse error in terms of the original source files.
+    pub fn parse_error(
+        &self,
+        e: lalrpop_util::ParseError<lexer::Loc, Tok, lexer::Error>,
+    ) -> String {
+        self.render_error(&Source::parse_failure(e))
+    }
+
+    /// Convert a parse error into a `CompileError` located at the offending token.
+    pub fn parse_failure(
+        e: lalrpop_util::ParseError<lexer::Loc, Tok, lexer::Error>,
+    ) -> CompileError {
+        use lalrpop_util::ParseError::*;
+        let (loc, msg) = match e {
+            InvalidToken { location } => (location, String::from("invalid token")),
+            UnrecognizedEof { location, expected } => (
+                location,
+                format!(
+                    "unexpected end of program, expected one of {}",
+                    expected.join(", ")
+                ),
+            ),
+            UnrecognizedToken {
+                token: (location, tok, _),
+                expected,
+            } => (
+                location,
+                format!(
+                    "unexpected {}, expected one of {}",
+                    tok,
+                    expected.join(", ")
+                ),
+            ),
+            ExtraToken {
+                token: (location, tok, _),
+            } => (location, format!("extra token {}", tok)),
+            User { error } => (error.location, String::from(error.desc)),
+        };
+        CompileError::new(msg).at(loc)
+    }
+
+    /// Render an error in terms of the original source files. If the error has a location, the
+    /// offending line is printed beneath the message, with a caret pointing at the location.
+    pub fn render_error(&self, e: &CompileError) -> String {
+        match e.1 {
+            Some(loc) => self.render(loc, &e.0),
+            None => e.0.clone(),
+        }
+    }
+
+    /// Render a warning at `loc` in the same format as `render_error`.
+    pub fn render_warning(&self, loc: lexer::Loc, msg: &str) -> String {
+        self.render(loc, &format!("warning: {}", msg))
+    }
+
+    fn render(&self, loc: lexer::Loc, msg: &str) -> String {
+        let mut res = format!("{}: {}", self.describe(loc), msg);
+        if let Some(line) = self.text.lines().nth(loc.line) {
+            // Preserve tabs so that the caret lines up with the source line.
+            let indent: String = line
+                .get(..loc.col)
+                .unwrap_or("")
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            res.push('\n');
+            res.push_str(line);
+            res.push('\n');
+            res.push_str(&indent);
+            res.push('^');
+        }
+        res
+    }
+}
+
+// Reads a file included by URL.
+type Fetch<'a> = &'a dyn Fn(&str) -> Result<String>;
+
+#[derive(Default)]
+struct Loader<'a> {
+    text: String,
+    lines: usize,
+    files: Vec<String>,
+    segments: Vec<Segment>,
+    // Files currently being expanded, used to detect cycles.
+    stack: Vec<String>,
+    // Files that have already been included.
+    seen: HashSet<String>,
+    // Reads files included by URL, if not `read_source`.
+    fetch: Option<Fetch<'a>>,
+}
+
+impl Loader<'_> {
+    fn finish(self) -> Source {
+        Source {
+            text: self.text,
+            files: self.files,
+            segments: self.segments,
+        }
+    }
+
+    fn begin_segment(&mut self, file: usize, line: usize) {
+        if let Some(last) = self.segments.last_mut() {
+            if last.start == self.lines {
+                // The previous segment is empty; replace it.
+                last.file = file;
+                last.line = line;
+                return;
+            }
+        }
+        self.segments.push(Segment {
+            start: self.lines,
+            file,
+            line,
+        });
+    }
+
+    fn expand(&mut self, name: String, text: &str) -> Result<()> {
+        let file = self.files.len();
+        self.files.push(name.clone());
+        self.stack.push(name.clone());
+        self.begin_segment(file, 0);
+        for (i, line) in text.lines().enumerate() {
+            if !is_include(line) {
+                self.text.push_str(line);
+                self.text.push('\n');
+                self.lines += 1;
+                continue;
+            }
+            let target = match INCLUDE.captures(line) {
+                Some(caps) => caps.get(1).unwrap().as_str(),
+                None => {
+                    return Err(CompileError::new(format!(
+                        "{}:{}: malformed @include directive, expected @include \"file\"",
+                        name,
+                        i + 1
+                    )))
+                }
+            };
+            let key = resolve(target, &name).ok_or_else(|| {
+                CompileError::new(format!(
+                    "{}:{}: cannot find included file {:?}",
+                    name,
+                    i + 1,
+                    target
+                ))
+            })?;
+            if self.stack.contains(&key) {
+                return Err(CompileError::new(format!(
+                    "{}:{}: @include cycle: {} -> {}",
+                    name,
+                    i + 1,
+                    self.stack.join(" -> "),
+                    key
+                )));
+            }
+            if self.seen.insert(key.clone()) {
+                let contents = match self.fetch {
+                    Some(fetch) if is_url(&key) => fetch(&key)?,
+                    _ => read_source(&key)?,
+                };
+                self.expand(key, &contents)?;
+            }
+            self.begin_segment(file, i + 1);
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+fn is_url(s: &str) -> bool {
+    s.starts_with("https://") || s.starts_with("http://")
+}
+
+fn canonical(p: &Path) -> Option<String> {
+    fs::canonicalize(p)
+        .ok()?
+        .into_os_string()
+        .into_string()
+        .ok()
+}
+
+pub(crate) fn read_source(name: &str) -> Result<String> {
+    if is_url(name) {
+        reqwest::blocking::get(name)
+            .and_then(|resp| resp.error_for_status())
+            .and_then(|resp| resp.text())
+            .map_err(|e| CompileError::new(format!("failed to read program from {}: {}", name, e)))
+    } else {
+        fs::read_to_string(name)
+            .map_err(|e| CompileError::new(format!("failed to read program from {}: {}", name, e)))
+    }
+}
+
+/// The directories searched for included files after the directory of the including file.
+fn search_path() -> Vec<PathBuf> {
+    let mut res: Vec<PathBuf> = env::var_os(SEARCH_PATH_VAR)
+        .map(|p| env::split_paths(&p).collect())
+        .unwrap_or_default();
+    if let Some(home) = dirs::home_dir() {
+        res.push(home.join(".awk").join("lib"));
+    }
+    res
+}
+
+/// Find the file that `target` refers to when included from `includer`, returning the name used
+/// to read it (and to de-duplicate includes).
+fn resolve(target: &str, includer: &str) -> Option<String> {
+    if is_url(target) {
+        return Some(target.to_string());
+    }
+    if is_url(includer) {
+        if let Ok(url) = url::Url::parse(includer).and_then(|base| base.join(target)) {
+            return Some(url.into());
+        }
+    }
+    let target = Path::new(target);
+    let mut roots = Vec::new();
+    if !target.is_absolute() {
+        let base = if includer == COMMAND_LINE {
+            env::current_dir().ok()
+        } else {
+            Path::new(includer).parent().map(Path::to_path_buf)
+        };
+        roots.extend(base);
+        roots.extend(search_path());
+    } else {
+        roots.push(PathBuf::new());
+    }
+    for dir in roots.iter() {
+        let candidate = dir.join(target);
+        if candidate.is_file() {
+            return canonical(&candidate);
+        }
+        // Like gawk, fall back to adding an ".awk" extension.
+        if candidate.extension().is_none() {
+            let candidate = candidate.with_extension("awk");
+            if candidate.is_file() {
+                return canonical(&candidate);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> String {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        canonical(&path).unwrap()
+    }
+
+    #[test]
+    fn nested_includes() {
+        let dir = tempdir().unwrap();
+        let strings = write(
+            dir.path(),
+            "lib/strings.awk",
+            "@include \"util\"\nfunction up(s) { return toupper(s) }\n",
+        );
+        let util = write(dir.path(), "lib/util.awk", "function id(x) { return x }\n");
+        let main = write(
+            dir.path(),
+            "main.awk",
+            "# main\n@include \"lib/strings.awk\"\n@include \"lib/util.awk\"\n{ print up(id($0)) }\n",
+        );
+        let src = Source::from_files([&main]).unwrap();
+        assert_eq!(
+            src.text,
+            "# main\nfunction id(x) { return x }\nfunction up(s) { return toupper(s) }\n{ print up(id($0)) }\n"
+        );
+        assert_eq!(src.locate(0), (main.as_str(), 0));
+        assert_eq!(src.locate(1), (util.as_str(), 0));
+        assert_eq!(src.locate(2), (strings.as_str(), 1));
+        assert_eq!(src.locate(3), (main.as_str(), 3));
+        assert_eq!(src.expanded_line("main.awk", 3), Some(3));
+        assert_eq!(src.expanded_line("lib/strings.awk", 1), Some(2));
+        assert_eq!(src.expanded_line(&util, 0), Some(1));
+        // The @include lines themselves do not survive expansion.
+        assert_eq!(src.expanded_line("main.awk", 1), None);
+        assert_eq!(src.expanded_line("ain.awk", 3), None);
+    }
+
+    #[test]
+    fn repeated_files() {
+        let dir = tempdir().unwrap();
+        let lib = write(dir.path(), "lib.awk", "function f() { return 1 }\n");
+        let main = write(
+            dir.path(),
+            "main.awk",
+            "@include \"lib.awk\"\nBEGIN { f() }\n",
+        );
+        let src = Source::from_files([&lib, &main, &lib]).unwrap();
+        assert_eq!(
+            src.text,
+            "function f() { return 1 }\nBEGIN { f() }\nfunction f() { return 1 }\n"
+        );
+        assert_eq!(src.locate(2), (lib.as_str(), 0));
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = tempdir().unwrap();
+        write(dir.path(), "a.awk", "@include \"b.awk\"\n");
+        write(dir.path(), "b.awk", "@include \"a.awk\"\n");
+        let err = Source::from_files([dir.path().join("a.awk").to_str().unwrap()])
+            .err()
+            .unwrap();
+        assert!(err.0.contains("@include cycle"), "{}", err);
+    }
+
+    #[test]
+    fn render_located_error() {
+        let src = Source::from_text("BEGIN {\n\tx = 1\n\ty = \"(\" ~ x\n}").unwrap();
+        let loc = lexer::Tokenizer::new(src.text.as_str())
+            .map(|tok| tok.unwrap().0)
+            .find(|loc| loc.line == 2)
+            .unwrap();
+        let err = CompileError::new("regex parse error").at(loc);
+        assert_eq!(
+            src.render_error(&err),
+            "<command line>:3:2: regex parse error\n\ty = \"(\" ~ x\n\t^"
+        );
+    }
+
+    #[test]
+    fn missing_include() {
+        let err = Source::from_text("@include \"this/does/not/exist.awk\"\nBEGIN {}")
+            .err()
+            .unwrap();
+        assert!(
+            err.0.starts_with("<command line>:1: cannot find"),
+            "{}",
+            err
+        );
+    }
+}
@@ -113,6 +113,14 @@ impl TaintedStringAnalysis {
                     self.dfa.add_query(cmd);
                 }
             }
+            ReadErrCoproc(dst, cmd) => {
+                self.dfa.add_src(dst, Taint::Tainted);
+                self.dfa.add_query(cmd);
+            }
+            NextLineCoproc(dst, cmd) => {
+                self.dfa.add_src(dst, Taint::Tainted);
+                self.dfa.add_query(cmd);
+            }
             GetColumn(dst, _) => self.dfa.add_src(dst, Taint::Tainted),
             ReadErrStdin(dst) => self.dfa.add_src(dst, Taint::Tainted),
             NextLineStdin(dst) => self.dfa.add_src(dst, Taint::Tainted),
@@ -120,11 +128,11 @@ impl TaintedStringAnalysis {
             StoreConstInt(dst, _) => self.dfa.add_src(dst, Taint::Okay),
             StoreConstFloat(dst, _) => self.dfa.add_src(dst, Taint::Okay),
             PrintAll {
-                output: Some((cmd, FileSpec::Cmd)),
+                output: Some((cmd, FileSpec::Cmd | FileSpec::Coproc)),
                 ..
             }
             | Printf {
-                output: Some((cmd, FileSpec::Cmd)),
+                output: Some((cmd, FileSpec::Cmd | FileSpec::Coproc)),
                 ..
             } => self.dfa.add_query(cmd),
             RunCmd(dst, cmd) => {
@@ -179,6 +187,8 @@ mod tests {
         let progs: &[&str] = &[
             "BEGIN { print $1 | $2; }",
             "BEGIN { while ($1 | getline) print; }",
+            "BEGIN { print $1 |& $2; }",
+            "BEGIN { while ($1 |& getline) print; }",
             "BEGIN { while (length($1) | getline) print; }",
             r#"BEGIN { while (getline x) print y | ("echo " x); }"#,
             "BEGIN { if ($2) { j = $1 3; x=j;} print y | x }",
@@ -202,6 +212,7 @@ mod tests {
         let progs: &[&str] = &[
             r#"BEGIN { print "hello" | "command"; }"#,
             r#"BEGIN { while ("command" | getline) print; }"#,
+            r#"BEGIN { print "x" |& "cat"; "cat" |& getline y; }"#,
             r#"BEGIN { if ($1) x=5; else y="hi"; print "should work" | x; }"#,
             r#"function x(a, b) { print $2; return a b;}
             BEGIN { while(x("echo ", "hi") | getline) print; }"#,
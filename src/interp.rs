@@ -2,12 +2,15 @@ use crate::builtins::Variable;
 use crate::bytecode::{Get, Instr, Label, Reg};
 use crate::common::{NumTy, Result, Stage};
 use crate::compile::{self, Ty};
+use crate::debugger::Debugger;
+use crate::cfg::SrcLoc;
+use crate::lexer::Loc;
+use crate::profile::Profile;
 use crate::pushdown::FieldSet;
-use crate::runtime::{self, Float, Int, Line, LineReader, Str, UniqueStr};
+use crate::runtime::{self, Float, Int, Line, LineReader, MapData, Str, UniqueStr};
 
 use crossbeam::scope;
 use crossbeam_channel::bounded;
-use hashbrown::HashMap;
 use rand::{self, rngs::StdRng, Rng, SeedableRng};
 use regex::bytes::Regex;
 
@@ -28,10 +31,18 @@ pub(crate) struct Storage<T> {
 pub(crate) struct Core<'a> {
     pub vars: runtime::Variables<'a>,
     pub regexes: runtime::RegexCache,
+    // Set while FIELDWIDTHS or FPAT, rather than FS, splits records into fields.
+    pub field_split: Option<runtime::splitter::FieldSplit>,
     pub write_files: runtime::FileWrite,
     pub rng: StdRng,
     pub current_seed: u64,
     pub slots: Slots,
+    // Set when running under `--profile`.
+    pub profile: Option<Box<Profile>>,
+    // Set by `--ordered-maps`: arrays allocated by this runtime iterate in insertion order.
+    pub ordered_maps: bool,
+    // Set by `-M`: `printf` prints the numbers held in strings exactly.
+    pub bignum: bool,
 }
 
 impl<'a> Drop for Core<'a> {
@@ -39,6 +50,9 @@ impl<'a> Drop for Core<'a> {
         if let Err(e) = self.write_files.shutdown() {
             eprintln_ignore!("{}", e);
         }
+        if let Some(profile) = &mut self.profile {
+            profile.finish();
+        }
     }
 }
 
@@ -52,12 +66,14 @@ pub(crate) struct Slots {
     pub int: Vec<Int>,
     pub float: Vec<Float>,
     pub strs: Vec<UniqueStr<'static>>,
-    pub intint: Vec<HashMap<Int, Int>>,
-    pub intfloat: Vec<HashMap<Int, Float>>,
-    pub intstr: Vec<HashMap<Int, UniqueStr<'static>>>,
-    pub strint: Vec<HashMap<UniqueStr<'static>, Int>>,
-    pub strfloat: Vec<HashMap<UniqueStr<'static>, Float>>,
-    pub strstr: Vec<HashMap<UniqueStr<'static>, UniqueStr<'static>>>,
+    pub intint: Vec<MapData<Int, Int>>,
+    pub intfloat: Vec<MapData<Int, Float>>,
+    pub intstr: Vec<MapData<Int, UniqueStr<'static>>>,
+    pub strint: Vec<MapData<UniqueStr<'static>, Int>>,
+    pub strfloat: Vec<MapData<UniqueStr<'static>, Float>>,
+    pub strstr: Vec<MapData<UniqueStr<'static>, UniqueStr<'static>>>,
+    pub intmap: Vec<MapData<Int, MapData<UniqueStr<'static>, UniqueStr<'static>>>>,
+    pub strmap: Vec<MapData<UniqueStr<'static>, MapData<UniqueStr<'static>, UniqueStr<'static>>>>,
 }
 
 /// A Simple helper trait for implement aggregations for slot values and variables.
@@ -88,14 +104,20 @@ impl<'a> Agg for UniqueStr<'a> {
     }
 }
 
-impl<K: std::hash::Hash + Eq, V: Agg + Default> Agg for HashMap<K, V> {
-    fn agg(mut self, other: HashMap<K, V>) -> HashMap<K, V> {
+impl<K: std::hash::Hash + Eq, V: Agg + Default> Agg for MapData<K, V> {
+    fn agg(self, other: MapData<K, V>) -> MapData<K, V> {
+        // Slots start out as empty hashed maps; take the representation of the incoming map.
+        let mut res = if self.len() == 0 {
+            other.new_like()
+        } else {
+            self
+        };
         for (k, v) in other {
-            let entry = self.entry(k).or_default();
+            let entry = res.entry_or_default(k);
             let v2 = mem::take(entry);
             *entry = v2.agg(v);
         }
-        self
+        res
     }
 }
 
@@ -106,6 +128,7 @@ pub(crate) struct StageResult {
     // TODO: put more variables in here? Most builtin variables are just going to be propagated
     // from the initial thread.
     nr: Int,
+    pid: Int,
     rc: i32,
 }
 
@@ -115,7 +138,7 @@ impl Slots {
             ($s1:ident, $s2:ident, $body:expr) => {
                 for_each_slot_pair!(
                     $s1, $s2, $body, int, float, strs, intint, intfloat, intstr, strint, strfloat,
-                    strstr
+                    strstr, intmap, strmap
                 );
             };
             ($s1:ident, $s2:ident, $body:expr, $($fld:tt),*) => {$({
@@ -133,6 +156,28 @@ impl Slots {
             }
         });
     }
+
+    // Put the keys of every array in a canonical order, for `--ordered-maps`.
+    fn sort_keys(&mut self) {
+        self.intint.iter_mut().for_each(MapData::sort_keys);
+        self.intfloat.iter_mut().for_each(MapData::sort_keys);
+        self.intstr.iter_mut().for_each(MapData::sort_keys);
+        self.strint.iter_mut().for_each(sort_str_keys);
+        self.strfloat.iter_mut().for_each(sort_str_keys);
+        self.strstr.iter_mut().for_each(sort_str_keys);
+        for m in self.intmap.iter_mut() {
+            m.sort_keys();
+            m.values_mut().for_each(sort_str_keys);
+        }
+        for m in self.strmap.iter_mut() {
+            sort_str_keys(m);
+            m.values_mut().for_each(sort_str_keys);
+        }
+    }
+}
+
+fn sort_str_keys<V>(m: &mut MapData<UniqueStr<'static>, V>) {
+    m.sort_by(|k1, k2| k1.with_bytes(|b1| k2.with_bytes(|b2| b1.cmp(b2))));
 }
 
 pub fn set_slot<T: Default>(vec: &mut Vec<T>, slot: usize, v: T) {
@@ -166,11 +211,20 @@ impl<'a> Core<'a> {
         let ors: UniqueStr<'a> = self.vars.ors.clone().into();
         let filename: UniqueStr<'a> = self.vars.filename.clone().into();
         let convfmt: UniqueStr<'a> = self.vars.convfmt.clone().into();
+        let ofmt: UniqueStr<'a> = self.vars.ofmt.clone().into();
+        let default_convfmt = self.vars.default_convfmt;
+        let default_ofmt = self.vars.default_ofmt;
+        let fieldwidths: UniqueStr<'a> = self.vars.fieldwidths.clone().into();
+        let fpat: UniqueStr<'a> = self.vars.fpat.clone().into();
+        let field_split = self.field_split.clone();
+        let ignorecase = self.vars.ignorecase;
         let argv = self.vars.argv.shuttle();
         let fi = self.vars.fi.shuttle();
         let environ = self.vars.environ.shuttle();
         let procinfo = self.vars.procinfo.shuttle();
         let slots = self.slots.clone();
+        let ordered_maps = self.ordered_maps;
+        let bignum = self.bignum;
         move || {
             let vars = Variables {
                 fs: fs.into_str(),
@@ -179,6 +233,13 @@ impl<'a> Core<'a> {
                 rs: rs.into_str(),
                 filename: filename.into_str(),
                 convfmt: convfmt.into_str(),
+                ofmt: ofmt.into_str(),
+                default_convfmt,
+                default_ofmt,
+                fieldwidths: fieldwidths.into_str(),
+                fpat: fpat.into_str(),
+                ignorecase,
+                rt: Default::default(),
                 pid,
                 nf: 0,
                 nr: 0,
@@ -194,10 +255,14 @@ impl<'a> Core<'a> {
             Core {
                 vars,
                 regexes: Default::default(),
+                field_split,
                 write_files: fw,
                 rng: rand::rngs::StdRng::seed_from_u64(seed),
                 current_seed: seed,
                 slots,
+                profile: None,
+                ordered_maps,
+                bignum,
             }
         }
     }
@@ -206,26 +271,107 @@ impl<'a> Core<'a> {
         Core {
             vars: Default::default(),
             regexes: Default::default(),
+            field_split: None,
             write_files: runtime::FileWrite::new(ff),
             rng: rand::rngs::StdRng::seed_from_u64(seed),
             current_seed: seed,
             slots: Default::default(),
+            profile: None,
+            ordered_maps: false,
+            bignum: false,
         }
     }
 
+    /// Store a string variable. Assigning `FIELDWIDTHS` or `FPAT` changes how records are split
+    /// into fields, and assigning `FS` goes back to splitting by `FS`. An empty `RS` also makes
+    /// newlines separate fields.
+    pub fn store_var_str(&mut self, var: Variable, s: Str<'a>) -> Result<()> {
+        use runtime::splitter::{widths::FieldWidths, FieldSplit};
+        match var {
+            Variable::FS => self.field_split = None,
+            Variable::FIELDWIDTHS => {
+                let widths = s.with_bytes(FieldWidths::parse)?;
+                self.field_split = widths.map(FieldSplit::Widths)
+            }
+            Variable::FPAT if s.is_empty() => self.field_split = None,
+            Variable::FPAT => {
+                let re = self.regexes().with_regex(&s, Regex::clone)?;
+                self.field_split = Some(FieldSplit::Pattern(re))
+            }
+            _ => {}
+        }
+        self.vars.store_str(var, s)
+    }
+
+    /// Store an integer variable. Assigning `IGNORECASE` switches regexes (including an active
+    /// `FPAT`), `index` and string comparisons to ignore case.
+    pub fn store_var_int(&mut self, var: Variable, i: Int) -> Result<()> {
+        use runtime::splitter::FieldSplit;
+        self.vars.store_int(var, i)?;
+        if let Variable::IGNORECASE = var {
+            if let Some(FieldSplit::Pattern(_)) = self.field_split {
+                let fpat = self.vars.fpat.clone();
+                self.store_var_str(Variable::FPAT, fpat)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `IGNORECASE` is set.
+    pub fn ignore_case(&self) -> bool {
+        self.vars.ignorecase != 0
+    }
+
+    /// The regex cache, set up to follow `IGNORECASE`, `RS`, `FIELDWIDTHS` and `FPAT`.
+    pub fn regexes(&mut self) -> runtime::Regexes<'_> {
+        self.regexes_and_vars().0
+    }
+
+    /// `regexes`, along with the variables, for operations that also need `FS`, `RS` or `RT`.
+    pub fn regexes_and_vars(&mut self) -> (runtime::Regexes<'_>, &mut runtime::Variables<'a>) {
+        let regexes = runtime::Regexes::new(&mut self.regexes)
+            .ignore_case(self.vars.ignorecase != 0)
+            .paragraph_mode(self.vars.rs.is_empty())
+            .field_split(self.field_split.as_ref());
+        (regexes, &mut self.vars)
+    }
+
     pub fn extract_result(&mut self, rc: i32) -> StageResult {
         StageResult {
             slots: mem::take(&mut self.slots),
             nr: self.vars.nr,
+            pid: self.vars.pid,
             rc,
         }
     }
 
-    pub fn combine(&mut self, StageResult { slots, nr, rc: _ }: StageResult) {
+    pub fn combine(&mut self, StageResult { slots, nr, .. }: StageResult) {
         self.slots.combine(slots);
         self.vars.nr = self.vars.nr.agg(nr);
     }
 
+    /// Combine the results of all worker threads. With `--ordered-maps` the results are combined
+    /// in worker order and the keys of the aggregated arrays are sorted, so that the output does
+    /// not depend on how the input was divided between workers.
+    pub fn combine_all(&mut self, mut results: Vec<StageResult>) {
+        if self.ordered_maps {
+            results.sort_by_key(|res| res.pid);
+        }
+        for res in results {
+            self.combine(res);
+        }
+        self.sort_slots();
+    }
+
+    /// With `--ordered-maps`, sort the keys of the arrays passed to the `END` block as
+    /// `combine_all` does. Parallel scripts that end up running on a single thread call this
+    /// before `END` so that they see the same arrays.
+    pub fn sort_slots(&mut self) {
+        if self.ordered_maps {
+            self.slots.sort_keys();
+        }
+    }
+
     pub fn reseed(&mut self, seed: u64) -> u64 /* old seed */ {
         self.rng = StdRng::seed_from_u64(seed);
         let old_seed = self.current_seed;
@@ -238,15 +384,21 @@ impl<'a> Core<'a> {
     }
 
     pub fn match_regex(&mut self, s: &Str<'a>, pat: &Str<'a>) -> Result<Int> {
-        self.regexes.regex_match_loc(&mut self.vars, pat, s)
+        let (mut regexes, vars) = self.regexes_and_vars();
+        regexes.regex_match_loc(vars, pat, s)
     }
 
-    pub fn match_const_regex(&mut self, s: &Str<'a>, pat: &Regex) -> Result<Int> {
+    pub fn match_const_regex(&mut self, s: &Str<'a>, pat: &runtime::RegexConst) -> Result<Int> {
+        let pat = pat.get(self.ignore_case());
         runtime::RegexCache::regex_const_match_loc(&mut self.vars, pat, s)
     }
 
     pub fn is_match_regex(&mut self, s: &Str<'a>, pat: &Str<'a>) -> Result<bool> {
-        self.regexes.is_regex_match(pat, s)
+        self.regexes().is_regex_match(pat, s)
+    }
+
+    pub fn match_regex_set(&mut self, s: &Str<'a>, pats: &Str<'a>) -> Result<Int> {
+        self.regexes().regex_set_match(pats, s)
     }
 
     pub fn load_int(&mut self, slot: usize) -> Int {
@@ -266,27 +418,36 @@ impl<'a> Core<'a> {
     }
     pub fn load_intstr(&mut self, slot: usize) -> runtime::IntMap<Str<'a>> {
         mem::take(&mut self.slots.intstr[slot])
-            .into_iter()
-            .map(|(k, v)| (k, v.into_str().upcast()))
-            .collect()
+            .into_map(|(k, v)| (k, v.into_str().upcast()))
+            .into()
     }
     pub fn load_strint(&mut self, slot: usize) -> runtime::StrMap<'a, Int> {
         mem::take(&mut self.slots.strint[slot])
-            .into_iter()
-            .map(|(k, v)| (k.into_str().upcast(), v))
-            .collect()
+            .into_map(|(k, v)| (k.into_str().upcast(), v))
+            .into()
     }
     pub fn load_strfloat(&mut self, slot: usize) -> runtime::StrMap<'a, Float> {
         mem::take(&mut self.slots.strfloat[slot])
-            .into_iter()
-            .map(|(k, v)| (k.into_str().upcast(), v))
-            .collect()
+            .into_map(|(k, v)| (k.into_str().upcast(), v))
+            .into()
     }
     pub fn load_strstr(&mut self, slot: usize) -> runtime::StrMap<'a, Str<'a>> {
         mem::take(&mut self.slots.strstr[slot])
-            .into_iter()
-            .map(|(k, v)| (k.into_str().upcast(), v.into_str().upcast()))
-            .collect()
+            .into_map(|(k, v)| (k.into_str().upcast(), v.into_str().upcast()))
+            .into()
+    }
+    pub fn load_intmap(&mut self, slot: usize) -> runtime::IntMap<runtime::StrMap<'a, Str<'a>>> {
+        mem::take(&mut self.slots.intmap[slot])
+            .into_map(|(k, v)| (k, load_submap(v)))
+            .into()
+    }
+    pub fn load_strmap(
+        &mut self,
+        slot: usize,
+    ) -> runtime::StrMap<'a, runtime::StrMap<'a, Str<'a>>> {
+        mem::take(&mut self.slots.strmap[slot])
+            .into_map(|(k, v)| (k.into_str().upcast(), load_submap(v)))
+            .into()
     }
 
     pub fn store_int(&mut self, slot: usize, i: Int) {
@@ -302,67 +463,91 @@ impl<'a> Core<'a> {
         set_slot(
             &mut self.slots.intint,
             slot,
-            s.iter(|i| i.map(|(k, v)| (*k, *v)).collect()),
+            s.map_data(|(k, v)| (*k, *v)),
         )
     }
     pub fn store_intfloat(&mut self, slot: usize, s: runtime::IntMap<Float>) {
         set_slot(
             &mut self.slots.intfloat,
             slot,
-            s.iter(|i| i.map(|(k, v)| (*k, *v)).collect()),
+            s.map_data(|(k, v)| (*k, *v)),
         )
     }
     pub fn store_intstr(&mut self, slot: usize, s: runtime::IntMap<Str<'a>>) {
         set_slot(
             &mut self.slots.intstr,
             slot,
-            s.iter(|i| i.map(|(k, v)| (*k, v.clone().unmoor().into())).collect()),
+            s.map_data(|(k, v)| (*k, v.clone().unmoor().into())),
         )
     }
     pub fn store_strint(&mut self, slot: usize, s: runtime::StrMap<'a, Int>) {
         set_slot(
             &mut self.slots.strint,
             slot,
-            s.iter(|i| i.map(|(k, v)| (k.clone().unmoor().into(), *v)).collect()),
+            s.map_data(|(k, v)| (k.clone().unmoor().into(), *v)),
         )
     }
     pub fn store_strfloat(&mut self, slot: usize, s: runtime::StrMap<'a, Float>) {
         set_slot(
             &mut self.slots.strfloat,
             slot,
-            s.iter(|i| i.map(|(k, v)| (k.clone().unmoor().into(), *v)).collect()),
+            s.map_data(|(k, v)| (k.clone().unmoor().into(), *v)),
         )
     }
     pub fn store_strstr(&mut self, slot: usize, s: runtime::StrMap<'a, Str<'a>>) {
         set_slot(
             &mut self.slots.strstr,
             slot,
-            s.iter(|i| {
-                i.map(|(k, v)| (k.clone().unmoor().into(), v.clone().unmoor().into()))
-                    .collect()
-            }),
+            s.map_data(|(k, v)| (k.clone().unmoor().into(), v.clone().unmoor().into())),
+        )
+    }
+    pub fn store_intmap(&mut self, slot: usize, s: runtime::IntMap<runtime::StrMap<'a, Str<'a>>>) {
+        set_slot(
+            &mut self.slots.intmap,
+            slot,
+            s.map_data(|(k, v)| (*k, store_submap(v))),
+        )
+    }
+    pub fn store_strmap(
+        &mut self,
+        slot: usize,
+        s: runtime::StrMap<'a, runtime::StrMap<'a, Str<'a>>>,
+    ) {
+        set_slot(
+            &mut self.slots.strmap,
+            slot,
+            s.map_data(|(k, v)| (k.clone().unmoor().into(), store_submap(v))),
         )
     }
 }
 
+// Helpers for moving the inner arrays of an array of arrays in and out of slots.
+fn load_submap<'a>(
+    m: MapData<UniqueStr<'static>, UniqueStr<'static>>,
+) -> runtime::StrMap<'a, Str<'a>> {
+    m.into_map(|(k, v)| (k.into_str().upcast(), v.into_str().upcast()))
+        .into()
+}
+
+fn store_submap<'a>(
+    m: &runtime::StrMap<'a, Str<'a>>,
+) -> MapData<UniqueStr<'static>, UniqueStr<'static>> {
+    m.map_data(|(k, v)| (k.clone().unmoor().into(), v.clone().unmoor().into()))
+}
+
 macro_rules! map_regs {
-    ($map_ty:expr, $map_reg:ident, $body:expr) => {{
-        let _placeholder_k = 0u32;
-        let _placeholder_v = 0u32;
-        map_regs!($map_ty, $map_reg, _placeholder_k, _placeholder_v, $body)
-    }};
-    ($map_ty:expr, $map_reg:ident, $key_reg:ident, $val_reg:ident, $body:expr) => {{
-        let _placeholder_iter = 0u32;
-        map_regs!(
-            $map_ty,
-            $map_reg,
-            $key_reg,
-            $val_reg,
-            _placeholder_iter,
-            $body
-        )
+    // Only the maps with scalar values. Used for operations like increments that do not make sense
+    // for arrays of arrays; the enclosing function must return a `Result`.
+    (@flat $map_ty:expr, $map_reg:ident, $key_reg:ident, $val_reg:ident, $iter_reg:ident, $body:expr) => {{
+        map_regs!(@scalar $map_ty, $map_reg, $key_reg, $val_reg, $iter_reg, $body, {
+            return err!(
+                "attempting to perform scalar map operations on an array of arrays: {:?}",
+                $map_ty
+            );
+        })
     }};
-    ($map_ty:expr, $map_reg:ident, $key_reg:ident, $val_reg:ident, $iter_reg:ident, $body:expr) => {{
+    // Dispatch on the maps with scalar values, evaluating `$nested` for arrays of arrays.
+    (@scalar $map_ty:expr, $map_reg:ident, $key_reg:ident, $val_reg:ident, $iter_reg:ident, $body:expr, $nested:expr) => {{
         let map_ty = $map_ty;
         match map_ty {
             Ty::MapIntInt => {
@@ -407,12 +592,50 @@ macro_rules! map_regs {
                 let $iter_reg: Reg<runtime::Iter<Str<'a>>> = $iter_reg.into();
                 $body
             }
+            Ty::MapIntMap | Ty::MapStrMap => $nested,
             Ty::Null | Ty::Int | Ty::Float | Ty::Str | Ty::IterInt | Ty::IterStr => panic!(
                 "attempting to perform map operations on non-map type: {:?}",
                 map_ty
             ),
         }
     }};
+    ($map_ty:expr, $map_reg:ident, $body:expr) => {{
+        let _placeholder_k = 0u32;
+        let _placeholder_v = 0u32;
+        map_regs!($map_ty, $map_reg, _placeholder_k, _placeholder_v, $body)
+    }};
+    ($map_ty:expr, $map_reg:ident, $key_reg:ident, $val_reg:ident, $body:expr) => {{
+        let _placeholder_iter = 0u32;
+        map_regs!(
+            $map_ty,
+            $map_reg,
+            $key_reg,
+            $val_reg,
+            _placeholder_iter,
+            $body
+        )
+    }};
+    ($map_ty:expr, $map_reg:ident, $key_reg:ident, $val_reg:ident, $iter_reg:ident, $body:expr) => {{
+        let map_ty = $map_ty;
+        match map_ty {
+            Ty::MapIntMap => {
+                let $map_reg: Reg<runtime::IntMap<runtime::StrMap<'a, Str<'a>>>> = $map_reg.into();
+                let $key_reg: Reg<Int> = $key_reg.into();
+                let $val_reg: Reg<runtime::StrMap<'a, Str<'a>>> = $val_reg.into();
+                let $iter_reg: Reg<runtime::Iter<Int>> = $iter_reg.into();
+                $body
+            }
+            Ty::MapStrMap => {
+                let $map_reg: Reg<runtime::StrMap<'a, runtime::StrMap<'a, Str<'a>>>> =
+                    $map_reg.into();
+                let $key_reg: Reg<Str<'a>> = $key_reg.into();
+                let $val_reg: Reg<runtime::StrMap<'a, Str<'a>>> = $val_reg.into();
+                let $iter_reg: Reg<runtime::Iter<Str<'a>>> = $iter_reg.into();
+                $body
+            }
+            _ => map_regs!(@scalar map_ty, $map_reg, $key_reg, $val_reg, $iter_reg, $body, unreachable!()),
+        }
+    }};
 }
 
 pub(crate) struct Interp<'a, LR: LineReader = ClassicReader> {
@@ -420,12 +643,16 @@ pub(crate) struct Interp<'a, LR: LineReader = ClassicReader> {
     main_func: Stage<usize>,
     num_workers: usize,
     instrs: Vec<Vec<Instr<'a>>>,
-    stack: Vec<(usize /*function*/, Label /*instr*/)>,
+    // For each function, the source location of the instructions starting at a given offset.
+    pub(crate) locs: Vec<Vec<(usize, Option<SrcLoc>)>>,
+    pub(crate) stack: Vec<(usize /*function*/, Label /*instr*/)>,
 
-    line: LR::Line,
+    pub(crate) line: LR::Line,
     read_files: runtime::FileRead<LR>,
 
-    core: Core<'a>,
+    pub(crate) core: Core<'a>,
+    // Set when running under `--debug`; consulted before each instruction is executed.
+    debugger: Option<Box<Debugger>>,
 
     // Core storage.
     // TODO: should these be smallvec<[T; 32]>? We never add registers, so could we allocate one
@@ -441,6 +668,9 @@ pub(crate) struct Interp<'a, LR: LineReader = ClassicReader> {
     pub(crate) maps_str_int: Storage<runtime::StrMap<'a, Int>>,
     pub(crate) maps_str_str: Storage<runtime::StrMap<'a, Str<'a>>>,
 
+    pub(crate) maps_int_map: Storage<runtime::IntMap<runtime::StrMap<'a, Str<'a>>>>,
+    pub(crate) maps_str_map: Storage<runtime::StrMap<'a, runtime::StrMap<'a, Str<'a>>>>,
+
     pub(crate) iters_int: Storage<runtime::Iter<Int>>,
     pub(crate) iters_str: Storage<runtime::Iter<Str<'a>>>,
 }
@@ -455,8 +685,10 @@ fn default_of<T: Default>(n: usize) -> Storage<T> {
 }
 
 impl<'a, LR: LineReader> Interp<'a, LR> {
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
     pub(crate) fn new(
         instrs: Vec<Vec<Instr<'a>>>,
+        locs: Vec<Vec<(usize, Option<SrcLoc>)>>,
         main_func: Stage<usize>,
         num_workers: usize,
         regs: impl Fn(compile::Ty) -> usize,
@@ -466,18 +698,25 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         named_columns: Option<Vec<&[u8]>>,
     ) -> Self {
         use compile::Ty::*;
+        let core = Core::new(ff);
         Interp {
             main_func,
             num_workers,
             instrs,
+            locs,
             stack: Default::default(),
+            debugger: None,
             floats: default_of(regs(Float)),
             ints: default_of(regs(Int)),
             strs: default_of(regs(Str)),
-            core: Core::new(ff),
-
             line: Default::default(),
-            read_files: runtime::FileRead::new(stdin, used_fields.clone(), named_columns),
+            read_files: runtime::FileRead::new(
+                stdin,
+                used_fields.clone(),
+                named_columns,
+                core.write_files.channels(),
+            ),
+            core,
 
             maps_int_float: default_of(regs(MapIntFloat)),
             maps_int_int: default_of(regs(MapIntInt)),
@@ -487,6 +726,9 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             maps_str_int: default_of(regs(MapStrInt)),
             maps_str_str: default_of(regs(MapStrStr)),
 
+            maps_int_map: default_of(regs(MapIntMap)),
+            maps_str_map: default_of(regs(MapStrMap)),
+
             iters_int: default_of(regs(IterInt)),
             iters_str: default_of(regs(IterStr)),
         }
@@ -496,8 +738,47 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         &self.instrs
     }
 
+    /// Make arrays iterate in insertion order, for `--ordered-maps`. This must be called before
+    /// the program runs, as it replaces the (empty) arrays held in registers.
+    pub(crate) fn set_ordered_maps(&mut self) {
+        self.core.ordered_maps = true;
+        self.reset_maps();
+    }
+
+    /// Read `getline < "-"` and `getline < "/dev/stdin"` from the main input, which is standard
+    /// input.
+    pub(crate) fn share_stdin(&mut self) {
+        self.read_files.share_stdin();
+    }
+
+    fn reset_maps(&mut self) {
+        fn reset<K, V>(maps: &mut Storage<runtime::SharedMap<K, V>>, ordered: bool) {
+            for m in maps.regs.iter_mut() {
+                *m = runtime::SharedMap::new(ordered);
+            }
+        }
+        let ordered = self.core.ordered_maps;
+        reset(&mut self.maps_int_int, ordered);
+        reset(&mut self.maps_int_float, ordered);
+        reset(&mut self.maps_int_str, ordered);
+        reset(&mut self.maps_str_int, ordered);
+        reset(&mut self.maps_str_float, ordered);
+        reset(&mut self.maps_str_str, ordered);
+        reset(&mut self.maps_int_map, ordered);
+        reset(&mut self.maps_str_map, ordered);
+    }
+
+    /// Run the program under `debugger`. Debugged programs always run serially.
+    pub(crate) fn set_debugger(&mut self, debugger: Debugger) {
+        self.num_workers = 1;
+        self.debugger = Some(Box::new(debugger));
+    }
+
     fn format_arg(&self, (reg, ty): (NumTy, Ty)) -> Result<runtime::FormatArg<'a>> {
         Ok(match ty {
+            Ty::Str if self.core.bignum => {
+                runtime::FormatArg::Big(self.get(Reg::<Str<'a>>::from(reg)).clone())
+            }
             Ty::Str => self.get(Reg::<Str<'a>>::from(reg)).clone().into(),
             Ty::Int => (*self.get(Reg::<Int>::from(reg))).into(),
             Ty::Float => (*self.get(Reg::<Float>::from(reg))).into(),
@@ -565,19 +846,24 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             let maps_str_int_size = self.maps_str_int.regs.len();
             let maps_str_float_size = self.maps_str_float.regs.len();
             let maps_str_str_size = self.maps_str_str.regs.len();
+            let maps_int_map_size = self.maps_int_map.regs.len();
+            let maps_str_map_size = self.maps_str_map.regs.len();
             let iters_int_size = self.iters_int.regs.len();
             let iters_str_size = self.iters_str.regs.len();
             for (i, handle) in handles.into_iter().enumerate() {
                 let sender = sender.clone();
                 let core_shuttle = self.core.shuttle(i as Int + 2);
                 let instrs = self.instrs.clone();
+                let locs = self.locs.clone();
                 s.spawn(move |_| {
                     if let Some(read_files) = handle() {
                         let mut interp = Interp {
                             main_func: Stage::Main(main_loop),
                             num_workers: 1,
                             instrs,
+                            locs,
                             stack: Default::default(),
+                            debugger: None,
                             core: core_shuttle(),
                             line: Default::default(),
                             read_files,
@@ -591,9 +877,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             maps_str_int: default_of(maps_str_int_size),
                             maps_str_float: default_of(maps_str_float_size),
                             maps_str_str: default_of(maps_str_str_size),
+                            maps_int_map: default_of(maps_int_map_size),
+                            maps_str_map: default_of(maps_str_map_size),
                             iters_int: default_of(iters_int_size),
                             iters_str: default_of(iters_str_size),
                         };
+                        if interp.core.ordered_maps {
+                            interp.reset_maps();
+                        }
                         let res = interp.run_at(main_loop);
 
                         // Ignore errors, as it means another thread executed with an error and we are
@@ -609,14 +900,15 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             self.core.vars.pid = 1;
             let mut rc = self.run_at(main_loop)?;
             self.core.vars.pid = 0;
+            let mut results = Vec::new();
             while let Ok(res) = receiver.recv() {
                 let res = res?;
-                let sub_rc = res.rc;
-                self.core.combine(res);
-                if rc == 0 && sub_rc != 0 {
-                    rc = sub_rc;
+                if rc == 0 && res.rc != 0 {
+                    rc = res.rc;
                 }
+                results.push(res);
             }
+            self.core.combine_all(results);
             Ok(rc)
         });
         let rc = wrap_error(scope_res)?;
@@ -633,7 +925,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
 
     pub(crate) fn run_serial(&mut self) -> Result<i32> {
         let offs: smallvec::SmallVec<[usize; 3]> = self.main_func.iter().cloned().collect();
+        let end = match &self.main_func {
+            Stage::Par { end, .. } => *end,
+            Stage::Main(_) => None,
+        };
         for off in offs.into_iter() {
+            if Some(off) == end {
+                self.core.sort_slots();
+            }
             let rc = self.run_at(off)?;
             if rc != 0 {
                 return Ok(rc);
@@ -649,22 +948,54 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         }
     }
 
+    pub(crate) fn run_at(&mut self, cur_fn: usize) -> Result<i32> {
+        // `run_from` executes out of `pc`, so when it fails `pc` still holds the failing
+        // instruction, which we map back to the statement that generated it.
+        let mut pc = (cur_fn, 0);
+        let res = if self.debugger.is_some() {
+            self.run_from::<true>(&mut pc)
+        } else {
+            self.run_from::<false>(&mut pc)
+        };
+        res.map_err(|e| e.at_opt(self.loc_of(pc)))
+    }
+
+    fn loc_of(&self, (func, instr): (usize, usize)) -> Option<Loc> {
+        let locs = &self.locs[func];
+        match locs.partition_point(|(off, _)| *off <= instr) {
+            0 => None,
+            ix => locs[ix - 1].1.map(|l| l.error()),
+        }
+    }
+
+    // DEBUG is a parameter rather than a runtime check so that programs that are not being debugged
+    // do not pay for the hook in the main loop.
     #[allow(clippy::never_loop)]
-    pub(crate) fn run_at(&mut self, mut cur_fn: usize) -> Result<i32> {
+    fn run_from<const DEBUG: bool>(&mut self, pc: &mut (usize, usize)) -> Result<i32> {
         use Instr::*;
+        // The current function and instruction live in `pc` itself rather than in copies, so that
+        // they are up to date if we bail out with an error.
+        let (cur_fn, cur) = pc;
         let mut scratch: Vec<runtime::FormatArg> = Vec::new();
         // We are only accessing one vector at a time here, but it's hard to convince the borrow
         // checker of this fact, so we access the vectors through raw pointers.
-        let mut instrs = (&mut self.instrs[cur_fn]) as *mut Vec<Instr<'a>>;
-        let mut cur = 0;
+        let mut instrs = (&mut self.instrs[*cur_fn]) as *mut Vec<Instr<'a>>;
 
         'outer: loop {
             // This somewhat ersatz structure is to allow 'cur' to be reassigned
             // in most but not all branches in the big match below.
-            cur = loop {
-                debug_assert!(cur < unsafe { (*instrs).len() });
+            *cur = loop {
+                debug_assert!(*cur < unsafe { (*instrs).len() });
+                if DEBUG {
+                    let mut debugger = self.debugger.take().unwrap();
+                    let res = debugger.before(self, (*cur_fn, *cur));
+                    self.debugger = Some(debugger);
+                    if let Some(rc) = res? {
+                        break 'outer Ok(rc);
+                    }
+                }
                 use Variable::*;
-                match unsafe { (*instrs).get_unchecked(cur) } {
+                match unsafe { (*instrs).get_unchecked(*cur) } {
                     StoreConstStr(sr, s) => {
                         let sr = *sr;
                         *self.get_mut(sr) = s.clone_str()
@@ -683,7 +1014,12 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         *self.get_mut(sr) = s;
                     }
                     FloatToStr(sr, fr) => {
-                        let s = runtime::convert::<_, Str>(*self.get(*fr));
+                        let s = self.core.vars.convfmt(*self.get(*fr));
+                        let sr = *sr;
+                        *self.get_mut(sr) = s;
+                    }
+                    FloatToStrOfmt(sr, fr) => {
+                        let s = self.core.vars.ofmt(*self.get(*fr));
                         let sr = *sr;
                         *self.get_mut(sr) = s;
                     }
@@ -1239,6 +1575,11 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let dst = *dst;
                         *self.get_mut(dst) = value;
                     }
+                    MapIntStrSum(dst, arr) => {
+                        let arr = self.get(*arr);
+                        let dst = *dst;
+                        *self.get_mut(dst) = Str::from(runtime::math_util::map_int_str_sum(arr));
+                    }
                     MapIntIntMean(dst, arr) => {
                         let arr = self.get(*arr);
                         let value = runtime::math_util::map_int_int_mean(arr);
@@ -1332,7 +1673,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     SqliteQuery(dst, db_path, sql) => {
                         let db_path = index(&self.strs, db_path);
                         let sql = index(&self.strs, sql);
-                        let res = runtime::sqlite::sqlite_query(db_path.as_str(), sql.as_str());
+                        let res = runtime::sqlite::sqlite_query(db_path.as_str(), sql.as_str())?;
                         let dst = *dst;
                         *self.get_mut(dst) = res;
                     }
@@ -1760,6 +2101,18 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let r = *self.get(*r);
                         *self.get_mut(res) = l / r;
                     }
+                    CheckedInt(op, res, l, r) => {
+                        let res = *res;
+                        let l = *self.get(*l);
+                        let r = *self.get(*r);
+                        *self.get_mut(res) = runtime::math_util::checked_int(*op, l, r)?;
+                    }
+                    CheckedFloat(op, res, l, r) => {
+                        let res = *res;
+                        let l = *self.get(*l);
+                        let r = *self.get(*r);
+                        *self.get_mut(res) = runtime::math_util::checked_float(*op, l, r)?;
+                    }
                     Pow(res, l, r) => {
                         let res = *res;
                         let l = *self.get(*l);
@@ -1809,6 +2162,46 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let dst = *dst;
                         *self.get_mut(dst) = bw.eval2(ix, iy);
                     }
+                    Bignum(op, dst, x, y, prec) => {
+                        let x = index(&self.strs, x);
+                        let y = index(&self.strs, y);
+                        let prec = index(&self.strs, prec);
+                        let res = x.with_bytes(|x| {
+                            y.with_bytes(|y| {
+                                prec.with_bytes(|prec| runtime::bignum::arith(*op, x, y, prec))
+                            })
+                        })?;
+                        *index_mut(&mut self.strs, dst) = Str::from(res);
+                    }
+                    BignumCmp(dst, x, y) => {
+                        let x = index(&self.strs, x);
+                        let y = index(&self.strs, y);
+                        let res = x.with_bytes(|x| y.with_bytes(|y| runtime::bignum::cmp(x, y)));
+                        let dst = *dst;
+                        *self.get_mut(dst) = res;
+                    }
+                    DecimalCmp(dst, x, y) => {
+                        let x = index(&self.strs, x);
+                        let y = index(&self.strs, y);
+                        let res = x.with_bytes(|x| {
+                            y.with_bytes(|y| runtime::math_util::decimal_cmp(x, y))
+                        })?;
+                        *index_mut(&mut self.ints, dst) = res;
+                    }
+                    Decimal(op, dst, x, y, scale, mode) => {
+                        let x = index(&self.strs, x);
+                        let y = index(&self.strs, y);
+                        let scale = *index(&self.ints, scale);
+                        let mode = index(&self.strs, mode);
+                        let res = runtime::math_util::decimal(
+                            *op,
+                            x.as_str(),
+                            y.as_str(),
+                            scale,
+                            mode.as_str(),
+                        )?;
+                        *index_mut(&mut self.strs, dst) = Str::from(res);
+                    }
                     Rand(dst) => {
                         let res: f64 = self.core.rng.gen_range(0.0..=1.0);
                         *index_mut(&mut self.floats, dst) = res;
@@ -1842,7 +2235,13 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             .is_match_regex(index(&self.strs, l), index(&self.strs, r))?
                             as Int;
                     }
+                    MatchSet(res, l, r) => {
+                        *index_mut(&mut self.ints, res) = self
+                            .core
+                            .match_regex_set(index(&self.strs, l), index(&self.strs, r))?;
+                    }
                     MatchConst(res, x, pat) => {
+                        let pat = pat.get(self.core.ignore_case());
                         *index_mut(&mut self.ints, res) =
                             runtime::RegexCache::regex_const_match(pat, index(&self.strs, x))
                                 as Int;
@@ -1855,13 +2254,18 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let res = *res;
                         let s = index(&self.strs, s);
                         let t = index(&self.strs, t);
-                        *self.get_mut(res) = runtime::string_search::index_substr(t, s);
+                        *self.get_mut(res) =
+                            runtime::string_search::index_substr(t, s, self.core.ignore_case());
                     }
                     SubstrLastIndex(res, s, t) => {
                         let res = *res;
                         let s = index(&self.strs, s);
                         let t = index(&self.strs, t);
-                        *self.get_mut(res) = runtime::string_search::last_index_substr(t, s);
+                        *self.get_mut(res) = runtime::string_search::last_index_substr(
+                            t,
+                            s,
+                            self.core.ignore_case(),
+                        );
                     }
                     Strlen(res, s) => {
                         let res = *res;
@@ -1884,7 +2288,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             let s = index(&self.strs, s);
                             let in_s = index(&self.strs, in_s);
                             self.core
-                                .regexes
+                                .regexes()
                                 .with_regex(pat, |re| in_s.subst_first(re, s))?
                         };
                         *index_mut(&mut self.strs, in_s) = subbed;
@@ -1896,7 +2300,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             let s = index(&self.strs, s);
                             let in_s = index(&self.strs, in_s);
                             self.core
-                                .regexes
+                                .regexes()
                                 .with_regex(pat, |re| in_s.subst_all(re, s))?
                         };
                         *index_mut(&mut self.strs, in_s) = subbed;
@@ -1909,7 +2313,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             let how = index(&self.strs, how);
                             let in_s = index(&self.strs, in_s);
                             self.core
-                                .regexes
+                                .regexes()
                                 .with_regex(pat, |re| in_s.gen_subst_dynamic(re, s, how))?
                         };
                         *index_mut(&mut self.strs, res) = subbed;
@@ -1970,7 +2374,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let res = *res;
                         let l = self.get(*l);
                         let r = self.get(*r);
-                        *self.get_mut(res) = l.with_bytes(|l| r.with_bytes(|r| l < r)) as Int;
+                        let ord = runtime::str_cmp(l, r, self.core.ignore_case());
+                        *self.get_mut(res) = ord.is_lt() as Int;
                     }
                     GTFloat(res, l, r) => {
                         let res = *res;
@@ -1988,7 +2393,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let res = *res;
                         let l = self.get(*l);
                         let r = self.get(*r);
-                        *self.get_mut(res) = l.with_bytes(|l| r.with_bytes(|r| l > r)) as Int;
+                        let ord = runtime::str_cmp(l, r, self.core.ignore_case());
+                        *self.get_mut(res) = ord.is_gt() as Int;
                     }
                     LTEFloat(res, l, r) => {
                         let res = *res;
@@ -2006,7 +2412,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let res = *res;
                         let l = self.get(*l);
                         let r = self.get(*r);
-                        *self.get_mut(res) = l.with_bytes(|l| r.with_bytes(|r| l <= r)) as Int;
+                        let ord = runtime::str_cmp(l, r, self.core.ignore_case());
+                        *self.get_mut(res) = ord.is_le() as Int;
                     }
                     GTEFloat(res, l, r) => {
                         let res = *res;
@@ -2024,7 +2431,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let res = *res;
                         let l = self.get(*l);
                         let r = self.get(*r);
-                        *self.get_mut(res) = l.with_bytes(|l| r.with_bytes(|r| l >= r)) as Int;
+                        let ord = runtime::str_cmp(l, r, self.core.ignore_case());
+                        *self.get_mut(res) = ord.is_ge() as Int;
                     }
                     EQFloat(res, l, r) => {
                         let res = *res;
@@ -2042,27 +2450,44 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let res = *res;
                         let l = self.get(*l);
                         let r = self.get(*r);
-                        *self.get_mut(res) = (l == r) as Int;
+                        let eq = if self.core.ignore_case() {
+                            runtime::str_cmp(l, r, true).is_eq()
+                        } else {
+                            l == r
+                        };
+                        *self.get_mut(res) = eq as Int;
+                    }
+                    StrNumCmp(res, l, r) => {
+                        let res = *res;
+                        let l = self.get(*l);
+                        let r = self.get(*r);
+                        let ignore_case = self.core.ignore_case();
+                        *self.get_mut(res) = runtime::strnum_cmp(l, r, ignore_case);
+                    }
+                    StrNumCmpFloat(res, l, r) => {
+                        let res = *res;
+                        let l = self.get(*l);
+                        let r = *self.get(*r);
+                        let ignore_case = self.core.ignore_case();
+                        *self.get_mut(res) =
+                            runtime::strnum_cmp_float(l, r, &self.core.vars, ignore_case);
                     }
                     SetColumn(dst, src) => {
                         let col = *self.get(*dst);
                         let v = index(&self.strs, src);
-                        self.line
-                            .set_col(col, v, &self.core.vars.ofs, &mut self.core.regexes)?;
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        self.line.set_col(col, v, &vars.ofs, &mut regexes)?;
                     }
                     GetColumn(dst, src) => {
                         let col = *self.get(*src);
                         let dst = *dst;
-                        let res = self.line.get_col(
-                            col,
-                            &self.core.vars.fs,
-                            &self.core.vars.ofs,
-                            &mut self.core.regexes,
-                        )?;
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let res = self.line.get_col(col, &vars.fs, &vars.ofs, &mut regexes)?;
                         *self.get_mut(dst) = res;
                     }
                     JoinCSV(dst, start, end) => {
-                        let nf = self.line.nf(&self.core.vars.fs, &mut self.core.regexes)?;
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let nf = self.line.nf(&vars.fs, &mut regexes)?;
                         *index_mut(&mut self.strs, dst) = {
                             let start = *index(&self.ints, start);
                             let end = *index(&self.ints, end);
@@ -2072,7 +2497,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         };
                     }
                     JoinTSV(dst, start, end) => {
-                        let nf = self.line.nf(&self.core.vars.fs, &mut self.core.regexes)?;
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let nf = self.line.nf(&vars.fs, &mut regexes)?;
                         *index_mut(&mut self.strs, dst) = {
                             let start = *index(&self.ints, start);
                             let end = *index(&self.ints, end);
@@ -2082,7 +2508,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         };
                     }
                     JoinColumns(dst, start, end, sep) => {
-                        let nf = self.line.nf(&self.core.vars.fs, &mut self.core.regexes)?;
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let nf = self.line.nf(&vars.fs, &mut regexes)?;
                         *index_mut(&mut self.strs, dst) = {
                             let sep = index(&self.strs, sep);
                             let start = *index(&self.ints, start);
@@ -2103,7 +2530,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let to_split = index(&self.strs, to_split);
                         let arr = index(&self.maps_int_str, arr);
                         let pat = index(&self.strs, pat);
-                        self.core.regexes.split_regex_intmap(pat, to_split, arr)?;
+                        self.core.regexes().split_regex_intmap(pat, to_split, arr)?;
                         let res = arr.len() as Int;
                         let flds = *flds;
                         *self.get_mut(flds) = res;
@@ -2113,7 +2540,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let to_split = index(&self.strs, to_split);
                         let arr = index(&self.maps_str_str, arr);
                         let pat = index(&self.strs, pat);
-                        self.core.regexes.split_regex_strmap(pat, to_split, arr)?;
+                        self.core.regexes().split_regex_strmap(pat, to_split, arr)?;
                         let res = arr.len() as Int;
                         let flds = *flds;
                         *self.get_mut(flds) = res;
@@ -2126,8 +2553,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         use runtime::str_impl::DynamicBuf;
                         let fmt_str = index(&self.strs, fmt);
                         let mut buf = DynamicBuf::new(0);
-                        fmt_str
-                            .with_bytes(|bs| runtime::printf::printf(&mut buf, bs, &scratch[..]))?;
+                        let convfmt = self.core.vars.convfmt_spec();
+                        fmt_str.with_bytes(|bs| {
+                            runtime::printf::printf(&mut buf, bs, &scratch[..], convfmt)
+                        })?;
                         scratch.clear();
                         let res = buf.into_str();
                         let dst = *dst;
@@ -2143,9 +2572,9 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             let out_path = index(&self.strs, out_path_reg);
                             self.core
                                 .write_files
-                                .write_all(&scratch_strs[..], Some((out_path, *fspec)))
+                                .write_all(&scratch_strs[..], Some((out_path, *fspec)))?
                         } else {
-                            self.core.write_files.write_all(&scratch_strs[..], None)
+                            self.core.write_files.write_all(&scratch_strs[..], None)?
                         };
                         if res.is_err() {
                             return Ok(0);
@@ -2163,23 +2592,31 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                                 Some((out_path, *fspec)),
                                 fmt_str,
                                 &scratch[..],
-                            )
+                                self.core.vars.convfmt_spec(),
+                            )?
                         } else {
                             // print to stdout.
-                            self.core.write_files.printf(None, fmt_str, &scratch[..])
+                            self.core.write_files.printf(
+                                None,
+                                fmt_str,
+                                &scratch[..],
+                                self.core.vars.convfmt_spec(),
+                            )?
                         };
                         if res.is_err() {
                             return Ok(0);
                         }
                         scratch.clear();
                     }
-                    Close(file) => {
+                    Close(file, how) => {
                         let file = index(&self.strs, file);
-                        // NB this may create an unused entry in write_files. It would not be
-                        // terribly difficult to optimize the close path to include an existence
-                        // check first.
-                        self.core.write_files.close(file)?;
-                        self.read_files.close(file);
+                        let how = index(&self.strs, how);
+                        runtime::close_file(
+                            &mut self.core.write_files,
+                            &mut self.read_files,
+                            file,
+                            how,
+                        )?;
                     }
                     RunCmd(dst, cmd) => {
                         let cmd = index(&self.strs, cmd);
@@ -2220,14 +2657,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         key,
                         by,
                         dst,
-                    } => self.inc_map_int(*map_ty, *map, *key, *by, *dst),
+                    } => self.inc_map_int(*map_ty, *map, *key, *by, *dst)?,
                     IncFloat {
                         map_ty,
                         map,
                         key,
                         by,
                         dst,
-                    } => self.inc_map_float(*map_ty, *map, *key, *by, *dst),
+                    } => self.inc_map_float(*map_ty, *map, *key, *by, *dst)?,
                     LoadVarStr(dst, var) => {
                         let s = self.core.vars.load_str(*var)?;
                         let dst = *dst;
@@ -2236,14 +2673,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     StoreVarStr(var, src) => {
                         let src = *src;
                         let s = self.get(src).clone();
-                        self.core.vars.store_str(*var, s)?;
+                        self.core.store_var_str(*var, s)?;
                     }
                     LoadVarInt(dst, var) => {
                         // If someone explicitly sets NF to a different value, this means we will
                         // ignore it. I think that is fine.
                         if let NF = *var {
-                            self.core.vars.nf =
-                                self.line.nf(&self.core.vars.fs, &mut self.core.regexes)? as Int;
+                            let (mut regexes, vars) = self.core.regexes_and_vars();
+                            vars.nf = self.line.nf(&vars.fs, &mut regexes)? as Int;
                         }
                         let i = self.core.vars.load_int(*var)?;
                         let dst = *dst;
@@ -2252,7 +2689,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     StoreVarInt(var, src) => {
                         let src = *src;
                         let s = *self.get(src);
-                        self.core.vars.store_int(*var, s)?;
+                        self.core.store_var_int(*var, s)?;
                     }
                     LoadVarIntMap(dst, var) => {
                         let arr = self.core.vars.load_intmap(*var)?;
@@ -2308,11 +2745,33 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     NextLine(dst, file, is_file) => {
                         let dst = *dst;
                         let file = index(&self.strs, file);
-                        match self.core.regexes.get_line(
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        match regexes.get_line(
                             file,
-                            &self.core.vars.rs,
+                            &vars.rs,
                             &mut self.read_files,
                             *is_file,
+                            &mut vars.rt,
+                        ) {
+                            Ok(l) => *self.get_mut(dst) = l,
+                            Err(_) => *self.get_mut(dst) = "".into(),
+                        };
+                    }
+                    ReadErrCoproc(dst, cmd) => {
+                        let dst = *dst;
+                        let cmd = index(&self.strs, cmd);
+                        let res = self.read_files.read_err_coproc(cmd)?;
+                        *self.get_mut(dst) = res;
+                    }
+                    NextLineCoproc(dst, cmd) => {
+                        let dst = *dst;
+                        let cmd = index(&self.strs, cmd);
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        match regexes.get_line_coproc(
+                            cmd,
+                            &vars.rs,
+                            &mut self.read_files,
+                            &mut vars.rt,
                         ) {
                             Ok(l) => *self.get_mut(dst) = l,
                             Err(_) => *self.get_mut(dst) = "".into(),
@@ -2325,20 +2784,21 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     }
                     NextLineStdin(dst) => {
                         let dst = *dst;
-                        let (changed, res) = self
-                            .core
-                            .regexes
-                            .get_line_stdin(&self.core.vars.rs, &mut self.read_files)?;
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let (changed, res) =
+                            regexes.get_line_stdin(&vars.rs, &mut self.read_files, &mut vars.rt)?;
                         if changed {
                             self.reset_file_vars();
                         }
                         *self.get_mut(dst) = res;
                     }
                     NextLineStdinFused() => {
-                        let changed = self.core.regexes.get_line_stdin_reuse(
-                            &self.core.vars.rs,
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let changed = regexes.get_line_stdin_reuse(
+                            &vars.rs,
                             &mut self.read_files,
                             &mut self.line,
+                            &mut vars.rt,
                         )?;
                         if changed {
                             self.reset_file_vars()
@@ -2355,13 +2815,24 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     SetFI(key, val) => {
                         let key = *index(&self.ints, key);
                         let val = *index(&self.ints, val);
-                        let col = self.line.get_col(
-                            key,
-                            &self.core.vars.fs,
-                            &self.core.vars.ofs,
-                            &mut self.core.regexes,
-                        )?;
-                        self.core.vars.fi.insert(col, val);
+                        let (mut regexes, vars) = self.core.regexes_and_vars();
+                        let col = self.line.get_col(key, &vars.fs, &vars.ofs, &mut regexes)?;
+                        vars.fi.insert(col, val);
+                    }
+                    ProfileMark(id) => {
+                        if let Some(profile) = &mut self.core.profile {
+                            profile.mark(*id);
+                        }
+                    }
+                    ProfileEnter(id) => {
+                        if let Some(profile) = &mut self.core.profile {
+                            profile.enter(*id);
+                        }
+                    }
+                    ProfileExit() => {
+                        if let Some(profile) = &mut self.core.profile {
+                            profile.exit();
+                        }
                     }
                     JmpIf(cond, lbl) => {
                         let cond = *cond;
@@ -2375,14 +2846,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     Push(ty, reg) => self.push_reg(*ty, *reg),
                     Pop(ty, reg) => self.pop_reg(*ty, *reg),
                     Call(func) => {
-                        self.stack.push((cur_fn, Label(cur + 1)));
-                        cur_fn = *func;
+                        self.stack.push((*cur_fn, Label(*cur + 1)));
+                        *cur_fn = *func;
                         instrs = &mut self.instrs[*func];
                         break 0;
                     }
                     Ret => {
                         if let Some((func, Label(inst))) = self.stack.pop() {
-                            cur_fn = func;
+                            *cur_fn = func;
                             instrs = &mut self.instrs[func];
                             break inst;
                         } else {
@@ -2390,7 +2861,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         }
                     }
                 };
-                break cur + 1;
+                break *cur + 1;
             };
         }
     }
@@ -2432,13 +2903,22 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                 let src = index(&self.maps_str_str, &src.into()).clone();
                 *index_mut(&mut self.maps_str_str, &dst.into()) = src;
             }
+            Ty::MapIntMap => {
+                let src = index(&self.maps_int_map, &src.into()).clone();
+                *index_mut(&mut self.maps_int_map, &dst.into()) = src;
+            }
+            Ty::MapStrMap => {
+                let src = index(&self.maps_str_map, &src.into()).clone();
+                *index_mut(&mut self.maps_str_map, &dst.into()) = src;
+            }
             Ty::Null | Ty::IterInt | Ty::IterStr => {
                 panic!("invalid type for move operation: {:?}", ty)
             }
         }
     }
     fn alloc_map(&mut self, ty: Ty, reg: NumTy) {
-        map_regs!(ty, reg, *self.get_mut(reg) = Default::default())
+        let ordered = self.core.ordered_maps;
+        map_regs!(ty, reg, *self.get_mut(reg) = runtime::SharedMap::new(ordered))
     }
     fn lookup(&mut self, map_ty: Ty, dst: NumTy, map: NumTy, key: NumTy) {
         map_regs!(map_ty, map, key, dst, {
@@ -2473,23 +2953,41 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             self.get(map).insert(k, v);
         });
     }
-    fn inc_map_int(&mut self, map_ty: Ty, map: NumTy, key: NumTy, by: Reg<Int>, dst: NumTy) {
-        map_regs!(map_ty, map, key, dst, {
+    fn inc_map_int(
+        &mut self,
+        map_ty: Ty,
+        map: NumTy,
+        key: NumTy,
+        by: Reg<Int>,
+        dst: NumTy,
+    ) -> Result<()> {
+        let _iter = 0u32;
+        map_regs!(@flat map_ty, map, key, dst, _iter, {
             let k = self.get(key);
             let m = self.get(map);
             let by = *self.get(by);
             let res = m.inc_int(k, by);
             *self.get_mut(dst) = res;
-        })
-    }
-    fn inc_map_float(&mut self, map_ty: Ty, map: NumTy, key: NumTy, by: Reg<Float>, dst: NumTy) {
-        map_regs!(map_ty, map, key, dst, {
+        });
+        Ok(())
+    }
+    fn inc_map_float(
+        &mut self,
+        map_ty: Ty,
+        map: NumTy,
+        key: NumTy,
+        by: Reg<Float>,
+        dst: NumTy,
+    ) -> Result<()> {
+        let _iter = 0u32;
+        map_regs!(@flat map_ty, map, key, dst, _iter, {
             let k = self.get(key);
             let m = self.get(map);
             let by = *self.get(by);
             let res = m.inc_float(k, by);
             *self.get_mut(dst) = res;
-        })
+        });
+        Ok(())
     }
     fn len(&mut self, map_ty: Ty, map: NumTy, dst: NumTy) {
         let len = map_regs!(map_ty, map, self.get(map).len() as Int);
@@ -2498,8 +2996,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
     fn iter_begin(&mut self, map_ty: Ty, map: NumTy, dst: NumTy) {
         let _k = 0u32;
         let _v = 0u32;
+        let order = self.core.vars.sorted_in();
+        let ignore_case = self.core.ignore_case();
         map_regs!(map_ty, map, _k, _v, dst, {
-            let iter = self.get(map).to_iter();
+            let iter = self.get(map).sorted_keys(order, ignore_case).into_iter().collect();
             *self.get_mut(dst) = iter;
         })
     }
@@ -2546,6 +3046,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             Ty::MapStrInt => do_load!(load_strint, maps_str_int),
             Ty::MapStrFloat => do_load!(load_strfloat, maps_str_float),
             Ty::MapStrStr => do_load!(load_strstr, maps_str_str),
+            Ty::MapIntMap => do_load!(load_intmap, maps_int_map),
+            Ty::MapStrMap => do_load!(load_strmap, maps_str_map),
             Ty::Null | Ty::IterInt | Ty::IterStr => {
                 panic!("unexpected operand type to slot operation: {:?}", ty)
             }
@@ -2569,6 +3071,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             Ty::MapStrInt => do_store!(store_strint, maps_str_int),
             Ty::MapStrFloat => do_store!(store_strfloat, maps_str_float),
             Ty::MapStrStr => do_store!(store_strstr, maps_str_str),
+            Ty::MapIntMap => do_store!(store_intmap, maps_int_map),
+            Ty::MapStrMap => do_store!(store_strmap, maps_str_map),
             Ty::Null | Ty::IterInt | Ty::IterStr => panic!("unsupported slot type: {:?}", ty),
         }
     }
@@ -2583,6 +3087,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             Ty::MapStrInt => push(&mut self.maps_str_int, &src.into()),
             Ty::MapStrFloat => push(&mut self.maps_str_float, &src.into()),
             Ty::MapStrStr => push(&mut self.maps_str_str, &src.into()),
+            Ty::MapIntMap => push(&mut self.maps_int_map, &src.into()),
+            Ty::MapStrMap => push(&mut self.maps_str_map, &src.into()),
             Ty::Null | Ty::IterInt | Ty::IterStr => {
                 panic!("unsupported register type for push operation: {:?}", ty)
             }
@@ -2611,6 +3117,12 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             Ty::MapStrStr => {
                 *index_mut(&mut self.maps_str_str, &dst.into()) = pop(&mut self.maps_str_str)
             }
+            Ty::MapIntMap => {
+                *index_mut(&mut self.maps_int_map, &dst.into()) = pop(&mut self.maps_int_map)
+            }
+            Ty::MapStrMap => {
+                *index_mut(&mut self.maps_str_map, &dst.into()) = pop(&mut self.maps_str_map)
+            }
             Ty::Null | Ty::IterInt | Ty::IterStr => {
                 panic!("unsupported register type for pop operation: {:?}", ty)
             }
@@ -2678,6 +3190,7 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         self.core.vars = Default::default();
         self.line = Default::default();
         self.core.regexes = Default::default();
+        self.core.field_split = None;
         self.floats.reset();
         self.ints.reset();
         self.strs.reset();
@@ -2687,6 +3200,8 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         self.maps_str_int.reset();
         self.maps_str_float.reset();
         self.maps_str_str.reset();
+        self.maps_int_map.reset();
+        self.maps_str_map.reset();
         self.iters_int.reset();
         self.iters_str.reset();
     }
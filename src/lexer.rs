@@ -16,6 +16,26 @@ pub struct Loc {
     offset: usize,
 }
 
+impl Loc {
+    /// Pack the line and column into a nonzero integer that compiled code can store in the
+    /// runtime; see `codegen::intrinsics::Runtime::loc`.
+    pub(crate) fn pack(&self) -> u64 {
+        ((self.line as u64 + 1) << 32) | (self.col as u64 & 0xffff_ffff)
+    }
+
+    /// The inverse of `pack`, where 0 means no location. The offset is not preserved.
+    pub(crate) fn unpack(packed: u64) -> Option<Loc> {
+        if packed == 0 {
+            return None;
+        }
+        Some(Loc {
+            line: (packed >> 32) as usize - 1,
+            col: (packed & 0xffff_ffff) as usize,
+            offset: 0,
+        })
+    }
+}
+
 pub type Spanned<T> = (Loc, T, Loc);
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -41,6 +61,9 @@ pub enum Tok<'a> {
     ExitLP,
     While,
     Do,
+    Switch,
+    Case,
+    DefaultCase,
 
     // { }
     LBrace,
@@ -84,6 +107,7 @@ pub enum Tok<'a> {
     QUESTION,
     COLON,
     Pipe,
+    Coproc, // |&
 
     Append, // >>
 
@@ -137,6 +161,8 @@ lazy_static! {
     static ref WS_SEMI_NL_RB: Regex = Regex::new(r"^[\s;\n}]").unwrap();
     static ref WS_SEMI_RPAREN: Regex = Regex::new(r"^[\s;)]").unwrap();
     static ref WS_PAREN: Regex = Regex::new(r"^[\s()]").unwrap();
+    static ref WS_COLON: Regex = Regex::new(r"^[\s:]").unwrap();
+    static ref CASE_LABEL: Regex = Regex::new(r#"^[\s("/+-]"#).unwrap();
 }
 
 keyword_map!(
@@ -161,6 +187,9 @@ keyword_map!(
     [b"exit", Tok::Exit],
     [b"while", Tok::While, WS_PAREN.clone()],
     [b"do", Tok::Do, WS_BRACE.clone()],
+    [b"switch", Tok::Switch, WS_PAREN.clone()],
+    [b"case", Tok::Case, CASE_LABEL.clone()],
+    [b"default", Tok::DefaultCase, WS_COLON.clone()],
     [b"{", Tok::LBrace],
     [b"}", Tok::RBrace],
     [b"[", Tok::LBrack],
@@ -169,6 +198,7 @@ keyword_map!(
     [b")", Tok::RParen],
     [b"getline", Tok::Getline, WS_SEMI_RPAREN.clone()],
     [b"|", Tok::Pipe],
+    [b"|&", Tok::Coproc],
     [b"=", Tok::Assign],
     [b"+", Tok::Add],
     [b"+=", Tok::AddAssign],
@@ -604,12 +634,8 @@ impl<'a> Tokenizer<'a> {
                 col: ix,
                 offset,
             },
-            Ok(line) => Loc {
-                line: line - 1,
-                col: ix - self.lines[line - 1] - 1,
-                offset,
-            },
-            Err(line) => Loc {
+            // A newline belongs to the line it ends.
+            Ok(line) | Err(line) => Loc {
                 line,
                 col: ix - self.lines[line - 1] - 1,
                 offset,
@@ -721,6 +747,14 @@ and the third"#;
                 offset: 23,
             }
         );
+        assert_eq!(
+            tok.index_to_loc(37),
+            Loc {
+                line: 1,
+                col: 14,
+                offset: 37,
+            }
+        );
         let tok2 = Tokenizer::new("\nhello");
         assert_eq!(
             tok2.index_to_loc(0),
@@ -0,0 +1,678 @@
+//! Static warnings for common AWK mistakes, used by `zawk lint`.
+//!
+//! Most checks run over the AST rather than the CFG: they need variable names and the order of
+//! statements, both of which are gone (or heavily rewritten) by the time a program is in SSA form.
+//! The checks about types use the types that `types.rs` infers for each assignment in the CFG
+//! instead. `zawk lint` also runs the program through type inference and taint analysis as the
+//! compiler does, and reports any errors those raise alongside these warnings.
+//!
+//! Warnings point at the start of the statement that triggered them. The checks are:
+//!
+//! * Global variables that are read but never assigned. Variables documented with a `# @var` tag
+//!   are assumed to be set with `-v`.
+//! * Function parameters that shadow a global or builtin variable.
+//! * Globals assigned both strings and numbers, which zawk represents as strings and converts
+//!   each time they are used as a number (see info/types.md).
+//! * Numeric variables that may be printed, concatenated or compared with a string before they
+//!   are assigned. zawk types these as numbers, so they read as 0 where other awks give the empty
+//!   string (see "Incompatibilities" in info/types.md).
+//! * Regex constants that fail to compile.
+//! * `# @var` tags naming a variable the program never references.
+use hashbrown::{HashMap, HashSet};
+use regex::bytes::Regex;
+
+use crate::arena::Arena;
+use crate::ast::{Binop, Expr, Pattern, Prog, Stmt, Unop};
+use crate::awk_util;
+use crate::builtins::{Function, Variable};
+use crate::cfg::{Escaper, ProgramContext};
+use crate::common::{CompileError, Either, Result, Stage};
+use crate::compile::{self, Ty};
+use crate::include::Source;
+use crate::lexer::{self, Loc};
+use crate::parsing::syntax::ProgParser;
+use crate::types;
+
+pub(crate) struct Warning {
+    pub loc: Loc,
+    pub msg: String,
+}
+
+/// Everything `zawk lint` has to say about a program.
+#[derive(Default)]
+pub(crate) struct Report {
+    pub warnings: Vec<Warning>,
+    /// A parse error, or the first error raised while compiling the program.
+    pub error: Option<CompileError>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty() && self.error.is_none()
+    }
+}
+
+/// Parse `src`, lint it, and type-check it.
+pub(crate) fn check(src: &Source) -> Report {
+    let a = Arena::default();
+    let text = a.alloc_str(src.text.as_str());
+    let mut buf = Vec::new();
+    let mut prog = Prog::from_stage(&a, Stage::Main(()));
+    let lexer = lexer::Tokenizer::new(text);
+    if let Err(e) = ProgParser::new().parse(&a, &mut buf, &mut prog, lexer) {
+        return Report {
+            warnings: Vec::new(),
+            error: Some(Source::parse_failure(e)),
+        };
+    }
+    let prog = a.alloc(prog);
+    let ctx = ProgramContext::from_prog(&a, prog, Escaper::default());
+    // Programs that fail to type-check get no warnings about types, just the error.
+    let inferred = match &ctx {
+        Ok(ctx) => infer(ctx).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    };
+    let warnings = lint(text, prog, &inferred);
+    let error = ctx
+        .and_then(|mut ctx| compile::context_compiles(&mut ctx))
+        .err();
+    Report { warnings, error }
+}
+
+/// What type inference says about the assignments to a global variable.
+#[derive(Default)]
+pub(crate) struct Inferred {
+    // The first statements assigning the variable a string and a number.
+    str_asgn: Option<Loc>,
+    num_asgn: Option<Loc>,
+    // Whether a number is stored in the variable while it is typed as a string.
+    converted: bool,
+    // Whether the variable is ever typed as something other than a number.
+    non_num: bool,
+}
+
+/// Summarize the types inferred for the assignments to each global variable in `ctx`.
+pub(crate) fn infer<'a>(ctx: &ProgramContext<'a, &'a str>) -> Result<HashMap<&'a str, Inferred>> {
+    let names: HashMap<_, _> = ctx
+        ._invert_ident()
+        .into_iter()
+        .map(|(id, name)| (id.low, name))
+        .collect();
+    let is_num = |ty: &Ty| matches!(ty, Ty::Int | Ty::Float);
+    let mut res = HashMap::<&str, Inferred>::new();
+    for asgn in types::assignments(ctx)? {
+        let name = match names.get(&asgn.var.low) {
+            Some(name) => *name,
+            None => continue,
+        };
+        let info = res.entry(name).or_default();
+        if let (Some(loc), false) = (asgn.loc, asgn.is_phi) {
+            match asgn.from.first() {
+                Some(Ty::Str) => {
+                    info.str_asgn.get_or_insert(loc);
+                }
+                Some(ty) if is_num(ty) => {
+                    info.num_asgn.get_or_insert(loc);
+                }
+                _ => {}
+            }
+        }
+        info.converted |= asgn.ty == Ty::Str && asgn.from.iter().any(is_num);
+        info.non_num |= !is_num(&asgn.ty);
+    }
+    Ok(res)
+}
+
+/// Check `prog`, whose source is `text`, returning any warnings in source order. `inferred` holds
+/// the output of `infer` for the program.
+pub(crate) fn lint<'a, 'b>(
+    text: &str,
+    prog: &Prog<'a, 'b, &'b str>,
+    inferred: &HashMap<&str, Inferred>,
+) -> Vec<Warning> {
+    let mut tags = Vec::new();
+    for (line, src) in text.lines().enumerate() {
+        for tag in awk_util::parse_comment_tags(src) {
+            if tag.type_name == "var" {
+                let name = tag.value1.trim_end_matches('?').to_string();
+                let mut loc = Loc::default();
+                loc.line = line;
+                loc.col = src.find(name.as_str()).unwrap_or(0);
+                tags.push((name, loc));
+            }
+        }
+    }
+    let mut l = Linter {
+        tagged: tags.iter().map(|(name, _)| name.clone()).collect(),
+        funcs: prog.decs.iter().map(|dec| dec.name).collect(),
+        ..Default::default()
+    };
+    l.prog(prog);
+
+    let mut warnings = Vec::new();
+    for (name, info) in l.vars.iter() {
+        if Variable::try_from(*name).is_ok() || l.tagged.contains(*name) {
+            continue;
+        }
+        if let (Some(loc), false) = (info.read, info.assigned) {
+            warnings.push(Warning {
+                loc,
+                msg: format!(
+                    "`{}` is read but never assigned; set it with -v or declare it with `# @var {}`",
+                    name, name
+                ),
+            });
+        }
+        let types = match inferred.get(name) {
+            Some(types) if types.converted => types,
+            _ => continue,
+        };
+        if let (Some(s), Some(n)) = (types.str_asgn, types.num_asgn) {
+            // Report the assignment that introduces the conflict.
+            let loc = if (s.line, s.col) > (n.line, n.col) {
+                s
+            } else {
+                n
+            };
+            warnings.push(Warning {
+                loc,
+                msg: format!(
+                    "`{}` is assigned both strings and numbers, so it is stored as a string and \
+                     converted each time it is used as a number",
+                    name
+                ),
+            });
+        }
+    }
+    l.warnings.extend(warnings);
+    let mut null_uses = std::mem::take(&mut l.null_uses);
+    // Only variables that are always numbers read as 0 before they are assigned.
+    null_uses.retain(|(name, _)| {
+        inferred
+            .get(name)
+            .is_some_and(|types| types.num_asgn.is_some() && !types.non_num)
+    });
+    let mut reported = HashSet::new();
+    for (name, loc) in null_uses {
+        if !l.tagged.contains(name) && reported.insert(name) {
+            l.warn(
+                loc,
+                format!(
+                    "`{}` may be used as a string before it is assigned; zawk treats it as 0 \
+                     here rather than the empty string",
+                    name
+                ),
+            );
+        }
+    }
+    for dec in prog.decs.iter() {
+        for arg in dec.args.iter() {
+            let what = if Variable::try_from(*arg).is_ok() {
+                "builtin"
+            } else if l.vars.contains_key(arg) {
+                "global"
+            } else {
+                continue;
+            };
+            l.warn(
+                dec.loc,
+                format!(
+                    "parameter `{}` of function `{}` shadows the {} variable `{}`",
+                    arg, dec.name, what, arg
+                ),
+            );
+        }
+    }
+    for (name, loc) in tags {
+        if !l.vars.contains_key(name.as_str()) {
+            l.warn(
+                loc,
+                format!("`{}` is declared with `# @var` but never used", name),
+            );
+        }
+    }
+    let mut res = l.warnings;
+    res.sort_by_key(|w| (w.loc.line, w.loc.col));
+    res
+}
+
+// What is known about a global variable from the syntax.
+#[derive(Default)]
+struct VarInfo {
+    // The first statement reading the variable.
+    read: Option<Loc>,
+    assigned: bool,
+}
+
+#[derive(Default)]
+struct Linter<'b> {
+    vars: HashMap<&'b str, VarInfo>,
+    funcs: HashSet<&'b str>,
+    tagged: HashSet<String>,
+    // The parameters of the function being checked.
+    locals: HashSet<&'b str>,
+    in_function: bool,
+    // Globals that are definitely assigned at the current point in the program.
+    defined: HashSet<&'b str>,
+    // Uses of variables in a string context where they may not have been assigned yet.
+    null_uses: Vec<(&'b str, Loc)>,
+    loc: Loc,
+    warnings: Vec<Warning>,
+}
+
+impl<'b> Linter<'b> {
+    fn warn(&mut self, loc: Loc, msg: String) {
+        self.warnings.push(Warning { loc, msg });
+    }
+
+    fn prog<'a>(&mut self, prog: &Prog<'a, 'b, &'b str>) {
+        for dec in prog.decs.iter() {
+            self.in_function = true;
+            self.locals = dec.args.iter().cloned().collect();
+            self.loc = dec.loc;
+            self.stmt(dec.body);
+        }
+        self.in_function = false;
+        self.locals.clear();
+        self.defined.clear();
+        for s in prog.begin.iter() {
+            self.stmt(s);
+        }
+        for s in prog.beginfile.iter() {
+            self.maybe(|l| l.stmt(s));
+        }
+        for (loc, pat, body) in prog.pats.iter() {
+            self.loc = *loc;
+            match pat {
+                Pattern::Null => {}
+                Pattern::Bool(e) => self.expr(e),
+                Pattern::Comma(l, r) => {
+                    self.expr(l);
+                    self.maybe(|l| l.expr(r));
+                }
+            }
+            if let Some(body) = body {
+                // The statements in a rule without a pattern run on every record, so anything
+                // they assign is visible to later rules (and to END, assuming there is input).
+                if let Pattern::Null = pat {
+                    self.stmt(body);
+                } else {
+                    self.maybe(|l| l.stmt(body));
+                }
+            }
+        }
+        for s in prog.endfile.iter().chain(prog.prepare.iter()) {
+            self.maybe(|l| l.stmt(s));
+        }
+        for s in prog.end.iter() {
+            self.stmt(s);
+        }
+    }
+
+    // Run `f` on code that may not execute: nothing it assigns is definitely assigned afterwards.
+    fn maybe(&mut self, f: impl FnOnce(&mut Self)) {
+        let before = self.defined.clone();
+        f(self);
+        self.defined = before;
+    }
+
+    fn is_global(&self, v: &str) -> bool {
+        !self.locals.contains(v)
+    }
+
+    fn read(&mut self, v: &'b str) {
+        if self.is_global(v) {
+            let loc = self.loc;
+            self.vars.entry(v).or_default().read.get_or_insert(loc);
+        }
+    }
+
+    fn assign(&mut self, v: &'b str) {
+        if !self.is_global(v) {
+            return;
+        }
+        self.vars.entry(v).or_default().assigned = true;
+        self.defined.insert(v);
+    }
+
+    // `e` is about to be used as a string.
+    fn string_use<'a>(&mut self, e: &Expr<'a, 'b, &'b str>) {
+        if let Expr::Var(v) = e {
+            if !self.in_function && self.is_global(v) && !self.defined.contains(v) {
+                self.null_uses.push((*v, self.loc));
+            }
+        }
+    }
+
+    fn stmt<'a>(&mut self, s: &Stmt<'a, 'b, &'b str>) {
+        match s {
+            Stmt::StartCond(_)
+            | Stmt::EndCond(_)
+            | Stmt::LastCond(_)
+            | Stmt::Break
+            | Stmt::Continue
+            | Stmt::Next
+            | Stmt::NextFile => {}
+            Stmt::Expr(e) => self.expr(e),
+            Stmt::Block(stmts) => {
+                for s in stmts.iter() {
+                    self.stmt(s)
+                }
+            }
+            Stmt::Print(args, out) => {
+                for a in args.iter() {
+                    self.expr(a);
+                    self.string_use(a);
+                }
+                if let Some((o, _)) = out {
+                    self.expr(o);
+                }
+            }
+            Stmt::Printf(fmt, args, out) => {
+                self.expr(fmt);
+                for a in args.iter() {
+                    self.expr(a);
+                }
+                if let Some((o, _)) = out {
+                    self.expr(o);
+                }
+            }
+            Stmt::If(cond, t, f) => {
+                self.expr(cond);
+                let before = self.defined.clone();
+                self.stmt(t);
+                let after = std::mem::replace(&mut self.defined, before);
+                if let Some(f) = f {
+                    self.stmt(f);
+                    self.defined.retain(|v| after.contains(v));
+                }
+            }
+            Stmt::For(init, cond, update, body) => {
+                if let Some(init) = init {
+                    self.stmt(init);
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                self.maybe(|l| {
+                    l.stmt(body);
+                    if let Some(update) = update {
+                        l.stmt(update);
+                    }
+                });
+            }
+            Stmt::DoWhile(cond, body) => {
+                self.stmt(body);
+                self.expr(cond);
+            }
+            Stmt::While(_, cond, body) => {
+                self.expr(cond);
+                self.maybe(|l| l.stmt(body));
+            }
+            Stmt::ForEach(v, arr, body) => {
+                self.expr(arr);
+                self.maybe(|l| {
+                    l.assign(v);
+                    l.stmt(body)
+                });
+            }
+            Stmt::Switch(e, cases) => {
+                self.expr(e);
+                for (label, body) in cases.iter() {
+                    if let Some(label) = label {
+                        self.expr(label);
+                    }
+                    self.maybe(|l| l.stmt(body));
+                }
+            }
+            Stmt::Return(e) => {
+                if let Some(e) = e {
+                    self.expr(e);
+                }
+            }
+            Stmt::Located(loc, s) => {
+                self.loc = *loc;
+                self.stmt(s)
+            }
+        }
+    }
+
+    fn expr<'a>(&mut self, e: &Expr<'a, 'b, &'b str>) {
+        match e {
+            Expr::ILit(_)
+            | Expr::FLit(_)
+            | Expr::StrLit(_)
+            | Expr::BigLit(_)
+            | Expr::ReadStdin
+            | Expr::Cond(_) => {}
+            Expr::PatLit(pat) => self.regex(pat),
+            Expr::Var(v) => self.read(v),
+            Expr::Index(arr, ix) => {
+                self.expr(arr);
+                self.expr(ix);
+            }
+            Expr::Unop(_, x) => self.expr(x),
+            Expr::Located(loc, x) => {
+                let outer = std::mem::replace(&mut self.loc, *loc);
+                self.expr(x);
+                self.loc = outer;
+            }
+            Expr::Binop(op, l, r) => {
+                self.expr(l);
+                self.expr(r);
+                match op {
+                    Binop::Concat => {
+                        self.string_use(l);
+                        self.string_use(r);
+                    }
+                    Binop::EQ => match (l, r) {
+                        (x, Expr::StrLit(_)) | (Expr::StrLit(_), x) => self.string_use(x),
+                        _ => {}
+                    },
+                    Binop::IsMatch => {
+                        if let Expr::StrLit(pat) = r {
+                            self.regex(pat)
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Expr::Call(f, args) => self.call(f, args),
+            Expr::Assign(l, r) => {
+                self.expr(r);
+                self.lvalue(l);
+            }
+            Expr::AssignOp(l, _, r) => {
+                self.expr(l);
+                self.expr(r);
+                self.lvalue(l);
+            }
+            Expr::And(l, r) | Expr::Or(l, r) => {
+                self.expr(l);
+                self.maybe(|s| s.expr(r));
+            }
+            Expr::ITE(c, t, f) => {
+                self.expr(c);
+                let before = self.defined.clone();
+                self.expr(t);
+                let after = std::mem::replace(&mut self.defined, before);
+                self.expr(f);
+                self.defined.retain(|v| after.contains(v));
+            }
+            Expr::Inc { x, .. } => {
+                self.expr(x);
+                self.lvalue(x);
+            }
+            Expr::Getline { into, from, .. } => {
+                if let Some(from) = from {
+                    self.expr(from);
+                }
+                if let Some(into) = into {
+                    self.lvalue(into);
+                }
+            }
+        }
+    }
+
+    fn lvalue<'a>(&mut self, e: &Expr<'a, 'b, &'b str>) {
+        match e {
+            Expr::Var(v) => self.assign(v),
+            Expr::Index(arr, ix) => {
+                self.expr(ix);
+                match arr {
+                    Expr::Var(v) => self.assign(v),
+                    arr => self.lvalue(arr),
+                }
+            }
+            Expr::Unop(Unop::Column, x) => self.expr(x),
+            e => self.expr(e),
+        }
+    }
+
+    fn call<'a>(&mut self, f: &Either<&'b str, Function>, args: &[&Expr<'a, 'b, &'b str>]) {
+        let name = match f {
+            Either::Left(name) => Some(*name),
+            Either::Right(_) => None,
+        };
+        let user = name.is_some_and(|n| self.funcs.contains(n));
+        for (i, a) in args.iter().enumerate() {
+            match a {
+                // Arrays are passed by reference, and builtins like `split` and `sub` assign to
+                // their later arguments; assume that any of these may be assigned by the call.
+                Expr::Var(v) if user || (name.is_some() && i > 0) => {
+                    self.read(v);
+                    self.assign(v);
+                }
+                a => self.expr(a),
+            }
+        }
+        // A function may assign any global; we do not track which.
+        if user {
+            for (v, info) in self.vars.iter() {
+                if info.assigned {
+                    self.defined.insert(v);
+                }
+            }
+        }
+        let regex_arg = match name {
+            Some("sub" | "gsub" | "gensub") => 0,
+            Some("match") => 1,
+            Some("split") => 2,
+            _ => return,
+        };
+        if let Some(Expr::StrLit(pat)) = args.get(regex_arg) {
+            self.regex(pat);
+        }
+    }
+
+    fn regex(&mut self, pat: &[u8]) {
+        let text = String::from_utf8_lossy(pat);
+        if let Err(e) = Regex::new(&text) {
+            // The error's last line describes the problem; earlier lines quote the pattern.
+            let msg = e.to_string();
+            let desc = msg.lines().last().unwrap_or("");
+            self.warn(
+                self.loc,
+                format!(
+                    "invalid regex /{}/: {}",
+                    text,
+                    desc.trim_start_matches("error: ")
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+    use crate::common::Stage;
+    use crate::lexer::Tokenizer;
+    use crate::parsing::syntax::ProgParser;
+
+    fn lint_str(text: &str) -> Vec<String> {
+        let arena = Arena::default();
+        let text = arena.alloc_str(text);
+        let mut buf = Vec::new();
+        let mut prog = Prog::from_stage(&arena, Stage::Main(()));
+        ProgParser::new()
+            .parse(&arena, &mut buf, &mut prog, Tokenizer::new(text))
+            .unwrap_or_else(|e| panic!("{:?}", e));
+        let prog = arena.alloc(prog);
+        let ctx = ProgramContext::from_prog(&arena, prog, Escaper::default()).unwrap();
+        let inferred = infer(&ctx).unwrap();
+        lint(text, prog, &inferred)
+            .into_iter()
+            .map(|w| format!("{}: {}", w.loc.line + 1, w.msg))
+            .collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        let warnings = lint_str(
+            r#"# @var limit maximum count
+BEGIN { n = 0 }
+$1 > limit { n++; names[n] = $2 }
+{ split($0, parts, ","); total += parts[1] }
+END { for (i = 1; i <= n; i++) print names[i]; print total, n }
+"#,
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn unassigned_and_shadowed() {
+        assert_eq!(
+            lint_str("function f(x, NR) { return x + y }\n{ x = $1; print f(x, 1) }\n"),
+            vec![
+                "1: parameter `x` of function `f` shadows the global variable `x`",
+                "1: parameter `NR` of function `f` shadows the builtin variable `NR`",
+                "1: `y` is read but never assigned; set it with -v or declare it with `# @var y`",
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_types() {
+        assert_eq!(
+            lint_str("BEGIN { x = 1 }\n{ x = $1 \"!\" }\nEND { print x }\n"),
+            vec!["2: `x` is assigned both strings and numbers, so it is stored as a string and converted each time it is used as a number"]
+        );
+        // The types come from inference, so they follow values through variables and functions.
+        assert_eq!(
+            lint_str("function name() { return \"n\" }\nBEGIN { x = 1; y = name() }\n{ x = y }\nEND { print x }\n"),
+            vec!["3: `x` is assigned both strings and numbers, so it is stored as a string and converted each time it is used as a number"]
+        );
+        // A global only used in the main loop is renamed by SSA; separate versions are not
+        // converted.
+        assert!(lint_str("BEGIN { x = 1; print x; x = \"a\"; print x }\n").is_empty());
+    }
+
+    #[test]
+    fn null_as_zero() {
+        assert_eq!(
+            lint_str("BEGIN { if (0) x = 6; print x }\n"),
+            vec!["1: `x` may be used as a string before it is assigned; zawk treats it as 0 here rather than the empty string"]
+        );
+        assert_eq!(
+            lint_str("$1 > 0 { s += $1 }\nEND { print \"sum: \" s }\n"),
+            vec!["2: `s` may be used as a string before it is assigned; zawk treats it as 0 here rather than the empty string"]
+        );
+        // Unconditional rules run before END whenever there is input.
+        assert!(lint_str("{ s += $1 }\nEND { print s }\n").is_empty());
+        assert!(lint_str("BEGIN { x = 0 }\n{ if (x == \"\") print x; x++ }\n").is_empty());
+    }
+
+    #[test]
+    fn regexes_and_tags() {
+        assert_eq!(
+            lint_str("# @var email? user email\n# @var nick user nick\n/a(/ { print nick }\n{ gsub(\"[\", \"\") }\n"),
+            vec![
+                "1: `email` is declared with `# @var` but never used",
+                "3: invalid regex /a(/: unclosed group",
+                "4: invalid regex /[/: unclosed character class",
+            ]
+        );
+    }
+}
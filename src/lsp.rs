@@ -0,0 +1,877 @@
+//! A language server for AWK programs, started with `zawk lsp`.
+//!
+//! The server speaks the Language Server Protocol over standard input and output, keeps the text
+//! of each open document, and supports:
+//!
+//! * Diagnostics: parse errors, the warnings from `zawk lint`, and errors raised by type inference
+//!   and taint analysis. These are republished every time a document changes.
+//! * Hover: documentation and a signature for builtin functions (taken from info/stdlib.md),
+//!   builtin variables, and user-defined functions.
+//! * Completion of builtin functions and variables, user-defined functions (including those
+//!   pulled in with `@include`), and of column names inside `FI["..."]`. Column names come from
+//!   the other `FI` lookups in the document and from the header line of a sample input file named
+//!   with a `# @meta sample data.csv` tag.
+//! * Go-to-definition for user-defined functions.
+//!
+//! Each document's includes are expanded when it is opened or changed, and the result is kept until
+//! the next change. Files included by URL are fetched on a background thread so that requests never
+//! wait on the network; the documents are re-expanded once a fetch finishes. User-defined functions
+//! are found with a regex rather than the parser so that completion and go-to-definition keep
+//! working while the program is being edited and does not parse.
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Value};
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::awk_util;
+use crate::builtins::{FUNCTIONS, VARIABLES};
+use crate::common::{CompileError, Result};
+use crate::include::{self, Source};
+use crate::lexer::Loc;
+use crate::lint;
+
+const STDLIB_DOCS: &str = include_str!("../info/stdlib.md");
+
+// Diagnostic severities.
+const ERROR: u32 = 1;
+const WARNING: u32 = 2;
+
+// Completion item kinds.
+const FUNCTION: u32 = 3;
+const FIELD: u32 = 5;
+const VARIABLE: u32 = 6;
+
+// The JSON-RPC error code for requests the server does not implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+lazy_static! {
+    static ref FUNCTION_DEF: Regex =
+        Regex::new(r"\bfunction\s+([a-zA-Z_][a-zA-Z_0-9]*)\(([^)]*)\)").unwrap();
+    static ref FI_KEY: Regex = Regex::new(r#"\bFI\[\s*"([^"\\]+)"\s*\]"#).unwrap();
+    // Matches the text before the cursor when it is inside the key of an `FI` lookup.
+    static ref FI_PREFIX: Regex = Regex::new(r#"\bFI\[\s*"[^"]*$"#).unwrap();
+    static ref DOCS: Vec<Section> = sections(STDLIB_DOCS);
+}
+
+enum Event {
+    Message(Value),
+    // A file included by URL has been fetched.
+    Fetched,
+    // The client closed its end of the connection.
+    Closed(io::Result<()>),
+}
+
+/// Run the server until the client asks it to exit, returning the process exit code.
+pub(crate) fn serve() -> io::Result<i32> {
+    let (tx, rx) = mpsc::channel();
+    let mut server = Server::default();
+    server.remote.notify = Some(tx.clone());
+    // Messages are read on their own thread so that finished fetches can be handled while the
+    // client is idle.
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let res = loop {
+            match read_message(&mut input) {
+                Ok(Some(msg)) => {
+                    if tx.send(Event::Message(msg)).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        let _ = tx.send(Event::Closed(res));
+    });
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    for event in rx {
+        let replies = match event {
+            Event::Message(msg) => server.handle(&msg),
+            Event::Fetched => server.refresh(),
+            // The client went away without asking the server to exit.
+            Event::Closed(res) => return res.map(|()| 1),
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if let Some(code) = server.exit {
+            return Ok(code);
+        }
+    }
+    Ok(1)
+}
+
+/// Read a single message, returning `None` at the end of the input. Messages that are not valid
+/// JSON are returned as `Value::Null`, which the server ignores.
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if len.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((key, val)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case("content-length") {
+                len = val.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut buf = vec![0; len.unwrap()];
+    r.read_exact(&mut buf)?;
+    Ok(Some(serde_json::from_slice(&buf).unwrap_or(Value::Null)))
+}
+
+fn write_message(w: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+/// Files included by URL. Each is fetched once, on a background thread.
+#[derive(Default)]
+struct Remote {
+    // The contents of each URL, or the error fetching it; `None` while the fetch is in flight.
+    cache: Arc<Mutex<HashMap<String, Option<Result<String>>>>>,
+    // Told when a fetch finishes.
+    notify: Option<mpsc::Sender<Event>>,
+}
+
+impl Remote {
+    /// The contents of `url`, or an error if it has not been fetched yet.
+    fn read(&self, url: &str) -> Result<String> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(url) {
+            Some(Some(res)) => return res.clone(),
+            Some(None) => {}
+            None => {
+                cache.insert(url.to_string(), None);
+                let url = url.to_string();
+                let cache = self.cache.clone();
+                let notify = self.notify.clone();
+                thread::spawn(move || {
+                    let res = include::read_source(&url);
+                    cache.lock().unwrap().insert(url, Some(res));
+                    if let Some(notify) = notify {
+                        let _ = notify.send(Event::Fetched);
+                    }
+                });
+            }
+        }
+        Err(CompileError::new(format!("still fetching {}", url)))
+    }
+}
+
+struct Document {
+    // The file path of the document if it has one, otherwise its URI. Includes are resolved
+    // relative to this.
+    name: String,
+    text: String,
+    // The document with its includes expanded, or just the document if an include fails.
+    source: Source,
+    // Why the includes could not be expanded.
+    include_error: Option<CompileError>,
+}
+
+impl Document {
+    fn new(uri: &str, text: String, remote: &Remote) -> Document {
+        let name = url::Url::parse(uri)
+            .ok()
+            .filter(|url| url.scheme() == "file")
+            .and_then(|url| url.to_file_path().ok())
+            .and_then(|path| path.into_os_string().into_string().ok())
+            .unwrap_or_else(|| uri.to_string());
+        let (source, include_error) =
+            match Source::from_buffer(&name, &text, &|url| remote.read(url)) {
+                Ok(src) => (src, None),
+                Err(e) => (Source::unexpanded(&name, &text), Some(e)),
+            };
+        Document {
+            name,
+            text,
+            source,
+            include_error,
+        }
+    }
+
+    fn line(&self, line: usize) -> &str {
+        self.text.lines().nth(line).unwrap_or("")
+    }
+
+    /// The identifier at the given LSP position.
+    fn word_at(&self, line: usize, character: usize) -> Option<&str> {
+        let text = self.line(line);
+        let col = from_utf16(text, character);
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let start = text[..col].rfind(|c| !is_word(c)).map_or(0, |ix| ix + 1);
+        let end = text[col..]
+            .find(|c| !is_word(c))
+            .map_or(text.len(), |ix| col + ix);
+        if start == end {
+            None
+        } else {
+            Some(&text[start..end])
+        }
+    }
+
+    /// Column names for completing `FI["..."]`.
+    fn columns(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        for tag in awk_util::parse_comment_tags(&self.text) {
+            if tag.type_name == "meta" && tag.value1 == "sample" {
+                if let Some(file) = &tag.value2 {
+                    res.extend(sample_header(&Path::new(&self.name).with_file_name(file)));
+                }
+            }
+        }
+        for caps in FI_KEY.captures_iter(&self.text) {
+            res.push(caps[1].to_string());
+        }
+        let mut seen = hashbrown::HashSet::new();
+        res.retain(|col| seen.insert(col.clone()));
+        res
+    }
+}
+
+/// The column names in the first line of the file at `path`.
+fn sample_header(path: &Path) -> Vec<String> {
+    let mut header = String::new();
+    let read = fs::File::open(path).and_then(|f| io::BufReader::new(f).read_line(&mut header));
+    if read.is_err() {
+        return Vec::new();
+    }
+    let header = header.trim_end_matches(['\r', '\n']);
+    let is_tsv = path.extension().is_some_and(|ext| ext == "tsv") || header.contains('\t');
+    header
+        .split(if is_tsv { '\t' } else { ',' })
+        .map(|col| col.trim().trim_matches('"').to_string())
+        .filter(|col| !col.is_empty())
+        .collect()
+}
+
+/// A function definition found in a document or one of its includes.
+struct FunctionDef {
+    name: String,
+    params: String,
+    // The file containing the definition, as named by `Source`.
+    file: String,
+    // The position of the function name within `file`, as an LSP position.
+    line: usize,
+    character: usize,
+}
+
+impl FunctionDef {
+    fn signature(&self) -> String {
+        format!("function {}({})", self.name, self.params)
+    }
+}
+
+fn user_functions(src: &Source) -> Vec<FunctionDef> {
+    let mut res = Vec::new();
+    for (i, text) in src.text.lines().enumerate() {
+        if text.trim_start().starts_with('#') {
+            continue;
+        }
+        for caps in FUNCTION_DEF.captures_iter(text) {
+            let name = caps.get(1).unwrap();
+            let (file, line) = src.locate(i);
+            res.push(FunctionDef {
+                name: name.as_str().to_string(),
+                params: caps[2]
+                    .split(',')
+                    .map(str::trim)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                file: file.to_string(),
+                line,
+                character: to_utf16(text, name.start()),
+            });
+        }
+    }
+    res
+}
+
+/// Convert a byte offset within `line` to a count of UTF-16 code units, which is how LSP measures
+/// columns.
+fn to_utf16(line: &str, col: usize) -> usize {
+    line.get(..col).unwrap_or(line).encode_utf16().count()
+}
+
+/// The inverse of `to_utf16`.
+fn from_utf16(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (ix, c) in line.char_indices() {
+        if units >= character {
+            return ix;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": {"line": line, "character": start},
+        "end": {"line": line, "character": end},
+    })
+}
+
+fn file_uri(name: &str) -> String {
+    if name.starts_with("http://") || name.starts_with("https://") {
+        return name.to_string();
+    }
+    url::Url::from_file_path(name)
+        .map(String::from)
+        .unwrap_or_else(|_| name.to_string())
+}
+
+/// A section of info/stdlib.md.
+struct Section {
+    // The functions named in the heading: `### index(haystack, needle)/last_index()` documents
+    // both `index` and `last_index`.
+    names: Vec<&'static str>,
+    heading: &'static str,
+    body: &'static str,
+}
+
+fn sections(text: &'static str) -> Vec<Section> {
+    // The start of each heading line, and the start of the body that follows it.
+    let mut headings = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.starts_with('#') {
+            headings.push((offset, offset + line.len()));
+        }
+        offset += line.len();
+    }
+    let mut res = Vec::new();
+    for (i, &(start, body)) in headings.iter().enumerate() {
+        let end = headings.get(i + 1).map_or(text.len(), |next| next.0);
+        let heading = text[start..body].trim_start_matches('#').trim();
+        let names = heading[..heading.find(':').unwrap_or(heading.len())]
+            .split('/')
+            .map(|part| {
+                let part = part.trim();
+                let len = part
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(part.len());
+                &part[..len]
+            })
+            .filter(|name| !name.is_empty())
+            .collect();
+        res.push(Section {
+            names,
+            heading,
+            body: &text[body..end],
+        });
+    }
+    res
+}
+
+/// The first call to `name` in `text`, including its arguments.
+fn find_call<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let pat = format!("{}(", name);
+    let mut from = 0;
+    while let Some(ix) = text[from..].find(pat.as_str()) {
+        let start = from + ix;
+        from = start + pat.len();
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        if text[..start].ends_with(is_word) {
+            continue;
+        }
+        let mut depth = 0;
+        for (i, c) in text[start..].char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 1 => return Some(&text[start..start + i + 1]),
+                ')' => depth -= 1,
+                '\n' => break,
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// The documentation for a builtin function: the section whose heading names it, or failing that
+/// the first section with an example calling it.
+fn builtin_doc(name: &str) -> Option<&'static Section> {
+    DOCS.iter()
+        .find(|s| s.names.contains(&name))
+        .or_else(|| DOCS.iter().find(|s| find_call(s.body, name).is_some()))
+}
+
+fn builtin_signature(name: &str) -> Option<String> {
+    let f = FUNCTIONS.get(name)?;
+    let documented = builtin_doc(name).and_then(|doc| {
+        // Headings like `### index(haystack, needle)/last_index()` elide the arguments of all but
+        // the first function; prefer an example from the body for those.
+        find_call(doc.heading, name)
+            .filter(|call| !call.ends_with("()"))
+            .or_else(|| find_call(doc.body, name))
+    });
+    Some(match documented {
+        Some(call) => call.to_string(),
+        None => match f.arity() {
+            Some(n) => format!(
+                "{}({})",
+                name,
+                (1..=n)
+                    .map(|i| format!("arg{}", i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => format!("{}(...)", name),
+        },
+    })
+}
+
+fn builtin_hover(name: &str) -> Option<String> {
+    let mut res = format!("```awk\n{}\n```", builtin_signature(name)?);
+    if let Some(doc) = builtin_doc(name) {
+        let body = doc.body.trim();
+        if !body.is_empty() {
+            res.push_str("\n\n");
+            res.push_str(body);
+        }
+    }
+    Some(res)
+}
+
+fn variable_doc(name: &str) -> Option<&'static str> {
+    VARIABLES.get(name)?;
+    Some(match name {
+        "ARGC" => "The number of command-line arguments.",
+        "ARGV" => "The command-line arguments, indexed from 0.",
+        "OFS" => "The output field separator, a single space by default.",
+        "ORS" => "The output record separator, a newline by default.",
+        "FS" => "The input field separator, a single space by default.",
+        "RS" => "The input record separator, a newline by default.",
+        "NF" => "The number of fields in the current record.",
+        "NR" => "The number of records read so far.",
+        "FNR" => "The number of records read so far from the current file.",
+        "FILENAME" => "The name of the current input file.",
+        "RSTART" => "The start of the last string matched by `match`.",
+        "RLENGTH" => "The length of the last string matched by `match`.",
+        "PID" => "The process id of zawk.",
+        "FI" => "Column names of the header read with `-H`, mapped to their index.",
+        "ENVIRON" => "The environment variables.",
+        "PROCINFO" => "Information about the running program. Setting `PROCINFO[\"sorted_in\"]` orders `for (k in m)` loops.",
+        "CONVFMT" => "The format used to convert numbers to strings, `%.6g` by default.",
+        "OFMT" => "The format used to print numbers, `%.6g` by default.",
+        "FIELDWIDTHS" => "Space-separated field widths; when set, records are split into fixed-width fields instead of by `FS`.",
+        "FPAT" => "A regex describing the contents of each field; when set, fields are the matches of `FPAT` instead of the text between `FS` separators.",
+        "IGNORECASE" => "When nonzero, regex matching, `index` and string comparisons ignore case.",
+        "RT" => "The text that matched `RS` at the end of the current record.",
+        _ => "A builtin variable.",
+    })
+}
+
+fn markdown(value: String) -> Value {
+    json!({"contents": {"kind": "markdown", "value": value}})
+}
+
+#[derive(Default)]
+struct Server {
+    docs: HashMap<String, Document>,
+    remote: Remote,
+    shutdown: bool,
+    exit: Option<i32>,
+}
+
+impl Server {
+    /// Handle a single message, returning the messages to send in reply.
+    fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+        let mut out = Vec::new();
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "completionProvider": {"triggerCharacters": ["\""]},
+                    "definitionProvider": true,
+                },
+                "serverInfo": {"name": "zawk", "version": crate::builtins::VERSION},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Value::Null)
+            }
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                None
+            }
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let text = if method == "textDocument/didOpen" {
+                    params["textDocument"]["text"].as_str()
+                } else {
+                    // We only ask for full-document syncs, so the last change holds all of the
+                    // text.
+                    params["contentChanges"]
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change["text"].as_str())
+                };
+                if let Some(text) = text {
+                    let doc = Document::new(uri, text.to_string(), &self.remote);
+                    out.push(publish(uri, diagnostics(&doc)));
+                    self.docs.insert(uri.to_string(), doc);
+                }
+                None
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                self.docs.remove(uri);
+                out.push(publish(uri, Vec::new()));
+                None
+            }
+            "textDocument/hover" => Some(self.hover(params).unwrap_or(Value::Null)),
+            "textDocument/completion" => Some(self.completion(params).unwrap_or(Value::Null)),
+            "textDocument/definition" => Some(self.definition(params).unwrap_or(Value::Null)),
+            _ => None,
+        };
+        // Notifications have no id and get no response.
+        if let Some(id) = msg.get("id") {
+            out.push(match result {
+                Some(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unsupported method {:?}", method),
+                    },
+                }),
+            });
+        }
+        out
+    }
+
+    /// Expand the includes of every open document again, returning their new diagnostics.
+    fn refresh(&mut self) -> Vec<Value> {
+        let mut out = Vec::new();
+        for (uri, doc) in self.docs.iter_mut() {
+            *doc = Document::new(uri, std::mem::take(&mut doc.text), &self.remote);
+            out.push(publish(uri, diagnostics(doc)));
+        }
+        out
+    }
+
+    /// The document and position that a request refers to.
+    fn position<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Document, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let doc = self.docs.get(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((uri, doc, line, character))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (_, doc, line, character) = self.position(params)?;
+        let word = doc.word_at(line, character)?;
+        if let Some(def) = user_functions(&doc.source)
+            .into_iter()
+            .find(|def| def.name == word)
+        {
+            return Some(markdown(format!("```awk\n{}\n```", def.signature())));
+        }
+        if let Some(hover) = builtin_hover(word) {
+            return Some(markdown(hover));
+        }
+        let doc = variable_doc(word)?;
+        Some(markdown(format!("```awk\n{}\n```\n\n{}", word, doc)))
+    }
+
+    fn completion(&self, params: &Value) -> Option<Value> {
+        let (_, doc, line, character) = self.position(params)?;
+        let text = doc.line(line);
+        if FI_PREFIX.is_match(&text[..from_utf16(text, character)]) {
+            let items: Vec<Value> = doc
+                .columns()
+                .into_iter()
+                .map(|col| json!({"label": col, "kind": FIELD}))
+                .collect();
+            return Some(Value::Array(items));
+        }
+        let mut items = Vec::new();
+        for def in user_functions(&doc.source) {
+            items.push(json!({"label": def.name, "kind": FUNCTION, "detail": def.signature()}));
+        }
+        let mut funcs: Vec<&str> = FUNCTIONS.keys().copied().collect();
+        funcs.sort_unstable();
+        for name in funcs {
+            items.push(json!({
+                "label": name,
+                "kind": FUNCTION,
+                "detail": builtin_signature(name),
+            }));
+        }
+        let mut vars: Vec<&str> = VARIABLES.keys().copied().collect();
+        vars.sort_unstable();
+        for name in vars {
+            items.push(json!({"label": name, "kind": VARIABLE, "detail": variable_doc(name)}));
+        }
+        Some(Value::Array(items))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (uri, doc, line, character) = self.position(params)?;
+        let word = doc.word_at(line, character)?;
+        let src = &doc.source;
+        let def = user_functions(src)
+            .into_iter()
+            .find(|def| def.name == word)?;
+        let target = if def.file == src.main_file() {
+            uri.to_string()
+        } else {
+            file_uri(&def.file)
+        };
+        let end = def.character + def.name.encode_utf16().count();
+        Some(json!({"uri": target, "range": range(def.line, def.character, end)}))
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+fn diagnostics(doc: &Document) -> Vec<Value> {
+    if let Some(e) = &doc.include_error {
+        return vec![diagnostic(&top(), ERROR, &e.0)];
+    }
+    let src = &doc.source;
+    let report = lint::check(src);
+    let mut res: Vec<Value> = report
+        .warnings
+        .iter()
+        .map(|w| located(src, Some(w.loc), WARNING, &w.msg))
+        .collect();
+    if let Some(e) = &report.error {
+        res.push(located(src, e.1, ERROR, &e.0));
+    }
+    res
+}
+
+/// The range at the very start of a document.
+fn top() -> Value {
+    range(0, 0, 0)
+}
+
+fn diagnostic(range: &Value, severity: u32, msg: &str) -> Value {
+    json!({"range": range, "severity": severity, "source": "zawk", "message": msg})
+}
+
+/// A diagnostic for `loc` in the expanded program `src`. Problems inside included files are
+/// reported at the top of the document, prefixed with their location.
+fn located(src: &Source, loc: Option<Loc>, severity: u32, msg: &str) -> Value {
+    let loc = match loc {
+        Some(loc) => loc,
+        None => return diagnostic(&top(), severity, msg),
+    };
+    let (file, line) = src.locate(loc.line);
+    if file != src.main_file() {
+        let msg = format!("{}: {}", src.describe(loc), msg);
+        return diagnostic(&top(), severity, &msg);
+    }
+    // Underline the token at the location.
+    let text = src.text.lines().nth(loc.line).unwrap_or("");
+    let col = loc.col.min(text.len());
+    let len = text[col..]
+        .find(char::is_whitespace)
+        .unwrap_or(text.len() - col)
+        .max(1);
+    let start = to_utf16(text, col);
+    let end = to_utf16(text, col + len).max(start + 1);
+    diagnostic(&range(line, start, end), severity, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(server: &mut Server, uri: &str, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": uri, "languageId": "awk", "version": 1, "text": text}},
+        }))
+    }
+
+    fn request(
+        server: &mut Server,
+        method: &str,
+        uri: &str,
+        line: usize,
+        character: usize,
+    ) -> Value {
+        let mut out = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": method,
+            "params": {
+                "textDocument": {"uri": uri},
+                "position": {"line": line, "character": character},
+            },
+        }));
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0]["id"], 7);
+        out.pop().unwrap()["result"].take()
+    }
+
+    #[test]
+    fn framing() {
+        let msg = json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"});
+        let mut buf = Vec::new();
+        write_message(&mut buf, &msg).unwrap();
+        write_message(&mut buf, &msg).unwrap();
+        let mut r = io::Cursor::new(buf);
+        assert_eq!(read_message(&mut r).unwrap(), Some(msg.clone()));
+        assert_eq!(read_message(&mut r).unwrap(), Some(msg));
+        assert_eq!(read_message(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn utf16_columns() {
+        let line = "x = \"你好😀\"; y";
+        let y = line.find('y').unwrap();
+        assert_eq!(to_utf16(line, y), 12);
+        assert_eq!(from_utf16(line, 12), y);
+        assert_eq!(from_utf16(line, 100), line.len());
+    }
+
+    #[test]
+    fn builtin_docs() {
+        assert_eq!(builtin_signature("substr").unwrap(), "substr(text, i[, j])");
+        assert_eq!(
+            builtin_signature("last_index").unwrap(),
+            "last_index(text,'s')"
+        );
+        assert!(builtin_hover("substr")
+            .unwrap()
+            .contains("1-indexed substring"));
+        assert!(builtin_hover("hmac").unwrap().contains("HmacSHA256"));
+        assert!(builtin_hover("not_a_builtin").is_none());
+        for name in FUNCTIONS.keys() {
+            assert!(builtin_hover(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn diagnostics_and_navigation() {
+        let mut server = Server::default();
+        let uri = "untitled:test.awk";
+        let text = "function add(x, y) { return x + y }\n\
+                    { total = add(total, $FI[\"amount\"]) }\n\
+                    END { print total, missing; s = $FI[\"\"] }\n";
+        let out = open(&mut server, uri, text);
+        assert_eq!(out.len(), 1);
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0]["severity"], WARNING);
+        assert_eq!(diags[0]["range"]["start"]["line"], 2);
+        assert!(diags[0]["message"].as_str().unwrap().contains("`missing`"));
+
+        let def = request(&mut server, "textDocument/definition", uri, 1, 11);
+        assert_eq!(def["uri"], uri);
+        assert_eq!(def["range"], range(0, 9, 12));
+
+        let hover = request(&mut server, "textDocument/hover", uri, 1, 11);
+        assert_eq!(
+            hover["contents"]["value"],
+            "```awk\nfunction add(x, y)\n```"
+        );
+
+        let items = request(&mut server, "textDocument/completion", uri, 2, 37);
+        let labels: Vec<&str> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, vec!["amount"]);
+
+        let items = request(&mut server, "textDocument/completion", uri, 2, 0);
+        let labels: Vec<&str> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels[0], "add");
+        assert!(labels.contains(&"gsub"));
+        assert!(labels.contains(&"NR"));
+
+        let out = open(&mut server, uri, "BEGIN { print 1 +; }\n");
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0]["severity"], ERROR);
+        assert_eq!(diags[0]["range"]["start"]["character"], 17);
+    }
+
+    #[test]
+    fn remote_includes() {
+        let mut server = Server::default();
+        let url = "https://example.com/lib.awk";
+        // Pretend the fetch is already in flight so that the test never touches the network.
+        server
+            .remote
+            .cache
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), None);
+        let uri = "untitled:remote.awk";
+        let text = format!("@include \"{}\"\nBEGIN {{ print twice(1) }}\n", url);
+        let out = open(&mut server, uri, &text);
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("still fetching"));
+
+        server.remote.cache.lock().unwrap().insert(
+            url.to_string(),
+            Some(Ok("function twice(x) { return 2 * x }\n".to_string())),
+        );
+        let out = server.refresh();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+        let hover = request(&mut server, "textDocument/hover", uri, 1, 16);
+        assert_eq!(hover["contents"]["value"], "```awk\nfunction twice(x)\n```");
+    }
+
+    #[test]
+    fn shutdown_and_exit() {
+        let mut server = Server::default();
+        let out = server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"}));
+        assert_eq!(
+            out,
+            vec![json!({"jsonrpc": "2.0", "id": 1, "result": null})]
+        );
+        assert!(server
+            .handle(&json!({"jsonrpc": "2.0", "method": "exit"}))
+            .is_empty());
+        assert_eq!(server.exit, Some(0));
+    }
+}
@@ -5,6 +5,7 @@ pub mod common;
 
 pub mod arena;
 pub mod ast;
+mod bignum;
 pub mod builtins;
 pub mod bytecode;
 pub mod cfg;
@@ -13,18 +14,26 @@ pub mod codegen;
 pub mod compile;
 pub mod cross_stage;
 pub mod dataflow;
+mod debugger;
 mod display;
 pub mod dom;
+mod format;
 #[cfg(test)]
 pub mod harness;
+mod include;
 mod input_taint;
 pub mod interp;
 pub mod lexer;
+mod lint;
+mod lsp;
 #[allow(unused_parens)] // Warnings appear in generated code
 #[allow(clippy::all)]
 pub mod parsing;
+mod profile;
 pub mod pushdown;
+mod repl;
 pub mod runtime;
+mod sort_loops;
 mod string_constants;
 #[cfg(test)]
 mod test_string_constants;
@@ -36,11 +45,12 @@ use clap::{Arg, Command};
 use arena::Arena;
 use cfg::Escaper;
 use codegen::intrinsics::IntoRuntime;
-use common::{CancelSignal, ExecutionStrategy, Stage};
+use common::{CancelSignal, CheckedArith, ExecutionStrategy, Stage};
 use runtime::{
     splitter::{
         batch::{ByteReader, CSVReader, InputFormat},
         regex::RegexSplitter,
+        RecordReader,
     },
     ChainedReader, LineReader, CHUNK_SIZE,
 };
@@ -65,6 +75,12 @@ struct PreludeScalars {
     arbitrary_shell: bool,
     fold_regexes: bool,
     parse_header: bool,
+    profile: bool,
+    posix: bool,
+    bignum: bool,
+    checked_arith: Option<CheckedArith>,
+    ordered_maps: bool,
+    stdin_is_input: bool,
     escaper: Escaper,
     stage: Stage<()>,
 }
@@ -175,16 +191,18 @@ fn get_prelude<'a>(a: &'a Arena, raw: &RawPrelude) -> Prelude<'a> {
 }
 
 fn get_context<'a>(
-    prog: &str,
+    src: &include::Source,
     a: &'a Arena,
     mut prelude: Prelude<'a>,
 ) -> cfg::ProgramContext<'a, &'a str> {
-    let prog = a.alloc_str(prog);
+    let prog = a.alloc_str(src.text.as_str());
     let lexer = lexer::Tokenizer::new(prog);
     let mut buf = Vec::new();
     let parser = parsing::syntax::ProgParser::new();
     let mut prog = ast::Prog::from_stage(a, prelude.scalars.stage.clone());
     prog.argv = mem::take(&mut prelude.argv);
+    // Set before parsing, which keeps the digits of numeric literals under -M.
+    prog.bignum = prelude.scalars.bignum;
     let stmt = match parser.parse(a, &mut buf, &mut prog, lexer) {
         Ok(()) => {
             prog.field_sep = prelude.field_sep;
@@ -192,35 +210,54 @@ fn get_context<'a>(
             prog.output_sep = prelude.output_sep;
             prog.output_record_sep = prelude.output_record_sep;
             prog.parse_header = prelude.scalars.parse_header;
+            prog.profile = prelude.scalars.profile;
+            prog.checked_arith = prelude.scalars.checked_arith;
             a.alloc(prog)
         }
         Err(e) => {
-            fail!("{}", e);
+            fail!("{}", src.parse_error(e));
         }
     };
     match cfg::ProgramContext::from_prog(a, stmt, prelude.scalars.escaper) {
         Ok(mut ctx) => {
             ctx.allow_arbitrary_commands = prelude.scalars.arbitrary_shell;
             ctx.fold_regex_constants = prelude.scalars.fold_regexes;
+            ctx.posix = prelude.scalars.posix;
+            ctx.ordered_maps = prelude.scalars.ordered_maps;
+            ctx.stdin_is_input = prelude.scalars.stdin_is_input;
             ctx
         }
-        Err(e) => fail!("failed to create program context: {}", e),
+        Err(e) => fail!("failed to create program context: {}", src.render_error(&e)),
     }
 }
 
 fn run_interp_with_context<'a>(
+    src: &include::Source,
     mut ctx: cfg::ProgramContext<'a, &'a str>,
     stdin: impl LineReader,
     ff: impl runtime::writers::FileFactory,
     num_workers: usize,
+    debug: bool,
 ) {
     let rc = {
-        let mut interp = match compile::bytecode(&mut ctx, stdin, ff, num_workers) {
+        let compiled = if debug {
+            // Program input may well come from standard input, so read commands from the terminal
+            // when there is one.
+            let commands: Box<dyn io::BufRead + Send> = match File::open("/dev/tty") {
+                Ok(tty) => Box::new(BufReader::new(tty)),
+                Err(_) => Box::new(BufReader::new(io::stdin())),
+            };
+            let debugger = debugger::Debugger::new(src.clone(), commands, Box::new(io::stderr()));
+            compile::bytecode_debug(&mut ctx, stdin, ff, debugger)
+        } else {
+            compile::bytecode(&mut ctx, stdin, ff, num_workers)
+        };
+        let mut interp = match compiled {
             Ok(ctx) => ctx,
-            Err(e) => fail!("bytecode compilation failure: {}", e),
+            Err(e) => fail!("bytecode compilation failure: {}", src.render_error(&e)),
         };
         match interp.run() {
-            Err(e) => fail!("fatal error during execution: {}", e),
+            Err(e) => fail!("fatal error during execution: {}", src.render_error(&e)),
             Ok(0) => return,
             Ok(n) => n,
         }
@@ -229,20 +266,23 @@ fn run_interp_with_context<'a>(
 }
 
 fn run_cranelift_with_context<'a>(
+    src: &include::Source,
     mut ctx: cfg::ProgramContext<'a, &'a str>,
     stdin: impl IntoRuntime,
     ff: impl runtime::writers::FileFactory,
     cfg: codegen::Config,
     signal: CancelSignal,
 ) {
+    codegen::intrinsics::set_source(src.clone());
     if let Err(e) = compile::run_cranelift(&mut ctx, stdin, ff, cfg, signal) {
-        fail!("error compiling cranelift: {}", e)
+        fail!("error compiling cranelift: {}", src.render_error(&e))
     }
 }
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "llvm_backend")] {
         fn run_llvm_with_context<'a>(
+            src: &include::Source,
             mut ctx: cfg::ProgramContext<'a, &'a str>,
             stdin: impl IntoRuntime,
             ff: impl runtime::writers::FileFactory,
@@ -250,14 +290,15 @@ cfg_if::cfg_if! {
             signal: CancelSignal,
         ) {
             if let Err(e) = compile::run_llvm(&mut ctx, stdin, ff, cfg, signal) {
-                fail!("error compiling llvm: {}", e)
+                fail!("error compiling llvm: {}", src.render_error(&e))
             }
         }
 
-        fn dump_llvm(prog: &str, cfg: codegen::Config, raw: &RawPrelude) -> String {
+        fn dump_llvm(src: &include::Source, cfg: codegen::Config, raw: &RawPrelude) -> String {
             let a = Arena::default();
-            let mut ctx = get_context(prog, &a, get_prelude(&a, raw));
-            compile::dump_llvm(&mut ctx, cfg).unwrap_or_else(|e| fail!("error compiling llvm: {}", e))
+            let mut ctx = get_context(src, &a, get_prelude(&a, raw));
+            compile::dump_llvm(&mut ctx, cfg)
+                .unwrap_or_else(|e| fail!("error compiling llvm: {}", src.render_error(&e)))
         }
 
     }
@@ -265,10 +306,10 @@ cfg_if::cfg_if! {
 
 const DEFAULT_OPT_LEVEL: i32 = 3;
 
-fn dump_bytecode(prog: &str, raw: &RawPrelude) -> String {
+fn dump_bytecode(src: &include::Source, raw: &RawPrelude) -> String {
     use std::io::Cursor;
     let a = Arena::default();
-    let mut ctx = get_context(prog, &a, get_prelude(&a, raw));
+    let mut ctx = get_context(src, &a, get_prelude(&a, raw));
     let fake_inp: Box<dyn io::Read + Send> = Box::new(Cursor::new(vec![]));
     let interp = match compile::bytecode(
         &mut ctx,
@@ -284,7 +325,7 @@ fn dump_bytecode(prog: &str, raw: &RawPrelude) -> String {
         /*num_workers=*/ 1,
     ) {
         Ok(ctx) => ctx,
-        Err(e) => fail!("bytecode compilation failure: {}", e),
+        Err(e) => fail!("bytecode compilation failure: {}", src.render_error(&e)),
     };
     let mut v = Vec::<u8>::new();
     for (i, func) in interp.instrs().iter().enumerate() {
@@ -297,6 +338,82 @@ fn dump_bytecode(prog: &str, raw: &RawPrelude) -> String {
     String::from_utf8(v).unwrap()
 }
 
+fn format_files(matches: &clap::ArgMatches) {
+    let check = matches.get_flag("check");
+    let files: Vec<&String> = matches
+        .get_many::<String>("awk-files")
+        .map(|files| files.collect())
+        .unwrap_or_default();
+    if files.is_empty() {
+        let mut text = String::new();
+        if let Err(e) = io::Read::read_to_string(&mut io::stdin(), &mut text) {
+            fail!("failed to read standard input: {}", e);
+        }
+        match format::format_source("-", &text) {
+            Ok(res) if check => {
+                if res != text {
+                    println!("-");
+                    std::process::exit(1);
+                }
+            }
+            Ok(res) => print!("{}", res),
+            Err(e) => fail!("{}", e),
+        }
+        return;
+    }
+    let mut failed = false;
+    for file in files {
+        let text = match std::fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(e) => fail!("failed to read {}: {}", file, e),
+        };
+        let res = match format::format_source(file, &text) {
+            Ok(res) => res,
+            Err(e) => fail!("{}", e),
+        };
+        if res == text {
+            continue;
+        }
+        if check {
+            println!("{}", file);
+            failed = true;
+        } else if let Err(e) = std::fs::write(file, res) {
+            fail!("failed to write {}: {}", file, e);
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// Print the warnings and errors found in `src`, returning whether there were any.
+fn lint_source(src: &include::Source) -> bool {
+    let report = lint::check(src);
+    for w in report.warnings.iter() {
+        println!("{}", src.render_warning(w.loc, &w.msg));
+    }
+    if let Some(e) = &report.error {
+        println!("{}", src.render_error(e));
+    }
+    !report.is_clean()
+}
+
+fn lint_files(matches: &clap::ArgMatches) {
+    let mut failed = false;
+    for file in matches.get_many::<String>("awk-files").unwrap() {
+        match include::Source::from_files(once(file)) {
+            Ok(src) => failed |= lint_source(&src),
+            Err(e) => {
+                println!("{}", e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     //.env load support
     dotenv::dotenv().ok();
@@ -317,6 +434,26 @@ fn main() {
             .required(true)
             .help("AWK file to create")
         );
+    let fmt_cmd = Command::new("fmt").about("Format AWK files, or standard input if no files are given")
+        .arg(Arg::new("check")
+            .long("check")
+            .num_args(0)
+            .help("List files that are not formatted instead of rewriting them, and exit with status 1 if there are any")
+        )
+        .arg(Arg::new("awk-files")
+            .index(1)
+            .num_args(1..)
+            .help("AWK files to format in place")
+        );
+    let lint_cmd = Command::new("lint").about("Check AWK files for common mistakes")
+        .arg(Arg::new("awk-files")
+            .index(1)
+            .required(true)
+            .num_args(1..)
+            .help("AWK files to check")
+        );
+    let repl_cmd = Command::new("repl").about("Run AWK statements interactively, optionally against sample records");
+    let lsp_cmd = Command::new("lsp").about("Run a language server for AWK files over standard input and output");
     #[allow(unused_mut)]
     let mut app = Command::new("zawk")
         .version(builtins::VERSION)
@@ -324,6 +461,10 @@ fn main() {
         .about("zawk is an AWK language implementation by Rust with stdlib support")
         .subcommand(dump_cmd)
         .subcommand(init_cmd)
+        .subcommand(fmt_cmd)
+        .subcommand(lint_cmd)
+        .subcommand(lsp_cmd)
+        .subcommand(repl_cmd)
         .arg(Arg::new("program-file")
             .long("program-file")
             .short('f')
@@ -379,6 +520,18 @@ fn main() {
             .value_name("FS")
             .conflicts_with("input-format")
             .help("Field separator `FS` for frawk program"))
+        .arg(Arg::new("field-widths")
+            .long("field-widths")
+            .num_args(1)
+            .value_name("WIDTHS")
+            .conflicts_with_all(["field-separator", "input-format"])
+            .help("Split records into fixed-width fields, e.g. `5,10,3`. This sets `FIELDWIDTHS`; see the frawk documentation for the full syntax"))
+        .arg(Arg::new("fpat")
+            .long("fpat")
+            .num_args(1)
+            .value_name("FPAT")
+            .conflicts_with_all(["field-separator", "field-widths", "input-format"])
+            .help("Split records into the matches of the regex FPAT, so fields are described by what they contain rather than by what separates them. This sets `FPAT`"))
         .arg(Arg::new("backend")
             .long("backend")
             .short('B')
@@ -401,6 +554,41 @@ fn main() {
             .short('p')
             .help("Attempt to execute the script in parallel. Strategy r[ecord] parallelizes within the current input file. Strategy f[ile] parallelizes between input files")
             .value_parser(["r", "record", "f", "file"]))
+        .arg(Arg::new("debug")
+            .long("debug")
+            .num_args(0)
+            .conflicts_with("parallel-strategy")
+            .help("Run the program in an interactive step debugger on the bytecode interpreter. Commands are read from the terminal; type `help` for a list"))
+        .arg(Arg::new("profile")
+            .long("profile")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("-")
+            .value_name("FILE")
+            .conflicts_with("parallel-strategy")
+            .help("Report how many times each rule, block and function ran and the time spent in it, along with time spent in builtins such as http_get and sqlite_query. The report is the program source annotated with counts and times, written to standard error or FILE; it is JSON if FILE ends in .json"))
+        .arg(Arg::new("posix")
+            .long("posix")
+            .num_args(0)
+            .conflicts_with("parallel-strategy")
+            .help("Follow POSIX rules for comparisons: fields, getline input, split pieces, ARGV and ENVIRON entries compare numerically when they look like numbers and as strings otherwise, and uninitialized variables print as the empty string. This can slow down programs that compare input values"))
+        .arg(Arg::new("bignum")
+            .short('M')
+            .long("bignum")
+            .num_args(0)
+            .help("Do arithmetic on exact decimal numbers of any size instead of 64-bit integers and doubles, like gawk's -M. Sums, differences and products are exact; quotients keep as many digits as PREC bits give (53 by default, or a name such as quad). printf's %d and %f print these numbers exactly"))
+        .arg(Arg::new("checked-arith")
+            .long("checked-arith")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("error")
+            .value_name("error|float")
+            .value_parser(["error", "float"])
+            .help("Check integer arithmetic. With `error` (the default), + - * %, and unary - on integers stop the program with an error naming the operation when the result overflows, instead of wrapping around, and so do / and % by zero. With `float`, + - * and % are done in floating point, like awks without integers. ^ is always computed in floating point"))
+        .arg(Arg::new("ordered-maps")
+            .long("ordered-maps")
+            .num_args(0)
+            .help("Make arrays iterate in insertion order, including in for-in loops, to_json, _join and to_csv, and keep that order when keys are deleted. With -pr, the arrays aggregated from worker threads have their keys sorted, so output does not depend on how input was divided between workers"))
         .arg(Arg::new("chunk-size")
             .long("chunk-size")
             .num_args(1)
@@ -468,6 +656,27 @@ fn main() {
         println!("{} created", awk_file);
         return;
     }
+    // fmt sub command
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        format_files(matches);
+        return;
+    }
+    // lint sub command
+    if let Some(matches) = matches.subcommand_matches("lint") {
+        lint_files(matches);
+        return;
+    }
+    // lsp sub command
+    if matches.subcommand_matches("lsp").is_some() {
+        match lsp::serve() {
+            Ok(code) => std::process::exit(code),
+            Err(e) => fail!("language server failed: {}", e),
+        }
+    }
+    // repl sub command
+    if matches.subcommand_matches("repl").is_some() {
+        std::process::exit(repl::run(runtime::writers::default_factory()));
+    }
     let ifmt = match matches.get_one::<String>("input-format").map(|s| s.as_str()) {
         Some("csv") => Some(InputFormat::CSV),
         Some("tsv") => Some(InputFormat::TSV),
@@ -514,40 +723,21 @@ fn main() {
         .get_many::<String>("input-files")
         .map(|x| x.map(String::from).collect())
         .unwrap_or_else(Vec::new);
-    let program_string = {
+    let source = {
         if let Some(prog_files) = matches.get_many::<String>("program-file") {
             // We specified a file on the command line, so the "program" will be
             // interpreted as another input file.
             if let Some(p) = matches.get_one::<String>("program") {
                 input_files.insert(0, p.into());
             }
-            let mut prog = String::new();
-            for prog_file in prog_files {
-                if prog_file.starts_with("https://") || prog_file.starts_with("http://") {
-                    match reqwest::blocking::get(prog_file).unwrap().text() {
-                        Ok(p) => {
-                            prog.push_str(p.as_str());
-                            prog.push('\n');
-                        }
-                        Err(e) => fail!("failed to read program from {}: {}", prog_file, e),
-                    }
-                } else {
-                    match std::fs::read_to_string(prog_file) {
-                        Ok(p) => {
-                            prog.push_str(p.as_str());
-                            prog.push('\n');
-                        }
-                        Err(e) => fail!("failed to read program from {}: {}", prog_file, e),
-                    }
-                }
-            }
-            prog
+            include::Source::from_files(prog_files)
         } else if let Some(p) = matches.get_one::<String>("program") {
-            String::from(p)
+            include::Source::from_text(p)
         } else {
             fail!("must specify program at command line, or in a file via -f");
         }
-    };
+    }
+    .unwrap_or_else(|e| fail!("{}", e));
     let (escaper, output_sep, output_record_sep) = match matches.get_one::<String>("output-format").map(|s| s.as_str()) {
         Some("csv") => (Escaper::CSV, Some(","), Some("\r\n")),
         Some("tsv") => (Escaper::TSV, Some("\t"), Some("\n")),
@@ -559,6 +749,14 @@ fn main() {
     };
     let arbitrary_shell = matches.get_flag("arbitrary-shell");
     let parse_header = matches.get_flag("parse-header");
+    let profile = matches.get_one::<String>("profile");
+    let posix = matches.get_flag("posix");
+    let bignum = matches.get_flag("bignum");
+    let checked_arith = match matches.get_one::<String>("checked-arith").map(|s| s.as_str()) {
+        Some("float") => Some(CheckedArith::Float),
+        Some(_) => Some(CheckedArith::Error),
+        None => None,
+    };
 
     let opt_level: i32 = match matches.get_one::<String>("opt-level").map(|s| s.as_str()) {
         Some("3") => 3,
@@ -569,12 +767,23 @@ fn main() {
         None => DEFAULT_OPT_LEVEL,
         Some(x) => panic!("this case should be covered by clap argument validation: found unexpected opt-level value {}", x),
     };
+    let mut var_decs: Vec<String> = matches
+        .get_many::<String>("var")
+        .map(|x| x.map(String::from).collect())
+        .unwrap_or_else(Vec::new);
+    if let Some(widths) = matches.get_one::<String>("field-widths") {
+        let widths = widths.replace(',', " ");
+        if let Err(e) = runtime::splitter::widths::FieldWidths::parse(widths.as_bytes()) {
+            fail!("invalid --field-widths: {}", e);
+        }
+        var_decs.push(format!("FIELDWIDTHS={}", widths));
+    }
+    if let Some(fpat) = matches.get_one::<String>("fpat") {
+        var_decs.push(format!("FPAT={}", fpat));
+    }
     let raw = RawPrelude {
         field_sep: matches.get_one::<String>("field-separator").map(String::from),
-        var_decs: matches
-            .get_many::<String>("var")
-            .map(|x| x.map(String::from).collect())
-            .unwrap_or_else(Vec::new),
+        var_decs,
         output_sep,
         scalars: PreludeScalars {
             escaper,
@@ -582,6 +791,12 @@ fn main() {
             fold_regexes: opt_level >= 3,
             stage: exec_strategy.stage(),
             parse_header,
+            profile: profile.is_some(),
+            posix,
+            bignum,
+            checked_arith,
+            ordered_maps: matches.get_flag("ordered-maps"),
+            stdin_is_input: input_files.is_empty(),
         },
         output_record_sep,
         argv,
@@ -599,7 +814,7 @@ fn main() {
                 let _ = write!(
                     io::stdout(),
                     "{}",
-                    dump_llvm(program_string.as_str(), config, &raw),
+                    dump_llvm(&source, config, &raw),
                 );
             }
         } else {
@@ -611,12 +826,12 @@ fn main() {
         let _ = write!(
             io::stdout(),
             "{}",
-            dump_bytecode(program_string.as_str(), &raw),
+            dump_bytecode(&source, &raw),
         );
     }
     if opt_dump_cfg {
         let a = Arena::default();
-        let ctx = get_context(program_string.as_str(), &a, get_prelude(&a, &raw));
+        let ctx = get_context(&source, &a, get_prelude(&a, &raw));
         let mut stdout = io::stdout();
         let _ = ctx.dbg_print(&mut stdout);
     }
@@ -650,11 +865,23 @@ fn main() {
                         cfg::SepAssign::Potential {
                             field_sep,
                             record_sep,
+                            split_lazily,
                         },
                     ) => {
                         let field_sep = field_sep.unwrap_or(b" ");
                         let record_sep = record_sep.unwrap_or(b"\n");
-                        if field_sep.len() == 1 && record_sep.len() == 1 {
+                        if split_lazily {
+                            let $inp = RecordReader::new(ByteReader::new(
+                                once((_reader, String::from("-"))),
+                                record_sep[0],
+                                record_sep[0],
+                                chunk_size,
+                                check_utf8,
+                                exec_strategy,
+                                signal.clone(),
+                            ));
+                            $body
+                        } else if field_sep.len() == 1 && record_sep.len() == 1 {
                             if field_sep == b" " && record_sep == b"\n" {
                                 let $inp = ByteReader::new_whitespace(
                                     once((_reader, String::from("-"))),
@@ -708,10 +935,27 @@ fn main() {
                     cfg::SepAssign::Potential {
                         field_sep,
                         record_sep,
+                        split_lazily,
                     } => {
                         let field_sep = field_sep.unwrap_or(b" ");
                         let record_sep = record_sep.unwrap_or(b"\n");
-                        if field_sep.len() == 1 && record_sep.len() == 1 {
+                        if split_lazily {
+                            let file_handles: Vec<_> = input_files
+                                .iter()
+                                .cloned()
+                                .map(move |file| (open_file_read(file.as_str()), file))
+                                .collect();
+                            let $inp = RecordReader::new(ByteReader::new(
+                                file_handles.into_iter(),
+                                record_sep[0],
+                                record_sep[0],
+                                chunk_size,
+                                check_utf8,
+                                exec_strategy,
+                                signal.clone(),
+                            ));
+                            $body
+                        } else if field_sep.len() == 1 && record_sep.len() == 1 {
                             let file_handles: Vec<_> = input_files
                                 .iter()
                                 .cloned()
@@ -763,12 +1007,27 @@ fn main() {
     }
 
     // validate AWK code by comment tags
-    let passed = awk_util::validate_awk_code(&program_string, &raw.var_decs);
+    let passed = awk_util::validate_awk_code(&source.text, &raw.var_decs);
     if !passed {
         return;
     }
     let a = Arena::default();
-    let ctx = get_context(program_string.as_str(), &a, get_prelude(&a, &raw));
+    let mut ctx = get_context(&source, &a, get_prelude(&a, &raw));
+    if let (Some(p), Some(path)) = (&mut ctx.profile, profile) {
+        let (format, out): (_, Box<dyn Write + Send>) = if path == "-" {
+            (profile::Format::Text, Box::new(io::stderr()))
+        } else {
+            let file = File::create(path)
+                .unwrap_or_else(|e| fail!("failed to open profile output {}: {}", path, e));
+            let format = if path.ends_with(".json") {
+                profile::Format::Json
+            } else {
+                profile::Format::Text
+            };
+            (format, Box::new(file))
+        };
+        p.report_to(source.clone(), format, out);
+    }
     let analysis_result = ctx.analyze_sep_assignments();
     let out_file = matches.get_one::<String>("out-file");
     macro_rules! with_io {
@@ -786,11 +1045,20 @@ fn main() {
             }
         };
     }
-    match matches.get_one::<String>("backend").map(|s| s.as_str()) {
+    let debug = matches.get_flag("debug");
+    let backend = match matches.get_one::<String>("backend").map(|s| s.as_str()) {
+        None if debug => Some("interp"),
+        Some(b) if debug && b != "interp" => {
+            fail!("--debug is only supported by the interp backend")
+        }
+        b => b,
+    };
+    match backend {
         Some("llvm") => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "llvm_backend")] {
                     with_io!(|inp, oup| run_llvm_with_context(
+                            &source,
                             ctx,
                             inp,
                             oup,
@@ -806,10 +1074,18 @@ fn main() {
             }
         }
         Some("interp") => {
-            with_io!(|inp, oup| run_interp_with_context(ctx, inp, oup, num_workers))
+            with_io!(|inp, oup| run_interp_with_context(
+                &source,
+                ctx,
+                inp,
+                oup,
+                num_workers,
+                debug,
+            ))
         }
         None | Some("cranelift") => {
             with_io!(|inp, oup| run_cranelift_with_context(
+                &source,
                 ctx,
                 inp,
                 oup,
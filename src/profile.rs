@@ -0,0 +1,511 @@
+//! Execution profiling, enabled with `--profile`.
+//!
+//! Profiling works by adding counters to the program itself, so every backend supports it. The
+//! program is split into regions: BEGIN and END blocks, the pattern and the action of each rule,
+//! user-defined functions, calls to the builtins that talk to the outside world or parse whole
+//! documents (see `timed`), and reading input. When desugaring the program, the parser adds a
+//! `ProfileMark` at the start of each toplevel region; the compiler brackets function bodies and
+//! timed builtin calls with `ProfileEnter` and `ProfileExit`. Each of these counts an entry into a
+//! region and charges the time since the previous one to the region that was running, so the
+//! times reported exclude any functions and timed builtins called from a region.
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use serde_json::{json, Map, Value};
+
+use crate::builtins::Function;
+use crate::common::NumTy;
+use crate::include::Source;
+use crate::lexer::Loc;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Begin,
+    // Evaluating the pattern of a rule, which happens once per record.
+    Pattern,
+    // Running the action of a rule, which happens once per record matching the pattern.
+    Action,
+    Prepare,
+    End,
+    Function,
+    Builtin,
+    // Reading the next record, along with anything else done between rules.
+    Input,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        use Kind::*;
+        match self {
+            Begin => "begin",
+            Pattern => "pattern",
+            Action => "action",
+            Prepare => "prepare",
+            End => "end",
+            Function => "function",
+            Builtin => "builtin",
+            Input => "input",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Region {
+    pub kind: Kind,
+    pub name: String,
+    pub loc: Option<Loc>,
+}
+
+impl Region {
+    pub(crate) fn new(kind: Kind, name: impl Into<String>, loc: Option<Loc>) -> Region {
+        Region {
+            kind,
+            name: name.into(),
+            loc,
+        }
+    }
+}
+
+/// Whether calls to `bf` are profiled separately from the code calling them.
+pub(crate) fn timed(bf: &Function) -> bool {
+    use Function::*;
+    matches!(
+        bf,
+        HttpGet
+            | HttpPost
+            | SendMail
+            | SmtpSend
+            | S3Get
+            | S3Put
+            | KvGet
+            | KvPut
+            | KvDelete
+            | KvClear
+            | SqliteQuery
+            | SqliteExecute
+            | LibsqlQuery
+            | LibsqlExecute
+            | MysqlQuery
+            | MysqlExecute
+            | PgQuery
+            | PgExecute
+            | Publish
+            | ReadAll
+            | WriteAll
+            | ReadConfig
+            | FromJson
+            | ToJson
+            | JsonValue
+            | JsonQuery
+            | HtmlValue
+            | HtmlQuery
+            | XmlValue
+            | XmlQuery
+            | FromCsv
+            | ToCsv
+            | Digest
+            | Hmac
+            | Jwt
+            | Dejwt
+            | Encrypt
+            | Decrypt
+            | Eval
+            | Fend
+            | System
+            | System2
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    // The program source, with counts and times in the margin.
+    Text,
+    Json,
+}
+
+struct Report {
+    src: Source,
+    format: Format,
+    out: Box<dyn Write + Send>,
+}
+
+/// The counters and timings for a running program.
+pub(crate) struct Profile {
+    regions: Vec<Region>,
+    counts: Vec<u64>,
+    times: Vec<Duration>,
+    // The regions entered but not yet exited. Time is charged to the last one.
+    stack: Vec<NumTy>,
+    last: Instant,
+    report: Option<Report>,
+}
+
+impl std::fmt::Debug for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Profile")
+            .field("regions", &self.regions)
+            .field("counts", &self.counts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Profile {
+    pub(crate) fn new(regions: Vec<Region>) -> Profile {
+        Profile {
+            counts: vec![0; regions.len()],
+            times: vec![Duration::ZERO; regions.len()],
+            regions,
+            stack: Vec::new(),
+            last: Instant::now(),
+            report: None,
+        }
+    }
+
+    /// Write a report for the program `src` to `out` once it finishes.
+    pub(crate) fn report_to(&mut self, src: Source, format: Format, out: Box<dyn Write + Send>) {
+        self.report = Some(Report { src, format, out });
+    }
+
+    pub(crate) fn function_region(&self, name: &str) -> Option<NumTy> {
+        self.regions
+            .iter()
+            .position(|r| r.kind == Kind::Function && r.name == name)
+            .map(|ix| ix as NumTy)
+    }
+
+    /// The region for calls to `bf`, which is added if this is the first one.
+    pub(crate) fn builtin_region(&mut self, bf: &Function) -> NumTy {
+        let name = bf.to_string();
+        let ix = match self
+            .regions
+            .iter()
+            .position(|r| r.kind == Kind::Builtin && r.name == name)
+        {
+            Some(ix) => ix,
+            None => {
+                self.regions.push(Region::new(Kind::Builtin, name, None));
+                self.counts.push(0);
+                self.times.push(Duration::ZERO);
+                self.regions.len() - 1
+            }
+        };
+        ix as NumTy
+    }
+
+    fn charge(&mut self) {
+        let now = Instant::now();
+        if let Some(cur) = self.stack.last() {
+            self.times[*cur as usize] += now - self.last;
+        }
+        self.last = now;
+    }
+
+    /// Start the toplevel region `id`, ending the previous one. Marks are only executed outside
+    /// of functions, and every function exit (including a `next` inside of one, which returns
+    /// through the function's exit block) runs `exit`, so that is all there is on the stack.
+    pub(crate) fn mark(&mut self, id: NumTy) {
+        self.charge();
+        debug_assert!(
+            self.stack.len() <= 1,
+            "unbalanced profile stack {:?}",
+            self.stack
+        );
+        self.counts[id as usize] += 1;
+        self.stack.clear();
+        self.stack.push(id);
+    }
+
+    pub(crate) fn enter(&mut self, id: NumTy) {
+        self.charge();
+        self.counts[id as usize] += 1;
+        self.stack.push(id);
+    }
+
+    pub(crate) fn exit(&mut self) {
+        self.charge();
+        debug_assert!(
+            !self.stack.is_empty(),
+            "profile exit without a matching enter"
+        );
+        self.stack.pop();
+    }
+
+    /// Stop the clock and write the report, if there is one. An `exit` statement (or an error)
+    /// inside of a function ends the program without leaving the function, so whatever regions
+    /// are still open are unwound here: the time since the last event goes to the innermost one.
+    pub(crate) fn finish(&mut self) {
+        self.charge();
+        self.stack.clear();
+        if let Some(mut report) = self.report.take() {
+            let text = match report.format {
+                Format::Text => self.text(&report.src),
+                Format::Json => {
+                    let mut s = serde_json::to_string_pretty(&self.json(&report.src)).unwrap();
+                    s.push('\n');
+                    s
+                }
+            };
+            if let Err(e) = report
+                .out
+                .write_all(text.as_bytes())
+                .and_then(|_| report.out.flush())
+            {
+                eprintln_ignore!("failed to write profile: {}", e);
+            }
+        }
+    }
+
+    fn total(&self) -> Duration {
+        self.times.iter().sum()
+    }
+
+    fn text(&self, src: &Source) -> String {
+        use std::fmt::Write;
+        // Rules are reported on the line they start on, with the number of times the action ran
+        // (i.e. the number of matches) and the time taken to evaluate the pattern and run the
+        // action.
+        let mut lines: HashMap<usize, (u64, Duration)> = HashMap::new();
+        for (i, region) in self.regions.iter().enumerate() {
+            if let Some(loc) = region.loc {
+                let (count, time) = lines.entry(loc.line).or_default();
+                if region.kind != Kind::Pattern {
+                    *count += self.counts[i];
+                }
+                *time += self.times[i];
+            }
+        }
+        let mut res = String::new();
+        let _ = writeln!(
+            &mut res,
+            "# zawk profile: the number of times each rule, block and function ran, and the time\n\
+             # spent in it in milliseconds. Times do not include functions or the builtins listed\n\
+             # at the end."
+        );
+        let mut cur_file = None;
+        for (i, line) in src.text.lines().enumerate() {
+            let (file, _) = src.locate(i);
+            if cur_file != Some(file) {
+                cur_file = Some(file);
+                let _ = writeln!(&mut res, "\n# {}", file);
+            }
+            match lines.get(&i) {
+                Some((count, time)) => {
+                    let _ = writeln!(&mut res, "{:>10} {:>10}  {}", count, millis(*time), line);
+                }
+                None if line.is_empty() => res.push('\n'),
+                None => {
+                    let _ = writeln!(&mut res, "{:21}  {}", "", line);
+                }
+            }
+        }
+        for (kind, title) in [(Kind::Function, "functions"), (Kind::Builtin, "builtins")] {
+            let mut rows: Vec<_> = (0..self.regions.len())
+                .filter(|i| self.regions[*i].kind == kind && self.counts[*i] > 0)
+                .collect();
+            if rows.is_empty() {
+                continue;
+            }
+            rows.sort_by(|x, y| self.times[*y].cmp(&self.times[*x]));
+            let _ = writeln!(&mut res, "\n# {}", title);
+            let _ = writeln!(&mut res, "{:>10} {:>10}  name", "# calls", "time (ms)");
+            for i in rows {
+                let _ = writeln!(
+                    &mut res,
+                    "{:>10} {:>10}  {}",
+                    self.counts[i],
+                    millis(self.times[i]),
+                    self.regions[i].name
+                );
+            }
+        }
+        let input: Duration = (0..self.regions.len())
+            .filter(|i| self.regions[*i].kind == Kind::Input)
+            .map(|i| self.times[i])
+            .sum();
+        let _ = writeln!(&mut res, "\n# reading input: {} ms", millis(input));
+        let _ = writeln!(&mut res, "# total: {} ms", millis(self.total()));
+        res
+    }
+
+    fn json(&self, src: &Source) -> Value {
+        let regions: Vec<Value> = self
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(i, region)| {
+                let mut obj = Map::new();
+                obj.insert("kind".into(), region.kind.name().into());
+                obj.insert("name".into(), region.name.clone().into());
+                if let Some(loc) = region.loc {
+                    let (file, line) = src.locate(loc.line);
+                    obj.insert("file".into(), file.into());
+                    obj.insert("line".into(), (line + 1).into());
+                }
+                obj.insert("count".into(), self.counts[i].into());
+                obj.insert("time_ms".into(), ms(self.times[i]).into());
+                Value::Object(obj)
+            })
+            .collect();
+        json!({
+            "total_ms": ms(self.total()),
+            "regions": regions,
+        })
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e3
+}
+
+fn millis(d: Duration) -> String {
+    format!("{:.3}", ms(d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+    use crate::ast;
+    use crate::cfg::{self, Escaper};
+    use crate::common::Stage;
+    use crate::compile;
+    use crate::lexer;
+    use crate::parsing::syntax::ProgParser;
+    use crate::runtime::splitter::regex::RegexSplitter;
+    use crate::runtime::writers::testing::FakeFs;
+    use crate::runtime::CHUNK_SIZE;
+    use std::io::{self, Cursor};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const PROG: &str = r#"function add(a, b) {
+    return a + b
+}
+BEGIN { n = 0 }
+$1 == "a" || $1 == "c" {
+    sum = add(sum, $2)
+}
+{ n++ }
+END { print sum, n }
+"#;
+
+    // Run PROG with profiling on the bytecode interpreter, returning the report and the program's
+    // output.
+    fn profile(format: Format) -> (String, String) {
+        let src = Source::from_text(PROG).unwrap();
+        let a = Arena::default();
+        let text = a.alloc_str(src.text.as_str());
+        let mut buf = Vec::new();
+        let mut prog = ast::Prog::from_stage(&a, Stage::Main(()));
+        ProgParser::new()
+            .parse(&a, &mut buf, &mut prog, lexer::Tokenizer::new(text))
+            .unwrap();
+        prog.profile = true;
+        let mut ctx =
+            cfg::ProgramContext::from_prog(&a, a.alloc(prog), Escaper::default()).unwrap();
+        let out = Output::default();
+        ctx.profile
+            .as_mut()
+            .unwrap()
+            .report_to(src, format, Box::new(out.clone()));
+        let fs = FakeFs::default();
+        let reader = RegexSplitter::new(
+            Cursor::new("a 1\nb 2\nc 3\n".to_string()),
+            CHUNK_SIZE,
+            "-",
+            /*check_utf8=*/ false,
+        );
+        let mut interp = compile::bytecode(&mut ctx, reader, fs.clone(), 1).unwrap();
+        assert_eq!(interp.run().unwrap(), 0);
+        drop(interp);
+        let report = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        (report, String::from_utf8(fs.stdout.read_data()).unwrap())
+    }
+
+    #[test]
+    fn counts() {
+        let (report, out) = profile(Format::Json);
+        assert_eq!(out, "4 3\n");
+        let report: Value = serde_json::from_str(&report).unwrap();
+        let counts: Vec<(String, String, u64)> = report["regions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["kind"].as_str().unwrap().to_string(),
+                    r["name"].as_str().unwrap().to_string(),
+                    r["count"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        let count = |kind: &str, name: &str| {
+            counts
+                .iter()
+                .find(|(k, n, _)| k == kind && n == name)
+                .map(|(_, _, c)| *c)
+        };
+        assert_eq!(count("begin", "BEGIN"), Some(1));
+        assert_eq!(count("pattern", "rule 1"), Some(3));
+        assert_eq!(count("action", "rule 1"), Some(2));
+        assert_eq!(count("pattern", "rule 2"), None);
+        assert_eq!(count("action", "rule 2"), Some(3));
+        assert_eq!(count("end", "END"), Some(1));
+        assert_eq!(count("function", "add"), Some(2));
+        assert_eq!(report["regions"][0]["line"], 4);
+    }
+
+    #[test]
+    fn annotated_source() {
+        let (report, _) = profile(Format::Text);
+        let line = |suffix: &str| {
+            report
+                .lines()
+                .find(|l| l.ends_with(suffix))
+                .unwrap_or_else(|| panic!("no line ending in {:?} in\n{}", suffix, report))
+        };
+        assert!(line("  function add(a, b) {").starts_with("         2 "));
+        assert!(line("  $1 == \"a\" || $1 == \"c\" {").starts_with("         2 "));
+        assert!(line("  { n++ }").starts_with("         3 "));
+        assert!(line("  END { print sum, n }").starts_with("         1 "));
+        assert!(line("      return a + b").starts_with("                       "));
+        assert!(report.contains("\n# <command line>\n"), "{}", report);
+        assert!(report.contains("\n# functions\n"), "{}", report);
+        assert!(!report.contains("\n# builtins\n"), "{}", report);
+    }
+
+    #[test]
+    fn nesting() {
+        let mut p = Profile::new(vec![
+            Region::new(Kind::Action, "rule 1", None),
+            Region::new(Kind::Function, "f", None),
+            Region::new(Kind::Input, "input", None),
+        ]);
+        p.mark(0);
+        p.enter(1);
+        p.enter(1);
+        p.exit();
+        assert_eq!(p.stack, vec![0, 1]);
+        p.exit();
+        p.mark(2);
+        assert_eq!(p.stack, vec![2]);
+        // `exit` inside of a function never returns from it; finishing unwinds the stack.
+        p.enter(1);
+        assert_eq!(p.counts, vec![1, 3, 1]);
+        p.finish();
+        assert!(p.stack.is_empty());
+    }
+}
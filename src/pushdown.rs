@@ -246,6 +246,9 @@ pub struct UsedFieldAnalysis {
     // the variables in question.  We can always add it in the future, but since join nodes are
     // always "leaves" we will just add the missing columns as a postprocessing step.
     joins: Vec<(Key /*lhs*/, Key /*rhs*/)>,
+    // Set if the program reads records into a variable with `getline var` (or `getline var < "-"`,
+    // which reads from the main input), in which case the reader has to keep the whole record.
+    whole_record: bool,
 }
 
 impl Default for UsedFieldAnalysis {
@@ -253,6 +256,7 @@ impl Default for UsedFieldAnalysis {
         let mut res = UsedFieldAnalysis {
             dfa: Default::default(),
             joins: Default::default(),
+            whole_record: false,
         };
         res.dfa.add_src(Key::Rng, FieldSet::all());
         res.dfa.add_src(Key::VarVal(Variable::FI), FieldSet::fi());
@@ -287,6 +291,10 @@ impl UsedFieldAnalysis {
                     self.dfa.add_src(dst, FieldSet::singleton(0))
                 }
             }),
+            NextLineStdin(dst) | NextLine(dst, _, /*is_file=*/ true) => {
+                self.whole_record = true;
+                self.dfa.add_src(dst, FieldSet::all());
+            }
             GetColumn(dst, col_reg) => {
                 self.dfa.add_query(col_reg);
                 self.dfa.add_src(dst, FieldSet::all());
@@ -314,6 +322,9 @@ impl UsedFieldAnalysis {
             l_flds.fill(r_flds);
             res.union(&l_flds);
         }
+        if self.whole_record {
+            res.union(&FieldSet::singleton(0));
+        }
         res
     }
 }
@@ -0,0 +1,854 @@
+//! An interactive read-eval-print loop, started with `zawk repl`.
+//!
+//! zawk infers types for a program as a whole before running it, so the REPL cannot keep one
+//! interpreter alive and feed it new code. Instead each input is compiled into a program of its
+//! own, run on the bytecode interpreter (which compiles fastest), and then thrown away. The
+//! program consists of every function defined so far and a single `BEGIN` block that:
+//!
+//! 1. Restores the global variables left behind by earlier inputs, along with `FS`, `OFS` and
+//!    `ORS`.
+//! 2. Sets `$0`, `NR`, `FNR` and `FILENAME` to the current sample record, if one is loaded.
+//! 3. Runs the input. An input that is a lone expression has its value printed.
+//! 4. Writes the new values of the globals to a "state file" that never reaches the disk: the
+//!    REPL intercepts it in its `FileFactory` and parses it once the program finishes.
+//!
+//! The program is type-checked once without step 4, to learn which globals are arrays and what
+//! their keys and values are. The state records the type of each value along with the value
+//! itself (floats with all of their digits, strings hex-encoded), and each array is restored even
+//! if it is empty, so that globals come back with exactly the values and types they had.
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::process::ChildStdin;
+use std::sync::{Arc, Mutex};
+
+use crate::arena::Arena;
+use crate::ast::{self, Expr, Stmt};
+use crate::cfg::{self, Escaper};
+use crate::common::{CompileError, Either, FileSpec, Result, Stage};
+use crate::compile::{self, Ty};
+use crate::include::Source;
+use crate::lexer::{self, Tok};
+use crate::parsing::syntax::ProgParser;
+use crate::runtime::splitter::regex::RegexSplitter;
+use crate::runtime::writers::FileFactory;
+use crate::runtime::CHUNK_SIZE;
+use crate::types;
+
+// The name of the state file written at the end of each program.
+const STATE_FILE: &str = "/dev/zawk-repl-state";
+
+// Prefix for the variables used by generated code.
+const HIDDEN: &str = "__repl_";
+
+// Builtin variables that keep their values between inputs.
+const SPECIAL_VARS: &[&str] = &["FS", "OFS", "ORS"];
+
+// Builtins called for their side effects; an input calling one of these is not printed.
+const VOID_FUNCS: &[&str] = &[
+    "var_dump",
+    "pprint",
+    "log_debug",
+    "log_info",
+    "log_warn",
+    "log_error",
+];
+
+const HELP: &str = "\
+Enter AWK statements to run them, or an expression to print its value. Globals and functions
+persist between inputs; use var_dump(x) to inspect a variable.
+
+  :load FILE   load FILE as sample input and make its first line the current record
+  :next        move to the next record
+  :record      print the current record
+  :reset       forget all variables and functions
+  :help        print this message
+  :quit        leave the REPL (as does end of input)";
+
+/// Run the REPL on standard input, returning the exit code.
+pub(crate) fn run(ff: impl FileFactory) -> i32 {
+    let mut session = Session::new(ff);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut pending = String::new();
+    println!("zawk {}, type :help for help", crate::builtins::VERSION);
+    loop {
+        print!(
+            "{}",
+            if pending.is_empty() {
+                "zawk> "
+            } else {
+                "  ... "
+            }
+        );
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("failed to read input: {}", e);
+                return 1;
+            }
+            None => {
+                println!();
+                return 0;
+            }
+        };
+        if pending.is_empty() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(cmd) = trimmed.strip_prefix(':') {
+                match session.command(cmd) {
+                    Ok(Some(msg)) => println!("{}", msg),
+                    Ok(None) => return 0,
+                    Err(msg) => eprintln!("{}", msg),
+                }
+                continue;
+            }
+        }
+        pending.push_str(&line);
+        pending.push('\n');
+        match session.eval(&pending) {
+            Ok(Outcome::Incomplete) => continue,
+            Ok(Outcome::Done) => {}
+            Ok(Outcome::Exit(rc)) => return rc,
+            Err(msg) => eprintln!("{}", msg),
+        }
+        pending.clear();
+    }
+}
+
+/// The result of evaluating an input.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The input ran to completion.
+    Done,
+    /// The input is not finished (e.g. it has an unclosed brace); ask for another line.
+    Incomplete,
+    /// The input called `exit` with the given status.
+    Exit(i32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Val {
+    Int(String),
+    Float(String),
+    Str(String),
+}
+
+impl Val {
+    /// Parse a value written by `snapshot_code`, where `kind` is the result of `kind`.
+    fn new(kind: &str, text: &str) -> Val {
+        match kind {
+            "i" => Val::Int(text.to_string()),
+            "f" => Val::Float(text.to_string()),
+            _ => Val::Str(unhex(text)),
+        }
+    }
+
+    /// A value of the given kind, used to give an empty array its type.
+    fn zero(kind: &str) -> Val {
+        Val::new(kind, if kind == "s" { "" } else { "0" })
+    }
+
+    /// An expression with this value and type.
+    fn literal(&self) -> String {
+        match self {
+            Val::Int(n) => n.clone(),
+            Val::Float(f) => match f.parse::<f64>() {
+                Ok(x) if x.is_nan() => String::from("log(-1)"),
+                Ok(x) if x.is_infinite() => {
+                    String::from(if x > 0.0 { "-log(0)" } else { "log(0)" })
+                }
+                // Keep integral floats from being read back as integers.
+                _ if !f.contains(['.', 'e', 'E']) => format!("{}.0", f),
+                _ => f.clone(),
+            },
+            Val::Str(s) => quote(s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Global {
+    Scalar(Val),
+    // An array, with a key and a value of its types (used if the array is empty) followed by its
+    // contents.
+    Map(Val, Val, Vec<(Val, Val)>),
+    // An array of arrays, with a key of its type. The inner arrays always have string keys and
+    // values; they are kept by outer key, so that empty ones survive as well.
+    Nested(Val, Vec<(Val, Vec<(String, String)>)>),
+}
+
+/// The state of a REPL session.
+pub(crate) struct Session<FF> {
+    ff: StateFactory<FF>,
+    funcs: Vec<(String, String)>,
+    globals: BTreeMap<String, Global>,
+    specials: BTreeMap<String, String>,
+    file: String,
+    records: Vec<String>,
+    cur: usize,
+}
+
+impl<FF: FileFactory> Session<FF> {
+    pub(crate) fn new(ff: FF) -> Session<FF> {
+        Session {
+            ff: StateFactory {
+                inner: ff,
+                state: Default::default(),
+            },
+            funcs: Vec::new(),
+            globals: BTreeMap::new(),
+            specials: BTreeMap::new(),
+            file: String::new(),
+            records: Vec::new(),
+            cur: 0,
+        }
+    }
+
+    /// Run a REPL command (without the leading `:`), returning a message to print, or `None` if
+    /// the session should end.
+    pub(crate) fn command(&mut self, cmd: &str) -> std::result::Result<Option<String>, String> {
+        let (name, arg) = match cmd.trim().split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (cmd.trim(), ""),
+        };
+        match name {
+            "load" if !arg.is_empty() => self.load(arg).map(Some),
+            "next" | "n" => {
+                if self.cur + 1 >= self.records.len() {
+                    return Err(String::from("no more records"));
+                }
+                self.cur += 1;
+                Ok(Some(self.record()))
+            }
+            "record" | "r" if !self.records.is_empty() => Ok(Some(self.record())),
+            "record" | "r" => Err(String::from("no records loaded; use :load FILE")),
+            "reset" => {
+                self.funcs.clear();
+                self.globals.clear();
+                self.specials.clear();
+                Ok(Some(String::from("cleared all variables and functions")))
+            }
+            "help" | "h" => Ok(Some(String::from(HELP))),
+            "quit" | "q" => Ok(None),
+            _ => Err(format!(
+                "unknown command :{}, type :help for help",
+                cmd.trim()
+            )),
+        }
+    }
+
+    fn load(&mut self, file: &str) -> std::result::Result<String, String> {
+        let text =
+            std::fs::read_to_string(file).map_err(|e| format!("failed to read {}: {}", file, e))?;
+        if text.is_empty() {
+            return Err(format!("{} is empty", file));
+        }
+        self.records = text.lines().map(String::from).collect();
+        self.file = file.to_string();
+        self.cur = 0;
+        let mut msg = format!("loaded {} records from {}", self.records.len(), file);
+        let sep = match file.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("csv") => Some(","),
+            Some("tsv") => Some("\t"),
+            _ => None,
+        };
+        if let Some(sep) = sep {
+            if !self.specials.contains_key("FS") {
+                self.specials.insert(String::from("FS"), sep.to_string());
+                msg.push_str(&format!(", FS set to {}", quote(sep)));
+            }
+        }
+        msg.push('\n');
+        msg.push_str(&self.record());
+        Ok(msg)
+    }
+
+    fn record(&self) -> String {
+        format!("{}: {}", self.cur + 1, self.records[self.cur])
+    }
+
+    /// Evaluate an input: a function definition, one or more statements, or an expression.
+    pub(crate) fn eval(&mut self, input: &str) -> std::result::Result<Outcome, String> {
+        if let Some(name) = defined_function(input) {
+            let mut funcs = self.funcs.clone();
+            funcs.retain(|(f, _)| *f != name);
+            funcs.push((name, input.to_string()));
+            let program = self.program(&funcs, "", None);
+            return match self.check(&program) {
+                Ok(Some(_)) => {
+                    self.funcs = funcs;
+                    Ok(Outcome::Done)
+                }
+                Ok(None) => Ok(Outcome::Incomplete),
+                Err(e) => Err(describe(&program, &e)),
+            };
+        }
+        let body = if is_value(input) {
+            format!("print ({})", input.trim_end())
+        } else {
+            input.to_string()
+        };
+        let program = self.program(&self.funcs, &body, None);
+        let globals = match self.check(&program) {
+            Ok(Some(globals)) => globals,
+            Ok(None) => return Ok(Outcome::Incomplete),
+            Err(e) => return Err(describe(&program, &e)),
+        };
+        let program = self.program(&self.funcs, &body, Some(&globals));
+        let rc = self.execute(&program).map_err(|e| describe(&program, &e))?;
+        let state = std::mem::take(&mut *self.ff.state.lock().unwrap());
+        match self.restore(&state) {
+            true => Ok(Outcome::Done),
+            // The program never reached the end of the BEGIN block, so it must have exited.
+            false => Ok(Outcome::Exit(rc)),
+        }
+    }
+
+    /// Generate the program for an input. When `snapshot` is present, the program ends by writing
+    /// the given globals to the state file.
+    fn program(
+        &self,
+        funcs: &[(String, String)],
+        body: &str,
+        snapshot: Option<&BTreeMap<String, Ty>>,
+    ) -> String {
+        let mut res = String::new();
+        for (_, def) in funcs.iter() {
+            res.push_str(def.trim_end());
+            res.push('\n');
+        }
+        // Keep the restored state on a single line, so that the input starts on a predictable one.
+        res.push_str("BEGIN {\n");
+        for (name, val) in self.specials.iter() {
+            res.push_str(&format!("{} = {}; ", name, quote(val)));
+        }
+        for (name, global) in self.globals.iter() {
+            match global {
+                Global::Scalar(v) => res.push_str(&format!("{} = {}; ", name, v.literal())),
+                Global::Map(k, v, entries) if entries.is_empty() => {
+                    let k = k.literal();
+                    res.push_str(&format!(
+                        "{n}[{k}] = {}; delete {n}[{k}]; ",
+                        v.literal(),
+                        n = name,
+                        k = k
+                    ));
+                }
+                Global::Map(_, _, entries) => {
+                    for (k, v) in entries.iter() {
+                        res.push_str(&format!("{}[{}] = {}; ", name, k.literal(), v.literal()));
+                    }
+                }
+                Global::Nested(k, outer) if outer.is_empty() => {
+                    let k = k.literal();
+                    res.push_str(&format!(
+                        "{n}[{k}][\"\"] = \"\"; delete {n}[{k}]; ",
+                        n = name,
+                        k = k
+                    ));
+                }
+                Global::Nested(_, outer) => {
+                    for (k, inner) in outer.iter() {
+                        let k = k.literal();
+                        if inner.is_empty() {
+                            res.push_str(&format!(
+                                "{n}[{k}][\"\"] = \"\"; delete {n}[{k}][\"\"]; ",
+                                n = name,
+                                k = k
+                            ));
+                        }
+                        for (j, v) in inner.iter() {
+                            res.push_str(&format!(
+                                "{}[{}][{}] = {}; ",
+                                name,
+                                k,
+                                quote(j),
+                                quote(v)
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(record) = self.records.get(self.cur) {
+            res.push_str(&format!(
+                "$0 = {}; NR = {}; FNR = {}; FILENAME = {};",
+                quote(record),
+                self.cur + 1,
+                self.cur + 1,
+                quote(&self.file)
+            ));
+        }
+        res.push('\n');
+        res.push_str(body);
+        if let Some(globals) = snapshot {
+            res.push('\n');
+            res.push_str(&snapshot_code(globals));
+        }
+        res.push_str("\n}\n");
+        res
+    }
+
+    /// Parse and type-check `program`, returning the types of its globals, or `None` if the
+    /// program ends early because the input is incomplete.
+    fn check(&self, program: &str) -> Result<Option<BTreeMap<String, Ty>>> {
+        let a = Arena::default();
+        let prog = match parse(&a, program) {
+            Ok(prog) => prog,
+            Err(lalrpop_util::ParseError::UnrecognizedEof { .. }) => return Ok(None),
+            Err(e) => return Err(Source::parse_failure(e)),
+        };
+        let ctx = cfg::ProgramContext::from_prog(&a, prog, Escaper::default())?;
+        let names = ctx._invert_ident();
+        let types::TypeInfo { var_tys, .. } = types::get_types(&ctx)?;
+        let mut res = BTreeMap::new();
+        for ((id, _, _), ty) in var_tys.iter() {
+            let name = match names.get(&id._base()) {
+                Some(name) if lexer::is_ident(name) && !name.starts_with(HIDDEN) => name,
+                _ => continue,
+            };
+            // A variable assigned different kinds of scalars gets a register of each type; keep
+            // the most general one.
+            let cur = res.entry(name.to_string()).or_insert(Ty::Null);
+            if generality(*ty) > generality(*cur) {
+                *cur = *ty;
+            }
+        }
+        res.retain(|_, ty| *ty != Ty::Null);
+        Ok(Some(res))
+    }
+
+    fn execute(&self, program: &str) -> Result<i32> {
+        let a = Arena::default();
+        let prog = parse(&a, program).map_err(Source::parse_failure)?;
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, Escaper::default())?;
+        let stdin = RegexSplitter::new(io::empty(), CHUNK_SIZE, "-", /*check_utf8=*/ false);
+        let mut interp = compile::bytecode(&mut ctx, stdin, self.ff.clone(), 1)?;
+        interp.run()
+        // Dropping the interpreter flushes the state file.
+    }
+
+    /// Replace the saved globals with those in `state`, returning false if the state is
+    /// incomplete.
+    fn restore(&mut self, state: &[u8]) -> bool {
+        let state = String::from_utf8_lossy(state);
+        let mut globals = BTreeMap::new();
+        // The kinds of the keys and values of each array.
+        let mut kinds = BTreeMap::new();
+        let mut specials = BTreeMap::new();
+        let mut complete = false;
+        for entry in state.split('\x1e') {
+            let fields: Vec<&str> = entry.split('\x1f').collect();
+            match fields[..] {
+                ["S", kind, name, val] => {
+                    globals.insert(name.to_string(), Global::Scalar(Val::new(kind, val)));
+                }
+                ["A", name, kkind, vkind] => {
+                    let global = if vkind == "m" {
+                        Global::Nested(Val::zero(kkind), Vec::new())
+                    } else {
+                        Global::Map(Val::zero(kkind), Val::zero(vkind), Vec::new())
+                    };
+                    globals.insert(name.to_string(), global);
+                    kinds.insert(name, (kkind, vkind));
+                }
+                ["M", name, key, val] => {
+                    if let (Some(Global::Map(_, _, entries)), Some((kkind, vkind))) =
+                        (globals.get_mut(name), kinds.get(name))
+                    {
+                        entries.push((Val::new(kkind, key), Val::new(vkind, val)));
+                    }
+                }
+                ["O", name, key] => {
+                    if let (Some(Global::Nested(_, outer)), Some((kkind, _))) =
+                        (globals.get_mut(name), kinds.get(name))
+                    {
+                        outer.push((Val::new(kkind, key), Vec::new()));
+                    }
+                }
+                ["N", name, inner, val] => {
+                    if let Some(Global::Nested(_, outer)) = globals.get_mut(name) {
+                        if let Some((_, entries)) = outer.last_mut() {
+                            entries.push((unhex(inner), unhex(val)));
+                        }
+                    }
+                }
+                ["B", name, val] => {
+                    specials.insert(name.to_string(), unhex(val));
+                }
+                ["E"] => complete = true,
+                _ => {}
+            }
+        }
+        if complete {
+            self.globals = globals;
+            // Only keep the builtins that differ from their defaults, to keep programs short.
+            specials.retain(|name, val| {
+                !matches!(
+                    (name.as_str(), val.as_str()),
+                    ("FS", " ") | ("OFS", " ") | ("ORS", "\n")
+                )
+            });
+            self.specials = specials;
+        }
+        complete
+    }
+}
+
+fn parse<'a>(
+    a: &'a Arena,
+    program: &str,
+) -> std::result::Result<
+    &'a ast::Prog<'a, 'a, &'a str>,
+    lalrpop_util::ParseError<lexer::Loc, Tok<'a>, lexer::Error>,
+> {
+    let text = a.alloc_str(program);
+    let mut buf = Vec::new();
+    let mut prog = ast::Prog::from_stage(a, Stage::Main(()));
+    ProgParser::new().parse(a, &mut buf, &mut prog, lexer::Tokenizer::new(text))?;
+    Ok(a.alloc(prog))
+}
+
+fn generality(ty: Ty) -> u8 {
+    match ty {
+        Ty::Null | Ty::IterInt | Ty::IterStr => 0,
+        Ty::Int => 1,
+        Ty::Float => 2,
+        Ty::Str => 3,
+        _ => 4,
+    }
+}
+
+fn kind(ty: Ty) -> &'static str {
+    match ty {
+        Ty::Int => "i",
+        Ty::Float => "f",
+        _ => "s",
+    }
+}
+
+/// The printf conversion and argument writing `expr`, of type `ty`, to the state file in a form
+/// `Val::new` reads back exactly.
+fn written(ty: Ty, expr: &str) -> (&'static str, String) {
+    match ty {
+        Ty::Int => ("%d", expr.to_string()),
+        Ty::Float => ("%.17g", expr.to_string()),
+        _ => ("%s", format!("encode(\"hex\", {})", expr)),
+    }
+}
+
+/// Decode a string written by `written`.
+fn unhex(s: &str) -> String {
+    String::from_utf8_lossy(&hex::decode(s).unwrap_or_default()).into_owned()
+}
+
+/// Code writing `globals` (and the special variables) to the state file, followed by an end
+/// marker.
+fn snapshot_code(globals: &BTreeMap<String, Ty>) -> String {
+    let out = format!("> {}", quote(STATE_FILE));
+    let mut res = String::new();
+    for name in SPECIAL_VARS {
+        res.push_str(&format!(
+            "printf \"B\\x1f{}\\x1f%s\\x1e\", encode(\"hex\", {}) {}\n",
+            name, name, out
+        ));
+    }
+    for (i, (name, ty)) in globals.iter().enumerate() {
+        if !ty.is_array() {
+            let (conv, arg) = written(*ty, name);
+            res.push_str(&format!(
+                "printf \"S\\x1f{}\\x1f{}\\x1f{}\\x1e\", {} {}\n",
+                kind(*ty),
+                name,
+                conv,
+                arg,
+                out
+            ));
+            continue;
+        }
+        let (key, val) = match (ty.key(), ty.val()) {
+            (Ok(key), Ok(val)) => (key, val),
+            _ => continue,
+        };
+        let (k, j) = (format!("{}k{}", HIDDEN, i), format!("{}j{}", HIDDEN, i));
+        let (kconv, karg) = written(key, &k);
+        let vkind = if ty.is_nested_array() { "m" } else { kind(val) };
+        res.push_str(&format!(
+            "printf \"A\\x1f{}\\x1f{}\\x1f{}\\x1e\" {}\n",
+            name,
+            kind(key),
+            vkind,
+            out
+        ));
+        let line = if ty.is_nested_array() {
+            let (jconv, jarg) = written(Ty::Str, &j);
+            let (vconv, varg) = written(Ty::Str, &format!("{}[{}][{}]", name, k, j));
+            format!(
+                "for ({k} in {n}) {{ printf \"O\\x1f{n}\\x1f{}\\x1e\", {} {out}; \
+                 for ({j} in {n}[{k}]) printf \"N\\x1f{n}\\x1f{}\\x1f{}\\x1e\", {}, {} {out}; }}",
+                kconv,
+                karg,
+                jconv,
+                vconv,
+                jarg,
+                varg,
+                n = name,
+                out = out,
+            )
+        } else {
+            let (vconv, varg) = written(val, &format!("{}[{}]", name, k));
+            format!(
+                "for ({k} in {n}) printf \"M\\x1f{n}\\x1f{}\\x1f{}\\x1e\", {}, {} {}",
+                kconv,
+                vconv,
+                karg,
+                varg,
+                out,
+                n = name,
+            )
+        };
+        res.push_str(&line);
+        res.push('\n');
+    }
+    res.push_str(&format!("printf \"E\\x1e\" {}", out));
+    res
+}
+
+/// Quote `s` as an AWK string literal.
+fn quote(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c if c.is_ascii_control() => res.push_str(&format!("\\x{:02x}", c as u8)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// If `input` is a function definition, the name of the function.
+fn defined_function(input: &str) -> Option<String> {
+    let rest = input.trim_start().strip_prefix("function")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let end = rest.find('(')?;
+    Some(rest[..end].trim().to_string())
+}
+
+/// Whether `input` is a lone expression whose value should be printed, rather than a statement or
+/// an expression evaluated for its side effects.
+fn is_value(input: &str) -> bool {
+    let a = Arena::default();
+    let prog = match parse(&a, &format!("BEGIN {{\n{}\n}}", input)) {
+        Ok(prog) if prog.begin.len() == 1 => prog,
+        _ => return false,
+    };
+    let mut stmt = prog.begin[0];
+    loop {
+        stmt = match stmt {
+            Stmt::Located(_, s) => *s,
+            Stmt::Block(stmts) if stmts.len() == 1 => stmts[0],
+            Stmt::Expr(mut e) => {
+                while let Expr::Located(_, x) = e {
+                    e = x;
+                }
+                return !matches!(
+                    e,
+                    Expr::Assign(..)
+                        | Expr::AssignOp(..)
+                        | Expr::Inc { .. }
+                        | Expr::Getline { .. }
+                        | Expr::Call(Either::Right(_), _)
+                ) && !matches!(e, Expr::Call(Either::Left(f), _) if VOID_FUNCS.contains(f))
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Render an error in the generated program. Its line numbers mean nothing to the user, so show
+/// the message and the offending line only.
+fn describe(program: &str, e: &CompileError) -> String {
+    let mut res = e.0.clone();
+    if let Some(line) =
+        e.1.and_then(|loc| program.lines().nth(loc.line).map(|l| (l, loc)))
+    {
+        let (text, loc) = line;
+        let indent: String = text
+            .get(..loc.col)
+            .unwrap_or("")
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        res.push_str(&format!("\n{}\n{}^", text, indent));
+    }
+    res
+}
+
+/// Passes writes to `STATE_FILE` to an in-memory buffer, and everything else to `inner`.
+#[derive(Clone)]
+struct StateFactory<FF> {
+    inner: FF,
+    state: Arc<Mutex<Vec<u8>>>,
+}
+
+enum Output<W> {
+    File(W),
+    State(Arc<Mutex<Vec<u8>>>),
+}
+
+impl<W: io::Write> io::Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(w) => w.write(buf),
+            Output::State(state) => {
+                state.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(w) => w.flush(),
+            Output::State(_) => Ok(()),
+        }
+    }
+}
+
+impl<FF: FileFactory> FileFactory for StateFactory<FF> {
+    type Output = Output<FF::Output>;
+    type Stdout = FF::Stdout;
+    fn cmd(&self, cmd: &[u8]) -> io::Result<ChildStdin> {
+        self.inner.cmd(cmd)
+    }
+    fn build(&self, path: &str, spec: FileSpec) -> io::Result<Self::Output> {
+        if path == STATE_FILE {
+            Ok(Output::State(self.state.clone()))
+        } else {
+            self.inner.build(path, spec).map(Output::File)
+        }
+    }
+    fn stdout(&self) -> Self::Stdout {
+        self.inner.stdout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::writers::testing::FakeFs;
+
+    fn eval(s: &mut Session<FakeFs>, fs: &FakeFs, input: &str) -> String {
+        fs.stdout.clear();
+        assert_eq!(s.eval(input), Ok(Outcome::Done), "{}", input);
+        String::from_utf8(fs.stdout.read_data()).unwrap()
+    }
+
+    #[test]
+    fn globals_persist() {
+        let fs = FakeFs::default();
+        let mut s = Session::new(fs.clone());
+        assert_eq!(eval(&mut s, &fs, "x = 2; name = \"a \\\"b\\\"\""), "");
+        assert_eq!(eval(&mut s, &fs, "x * 3"), "6\n");
+        assert_eq!(eval(&mut s, &fs, "a[\"k\"] = x; b[1][\"z\"] = name"), "");
+        assert_eq!(eval(&mut s, &fs, "a[\"k\"] + 1"), "3\n");
+        assert_eq!(eval(&mut s, &fs, "b[1][\"z\"]"), "a \"b\"\n");
+        assert_eq!(eval(&mut s, &fs, "OFS = \"-\"; x++"), "");
+        assert_eq!(eval(&mut s, &fs, "print x, length(a)"), "3-1\n");
+        assert_eq!(eval(&mut s, &fs, "function double(n) { return n * 2 }"), "");
+        assert_eq!(eval(&mut s, &fs, "double(x)"), "6\n");
+        assert_eq!(s.eval("if (x > 1) {\n"), Ok(Outcome::Incomplete));
+        assert_eq!(
+            eval(&mut s, &fs, "if (x > 1) {\nprint \"big\"\n}\n"),
+            "big\n"
+        );
+        assert!(s.eval("x = = 1").is_err());
+        assert!(s.eval("x[1] = 2").is_err());
+        assert_eq!(eval(&mut s, &fs, "x"), "3\n");
+        assert_eq!(s.eval("exit 3"), Ok(Outcome::Exit(3)));
+    }
+
+    #[test]
+    fn exact_state() {
+        let fs = FakeFs::default();
+        let mut s = Session::new(fs.clone());
+        let input = "f = 2^53; g = 1/3; h = -log(0); sep = \"a\\x1eb\\x1fc\"; FS = \"\\x1e\"";
+        assert_eq!(eval(&mut s, &fs, input), "");
+        // Floats stay floats, with all of their digits.
+        assert_eq!(eval(&mut s, &fs, "f + 1"), "9007199254740992\n");
+        assert_eq!(eval(&mut s, &fs, "g * 3"), "1\n");
+        assert_eq!(eval(&mut s, &fs, "h"), "inf\n");
+        assert_eq!(
+            eval(
+                &mut s,
+                &fs,
+                "print length(sep), (sep == \"a\\x1eb\\x1fc\"), (FS == \"\\x1e\")"
+            ),
+            "5 1 1\n"
+        );
+        // Empty arrays, including empty arrays of arrays, stay arrays.
+        let input = "split(\"\", e); n[1][\"\"] = \"\"; delete n[1][\"\"]; m[\"x\"] = 1.5";
+        assert_eq!(eval(&mut s, &fs, input), "");
+        assert_eq!(
+            eval(
+                &mut s,
+                &fs,
+                "e[\"k\"] = 1; print length(e), length(n), length(n[1]), m[\"x\"] * 2"
+            ),
+            "1 1 0 3\n"
+        );
+    }
+
+    #[test]
+    fn records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("people.csv");
+        std::fs::write(&path, "name,age\nalice,31\nbob,42\n").unwrap();
+        let fs = FakeFs::default();
+        let mut s = Session::new(fs.clone());
+        let msg = s
+            .command(&format!("load {}", path.display()))
+            .unwrap()
+            .unwrap();
+        assert!(msg.ends_with("1: name,age"), "{}", msg);
+        s.command("next").unwrap();
+        assert_eq!(eval(&mut s, &fs, "$2 + 1"), "32\n");
+        assert_eq!(eval(&mut s, &fs, "total += $2"), "");
+        s.command("next").unwrap();
+        assert_eq!(
+            eval(&mut s, &fs, "total += $2; print NR, $1, total"),
+            "3 bob 73\n"
+        );
+        assert!(s.command("next").is_err());
+        assert_eq!(s.command("quit"), Ok(None));
+    }
+
+    #[test]
+    fn values() {
+        assert!(is_value("x"));
+        assert!(is_value("length($0) * 2"));
+        assert!(!is_value("x = 1"));
+        assert!(!is_value("x++"));
+        assert!(!is_value("var_dump(x)"));
+        assert!(!is_value("print x"));
+        assert!(!is_value("x; y"));
+        assert_eq!(
+            defined_function("function f(a) { }"),
+            Some(String::from("f"))
+        );
+        assert_eq!(defined_function("functions = 1"), None);
+        assert_eq!(quote("a\"\\\n\x01"), "\"a\\\"\\\\\\n\\x01\"");
+    }
+}
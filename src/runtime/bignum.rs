@@ -0,0 +1,613 @@
+//! Exact decimal numbers for `-M` (bignum) mode.
+//!
+//! In bignum mode arithmetic and comparisons are rewritten into calls to [`arith`] and [`cmp`]
+//! (see `crate::bignum`), and numbers travel between them as strings holding their exact decimal
+//! value. Sums, differences, products and remainders are exact. Quotients are rounded, half to
+//! even, to the number of significant digits that `PREC` bits give. `print` and concatenation
+//! write numbers that are not integers with `OFMT` and `CONVFMT` (see [`format`]).
+//!
+//! The `decimal_*` builtins (see `runtime::math_util`) use the same numbers outside of bignum mode.
+//! They keep the number of decimal places that was written, so that `1.10 + 2.20` is `3.30`.
+use std::cmp::Ordering;
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use crate::builtins::BigOp;
+use crate::common::Result;
+use crate::runtime::printf::{printf, FormatArg};
+
+/// The precision, in bits, used when `PREC` is unset.
+pub const DEFAULT_PREC: u64 = 53;
+
+// Exponents larger than this are not read, rather than being expanded into millions of digits.
+const MAX_EXP: i64 = 100_000;
+
+// The largest power, in bits, that `^` computes exactly. Anything larger is an error.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+/// The number `digits * 10^-scale`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    digits: BigInt,
+    scale: u32,
+}
+
+fn pow10(n: u32) -> BigInt {
+    BigInt::from(10u32).pow(n)
+}
+
+/// How to round a number that lies exactly halfway between two candidates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards the neighbor with an even last digit, as IEEE arithmetic and `printf` do.
+    HalfEven,
+    /// Away from zero, as is usual when rounding by hand.
+    HalfUp,
+}
+
+impl Rounding {
+    /// The mode called `name`. The empty string selects the default, `half_even`.
+    pub fn from_name(name: &[u8]) -> Option<Rounding> {
+        match name {
+            b"" | b"half_even" => Some(Rounding::HalfEven),
+            b"half_up" => Some(Rounding::HalfUp),
+            _ => None,
+        }
+    }
+}
+
+/// `num / den` rounded to an integer, with ties broken according to `mode`.
+fn round_div(num: &BigInt, den: &BigInt, mode: Rounding) -> BigInt {
+    let q = num / den;
+    let r = num % den;
+    if r.is_zero() {
+        return q;
+    }
+    let away = match (r.abs() * 2u32).cmp(&den.abs()) {
+        Ordering::Less => false,
+        Ordering::Greater => true,
+        Ordering::Equal => mode == Rounding::HalfUp || !(&q % 2u32).is_zero(),
+    };
+    if !away {
+        q
+    } else if num.is_negative() != den.is_negative() {
+        q - 1
+    } else {
+        q + 1
+    }
+}
+
+impl Decimal {
+    pub fn zero() -> Decimal {
+        Decimal {
+            digits: BigInt::zero(),
+            scale: 0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_zero()
+    }
+
+    /// The number of digits after the decimal point.
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Parse the longest prefix of `s` that is a number, skipping leading whitespace, and return
+    /// it with the number of bytes read. As in awk's string-to-number conversion, the number may
+    /// have a sign, a decimal point and an exponent. Trailing zeros after the decimal point are
+    /// kept.
+    pub fn parse_prefix(s: &[u8]) -> Option<(Decimal, usize)> {
+        let mut i = s.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        let neg = match s.get(i) {
+            Some(b'-') => {
+                i += 1;
+                true
+            }
+            Some(b'+') => {
+                i += 1;
+                false
+            }
+            _ => false,
+        };
+        let mut mantissa = Vec::new();
+        let mut frac_digits = 0i64;
+        while let Some(c @ b'0'..=b'9') = s.get(i).copied() {
+            mantissa.push(c);
+            i += 1;
+        }
+        if s.get(i) == Some(&b'.') {
+            i += 1;
+            while let Some(c @ b'0'..=b'9') = s.get(i).copied() {
+                mantissa.push(c);
+                frac_digits += 1;
+                i += 1;
+            }
+        }
+        if mantissa.is_empty() {
+            return None;
+        }
+        let mut exp = 0i64;
+        if let Some(b'e' | b'E') = s.get(i) {
+            let mut j = i + 1;
+            let exp_neg = match s.get(j) {
+                Some(b'-') => {
+                    j += 1;
+                    true
+                }
+                Some(b'+') => {
+                    j += 1;
+                    false
+                }
+                _ => false,
+            };
+            let start = j;
+            let mut e = 0i64;
+            while let Some(c @ b'0'..=b'9') = s.get(j).copied() {
+                e = (e * 10 + (c - b'0') as i64).min(MAX_EXP + 1);
+                j += 1;
+            }
+            if j > start && e <= MAX_EXP {
+                exp = if exp_neg { -e } else { e };
+                i = j;
+            }
+        }
+        let mut digits = BigInt::parse_bytes(&mantissa, 10)?;
+        if neg {
+            digits = -digits;
+        }
+        let shift = exp - frac_digits;
+        let res = if shift >= 0 {
+            Decimal {
+                digits: digits * pow10(shift as u32),
+                scale: 0,
+            }
+        } else {
+            Decimal {
+                digits,
+                scale: (-shift) as u32,
+            }
+        };
+        Some((res, i))
+    }
+
+    /// Parse `s` if it holds a number and nothing else apart from surrounding whitespace.
+    pub fn parse_exact(s: &[u8]) -> Option<Decimal> {
+        let (d, n) = Decimal::parse_prefix(s)?;
+        if s[n..].iter().all(u8::is_ascii_whitespace) {
+            Some(d.normalize())
+        } else {
+            None
+        }
+    }
+
+    /// Parse `s` the way arithmetic reads it: a string that does not start with a number is 0.
+    pub fn parse_lossy(s: &[u8]) -> Decimal {
+        Decimal::parse_written(s).normalize()
+    }
+
+    /// Like `parse_lossy`, but keeping the decimal places that were written.
+    pub fn parse_written(s: &[u8]) -> Decimal {
+        Decimal::parse_prefix(s)
+            .map(|(d, _)| d)
+            .unwrap_or_else(Decimal::zero)
+    }
+
+    fn from_f64(f: f64) -> Option<Decimal> {
+        if f.is_finite() {
+            Decimal::parse_exact(f.to_string().as_bytes())
+        } else {
+            None
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(0.0)
+    }
+
+    /// `self` without trailing zeros after the decimal point.
+    pub fn normalize(mut self) -> Decimal {
+        let ten = BigInt::from(10u32);
+        while self.scale > 0 && (&self.digits % &ten).is_zero() {
+            self.digits /= &ten;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    fn rescale(&self, scale: u32) -> BigInt {
+        &self.digits * pow10(scale - self.scale)
+    }
+
+    fn aligned(&self, other: &Decimal) -> (BigInt, BigInt, u32) {
+        let scale = self.scale.max(other.scale);
+        (self.rescale(scale), other.rescale(scale), scale)
+    }
+
+    // The number of digits before the decimal point, which is zero or negative for numbers below
+    // one.
+    fn int_digits(&self) -> i64 {
+        self.digits.abs().to_string().len() as i64 - self.scale as i64
+    }
+
+    // Sums, differences and products are not normalized: a sum has as many decimal places as the
+    // longer operand and a product has as many as both operands together.
+
+    pub fn add(&self, other: &Decimal) -> Decimal {
+        let (x, y, scale) = self.aligned(other);
+        Decimal {
+            digits: x + y,
+            scale,
+        }
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Decimal {
+        let (x, y, scale) = self.aligned(other);
+        Decimal {
+            digits: x - y,
+            scale,
+        }
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Decimal {
+        Decimal {
+            digits: &self.digits * &other.digits,
+            scale: self.scale + other.scale,
+        }
+    }
+
+    /// `self / other` rounded half to even to `sig` significant digits, though digits before the
+    /// decimal point are always kept. Returns `None` when `other` is zero.
+    pub fn div(&self, other: &Decimal, sig: u32) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        // The leading digit of the quotient is within one place of this estimate.
+        let mag = self.int_digits() - other.int_digits();
+        let scale = (sig as i64 - mag).clamp(0, u32::MAX as i64) as u32;
+        Some(
+            self.div_to_scale(other, scale, Rounding::HalfEven)
+                .normalize(),
+        )
+    }
+
+    /// `self / other` rounded to `scale` decimal places. `other` must not be zero.
+    pub fn div_to_scale(&self, other: &Decimal, scale: u32, mode: Rounding) -> Decimal {
+        // self / other = (A / B) * 10^(sb - sa), so the rounded result is A * 10^k / B.
+        let k = scale as i64 + other.scale as i64 - self.scale as i64;
+        let (num, den) = if k >= 0 {
+            (&self.digits * pow10(k as u32), other.digits.clone())
+        } else {
+            (self.digits.clone(), &other.digits * pow10((-k) as u32))
+        };
+        Decimal {
+            digits: round_div(&num, &den, mode),
+            scale,
+        }
+    }
+
+    /// The remainder of truncating division, which has the sign of `self`. Returns `None` when
+    /// `other` is zero.
+    pub fn rem(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        let (x, y, scale) = self.aligned(other);
+        Some(
+            Decimal {
+                digits: x % y,
+                scale,
+            }
+            .normalize(),
+        )
+    }
+
+    /// `self` without its fractional part.
+    pub fn trunc(&self) -> Decimal {
+        Decimal {
+            digits: &self.digits / pow10(self.scale),
+            scale: 0,
+        }
+    }
+
+    /// `self` rounded to at most `scale` decimal places.
+    pub fn round(&self, scale: u32, mode: Rounding) -> Decimal {
+        if scale >= self.scale {
+            return self.clone();
+        }
+        Decimal {
+            digits: round_div(&self.digits, &pow10(self.scale - scale), mode),
+            scale,
+        }
+    }
+
+    // `self^e`, or `None` if the result would be too large to compute.
+    fn powi(&self, e: &BigInt) -> Option<Decimal> {
+        if self.scale == 0 && self.digits.abs().is_one() {
+            let odd = e.bit(0);
+            return Some(Decimal {
+                digits: if odd { self.digits.clone() } else { BigInt::one() },
+                scale: 0,
+            });
+        }
+        let e = e.to_u32()?;
+        if self.digits.bits().saturating_mul(e as u64) > MAX_POW_BITS {
+            return None;
+        }
+        Some(
+            Decimal {
+                digits: self.digits.pow(e),
+                scale: self.scale.checked_mul(e)?,
+            }
+            .normalize(),
+        )
+    }
+
+    /// Write `self` with exactly `places` digits after the decimal point.
+    pub fn to_fixed(&self, places: u32, mode: Rounding) -> String {
+        let rounded = self.round(places, mode);
+        Decimal {
+            digits: rounded.rescale(places),
+            scale: places,
+        }
+        .to_string()
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let (x, y, _) = self.aligned(other);
+        x.cmp(&y)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.digits.abs().to_string();
+        if self.digits.is_negative() {
+            f.write_str("-")?;
+        }
+        let scale = self.scale as usize;
+        if scale == 0 {
+            f.write_str(&s)
+        } else if s.len() > scale {
+            let (int, frac) = s.split_at(s.len() - scale);
+            write!(f, "{}.{}", int, frac)
+        } else {
+            write!(f, "0.{}{}", "0".repeat(scale - s.len()), s)
+        }
+    }
+}
+
+/// The number of significant digits kept by division for a given value of `PREC`. `PREC` is a
+/// number of bits, or one of gawk's names for the IEEE formats.
+pub fn sig_digits(prec: &[u8]) -> u32 {
+    let bits = match prec {
+        b"half" => 11,
+        b"single" => 24,
+        b"double" => 53,
+        b"quad" => 113,
+        b"oct" => 237,
+        _ => Decimal::parse_lossy(prec)
+            .trunc()
+            .digits
+            .to_u64()
+            .filter(|bits| *bits > 0)
+            .unwrap_or(DEFAULT_PREC),
+    };
+    ((bits as f64) * std::f64::consts::LOG10_2)
+        .ceil()
+        .clamp(1.0, 1_000_000.0) as u32
+}
+
+/// Apply `op` to the numbers in `x` and `y`. `Int` ignores `y`, and `Format` takes the format to
+/// use as `y`.
+pub fn arith(op: BigOp, x: &[u8], y: &[u8], prec: &[u8]) -> Result<String> {
+    if op == BigOp::Format {
+        return Ok(format(x, y));
+    }
+    let (x, y) = (Decimal::parse_lossy(x), Decimal::parse_lossy(y));
+    let res = match op {
+        BigOp::Add => x.add(&y),
+        BigOp::Sub => x.sub(&y),
+        BigOp::Mul => x.mul(&y),
+        BigOp::Int => x.trunc(),
+        BigOp::Div => match x.div(&y, sig_digits(prec)) {
+            Some(q) => q,
+            None => return err!("division by zero"),
+        },
+        BigOp::Mod => match x.rem(&y) {
+            Some(r) => r,
+            None => return err!("division by zero in %"),
+        },
+        BigOp::Pow if y.scale == 0 => {
+            let p = match x.powi(&y.digits.abs()) {
+                Some(p) => p,
+                None => return err!("exponent too large in {} ^ {}", x, y),
+            };
+            if y.digits.is_negative() {
+                match Decimal::parse_lossy(b"1").div(&p, sig_digits(prec)) {
+                    Some(q) => q,
+                    None => return err!("division by zero in ^"),
+                }
+            } else {
+                p
+            }
+        }
+        // Fractional powers are computed in floating point.
+        BigOp::Pow => {
+            let f = x.to_f64().powf(y.to_f64());
+            return Ok(match Decimal::from_f64(f) {
+                Some(d) => d.to_string(),
+                None => f.to_string(),
+            });
+        }
+        BigOp::Format => unreachable!(),
+    };
+    Ok(res.normalize().to_string())
+}
+
+/// Compare `x` and `y`, returning -1, 0 or 1. Two numbers compare numerically, with the empty
+/// string counting as zero the way an unset variable does; anything else compares as strings.
+pub fn cmp(x: &[u8], y: &[u8]) -> i64 {
+    let num = |s: &[u8]| {
+        if s.is_empty() {
+            Some(Decimal::zero())
+        } else {
+            Decimal::parse_exact(s)
+        }
+    };
+    let ord = match (num(x), num(y)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        _ => x.cmp(y),
+    };
+    ord as i64
+}
+
+/// The integer part of `s`, for `printf("%d")`.
+pub fn format_int(s: &[u8]) -> String {
+    Decimal::parse_lossy(s).trunc().to_string()
+}
+
+/// `s` with `places` digits after the decimal point, for `printf("%f")`.
+pub fn format_fixed(s: &[u8], places: u32) -> String {
+    Decimal::parse_lossy(s).to_fixed(places, Rounding::HalfEven)
+}
+
+/// `s` with `sig` significant digits, for `printf("%g")`: in exponential notation if its exponent
+/// is below -4 or at least `sig`, and in fixed notation otherwise, without trailing zeros.
+pub fn format_general(s: &[u8], sig: u32) -> String {
+    let sig = sig.max(1) as i64;
+    let d = Decimal::parse_lossy(s);
+    if d.is_zero() {
+        return String::from("0");
+    }
+    // `d` lies in [10^exp, 10^(exp+1)). Round it to the integer `m` times 10^-places, where `m`
+    // has `sig` digits unless rounding carried into a new leading digit.
+    let mut exp = d.int_digits() - 1;
+    let mut places = sig - 1 - exp;
+    let k = places - d.scale as i64;
+    let mut m = if k >= 0 {
+        &d.digits * pow10(k as u32)
+    } else {
+        round_div(&d.digits, &pow10((-k) as u32), Rounding::HalfEven)
+    };
+    if m.abs() >= pow10(sig as u32) {
+        m /= 10u32;
+        exp += 1;
+        places -= 1;
+    }
+    if (-4..sig).contains(&exp) {
+        return Decimal {
+            digits: m,
+            scale: places as u32,
+        }
+        .normalize()
+        .to_string();
+    }
+    let digits = m.abs().to_string();
+    let (lead, rest) = digits.split_at(1);
+    let rest = rest.trim_end_matches('0');
+    format!(
+        "{}{}{}{}e{}{:02}",
+        if m.is_negative() { "-" } else { "" },
+        lead,
+        if rest.is_empty() { "" } else { "." },
+        rest,
+        if exp < 0 { '-' } else { '+' },
+        exp.abs()
+    )
+}
+
+/// `s` converted to a string with `fmt`, the value of `OFMT` or `CONVFMT`.
+///
+/// Only strings written the way arithmetic writes its results, with no exponent or trailing zeros,
+/// are numbers here, and as in other awks integers are left alone. Other strings, like `0.10` read
+/// from the input, are kept as they are. Numbers are strings in bignum mode, so this cannot tell
+/// input like `0.1234567` from a computed result once it is stored in a variable, and formats
+/// both.
+pub fn format(s: &[u8], fmt: &[u8]) -> String {
+    let text = String::from_utf8_lossy(s);
+    match Decimal::parse_exact(s) {
+        Some(d) if d.scale > 0 && d.to_string() == text => {
+            let mut buf = Vec::new();
+            match printf(&mut buf, fmt, &[FormatArg::Big(s.into())], None) {
+                Ok(()) => String::from_utf8_lossy(&buf).into_owned(),
+                Err(_) => text.into_owned(),
+            }
+        }
+        _ => text.into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(op: BigOp, x: &str, y: &str) -> String {
+        arith(op, x.as_bytes(), y.as_bytes(), b"").unwrap()
+    }
+
+    #[test]
+    fn decimal_arith() {
+        assert_eq!(op(BigOp::Add, "0.1", "0.2"), "0.3");
+        assert_eq!(
+            op(BigOp::Add, "9223372036854775807", "1"),
+            "9223372036854775808"
+        );
+        assert_eq!(op(BigOp::Sub, "1.50", "2"), "-0.5");
+        assert_eq!(op(BigOp::Mul, "1e3", "-2.5"), "-2500");
+        assert_eq!(op(BigOp::Div, "1", "4"), "0.25");
+        assert_eq!(op(BigOp::Div, "2", "3"), "0.6666666666666667");
+        assert_eq!(op(BigOp::Mod, "-7.5", "2"), "-1.5");
+        assert_eq!(
+            op(BigOp::Pow, "2", "100"),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(op(BigOp::Pow, "2", "-2"), "0.25");
+        assert_eq!(op(BigOp::Pow, "-1", "1e50"), "1");
+        assert!(arith(BigOp::Pow, b"2", b"1e50", b"").is_err());
+        assert!(arith(BigOp::Pow, b"10", b"2000000", b"").is_err());
+        assert_eq!(op(BigOp::Int, "-3.99", ""), "-3");
+        assert_eq!(op(BigOp::Add, " 12abc", "x"), "12");
+        assert!(arith(BigOp::Div, b"1", b"0", b"").is_err());
+        assert_eq!(
+            arith(BigOp::Div, b"1", b"3", b"single").unwrap(),
+            "0.33333333"
+        );
+    }
+
+    #[test]
+    fn decimal_cmp_and_format() {
+        assert_eq!(cmp(b"10", b"9"), 1);
+        assert_eq!(cmp(b"1.0", b"1"), 0);
+        assert_eq!(cmp(b"", b"0"), 0);
+        assert_eq!(cmp(b"abc", b"9"), 1);
+        assert_eq!(
+            format_int(b"-12345678901234567890.9"),
+            "-12345678901234567890"
+        );
+        assert_eq!(format_fixed(b"2.675", 2), "2.68");
+        assert_eq!(format_fixed(b"0.125", 2), "0.12");
+        assert_eq!(format_fixed(b"-1", 3), "-1.000");
+        assert_eq!(format_general(b"0.3", 6), "0.3");
+        assert_eq!(format_general(b"-18446744073709551614.1", 6), "-1.84467e+19");
+        assert_eq!(format_general(b"999999.5", 6), "1e+06");
+        assert_eq!(format_general(b"0.0001234567", 6), "0.000123457");
+        assert_eq!(format_general(b"0.00001", 6), "1e-05");
+        assert_eq!(format_general(b"120", 2), "1.2e+02");
+        assert_eq!(format(b"0.3333333333333333", b"%.6g"), "0.333333");
+        assert_eq!(format(b"12345678901234567890", b"%.6g"), "12345678901234567890");
+        assert_eq!(format(b"0.10", b"%.6g"), "0.10");
+        assert_eq!(format(b"2.675", b"%.2f"), "2.68");
+    }
+}
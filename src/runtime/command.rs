@@ -1,7 +1,9 @@
 use std::io;
-use std::process::{ChildStdin, Command, Stdio};
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
 
 use grep_cli::{CommandError, CommandReader};
+use hashbrown::HashMap;
 
 use crate::runtime::{Int, Str, StrMap};
 
@@ -59,3 +61,66 @@ pub fn command_for_read(bs: &[u8]) -> Result<CommandReader, CommandError> {
     let mut cmd = prepare_command(String::from_utf8_lossy(bs).as_ref())?;
     CommandReader::new(&mut cmd)
 }
+
+struct Coproc {
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+}
+
+fn claimed(bs: &[u8], end: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        format!(
+            "{} of coprocess '{}' is already in use or closed",
+            end,
+            String::from_utf8_lossy(bs)
+        ),
+    )
+}
+
+/// Coprocesses started with `|&`. The writer (`print |& cmd`) and the reader (`cmd |& getline`)
+/// each claim one of the process's pipes; whichever comes first starts the process and leaves the
+/// other pipe here until it is claimed. Copies share the same processes.
+#[derive(Clone, Default)]
+pub struct Coprocs(Arc<Mutex<HashMap<Box<[u8]>, Coproc>>>);
+
+impl Coprocs {
+    fn with_coprocess<R>(&self, bs: &[u8], f: impl FnOnce(&mut Coproc) -> R) -> io::Result<R> {
+        let mut coprocs = self.0.lock().unwrap();
+        if !coprocs.contains_key(bs) {
+            let mut cmd = prepare_command(String::from_utf8_lossy(bs).as_ref())?;
+            let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+            let coproc = Coproc {
+                stdin: child.stdin.take(),
+                stdout: child.stdout.take(),
+            };
+            coprocs.insert(bs.into(), coproc);
+        }
+        let coproc = coprocs.get_mut(bs).unwrap();
+        let res = f(coproc);
+        if coproc.stdin.is_none() && coproc.stdout.is_none() {
+            coprocs.remove(bs);
+        }
+        Ok(res)
+    }
+
+    /// Start the coprocess `bs` if it is not running yet.
+    pub fn start(&self, bs: &[u8]) -> io::Result<()> {
+        self.with_coprocess(bs, |_| ())
+    }
+
+    pub fn for_write(&self, bs: &[u8]) -> io::Result<ChildStdin> {
+        self.with_coprocess(bs, |coproc| coproc.stdin.take())?
+            .ok_or_else(|| claimed(bs, "input"))
+    }
+
+    pub fn for_read(&self, bs: &[u8]) -> io::Result<ChildStdout> {
+        self.with_coprocess(bs, |coproc| coproc.stdout.take())?
+            .ok_or_else(|| claimed(bs, "output"))
+    }
+
+    /// Drop any pipes of the coprocess `bs` that were never claimed, after it is closed.
+    pub fn close(&self, bs: &[u8]) {
+        self.0.lock().unwrap().remove(bs);
+    }
+}
@@ -20,7 +20,9 @@ pub(crate) fn from_csv<'a>(text: &str) -> IntMap<Str<'a>> {
 pub(crate) fn map_int_int_to_csv(csv: &IntMap<Int>) -> String {
     let mut items: Vec<String> = vec![];
     let mut keys = csv.to_vec();
-    keys.sort();
+    if !csv.is_ordered() {
+        keys.sort();
+    }
     for key in keys {
         items.push(csv.get(&key).to_string());
     }
@@ -30,7 +32,9 @@ pub(crate) fn map_int_int_to_csv(csv: &IntMap<Int>) -> String {
 pub(crate) fn map_int_float_to_csv(csv: &IntMap<Float>) -> String {
     let mut items: Vec<String> = vec![];
     let mut keys = csv.to_vec();
-    keys.sort();
+    if !csv.is_ordered() {
+        keys.sort();
+    }
     for key in keys {
         items.push(csv.get(&key).to_string());
     }
@@ -38,12 +42,13 @@ pub(crate) fn map_int_float_to_csv(csv: &IntMap<Float>) -> String {
 }
 
 pub(crate) fn map_int_str_to_csv(csv: &IntMap<Str>) -> String {
-    let mut items: Vec<&str> = vec![];
     let mut keys = csv.to_vec();
-    keys.sort();
-    for key in keys {
-        items.push(csv.get(&key).as_str());
+    if !csv.is_ordered() {
+        keys.sort();
     }
+    // Keep the values alive while the fields borrow from them.
+    let values: Vec<Str> = keys.iter().map(|key| csv.get(key)).collect();
+    let items: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
     vec_to_csv(&items)
 }
 
@@ -75,6 +75,24 @@ pub fn strtod(bs: &[u8]) -> f64 {
     }
 }
 
+/// Parse `bs` as a number if the whole string (save for surrounding blanks) looks like one, as
+/// POSIX awk does when deciding whether input is a "strnum".
+pub fn strnum(bs: &[u8]) -> Option<f64> {
+    let is_blank = |b: &u8| matches!(b, b' ' | b'\t' | b'\n' | b'\r');
+    let start = bs.iter().position(|b| !is_blank(b))?;
+    let end = bs.iter().rposition(|b| !is_blank(b))? + 1;
+    let bs = &bs[start..end];
+    // fast_float accepts "inf" and "nan"; awk does not.
+    let first = bs.iter().find(|b| !matches!(b, b'+' | b'-'))?;
+    if !(first.is_ascii_digit() || *first == b'.') {
+        return None;
+    }
+    match fast_float::parse_partial(bs) {
+        Ok((f, len)) if len == bs.len() => Some(f),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,4 +109,16 @@ mod tests {
         assert_eq!(strtod(imax.as_bytes()), i64::max_value() as f64);
         assert_eq!(strtod(imin.as_bytes()), i64::min_value() as f64);
     }
+
+    #[test]
+    fn strnums() {
+        assert_eq!(strnum(b"10"), Some(10.0));
+        assert_eq!(strnum(b" -2.5e3\n"), Some(-2500.0));
+        assert_eq!(strnum(b".5"), Some(0.5));
+        assert_eq!(strnum(b""), None);
+        assert_eq!(strnum(b"  "), None);
+        assert_eq!(strnum(b"10abc"), None);
+        assert_eq!(strnum(b"inf"), None);
+        assert_eq!(strnum(b"-"), None);
+    }
 }
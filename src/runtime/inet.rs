@@ -0,0 +1,287 @@
+//! gawk's special files for network I/O: `/inet/tcp/lport/host/rport` and `/inet/udp/...`.
+//!
+//! A name with a local port of 0 connects to `host:rport` from any local port, and one with both
+//! ports connects from `lport`. A name with a remote port of 0 listens on the local port of `host`
+//! instead (on every interface if `host` is 0), and talks to the first peer that connects (or, for
+//! UDP, that sends a datagram). As with coprocesses, `print` and
+//! `getline` share a connection: whichever uses the name first opens it and leaves a handle for
+//! the other.
+use std::io::{self, Read, Write};
+use std::net::{
+    Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::sync::{Arc, Mutex};
+
+use hashbrown::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Proto {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Addr<'a> {
+    proto: Proto,
+    lport: u16,
+    host: &'a str,
+    rport: u16,
+}
+
+/// Whether `name` is one of the special files handled by this module.
+pub fn is_inet(name: &[u8]) -> bool {
+    name.starts_with(b"/inet/")
+}
+
+fn invalid(name: &[u8], msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "invalid network file '{}': {}",
+            String::from_utf8_lossy(name),
+            msg
+        ),
+    )
+}
+
+fn parse(name: &[u8]) -> io::Result<Addr<'_>> {
+    const EXPECTED: &str = "expected /inet/tcp/lport/host/rport or /inet/udp/lport/host/rport";
+    let s = std::str::from_utf8(name).map_err(|_| invalid(name, EXPECTED))?;
+    let parts: Vec<&str> = s.split('/').collect();
+    let (proto, lport, host, rport) = match parts[..] {
+        ["", "inet", proto, lport, host, rport] => (proto, lport, host, rport),
+        _ => return Err(invalid(name, EXPECTED)),
+    };
+    let proto = match proto {
+        "tcp" => Proto::Tcp,
+        "udp" => Proto::Udp,
+        _ => return Err(invalid(name, EXPECTED)),
+    };
+    let port = |p: &str| {
+        p.parse::<u16>()
+            .map_err(|_| invalid(name, "ports must be numbers"))
+    };
+    let (lport, rport) = (port(lport)?, port(rport)?);
+    if lport == 0 && rport == 0 {
+        return Err(invalid(name, "one of the ports must be nonzero"));
+    }
+    Ok(Addr {
+        proto,
+        lport,
+        host,
+        rport,
+    })
+}
+
+fn resolve(name: &[u8], host: &str, port: u16) -> io::Result<SocketAddr> {
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid(name, "host not found"))
+}
+
+/// The address of `port` on every interface of the same family as `remote`.
+fn unspecified(remote: SocketAddr, port: u16) -> SocketAddr {
+    let ip = if remote.is_ipv4() {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv6Addr::UNSPECIFIED.into()
+    };
+    SocketAddr::new(ip, port)
+}
+
+enum Conn {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Conn {
+    fn open(name: &[u8]) -> io::Result<Conn> {
+        let addr = parse(name)?;
+        // The address to listen on.
+        let local = if addr.host == "0" {
+            "0.0.0.0"
+        } else {
+            addr.host
+        };
+        match (addr.proto, addr.lport, addr.rport) {
+            (Proto::Tcp, 0, rport) => Ok(Conn::Tcp(TcpStream::connect((addr.host, rport))?)),
+            (Proto::Tcp, lport, 0) => {
+                let (stream, _) = TcpListener::bind((local, lport))?.accept()?;
+                Ok(Conn::Tcp(stream))
+            }
+            (Proto::Tcp, lport, rport) => {
+                use socket2::{Domain, Socket, Type};
+                let remote = resolve(name, addr.host, rport)?;
+                let socket = Socket::new(Domain::for_address(remote), Type::STREAM, None)?;
+                // Let a program reconnect from the same port right after closing the connection.
+                socket.set_reuse_address(true)?;
+                socket.bind(&unspecified(remote, lport).into())?;
+                socket.connect(&remote.into())?;
+                Ok(Conn::Tcp(socket.into()))
+            }
+            (Proto::Udp, lport, 0) => {
+                let socket = UdpSocket::bind((local, lport))?;
+                // Reply to whoever sends the first datagram.
+                let (_, peer) = socket.peek_from(&mut [0; 1])?;
+                socket.connect(peer)?;
+                Ok(Conn::Udp(socket))
+            }
+            (Proto::Udp, lport, rport) => {
+                let remote = resolve(name, addr.host, rport)?;
+                let socket = UdpSocket::bind(unspecified(remote, lport))?;
+                socket.connect(remote)?;
+                Ok(Conn::Udp(socket))
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Conn> {
+        match self {
+            Conn::Tcp(s) => Ok(Conn::Tcp(s.try_clone()?)),
+            Conn::Udp(s) => Ok(Conn::Udp(s.try_clone()?)),
+        }
+    }
+}
+
+/// The reading or the writing end of a network connection.
+pub struct Socket {
+    conn: Conn,
+    is_write: bool,
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.conn {
+            Conn::Tcp(s) => s.read(buf),
+            Conn::Udp(s) => s.recv(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.conn {
+            Conn::Tcp(s) => s.write(buf),
+            Conn::Udp(s) => s.send(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.conn {
+            Conn::Tcp(s) => s.flush(),
+            Conn::Udp(_) => Ok(()),
+        }
+    }
+}
+
+// Closing the writing end of a TCP connection tells the peer that no more data is coming, even
+// while the reading end is still open.
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let (Conn::Tcp(s), true) = (&self.conn, self.is_write) {
+            let _ = s.shutdown(Shutdown::Write);
+        }
+    }
+}
+
+struct Ends {
+    read: Option<Socket>,
+    write: Option<Socket>,
+}
+
+impl Ends {
+    fn open(name: &[u8]) -> io::Result<Ends> {
+        let conn = Conn::open(name)?;
+        Ok(Ends {
+            write: Some(Socket {
+                conn: conn.try_clone()?,
+                is_write: true,
+            }),
+            read: Some(Socket {
+                conn,
+                is_write: false,
+            }),
+        })
+    }
+}
+
+fn claimed(name: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        format!(
+            "network file '{}' is already in use or closed",
+            String::from_utf8_lossy(name)
+        ),
+    )
+}
+
+/// The connections a program has opened, holding the ends that have not been claimed yet. Copies
+/// share the same connections.
+#[derive(Clone, Default)]
+pub struct Sockets(Arc<Mutex<HashMap<Box<[u8]>, Ends>>>);
+
+impl Sockets {
+    fn with_ends<R>(&self, name: &[u8], f: impl FnOnce(&mut Ends) -> R) -> io::Result<R> {
+        let mut opened = None;
+        loop {
+            let mut sockets = self.0.lock().unwrap();
+            if let Some(ends) = opened.take() {
+                // Another thread may have opened the connection first; if so, ours is dropped.
+                sockets.entry(name.into()).or_insert(ends);
+            }
+            if let Some(ends) = sockets.get_mut(name) {
+                let res = f(ends);
+                if ends.read.is_none() && ends.write.is_none() {
+                    sockets.remove(name);
+                }
+                return Ok(res);
+            }
+            drop(sockets);
+            // Listening for a peer can take arbitrarily long, so connect without the lock held.
+            opened = Some(Ends::open(name)?);
+        }
+    }
+
+    /// Open the connection `name` if it is not open yet.
+    pub fn open(&self, name: &[u8]) -> io::Result<()> {
+        self.with_ends(name, |_| ())
+    }
+
+    pub fn for_write(&self, name: &[u8]) -> io::Result<Socket> {
+        self.with_ends(name, |ends| ends.write.take())?
+            .ok_or_else(|| claimed(name))
+    }
+
+    pub fn for_read(&self, name: &[u8]) -> io::Result<Socket> {
+        self.with_ends(name, |ends| ends.read.take())?
+            .ok_or_else(|| claimed(name))
+    }
+
+    /// Drop any ends of the connection `name` that were never claimed, after it is closed.
+    pub fn close(&self, name: &[u8]) {
+        self.0.lock().unwrap().remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(
+            parse(b"/inet/tcp/0/localhost/8080").unwrap(),
+            Addr {
+                proto: Proto::Tcp,
+                lport: 0,
+                host: "localhost",
+                rport: 8080,
+            }
+        );
+        assert_eq!(parse(b"/inet/udp/514/0/0").unwrap().lport, 514);
+        assert!(parse(b"/inet/tcp/0/localhost").is_err());
+        assert!(parse(b"/inet/raw/0/localhost/80").is_err());
+        assert!(parse(b"/inet/tcp/0/localhost/http").is_err());
+        assert!(parse(b"/inet/udp/0/localhost/0").is_err());
+    }
+}
@@ -39,35 +39,35 @@ pub(crate) fn map_int_str_to_json(arr: &IntMap<Str>) -> String {
 }
 
 pub(crate) fn map_str_int_to_json(obj: &StrMap<Int>) -> String {
-    let mut json_obj: HashMap<String, Int> = HashMap::new();
-    obj.iter(|map| {
-        for (key, value) in map {
-            json_obj.insert(key.to_string(), *value);
-        }
-    });
-    json::to_string(&json_obj)
+    to_json_object(obj, |value| Some(json::to_string(value)))
 }
 
 pub(crate) fn map_str_float_to_json(obj: &StrMap<Float>) -> String {
-    let mut json_obj: HashMap<String, Float> = HashMap::new();
-    obj.iter(|map| {
-        for (key, value) in map {
-            json_obj.insert(key.to_string(), *value);
-        }
-    });
-    json::to_string(&json_obj)
+    to_json_object(obj, |value| Some(json::to_string(value)))
 }
 
 pub(crate) fn map_str_str_to_json(obj: &StrMap<Str>) -> String {
-    let mut json_obj: HashMap<String, String> = HashMap::new();
+    to_json_object(obj, |value| {
+        if value.is_empty() {
+            None
+        } else {
+            Some(json::to_string(&value.to_string()))
+        }
+    })
+}
+
+// Fields are written in the iteration order of the map, so `--ordered-maps` yields insertion
+// order.
+fn to_json_object<V>(obj: &StrMap<V>, to_json: impl Fn(&V) -> Option<String>) -> String {
+    let mut fields: Vec<String> = vec![];
     obj.iter(|map| {
         for (key, value) in map {
-            if !value.is_empty() {
-                json_obj.insert(key.to_string(), value.to_string());
+            if let Some(value) = to_json(value) {
+                fields.push(format!("{}:{}", json::to_string(&key.to_string()), value));
             }
         }
     });
-    json::to_string(&json_obj)
+    format!("{{{}}}", fields.join(","))
 }
 
 pub(crate) fn str_to_json(text: &str) -> String {
@@ -0,0 +1,241 @@
+//! The storage behind AWK arrays.
+//!
+//! Arrays are plain hash maps unless the program runs with `--ordered-maps`, in which case they
+//! remember the order their keys were inserted in. The choice is made when an array is allocated
+//! (see `Core::ordered_maps`), and maps derived from an existing map keep its representation.
+use super::MapValue;
+use hashbrown::{DefaultHashBuilder, HashMap};
+use indexmap::IndexMap;
+use std::hash::Hash;
+use std::iter::FromIterator;
+
+#[derive(Clone, Debug)]
+pub(crate) enum MapData<K, V> {
+    Hashed(HashMap<K, V>),
+    Ordered(IndexMap<K, V, DefaultHashBuilder>),
+}
+
+impl<K, V> Default for MapData<K, V> {
+    fn default() -> MapData<K, V> {
+        MapData::Hashed(Default::default())
+    }
+}
+
+impl<K, V> MapData<K, V> {
+    pub(crate) fn new(ordered: bool) -> MapData<K, V> {
+        if ordered {
+            MapData::Ordered(Default::default())
+        } else {
+            MapData::Hashed(Default::default())
+        }
+    }
+
+    pub(crate) fn is_ordered(&self) -> bool {
+        matches!(self, MapData::Ordered(_))
+    }
+
+    /// An empty map with the same representation as `self`.
+    pub(crate) fn new_like<K2, V2>(&self) -> MapData<K2, V2> {
+        MapData::new(self.is_ordered())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            MapData::Hashed(m) => m.len(),
+            MapData::Ordered(m) => m.len(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            MapData::Hashed(m) => m.clear(),
+            MapData::Ordered(m) => m.clear(),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Iter<'_, K, V> {
+        match self {
+            MapData::Hashed(m) => Iter::Hashed(m.iter()),
+            MapData::Ordered(m) => Iter::Ordered(m.iter()),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        let res: Box<dyn Iterator<Item = &mut V>> = match self {
+            MapData::Hashed(m) => Box::new(m.values_mut()),
+            MapData::Ordered(m) => Box::new(m.values_mut()),
+        };
+        res
+    }
+
+    /// Build a map with the same representation as `self` out of its entries.
+    pub(crate) fn map<K2: Hash + Eq, V2>(
+        &self,
+        f: impl FnMut((&K, &V)) -> (K2, V2),
+    ) -> MapData<K2, V2> {
+        let mut res = self.new_like();
+        res.extend(self.iter().map(f));
+        res
+    }
+
+    /// Like `map`, but consumes `self`.
+    pub(crate) fn into_map<K2: Hash + Eq, V2>(
+        self,
+        f: impl FnMut((K, V)) -> (K2, V2),
+    ) -> MapData<K2, V2> {
+        let mut res = self.new_like();
+        res.extend(self.into_iter().map(f));
+        res
+    }
+
+    /// Sort the keys of an ordered map; hashed maps have no order to change.
+    pub(crate) fn sort_by(&mut self, mut cmp: impl FnMut(&K, &K) -> std::cmp::Ordering) {
+        if let MapData::Ordered(m) = self {
+            m.sort_by(|k1, _, k2, _| cmp(k1, k2))
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> MapData<K, V> {
+    pub(crate) fn get(&self, k: &K) -> Option<&V> {
+        match self {
+            MapData::Hashed(m) => m.get(k),
+            MapData::Ordered(m) => m.get(k),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, k: K, v: V) {
+        match self {
+            MapData::Hashed(m) => {
+                m.insert(k, v);
+            }
+            MapData::Ordered(m) => {
+                m.insert(k, v);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, k: &K) {
+        match self {
+            MapData::Hashed(m) => {
+                m.remove(k);
+            }
+            // Shifting is O(n), but unlike swapping it keeps the remaining keys in insertion
+            // order.
+            MapData::Ordered(m) => {
+                m.shift_remove(k);
+            }
+        }
+    }
+
+    pub(crate) fn entry_or_default(&mut self, k: K) -> &mut V
+    where
+        V: Default,
+    {
+        match self {
+            MapData::Hashed(m) => m.entry(k).or_default(),
+            MapData::Ordered(m) => m.entry(k).or_default(),
+        }
+    }
+
+    /// Look up `k`, inserting an empty value if it is not present. `k` is only cloned when it is
+    /// inserted.
+    pub(crate) fn get_or_insert_empty(&mut self, k: &K) -> &mut V
+    where
+        K: Clone,
+        V: MapValue,
+    {
+        match self {
+            MapData::Hashed(m) => {
+                m.raw_entry_mut()
+                    .from_key(k)
+                    .or_insert_with(|| (k.clone(), V::empty(false)))
+                    .1
+            }
+            MapData::Ordered(m) => match m.get_index_of(k) {
+                Some(ix) => &mut m[ix],
+                None => m.entry(k.clone()).or_insert_with(|| V::empty(true)),
+            },
+        }
+    }
+}
+
+impl<K: Ord, V> MapData<K, V> {
+    pub(crate) fn sort_keys(&mut self) {
+        if let MapData::Ordered(m) = self {
+            m.sort_keys()
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Extend<(K, V)> for MapData<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        match self {
+            MapData::Hashed(m) => m.extend(iter),
+            MapData::Ordered(m) => m.extend(iter),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for MapData<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        MapData::Hashed(iter.into_iter().collect())
+    }
+}
+
+impl<K, V> IntoIterator for MapData<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+    fn into_iter(self) -> IntoIter<K, V> {
+        match self {
+            MapData::Hashed(m) => IntoIter::Hashed(m.into_iter()),
+            MapData::Ordered(m) => IntoIter::Ordered(m.into_iter()),
+        }
+    }
+}
+
+pub(crate) enum Iter<'a, K, V> {
+    Hashed(hashbrown::hash_map::Iter<'a, K, V>),
+    Ordered(indexmap::map::Iter<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        match self {
+            Iter::Hashed(i) => i.next(),
+            Iter::Ordered(i) => i.next(),
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Hashed(i) => i.size_hint(),
+            Iter::Ordered(i) => i.size_hint(),
+        }
+    }
+}
+
+pub(crate) enum IntoIter<K, V> {
+    Hashed(hashbrown::hash_map::IntoIter<K, V>),
+    Ordered(indexmap::map::IntoIter<K, V>),
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+    fn next(&mut self) -> Option<(K, V)> {
+        match self {
+            IntoIter::Hashed(i) => i.next(),
+            IntoIter::Ordered(i) => i.next(),
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            IntoIter::Hashed(i) => i.size_hint(),
+            IntoIter::Ordered(i) => i.size_hint(),
+        }
+    }
+}
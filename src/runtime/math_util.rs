@@ -5,6 +5,10 @@ use lazy_static::lazy_static;
 use logos::Logos;
 use semver::{Version};
 use snowflake::SnowflakeIdGenerator;
+use crate::ast::Binop;
+use crate::builtins::DecimalOp;
+use crate::common::Result;
+use crate::runtime::bignum::{Decimal, Rounding};
 use crate::runtime::{Float, Int, IntMap, Str, StrMap};
 
 pub fn min(first: &str, second: &str, third: &str) -> String {
@@ -169,7 +173,9 @@ pub(crate) fn map_int_str_asort(obj: &IntMap<Str>, target_obj: &IntMap<Str>) {
 pub(crate) fn map_int_int_join(obj: &IntMap<Int>, sep: &str) -> String {
     let mut items: Vec<String> = vec![];
     let mut keys = obj.to_vec().clone();
-    keys.sort();
+    if !obj.is_ordered() {
+        keys.sort();
+    }
     for index in keys {
         items.push(obj.get(&index).to_string());
     }
@@ -179,7 +185,9 @@ pub(crate) fn map_int_int_join(obj: &IntMap<Int>, sep: &str) -> String {
 pub(crate) fn map_int_float_join(obj: &IntMap<Float>, sep: &str) -> String {
     let mut items: Vec<String> = vec![];
     let mut keys = obj.to_vec().clone();
-    keys.sort();
+    if !obj.is_ordered() {
+        keys.sort();
+    }
     for index in keys {
         items.push(obj.get(&index).to_string());
     }
@@ -189,7 +197,9 @@ pub(crate) fn map_int_float_join(obj: &IntMap<Float>, sep: &str) -> String {
 pub(crate) fn map_int_str_join(obj: &IntMap<Str>, sep: &str) -> String {
     let mut items: Vec<String> = vec![];
     let mut keys = obj.to_vec().clone();
-    keys.sort();
+    if !obj.is_ordered() {
+        keys.sort();
+    }
     for index in keys {
         items.push(obj.get(&index).to_string());
     }
@@ -337,6 +347,111 @@ pub(crate) fn map_int_float_mean(obj: &IntMap<Float>) -> Float {
     };
 }
 
+/// `l op r` under `--checked-arith`: an overflow is an error rather than wrapping around, and so
+/// is a zero divisor.
+pub(crate) fn checked_int(op: Binop, l: Int, r: Int) -> Result<Int> {
+    let res = match op {
+        Binop::Plus => l.checked_add(r),
+        Binop::Minus => l.checked_sub(r),
+        Binop::Mult => l.checked_mul(r),
+        Binop::Mod if r == 0 => return err!("division by zero in {} % {}", l, r),
+        // Only Int::MIN % -1 overflows, and its remainder is 0 all the same.
+        Binop::Mod => Some(l.wrapping_rem(r)),
+        _ => return err!("checked_int: unexpected operator {}", op),
+    };
+    match res {
+        Some(res) => Ok(res),
+        None => err!("integer overflow in {} {} {}", l, op, r),
+    }
+}
+
+/// `l / r` or `l % r` under `--checked-arith`, where dividing by zero is an error.
+pub(crate) fn checked_float(op: Binop, l: Float, r: Float) -> Result<Float> {
+    if r == 0.0 {
+        return err!("division by zero in {} {} {}", l, op, r);
+    }
+    Ok(match op {
+        Binop::Div => l / r,
+        Binop::Mod => l % r,
+        _ => return err!("checked_float: unexpected operator {}", op),
+    })
+}
+
+/// Exact sum of the decimal numbers in `obj`, with as many decimal places as the longest of them.
+pub(crate) fn map_int_str_sum(obj: &IntMap<Str>) -> String {
+    obj.iter(|map| {
+        map.fold(Decimal::zero(), |total, (_, item)| {
+            total.add(&item.with_bytes(Decimal::parse_written))
+        })
+    })
+    .to_string()
+}
+
+/// The largest `scale` the `decimal_*` builtins accept.
+const MAX_DECIMAL_SCALE: Int = 1000;
+
+/// The `decimal_*` builtins. Without a `scale` (a negative one) sums and differences keep the
+/// decimal places of the longer operand, products keep those of both operands, quotients keep
+/// those of the longer operand and `decimal_round` rounds to an integer. Otherwise the result is
+/// rounded to `scale` places and written with exactly that many.
+pub(crate) fn decimal(op: DecimalOp, x: &str, y: &str, scale: Int, mode: &str) -> Result<String> {
+    let mode = match Rounding::from_name(mode.as_bytes()) {
+        Some(mode) => mode,
+        None => {
+            return err!(
+                "unknown rounding mode '{}', expected half_even or half_up",
+                mode
+            )
+        }
+    };
+    if scale > MAX_DECIMAL_SCALE {
+        return err!(
+            "{}: scale {} is larger than the maximum of {}",
+            op.func_name(),
+            scale,
+            MAX_DECIMAL_SCALE
+        );
+    }
+    let x = Decimal::parse_written(x.as_bytes());
+    let y = Decimal::parse_written(y.as_bytes());
+    let scale = u32::try_from(scale).ok();
+    let res = match op {
+        DecimalOp::Add => x.add(&y),
+        DecimalOp::Sub => x.sub(&y),
+        DecimalOp::Mul => x.mul(&y),
+        DecimalOp::Div => {
+            if y.is_zero() {
+                return err!("division by zero in decimal_div");
+            }
+            let places = scale.unwrap_or_else(|| x.scale().max(y.scale()));
+            x.div_to_scale(&y, places, mode)
+        }
+        DecimalOp::Round => return Ok(x.to_fixed(scale.unwrap_or(0), mode)),
+    };
+    Ok(match scale {
+        Some(places) => res.to_fixed(places, mode),
+        None => res.to_string(),
+    })
+}
+
+/// `decimal_cmp`: -1, 0 or 1. Unlike comparisons under `-M`, which fall back to comparing strings,
+/// both operands must be numbers (or empty, which counts as zero).
+pub(crate) fn decimal_cmp(x: &[u8], y: &[u8]) -> Result<Int> {
+    let num = |s: &[u8]| {
+        if s.is_empty() {
+            return Ok(Decimal::zero());
+        }
+        match Decimal::parse_exact(s) {
+            Some(d) => Ok(d),
+            None => err!(
+                "decimal_cmp: '{}' is not a number",
+                String::from_utf8_lossy(s)
+            ),
+        }
+    };
+    Ok(num(x)?.cmp(&num(y)?) as Int)
+}
+
 
 const NO: &'static [&'static str] = &["false", "no", "𐄂", "0", "0.0", "0.00", "00.0",
     "0x0", "0x00", "0X0", "0X00", "0o0", "0o00", "0O0", "0O00", "0b0", "0b00", "0B0", "0B00"];
@@ -867,6 +982,46 @@ mod tests {
         println!("{}", hex);
     }
 
+    #[test]
+    fn test_checked_arith() {
+        assert_eq!(checked_int(Binop::Plus, 1, 2).unwrap(), 3);
+        assert_eq!(checked_int(Binop::Mod, Int::MIN, -1).unwrap(), 0);
+        let err = checked_int(Binop::Mult, Int::MAX, 2).unwrap_err();
+        assert!(err.0.ends_with(&format!("integer overflow in {} * 2", Int::MAX)));
+        assert!(checked_int(Binop::Minus, Int::MIN, 1).is_err());
+        assert!(checked_int(Binop::Mod, 1, 0).is_err());
+        assert_eq!(checked_float(Binop::Div, 1.0, 4.0).unwrap(), 0.25);
+        assert!(checked_float(Binop::Div, 1.0, 0.0).is_err());
+        assert!(checked_float(Binop::Mod, 1.5, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_decimal() {
+        let dec = |op, x, y, scale, mode| decimal(op, x, y, scale, mode).unwrap();
+        assert_eq!(dec(DecimalOp::Add, "1.10", "2.20", -1, ""), "3.30");
+        assert_eq!(dec(DecimalOp::Sub, "0.3", "0.1", -1, ""), "0.2");
+        assert_eq!(dec(DecimalOp::Mul, "19.99", "1.5", -1, ""), "29.985");
+        assert_eq!(dec(DecimalOp::Mul, "19.99", "1.5", 2, ""), "29.98");
+        assert_eq!(dec(DecimalOp::Mul, "19.99", "1.5", 2, "half_up"), "29.99");
+        assert_eq!(dec(DecimalOp::Div, "10.00", "3", -1, ""), "3.33");
+        assert_eq!(dec(DecimalOp::Div, "2", "3", 4, "half_up"), "0.6667");
+        assert_eq!(dec(DecimalOp::Round, "7", "", 2, ""), "7.00");
+        assert_eq!(dec(DecimalOp::Round, "-0.5", "", -1, "half_up"), "-1");
+        assert!(decimal(DecimalOp::Div, "1", "0", 2, "").is_err());
+        assert!(decimal(DecimalOp::Add, "1", "2", 2, "ceiling").is_err());
+        assert!(decimal(DecimalOp::Div, "1", "3", Int::MAX, "").is_err());
+        assert_eq!(decimal_cmp(b"10", b"9.5").unwrap(), 1);
+        assert_eq!(decimal_cmp(b"1.0", b"").unwrap(), 1);
+        assert_eq!(decimal_cmp(b"-1e2", b"-100").unwrap(), 0);
+        assert!(decimal_cmp(b"abc", b"9").is_err());
+
+        let items: IntMap<Str> = IntMap::default();
+        for (i, item) in ["19.99", "0.1", "0.20", "1e2"].iter().enumerate() {
+            items.insert(i as Int, Str::from(*item));
+        }
+        assert_eq!(map_int_str_sum(&items), "120.29");
+    }
+
     #[test]
     fn test_eval_context() {
         let context: StrMap<Str> = StrMap::default();
@@ -1,20 +1,26 @@
 use crate::common::{FileSpec, Result};
 use grep_cli::CommandReader;
 use hashbrown::HashMap;
-use regex::bytes::Regex;
+use regex::bytes::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
 use std::cell::{Cell, RefCell};
 use std::fs::File;
 use std::hash::Hash;
 use std::io;
 use std::iter::FromIterator;
 use std::mem;
+use std::process::ChildStdout;
 use std::rc::Rc;
 use std::str;
 
+pub mod bignum;
 mod command;
+pub mod inet;
+mod map_data;
+pub mod special_files;
 pub mod float_parse;
 pub mod printf;
 pub mod splitter;
+pub mod sorted_in;
 pub mod str_impl;
 pub mod string_search;
 pub mod utf8;
@@ -43,12 +49,14 @@ pub mod config_util;
 
 use crate::pushdown::FieldSet;
 use splitter::regex::RegexSplitter;
+use splitter::FieldSplit;
 
 // TODO: remove the pub use for Variables here.
 pub(crate) use crate::builtins::Variables;
 pub use command::run_command;
 pub use command::run_command2;
-pub(crate) use float_parse::{hextoi, strtod, strtoi};
+pub(crate) use float_parse::{hextoi, strnum, strtod, strtoi};
+pub(crate) use map_data::MapData;
 pub(crate) use printf::FormatArg;
 pub use splitter::{
     batch::{escape_csv, escape_tsv},
@@ -56,17 +64,142 @@ pub use splitter::{
 };
 pub use str_impl::{Str, UniqueStr};
 
+/// A regex constant compiled ahead of time. Programs that assign `IGNORECASE` also compile a
+/// case-insensitive copy, which is matched against while `IGNORECASE` is set.
+#[derive(Debug)]
+pub struct RegexConst {
+    exact: Regex,
+    folded: Option<Regex>,
+}
+
+impl RegexConst {
+    pub(crate) fn new(pat: &str, fold_case: bool) -> std::result::Result<RegexConst, regex::Error> {
+        let folded = if fold_case {
+            Some(RegexBuilder::new(pat).case_insensitive(true).build()?)
+        } else {
+            None
+        };
+        Ok(RegexConst {
+            exact: Regex::new(pat)?,
+            folded,
+        })
+    }
+    pub(crate) fn get(&self, ignore_case: bool) -> &Regex {
+        match &self.folded {
+            Some(re) if ignore_case => re,
+            _ => &self.exact,
+        }
+    }
+}
+
+/// Compiled regexes, keyed by pattern.
 #[derive(Default)]
-pub struct RegexCache(Registry<Regex>);
+pub struct RegexCache {
+    regexes: Registry<Regex>,
+    // Case-insensitive versions of the patterns in `regexes`, used when `IGNORECASE` is set.
+    folded: Registry<Regex>,
+    // The regex cases of `switch` statements, keyed by their patterns joined with newlines, and
+    // their case-insensitive versions.
+    sets: Registry<RegexSet>,
+    folded_sets: Registry<RegexSet>,
+    // The last `FS` used in paragraph mode, along with the pattern it splits by if it is a single
+    // character.
+    paragraph_fs: Option<(Str<'static>, Option<Str<'static>>)>,
+}
 
 impl RegexCache {
+    fn registry(&mut self, ignore_case: bool) -> &mut Registry<Regex> {
+        if ignore_case {
+            &mut self.folded
+        } else {
+            &mut self.regexes
+        }
+    }
+    fn compile(pat: &str, ignore_case: bool) -> Result<Regex> {
+        match RegexBuilder::new(pat).case_insensitive(ignore_case).build() {
+            Ok(r) => Ok(r),
+            Err(e) => err!("{}", e),
+        }
+    }
+    fn compile_set(pats: &str, ignore_case: bool) -> Result<RegexSet> {
+        match RegexSetBuilder::new(pats.split('\n'))
+            .case_insensitive(ignore_case)
+            .build()
+        {
+            Ok(r) => Ok(r),
+            Err(e) => err!("{}", e),
+        }
+    }
+
+    pub(crate) fn regex_const_match_loc(vars: &mut Variables, re: &Regex, s: &Str) -> Result<Int> {
+        use crate::builtins::Variable;
+        let (start, len) = s.with_bytes(|bs| match re.find(bs) {
+            Some(m) => {
+                let start = m.start() as Int;
+                let end = m.end() as Int;
+                (start + 1, end - start)
+            }
+            None => (0, -1),
+        });
+        vars.store_int(Variable::RSTART, start)?;
+        vars.store_int(Variable::RLENGTH, len)?;
+        Ok(start)
+    }
+
+    pub(crate) fn regex_const_match(pat: &Regex, s: &Str) -> bool {
+        s.with_bytes(|bs| pat.is_match(bs))
+    }
+}
+
+/// The regex cache, along with the settings that decide how patterns are compiled and how records
+/// are split into fields. The settings follow variables (`IGNORECASE`, `RS`, `FIELDWIDTHS` and
+/// `FPAT`) whose state lives in the interpreter's `Core`, which hands one of these out for each
+/// operation that needs them.
+pub struct Regexes<'c> {
+    cache: &'c mut RegexCache,
+    ignore_case: bool,
+    paragraph_mode: bool,
+    // The active FIELDWIDTHS or FPAT, if any.
+    field_split: Option<&'c FieldSplit>,
+}
+
+impl<'c> Regexes<'c> {
+    /// Match patterns as written and split records by `FS`.
+    pub(crate) fn new(cache: &'c mut RegexCache) -> Regexes<'c> {
+        Regexes {
+            cache,
+            ignore_case: false,
+            paragraph_mode: false,
+            field_split: None,
+        }
+    }
+    /// Compile patterns case-insensitively (for `IGNORECASE`).
+    pub(crate) fn ignore_case(self, ignore_case: bool) -> Regexes<'c> {
+        Regexes {
+            ignore_case,
+            ..self
+        }
+    }
+    /// Treat newlines as field separators, as is done when `RS` is empty.
+    pub(crate) fn paragraph_mode(self, paragraph_mode: bool) -> Regexes<'c> {
+        Regexes {
+            paragraph_mode,
+            ..self
+        }
+    }
+    /// Split records with `split` rather than by `FS`, if it is set.
+    pub(crate) fn field_split(self, field_split: Option<&'c FieldSplit>) -> Regexes<'c> {
+        Regexes {
+            field_split,
+            ..self
+        }
+    }
+
     pub(crate) fn with_regex<T>(&mut self, pat: &Str, mut f: impl FnMut(&Regex) -> T) -> Result<T> {
-        self.0.get(
+        let ignore_case = self.ignore_case;
+        self.cache.registry(ignore_case).get(
             pat,
-            |s| match Regex::new(s) {
-                Ok(r) => Ok(r),
-                Err(e) => err!("{}", e),
-            },
+            |s| RegexCache::compile(s, ignore_case),
             // eta-expansion required to get this compiling..
             |x| f(x),
         )
@@ -76,55 +209,118 @@ impl RegexCache {
         pat: &Str,
         mut f: impl FnMut(&Regex) -> Result<T>,
     ) -> Result<T> {
-        self.0.get_fallible(
+        let ignore_case = self.ignore_case;
+        self.cache.registry(ignore_case).get_fallible(
             pat,
-            |s| match Regex::new(s) {
-                Ok(r) => Ok(r),
-                Err(e) => err!("{}", e),
-            },
+            |s| RegexCache::compile(s, ignore_case),
             // eta-expansion required to get this compiling..
             |x| f(x),
         )
     }
 
+    // The `get_line` functions store the text that terminated the record in `rt`.
     pub(crate) fn get_line<'a, LR: LineReader>(
         &mut self,
         file: &Str<'a>,
         pat: &Str<'a>,
         reg: &mut FileRead<LR>,
         is_file: bool,
+        rt: &mut Str<'a>,
     ) -> Result<Str<'a>> {
-        Ok(if is_file {
+        if is_file && reg.is_shared_stdin(file) {
+            return Ok(self.get_line_stdin(pat, reg, rt)?.1);
+        }
+        Ok(if is_file && file.with_bytes(inet::is_inet) {
+            reg.with_socket(file, |reader| {
+                let line = reader.read_record(pat, self)?;
+                *rt = reader.record_terminator().upcast();
+                Ok(line)
+            })?
+        } else if is_file {
             reg.with_file(file, |reader| {
-                self.with_regex(pat, |re| reader.read_line_regex(re))
+                let line = reader.read_record(pat, self)?;
+                *rt = reader.record_terminator().upcast();
+                Ok(line)
             })?
         } else {
             reg.with_cmd(file, |reader| {
-                self.with_regex(pat, |re| reader.read_line_regex(re))
+                let line = reader.read_record(pat, self)?;
+                *rt = reader.record_terminator().upcast();
+                Ok(line)
             })?
         }
-        .clone()
         .upcast())
     }
 
+    pub(crate) fn get_line_coproc<'a, LR: LineReader>(
+        &mut self,
+        cmd: &Str<'a>,
+        pat: &Str<'a>,
+        reg: &mut FileRead<LR>,
+        rt: &mut Str<'a>,
+    ) -> Result<Str<'a>> {
+        let line = if cmd.with_bytes(inet::is_inet) {
+            reg.with_socket(cmd, |reader| {
+                let line = reader.read_record(pat, self)?;
+                *rt = reader.record_terminator().upcast();
+                Ok(line)
+            })?
+        } else {
+            reg.with_coproc(cmd, |reader| {
+                let line = reader.read_record(pat, self)?;
+                *rt = reader.record_terminator().upcast();
+                Ok(line)
+            })?
+        };
+        Ok(line.upcast())
+    }
+
     // This only gets used if getline is invoked explicitly without an input file argument.
     pub(crate) fn get_line_stdin<'a, LR: LineReader>(
         &mut self,
         pat: &Str<'a>,
         reg: &mut FileRead<LR>,
+        rt: &mut Str<'a>,
     ) -> Result<(/* file changed */ bool, Str<'a>)> {
         let (changed, mut line) = reg.stdin.read_line(pat, self)?;
+        *rt = reg.stdin.record_terminator().upcast();
         // NB both of these `pat`s are "wrong" but we are fine because they are only used
         // when the column is nonzero, or someone has overwritten a nonzero column.
         Ok((changed, line.get_col(0, pat, pat, self)?.clone().upcast()))
     }
-    pub(crate) fn get_line_stdin_reuse<LR: LineReader>(
+    pub(crate) fn get_line_stdin_reuse<'a, LR: LineReader>(
         &mut self,
         pat: &Str,
         reg: &mut FileRead<LR>,
         old_line: &mut LR::Line,
+        rt: &mut Str<'a>,
     ) -> Result</*file changed */ bool> {
-        reg.stdin.read_line_reuse(pat, self, old_line)
+        let changed = reg.stdin.read_line_reuse(pat, self, old_line)?;
+        *rt = reg.stdin.record_terminator().upcast();
+        Ok(changed)
+    }
+    // Split on runs of `blanks`, ignoring any at the start and end of `s`.
+    fn split_blanks<'a>(
+        &mut self,
+        blanks: &str,
+        s: &Str<'a>,
+        used_fields: &FieldSet,
+        mut push: impl FnMut(Str<'a>),
+    ) -> Result<()> {
+        self.with_regex(&Str::from(blanks), |re| {
+            s.split(
+                re,
+                |s, is_empty| {
+                    if !is_empty {
+                        push(s);
+                        1
+                    } else {
+                        0
+                    }
+                },
+                used_fields,
+            )
+        })
     }
     fn split_internal<'a>(
         &mut self,
@@ -134,20 +330,7 @@ impl RegexCache {
         mut push: impl FnMut(Str<'a>),
     ) -> Result<()> {
         if pat == &Str::from(" ") {
-            self.with_regex(&Str::from(r#"[ \t]+"#), |re| {
-                s.split(
-                    re,
-                    |s, is_empty| {
-                        if !is_empty {
-                            push(s);
-                            1
-                        } else {
-                            0
-                        }
-                    },
-                    used_fields,
-                )
-            })
+            self.split_blanks(r#"[ \t]+"#, s, used_fields, push)
         } else {
             self.with_regex(pat, |re| {
                 s.split(
@@ -161,6 +344,8 @@ impl RegexCache {
             })
         }
     }
+    // Split a record into fields, by FIELDWIDTHS or FPAT if one is set and by `pat` (FS)
+    // otherwise.
     pub(crate) fn split_regex<'a>(
         &mut self,
         pat: &Str,
@@ -168,6 +353,33 @@ impl RegexCache {
         used_fields: &FieldSet,
         v: &mut Vec<Str<'a>>,
     ) -> Result<()> {
+        if let Some(split) = self.field_split {
+            split.split(s, used_fields, |s| v.push(s));
+            return Ok(());
+        }
+        if self.paragraph_mode {
+            // As in gawk, newlines separate fields in paragraph mode when FS is a single
+            // character.
+            if pat == &Str::from(" ") {
+                return self.split_blanks("[ \t\n]+", s, used_fields, |s| v.push(s));
+            }
+            let single_char = match &self.cache.paragraph_fs {
+                Some((fs, re)) if fs.with_bytes(|fs| pat.with_bytes(|pat| fs == pat)) => re.clone(),
+                _ => {
+                    let re = pat.with_bytes(|bs| match str::from_utf8(bs) {
+                        Ok(c) if c.chars().count() == 1 => {
+                            Some(Str::from(format!("{}|\n", regex::escape(c))))
+                        }
+                        _ => None,
+                    });
+                    self.cache.paragraph_fs = Some((pat.clone().unmoor(), re.clone()));
+                    re
+                }
+            };
+            if let Some(fs) = single_char {
+                return self.split_internal(&fs, s, used_fields, |s| v.push(s));
+            }
+        }
         self.split_internal(pat, s, used_fields, |s| v.push(s))
     }
 
@@ -201,38 +413,78 @@ impl RegexCache {
         })
     }
 
-    pub(crate) fn regex_const_match_loc(vars: &mut Variables, re: &Regex, s: &Str) -> Result<Int> {
-        use crate::builtins::Variable;
-        let (start, len) = s.with_bytes(|bs| match re.find(bs) {
-            Some(m) => {
-                let start = m.start() as Int;
-                let end = m.end() as Int;
-                (start + 1, end - start)
-            }
-            None => (0, -1),
-        });
-        vars.store_int(Variable::RSTART, start)?;
-        vars.store_int(Variable::RLENGTH, len)?;
-        Ok(start)
-    }
     pub(crate) fn regex_match_loc(
         &mut self,
         vars: &mut Variables,
         pat: &Str,
         s: &Str,
     ) -> Result<Int> {
-        self.with_regex_fallible(pat, |re| Self::regex_const_match_loc(vars, re, s))
+        self.with_regex_fallible(pat, |re| RegexCache::regex_const_match_loc(vars, re, s))
     }
 
-    pub(crate) fn regex_const_match(pat: &Regex, s: &Str) -> bool {
-        s.with_bytes(|bs| pat.is_match(bs))
+    pub(crate) fn is_regex_match(&mut self, pat: &Str, s: &Str) -> Result<bool> {
+        self.with_regex(pat, |re| RegexCache::regex_const_match(re, s))
     }
 
-    pub(crate) fn is_regex_match(&mut self, pat: &Str, s: &Str) -> Result<bool> {
-        self.with_regex(pat, |re| Self::regex_const_match(re, s))
+    /// The (1-based) index of the first of the newline-separated patterns in `pats` that matches
+    /// `s`, or 0 if none of them do.
+    pub(crate) fn regex_set_match(&mut self, pats: &Str, s: &Str) -> Result<Int> {
+        let ignore_case = self.ignore_case;
+        let sets = if ignore_case {
+            &mut self.cache.folded_sets
+        } else {
+            &mut self.cache.sets
+        };
+        sets.get(
+            pats,
+            |p| RegexCache::compile_set(p, ignore_case),
+            |set| s.with_bytes(|bs| set.matches(bs).iter().next().map_or(0, |i| i as Int + 1)),
+        )
     }
 }
 
+/// Close a file, command or coprocess, for `close(path, how)`. `how` is `"to"` to close only the
+/// input of a coprocess, `"from"` to close only its output, or empty to close both.
+pub(crate) fn close_file<LR: LineReader>(
+    write_files: &mut FileWrite,
+    read_files: &mut FileRead<LR>,
+    path: &Str,
+    how: &Str,
+) -> Result<()> {
+    let (to, from) = how.with_bytes(|bs| match bs {
+        b"" => Ok((true, true)),
+        b"to" => Ok((true, false)),
+        b"from" => Ok((false, true)),
+        _ => err!(
+            "second argument to close must be \"to\" or \"from\", got {:?}",
+            String::from_utf8_lossy(bs)
+        ),
+    })?;
+    // NB this may create an unused entry in write_files. It would not be terribly difficult to
+    // optimize the close path to include an existence check first.
+    if to {
+        write_files.close(path)?;
+    }
+    if from {
+        read_files.close(path);
+    }
+    if to && from {
+        let channels = &read_files.channels;
+        path.with_bytes(|bs| channels.coprocs.close(bs));
+        path.with_bytes(|bs| channels.sockets.close(bs));
+    }
+    Ok(())
+}
+
+/// Coprocesses and connections, whose reading and writing ends are claimed separately, by
+/// `FileRead` and by the writer threads of `FileWrite`. Both hold a copy, as do the copies made for
+/// worker threads.
+#[derive(Clone, Default)]
+pub(crate) struct Channels {
+    pub(crate) coprocs: command::Coprocs,
+    pub(crate) sockets: inet::Sockets,
+}
+
 #[derive(Clone)]
 pub(crate) struct FileWrite(writers::Registry);
 
@@ -252,17 +504,26 @@ impl FileWrite {
     pub(crate) fn new(ff: impl writers::FileFactory) -> FileWrite {
         FileWrite(writers::Registry::from_factory(ff))
     }
+    pub(crate) fn channels(&self) -> Channels {
+        self.0.channels().clone()
+    }
 
     pub(crate) fn shutdown(&mut self) -> Result<()> {
         self.0.destroy_and_flush_all_files()
     }
 
+    // The printing functions return two layers of errors. The outer one is an output that cannot
+    // be opened (say, a network connection that is refused) or a bad format string, and is
+    // reported like any other runtime error. The inner one is a failed write, which usually means
+    // that whoever reads our output has gone away (as with `| head`), and ends the program quietly.
+
     pub(crate) fn printf(
         &mut self,
         path: Option<(&Str, FileSpec)>,
         spec: &Str,
         pa: &[printf::FormatArg],
-    ) -> Result<()> {
+        convfmt: Option<&Str>,
+    ) -> Result<Result<()>> {
         let (handle, fspec) = if let Some((out_file, fspec)) = path {
             (self.0.get_handle(Some(out_file), fspec)?, fspec)
         } else {
@@ -272,22 +533,22 @@ impl FileWrite {
             )
         };
         let mut text = str_impl::DynamicBuf::default();
-        spec.with_bytes(|spec| printf::printf(&mut text, spec, pa))?;
+        spec.with_bytes(|spec| printf::printf(&mut text, spec, pa, convfmt))?;
         let s = text.into_str();
-        handle.write(&s, fspec)
+        Ok(handle.write(&s, fspec))
     }
     pub(crate) fn write_all(
         &mut self,
         ss: &[&Str],
         out_spec: Option<(&Str, FileSpec)>,
-    ) -> Result<()> {
-        if let Some((path, spec)) = out_spec {
+    ) -> Result<Result<()>> {
+        Ok(if let Some((path, spec)) = out_spec {
             self.0.get_handle(Some(path), spec)?.write_all(ss, spec)
         } else {
             self.0
                 .get_handle(None, FileSpec::default())?
                 .write_all(ss, FileSpec::Append)
-        }
+        })
     }
 }
 
@@ -297,11 +558,17 @@ pub const CHUNK_SIZE: usize = 8 << 10;
 pub(crate) struct Inputs {
     files: Registry<RegexSplitter<File>>,
     commands: Registry<RegexSplitter<CommandReader>>,
+    coprocs: Registry<RegexSplitter<ChildStdout>>,
+    sockets: Registry<RegexSplitter<inet::Socket>>,
 }
 
 pub(crate) struct FileRead<LR = RegexSplitter<Box<dyn io::Read + Send>>> {
     pub(crate) inputs: Inputs,
+    channels: Channels,
     stdin: LR,
+    // Whether `stdin` reads standard input, in which case `-` and `/dev/stdin` read records from
+    // it rather than from a second handle on the same descriptor.
+    share_stdin: bool,
     named_columns: Option<Vec<Str<'static>>>,
     used_fields: FieldSet,
     backup_used_fields: FieldSet,
@@ -314,11 +581,14 @@ impl<LR: LineReader> FileRead<LR> {
             .into_iter()
             .map(|x| {
                 let fields = self.used_fields.clone();
+                let channels = self.channels.clone();
                 move || {
                     let stdin = x();
                     if stdin.wait() {
                         Some(FileRead {
                             inputs: Default::default(),
+                            channels,
+                            share_stdin: false,
                             named_columns: None,
                             used_fields: fields.clone(),
                             backup_used_fields: fields,
@@ -335,12 +605,15 @@ impl<LR: LineReader> FileRead<LR> {
     pub(crate) fn close(&mut self, path: &Str) {
         self.inputs.files.remove(path);
         self.inputs.commands.remove(path);
+        self.inputs.coprocs.remove(path);
+        self.inputs.sockets.remove(path);
     }
 
     pub(crate) fn new(
         stdin: LR,
         used_fields: FieldSet,
         named_columns: Option<Vec<&[u8]>>,
+        channels: Channels,
     ) -> FileRead<LR> {
         let backup_used_fields = used_fields;
         let used_fields = if named_columns.is_some() {
@@ -353,7 +626,9 @@ impl<LR: LineReader> FileRead<LR> {
         };
         let mut res = FileRead {
             inputs: Default::default(),
+            channels,
             stdin,
+            share_stdin: false,
             used_fields,
             backup_used_fields,
             named_columns: named_columns
@@ -395,6 +670,17 @@ impl<LR: LineReader> FileRead<LR> {
         self.stdin.set_used_fields(&self.used_fields)
     }
 
+    /// Read `-` and `/dev/stdin` from the main input, which must be standard input.
+    pub(crate) fn share_stdin(&mut self) {
+        self.share_stdin = true;
+    }
+
+    // Whether `path` names standard input and is read from the main input.
+    fn is_shared_stdin(&self, path: &Str) -> bool {
+        self.share_stdin
+            && path.with_bytes(|bs| special_files::special_fd(bs, /*reading=*/ true)) == Some(0)
+    }
+
     pub(crate) fn stdin_filename(&self) -> Str<'static> {
         self.stdin.filename()
     }
@@ -404,11 +690,23 @@ impl<LR: LineReader> FileRead<LR> {
     }
 
     pub(crate) fn read_err(&mut self, path: &Str) -> Result<Int> {
+        if path.with_bytes(inet::is_inet) {
+            return self.with_socket(path, |reader| Ok(reader.read_state()));
+        }
+        if self.is_shared_stdin(path) {
+            return Ok(self.stdin.read_state());
+        }
         self.with_file(path, |reader| Ok(reader.read_state()))
     }
     pub(crate) fn read_err_cmd(&mut self, cmd: &Str) -> Result<Int> {
         self.with_cmd(cmd, |reader| Ok(reader.read_state()))
     }
+    pub(crate) fn read_err_coproc(&mut self, cmd: &Str) -> Result<Int> {
+        if cmd.with_bytes(inet::is_inet) {
+            return self.with_socket(cmd, |reader| Ok(reader.read_state()));
+        }
+        self.with_coproc(cmd, |reader| Ok(reader.read_state()))
+    }
 
     pub(crate) fn next_file(&mut self) -> Result<()> {
         let _ = self.stdin.next_file()?;
@@ -436,6 +734,50 @@ impl<LR: LineReader> FileRead<LR> {
         )
     }
 
+    fn with_coproc<R>(
+        &mut self,
+        cmd: &Str,
+        f: impl FnMut(&mut RegexSplitter<ChildStdout>) -> Result<R>,
+    ) -> Result<R> {
+        let check_utf8 = self.stdin.check_utf8();
+        let coprocs = &self.channels.coprocs;
+        self.inputs.coprocs.get_fallible(
+            cmd,
+            |s| match coprocs.for_read(s.as_bytes()) {
+                Ok(r) => Ok(RegexSplitter::new_interactive(
+                    r,
+                    CHUNK_SIZE,
+                    cmd.clone().unmoor(),
+                    check_utf8,
+                )),
+                Err(e) => err!("failed to read from coprocess: {}", e),
+            },
+            f,
+        )
+    }
+
+    fn with_socket<R>(
+        &mut self,
+        name: &Str,
+        f: impl FnMut(&mut RegexSplitter<inet::Socket>) -> Result<R>,
+    ) -> Result<R> {
+        let check_utf8 = self.stdin.check_utf8();
+        let sockets = &self.channels.sockets;
+        self.inputs.sockets.get_fallible(
+            name,
+            |s| match sockets.for_read(s.as_bytes()) {
+                Ok(r) => Ok(RegexSplitter::new_interactive(
+                    r,
+                    CHUNK_SIZE,
+                    name.clone().unmoor(),
+                    check_utf8,
+                )),
+                Err(e) => err!("failed to read from network file: {}", e),
+            },
+            f,
+        )
+    }
+
     fn with_file<R>(
         &mut self,
         path: &Str,
@@ -444,14 +786,25 @@ impl<LR: LineReader> FileRead<LR> {
         let check_utf8 = self.stdin.check_utf8();
         self.inputs.files.get_fallible(
             path,
-            |s| match File::open(s) {
-                Ok(f) => Ok(RegexSplitter::new(
-                    f,
-                    CHUNK_SIZE,
-                    path.clone().unmoor(),
-                    check_utf8,
-                )),
-                Err(e) => err!("failed to open file '{}': {}", s, e),
+            |s| {
+                // Special files like `-` and `/dev/stdin` are often terminals or pipes, so records
+                // are returned as they arrive.
+                let fd = special_files::special_fd(s.as_bytes(), /*reading=*/ true);
+                match fd.map_or_else(|| File::open(s), special_files::dup_fd) {
+                    Ok(f) if fd.is_some() => Ok(RegexSplitter::new_interactive(
+                        f,
+                        CHUNK_SIZE,
+                        path.clone().unmoor(),
+                        check_utf8,
+                    )),
+                    Ok(f) => Ok(RegexSplitter::new(
+                        f,
+                        CHUNK_SIZE,
+                        path.clone().unmoor(),
+                        check_utf8,
+                    )),
+                    Err(e) => err!("failed to open file '{}': {}", s, e),
+                }
             },
             f,
         )
@@ -588,13 +941,30 @@ impl Inc for Float {
 
 impl<'a> Inc for Str<'a> {
     fn inc_int(&mut self, by: Int) {
-        *self = convert::<_, Self>(convert::<_, Int>(self as &_) + by);
+        self.inc_float(by as Float)
     }
     fn inc_float(&mut self, by: Float) {
         *self = convert::<_, Self>(convert::<_, Float>(self as &_) + by);
     }
 }
 
+/// Values that arrays fill in for missing keys. The inner arrays of an array of arrays take the
+/// representation of the outer array, so `--ordered-maps` applies to them as well.
+pub(crate) trait MapValue: Default {
+    fn empty(_ordered: bool) -> Self {
+        Self::default()
+    }
+}
+
+impl MapValue for Int {}
+impl MapValue for Float {}
+impl<'a> MapValue for Str<'a> {}
+impl<K, V> MapValue for SharedMap<K, V> {
+    fn empty(ordered: bool) -> Self {
+        SharedMap::new(ordered)
+    }
+}
+
 pub(crate) fn convert<S, T>(s: S) -> T
 where
     _Carrier: Convert<S, T>,
@@ -602,11 +972,64 @@ where
     _Carrier::convert(s)
 }
 
+/// Compare two strnums (see `--posix`), returning -1, 0 or 1. They are compared as numbers if they
+/// both look like numbers, and as strings otherwise.
+pub(crate) fn strnum_cmp(s1: &Str, s2: &Str, ignore_case: bool) -> Int {
+    use std::cmp::Ordering;
+    let ord = s1.with_bytes(|bs1| {
+        s2.with_bytes(|bs2| match (strnum(bs1), strnum(bs2)) {
+            (Some(f1), Some(f2)) => f1.partial_cmp(&f2).unwrap_or(Ordering::Equal),
+            _ => bytes_cmp(bs1, bs2, ignore_case),
+        })
+    });
+    ord as Int
+}
+
+/// Compare a strnum with a number, returning -1, 0 or 1. They are compared as numbers if `s` looks
+/// like one, and otherwise as strings, with `f` converted to a string using `CONVFMT`.
+pub(crate) fn strnum_cmp_float(s: &Str, f: Float, vars: &Variables, ignore_case: bool) -> Int {
+    use std::cmp::Ordering;
+    let ord = s.with_bytes(|bs| match strnum(bs) {
+        Some(sf) => sf.partial_cmp(&f).unwrap_or(Ordering::Equal),
+        None => vars
+            .convfmt(f)
+            .with_bytes(|fs| bytes_cmp(bs, fs, ignore_case)),
+    });
+    ord as Int
+}
+
+/// Compare two strings, ignoring case if `ignore_case` is set (i.e. if `IGNORECASE` is nonzero).
+pub(crate) fn str_cmp(s1: &Str, s2: &Str, ignore_case: bool) -> std::cmp::Ordering {
+    s1.with_bytes(|bs1| s2.with_bytes(|bs2| bytes_cmp(bs1, bs2, ignore_case)))
+}
+
+fn bytes_cmp(bs1: &[u8], bs2: &[u8], ignore_case: bool) -> std::cmp::Ordering {
+    if ignore_case {
+        fold_case(bs1).cmp(fold_case(bs2))
+    } else {
+        bs1.cmp(bs2)
+    }
+}
+
+/// The characters of `bs` with case folded away, for `IGNORECASE`. Valid UTF-8 is lowercased a
+/// character at a time, in line with the Unicode case folding of case-insensitive regexes. Bytes
+/// that are not valid UTF-8 are kept as they are, ordered after every character.
+pub(crate) fn fold_case(bs: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bs.utf8_chunks().flat_map(|chunk| {
+        let chars = chunk.valid().chars().flat_map(char::to_lowercase);
+        let invalid = chunk
+            .invalid()
+            .iter()
+            .map(|b| char::MAX as u32 + 1 + *b as u32);
+        chars.map(u32::from).chain(invalid)
+    })
+}
+
 // AWK arrays are inherently shared and mutable, so we have to do this, even if it is a code smell.
 // NB These are repr(transparent) because we pass them around as void* when compiling with LLVM.
 #[repr(transparent)]
 #[derive(Debug)]
-pub(crate) struct SharedMap<K, V>(pub(crate) Rc<RefCell<HashMap<K, V>>>);
+pub(crate) struct SharedMap<K, V>(pub(crate) Rc<RefCell<MapData<K, V>>>);
 
 impl<K, V> Default for SharedMap<K, V> {
     fn default() -> SharedMap<K, V> {
@@ -614,6 +1037,16 @@ impl<K, V> Default for SharedMap<K, V> {
     }
 }
 
+impl<K, V> SharedMap<K, V> {
+    /// An empty array; `ordered` is set for `--ordered-maps`.
+    pub(crate) fn new(ordered: bool) -> SharedMap<K, V> {
+        MapData::new(ordered).into()
+    }
+    pub(crate) fn is_ordered(&self) -> bool {
+        self.0.borrow().is_ordered()
+    }
+}
+
 impl<K, V> Clone for SharedMap<K, V> {
     fn clone(&self) -> Self {
         SharedMap(self.0.clone())
@@ -632,16 +1065,23 @@ impl<K: Hash + Eq, V> SharedMap<K, V> {
     }
     pub(crate) fn iter<F, R>(&self, f: F) -> R
     where
-        F: FnOnce(hashbrown::hash_map::Iter<K, V>) -> R,
+        F: FnOnce(map_data::Iter<K, V>) -> R,
     {
         f(self.0.borrow().iter())
     }
     pub(crate) fn clear(&self) {
         self.borrow_mut().clear();
     }
+    /// Copy the entries of the map into a new `MapData` with the same representation.
+    pub(crate) fn map_data<K2: Hash + Eq, V2>(
+        &self,
+        f: impl FnMut((&K, &V)) -> (K2, V2),
+    ) -> MapData<K2, V2> {
+        self.0.borrow().map(f)
+    }
 }
 
-impl<K: Hash + Eq + Clone, V: Inc + Default + Clone> SharedMap<K, V> {
+impl<K: Hash + Eq + Clone, V: Inc + MapValue + Clone> SharedMap<K, V> {
     pub(crate) fn inc_int(&self, k: &K, by: Int) -> V {
         self.with_inserted(k, |kref| {
             kref.inc_int(by);
@@ -657,44 +1097,33 @@ impl<K: Hash + Eq + Clone, V: Inc + Default + Clone> SharedMap<K, V> {
     }
 
     fn with_inserted<R>(&self, k: &K, f: impl FnOnce(&mut V) -> R) -> R {
-        let mut slf = self.0.borrow_mut();
-        if let Some(k) = slf.get_mut(k) {
-            f(k)
-        } else {
-            f(slf.entry(k.clone()).or_insert(Default::default()))
-        }
+        f(self.0.borrow_mut().get_or_insert_empty(k))
     }
 }
 
 // When sending SharedMaps across threads we have to clone them and clone their contents, as Rc is
 // not thread-safe (and we don't want to pay the cost of Arc clones during normal execution).
 pub(crate) struct Shuttle<T>(T);
-impl<'a> From<Shuttle<HashMap<Int, UniqueStr<'a>>>> for IntMap<Str<'a>> {
-    fn from(sh: Shuttle<HashMap<Int, UniqueStr<'a>>>) -> Self {
-        SharedMap(Rc::new(RefCell::new(
-            sh.0.into_iter().map(|(x, y)| (x, y.into_str())).collect(),
-        )))
+impl<'a> From<Shuttle<MapData<Int, UniqueStr<'a>>>> for IntMap<Str<'a>> {
+    fn from(sh: Shuttle<MapData<Int, UniqueStr<'a>>>) -> Self {
+        sh.0.into_map(|(x, y)| (x, y.into_str())).into()
     }
 }
 
-impl<'a> From<Shuttle<HashMap<UniqueStr<'a>, Int>>> for StrMap<'a, Int> {
-    fn from(sh: Shuttle<HashMap<UniqueStr<'a>, Int>>) -> Self {
-        SharedMap(Rc::new(RefCell::new(
-            sh.0.into_iter().map(|(x, y)| (x.into_str(), y)).collect(),
-        )))
+impl<'a> From<Shuttle<MapData<UniqueStr<'a>, Int>>> for StrMap<'a, Int> {
+    fn from(sh: Shuttle<MapData<UniqueStr<'a>, Int>>) -> Self {
+        sh.0.into_map(|(x, y)| (x.into_str(), y)).into()
     }
 }
 
-impl<'a> From<Shuttle<HashMap<UniqueStr<'a>, UniqueStr<'a>>>> for StrMap<'a, Str<'a>> {
-    fn from(sh: Shuttle<HashMap<UniqueStr<'a>, UniqueStr<'a>>>) -> Self {
-        SharedMap(Rc::new(RefCell::new(
-            sh.0.into_iter().map(|(x, y)| (x.into_str(), y.into_str())).collect(),
-        )))
+impl<'a> From<Shuttle<MapData<UniqueStr<'a>, UniqueStr<'a>>>> for StrMap<'a, Str<'a>> {
+    fn from(sh: Shuttle<MapData<UniqueStr<'a>, UniqueStr<'a>>>) -> Self {
+        sh.0.into_map(|(x, y)| (x.into_str(), y.into_str())).into()
     }
 }
 
 impl<K, V> SharedMap<K, V> {
-    fn borrow_mut(&self) -> impl std::ops::DerefMut<Target = HashMap<K, V>> + '_ {
+    fn borrow_mut(&self) -> impl std::ops::DerefMut<Target = MapData<K, V>> + '_ {
         // Unlike the full std::collections APIs, we are careful not to hand out any references
         // internal to a SharedMap from a public function. That means that functions which mutate
         // the map are "Cell"-like, in that they swap out values or drop them in, but never hold
@@ -726,57 +1155,31 @@ impl<K: Hash + Eq, V: Clone> SharedMap<K, V> {
     }
 }
 
-impl<K: Hash + Eq + Clone, V: Clone + Default> SharedMap<K, V> {
+impl<K: Hash + Eq + Clone, V: Clone + MapValue> SharedMap<K, V> {
     pub(crate) fn get(&self, k: &K) -> V {
-        self.borrow_mut()
-            .raw_entry_mut()
-            .from_key(k)
-            .or_insert_with(|| (k.clone(), V::default()))
-            .1
-            .clone()
+        self.borrow_mut().get_or_insert_empty(k).clone()
     }
 }
 
 impl<'a> IntMap<Str<'a>> {
-    pub(crate) fn shuttle(&self) -> Shuttle<HashMap<Int, UniqueStr<'a>>> {
-        Shuttle(
-            self.0
-                .borrow()
-                .iter()
-                .map(|(x, y)| (*x, UniqueStr::from(y.clone())))
-                .collect(),
-        )
+    pub(crate) fn shuttle(&self) -> Shuttle<MapData<Int, UniqueStr<'a>>> {
+        Shuttle(self.map_data(|(x, y)| (*x, UniqueStr::from(y.clone()))))
     }
 }
 
 impl<'a> StrMap<'a, Int> {
-    pub(crate) fn shuttle(&self) -> Shuttle<HashMap<UniqueStr<'a>, Int>> {
-        Shuttle(
-            self.0
-                .borrow()
-                .iter()
-                .map(|(x, y)| (UniqueStr::from(x.clone()), *y))
-                .collect(),
-        )
+    pub(crate) fn shuttle(&self) -> Shuttle<MapData<UniqueStr<'a>, Int>> {
+        Shuttle(self.map_data(|(x, y)| (UniqueStr::from(x.clone()), *y)))
     }
 }
 
 impl<'a> StrMap<'a, Str<'a>> {
-    pub(crate) fn shuttle(&self) -> Shuttle<HashMap<UniqueStr<'a>, UniqueStr<'a>>> {
-        Shuttle(
-            self.0
-                .borrow()
-                .iter()
-                .map(|(x, y)| (UniqueStr::from(x.clone()), UniqueStr::from(y.clone())))
-                .collect(),
-        )
+    pub(crate) fn shuttle(&self) -> Shuttle<MapData<UniqueStr<'a>, UniqueStr<'a>>> {
+        Shuttle(self.map_data(|(x, y)| (UniqueStr::from(x.clone()), UniqueStr::from(y.clone()))))
     }
 }
 
 impl<K: Hash + Eq + Clone, V> SharedMap<K, V> {
-    pub(crate) fn to_iter(&self) -> Iter<K> {
-        self.0.borrow().keys().cloned().collect()
-    }
     pub(crate) fn to_vec(&self) -> Vec<K> {
         self.0.borrow().keys().cloned().collect()
     }
@@ -784,6 +1187,12 @@ impl<K: Hash + Eq + Clone, V> SharedMap<K, V> {
 
 impl<K: Hash + Eq, V> From<HashMap<K, V>> for SharedMap<K, V> {
     fn from(m: HashMap<K, V>) -> SharedMap<K, V> {
+        MapData::Hashed(m).into()
+    }
+}
+
+impl<K, V> From<MapData<K, V>> for SharedMap<K, V> {
+    fn from(m: MapData<K, V>) -> SharedMap<K, V> {
         SharedMap(Rc::new(RefCell::new(m)))
     }
 }
@@ -794,7 +1203,7 @@ impl<K: Hash + Eq, V> FromIterator<(K, V)> for SharedMap<K, V> {
         T: IntoIterator<Item = (K, V)>,
     {
         SharedMap(Rc::new(RefCell::new(
-            iter.into_iter().collect::<HashMap<K, V>>(),
+            iter.into_iter().collect::<MapData<K, V>>(),
         )))
     }
 }
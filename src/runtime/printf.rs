@@ -10,7 +10,7 @@
 //! appends a newline) may find some bytes replaced inadvertently. We could solve this by adding a
 //! new print function that does not append a newline.
 use crate::common::Result;
-use crate::runtime::{convert, strtoi, Float, Int, Str};
+use crate::runtime::{bignum, convert, str_impl, strtoi, Float, Int, Str};
 
 use std::convert::TryFrom;
 use std::fmt;
@@ -48,6 +48,8 @@ impl<'a> fmt::Display for DisplayBytes<'a> {
 #[derive(Clone, Debug)]
 pub(crate) enum FormatArg<'a> {
     S(Str<'a>),
+    // A string in bignum mode (-M), where numbers are strings holding their exact value.
+    Big(Str<'a>),
     F(Float),
     I(Int),
     Null,
@@ -87,7 +89,7 @@ impl<'a> FormatArg<'a> {
     fn to_float(&self) -> f64 {
         use FormatArg::*;
         match self {
-            S(s) => convert::<_, f64>(s),
+            S(s) | Big(s) => convert::<_, f64>(s),
             F(f) => *f,
             I(i) => convert::<_, f64>(*i),
             Null => 0.0,
@@ -96,17 +98,22 @@ impl<'a> FormatArg<'a> {
     fn to_int(&self) -> i64 {
         use FormatArg::*;
         match self {
-            S(s) => convert::<_, i64>(s),
+            S(s) | Big(s) => convert::<_, i64>(s),
             F(f) => convert::<_, i64>(*f),
             I(i) => *i,
             Null => 0,
         }
     }
-    fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+    // Floats are converted with `convfmt`, the value of `CONVFMT` (`None` while it is the
+    // default).
+    fn with_bytes<R>(&self, convfmt: Option<&Str>, f: impl FnOnce(&[u8]) -> R) -> R {
         use FormatArg::*;
         let s: Str<'a> = match self {
-            S(s) => s.clone(),
-            F(f) => convert::<_, Str>(*f),
+            S(s) | Big(s) => s.clone(),
+            F(f) => match convfmt {
+                Some(fmt) => format_float(*f, fmt, false),
+                None => convert::<_, Str>(*f),
+            },
             I(i) => convert::<_, Str>(*i),
             Null => return f(&[]),
         };
@@ -144,7 +151,12 @@ fn is_spec(c: u8) -> bool {
     matches!(c, b'f' | b'c' | b'd' | b'e' | b'g' | b'o' | b's' | b'x')
 }
 
-fn process_spec(mut w: impl Write, fspec: &mut FormatSpec, arg: &FormatArg) -> Result<()> {
+fn process_spec(
+    mut w: impl Write,
+    fspec: &mut FormatSpec,
+    arg: &FormatArg,
+    convfmt: Option<&Str>,
+) -> Result<()> {
     macro_rules! match_for_spec {
         ($s:expr, $arg:expr) => {
             match (
@@ -188,6 +200,21 @@ fn process_spec(mut w: impl Write, fspec: &mut FormatSpec, arg: &FormatArg) -> R
             }
         };
     }
+    // In bignum mode (-M) numbers are held in strings. Print them exactly, rather than going
+    // through an i64 or an f64.
+    if let (b'd' | b'f' | b'g', FormatArg::Big(s)) = (fspec.spec, arg) {
+        let places = if fspec.rnum == usize::MAX {
+            6
+        } else {
+            fspec.rnum as u32
+        };
+        let num = s.with_bytes(|bs| match fspec.spec {
+            b'd' => bignum::format_int(bs),
+            b'f' => bignum::format_fixed(bs, places),
+            _ => bignum::format_general(bs, places),
+        });
+        return write_number(w, fspec, &num);
+    }
     let res = match fspec.spec {
         b'f' => {
             if !fspec.leading_zeros && fspec.lnum == 0 && fspec.rnum == usize::max_value() {
@@ -206,10 +233,10 @@ fn process_spec(mut w: impl Write, fspec: &mut FormatSpec, arg: &FormatArg) -> R
             // %g means "pick the shorter of standard and scientific notation". We do the obvious
             // thing of computing both and writing out the smaller one.
             fspec.spec = b'f';
-            process_spec(&mut buf, fspec, arg)?;
+            process_spec(&mut buf, fspec, arg, convfmt)?;
             let l1 = buf.len();
             fspec.spec = b'e';
-            process_spec(&mut buf, fspec, arg)?;
+            process_spec(&mut buf, fspec, arg, convfmt)?;
             let l2 = buf.len() - l1;
             let bytes = if l1 < l2 {
                 &buf.0[0..l1]
@@ -231,7 +258,7 @@ fn process_spec(mut w: impl Write, fspec: &mut FormatSpec, arg: &FormatArg) -> R
                 _ => match_for_spec!("", "?"),
             }
         }
-        b's' => arg.with_bytes(|bs| match_for_spec!("", DisplayBytes(bs))),
+        b's' => arg.with_bytes(convfmt, |bs| match_for_spec!("", DisplayBytes(bs))),
         x => return err!("unsupported format specifier: {}", x),
     };
     wrap_result(res)
@@ -244,11 +271,41 @@ fn wrap_result<T>(r: std::result::Result<T, impl fmt::Display>) -> Result<()> {
     }
 }
 
+// Pad a number formatted by bignum mode. Zeros go between the sign and the digits, as they do for
+// `{:0>}` on an integer.
+fn write_number(mut w: impl Write, fspec: &FormatSpec, num: &str) -> Result<()> {
+    let res = if fspec.minus {
+        write!(w, "{:<l$}", num, l = fspec.lnum)
+    } else if fspec.leading_zeros {
+        let (sign, digits) = match num.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", num),
+        };
+        write!(
+            w,
+            "{}{:0>l$}",
+            sign,
+            digits,
+            l = fspec.lnum.saturating_sub(sign.len())
+        )
+    } else {
+        write!(w, "{:>l$}", num, l = fspec.lnum)
+    };
+    wrap_result(res)
+}
+
 fn write_bytes(mut w: impl Write, bs: &[u8]) -> Result<()> {
     wrap_result(w.write(bs))
 }
 
-pub(crate) fn printf(mut w: impl Write, spec: &[u8], mut args: &[FormatArg]) -> Result<()> {
+/// Write `args` to `w` as described by the format string `spec`. Numbers printed with `%s` are
+/// converted using `convfmt`, the value of `CONVFMT`, or as with its default value when `None`.
+pub(crate) fn printf(
+    mut w: impl Write,
+    spec: &[u8],
+    mut args: &[FormatArg],
+    convfmt: Option<&Str>,
+) -> Result<()> {
     #[derive(Copy, Clone)]
     enum State {
         // Byte index of start of string
@@ -316,13 +373,13 @@ pub(crate) fn printf(mut w: impl Write, spec: &[u8], mut args: &[FormatArg]) ->
                     match (ch, stage) {
                         (b'%', Begin) => {
                             fs.spec = b'%';
-                            process_spec(&mut w, &mut fs, next_arg())?;
+                            process_spec(&mut w, &mut fs, next_arg(), convfmt)?;
                             state = Raw(ix + 1);
                             continue 'outer;
                         }
                         (ch, _) if is_spec(ch) => {
                             fs.spec = ch;
-                            process_spec(&mut w, &mut fs, next_arg())?;
+                            process_spec(&mut w, &mut fs, next_arg(), convfmt)?;
                             state = Raw(ix + 1);
                             continue 'outer;
                         }
@@ -396,6 +453,35 @@ pub(crate) fn printf(mut w: impl Write, spec: &[u8], mut args: &[FormatArg]) ->
     Ok(())
 }
 
+/// The default value of `CONVFMT` and `OFMT`.
+pub(crate) const DEFAULT_FLOAT_FMT: &str = "%.6g";
+
+/// Whether `fmt` is [`DEFAULT_FLOAT_FMT`]. This is checked when `CONVFMT` or `OFMT` is assigned,
+/// rather than on every conversion.
+pub(crate) fn is_default_float_fmt(fmt: &Str) -> bool {
+    fmt.with_bytes(|bs| bs == DEFAULT_FLOAT_FMT.as_bytes())
+}
+
+/// Convert `f` to a string using `fmt`, the value of `CONVFMT` or `OFMT`.
+///
+/// While `fmt` has its default value (`is_default`) we keep using ryu (see the `From<Float>` impl
+/// for `Str`), as frawk always has. Like other awks, integral values are converted as integers
+/// whatever the format, as long as they fit in an `Int`.
+pub(crate) fn format_float<'a>(f: Float, fmt: &Str, is_default: bool) -> Str<'a> {
+    if is_default {
+        return f.into();
+    }
+    // `f as Int` saturates, so check the range first: 2^63 and above are not integers here.
+    if (Int::MIN as Float..-(Int::MIN as Float)).contains(&f) && f.fract() == 0.0 {
+        return (f as Int).into();
+    }
+    let mut text = str_impl::DynamicBuf::default();
+    match fmt.with_bytes(|bs| printf(&mut text, bs, &[FormatArg::F(f)], None)) {
+        Ok(()) => text.into_str(),
+        Err(_) => f.into(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -406,7 +492,7 @@ mod tests {
         ($fmt:expr $(, $e:expr)*) => {{
             let mut v = Vec::<u8>::new();
             let w = Cursor::new(&mut v);
-            printf(w, $fmt, &[$( $e.into() ),*]).expect("printf failure");
+            printf(w, $fmt, &[$( $e.into() ),*], None).expect("printf failure");
             String::from_utf8(v).expect("printf should produce valid utf8")
         }}
     }
@@ -421,6 +507,7 @@ mod tests {
             w,
             b"Hi %s, to my %d friends %f percent of the time: %g!",
             &[S("there".into()), F(2.5), I(1), F(1.25369E23)],
+            None,
         )
         .expect("printf failed");
         let s = str::from_utf8(&v[..]).unwrap();
@@ -448,4 +535,20 @@ mod tests {
         let s2 = sprintf!(b"%.2f", 2.375);
         assert_eq!(s2.as_str(), "2.38");
     }
+
+    #[test]
+    fn float_formats() {
+        let fmt = |f: Float, spec: &str| {
+            let spec = Str::from(spec);
+            format_float(f, &spec, is_default_float_fmt(&spec))
+                .as_str()
+                .to_string()
+        };
+        assert_eq!(fmt(3.14159265, DEFAULT_FLOAT_FMT), "3.14159265");
+        assert_eq!(fmt(3.14159265, "%.2f"), "3.14");
+        assert_eq!(fmt(17.0, "%.2f"), "17");
+        assert_eq!(fmt(-2.5, "[%d]"), "[-2]");
+        assert_eq!(fmt(2f64.powi(63), "%.2f"), "9223372036854775808.00");
+        assert_eq!(fmt(-(2f64.powi(63)), "%.2f"), "-9223372036854775808");
+    }
 }
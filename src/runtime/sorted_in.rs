@@ -0,0 +1,236 @@
+//! Ordered `for (k in m)` loops, for `PROCINFO["sorted_in"]`.
+//!
+//! The builtin orders (`@ind_str_asc`, `@val_num_desc` and so on) are applied here, when a loop
+//! starts. Orders given by the name of a user-defined comparison function are handled when the
+//! program is compiled, by [crate::sort_loops].
+use std::cmp::Ordering;
+use std::hash::Hash;
+
+use super::{strnum, strtod, Float, Int, SharedMap, Str};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum By {
+    IndStr,
+    IndNum,
+    ValType,
+    ValStr,
+    ValNum,
+}
+
+/// A builtin iteration order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SortedIn {
+    by: By,
+    desc: bool,
+}
+
+impl SortedIn {
+    /// Parse a `PROCINFO["sorted_in"]` value. `@unsorted`, the names of comparison functions and
+    /// any other unrecognized values yield `None`: the loop visits keys in hash order.
+    pub(crate) fn parse(s: &[u8]) -> Option<SortedIn> {
+        use By::*;
+        let (by, desc) = match s {
+            b"@ind_str_asc" => (IndStr, false),
+            b"@ind_str_desc" => (IndStr, true),
+            b"@ind_num_asc" => (IndNum, false),
+            b"@ind_num_desc" => (IndNum, true),
+            b"@val_type_asc" => (ValType, false),
+            b"@val_type_desc" => (ValType, true),
+            b"@val_str_asc" => (ValStr, false),
+            b"@val_str_desc" => (ValStr, true),
+            b"@val_num_asc" => (ValNum, false),
+            b"@val_num_desc" => (ValNum, true),
+            _ => return None,
+        };
+        Some(SortedIn { by, desc })
+    }
+
+    // Ties are broken by comparing indices as strings, so that the order is deterministic.
+    fn sort<K, V>(&self, map: &SharedMap<K, V>, ignore_case: bool) -> Vec<K>
+    where
+        K: Hash + Eq + Clone + SortValue,
+        V: SortValue,
+    {
+        use By::*;
+        let m = map.0.borrow();
+        let mut entries: Vec<(&K, SortKey, Option<SortKey>)> = m
+            .iter()
+            .map(|(k, v)| {
+                let val = match self.by {
+                    IndStr | IndNum => None,
+                    ValType | ValStr | ValNum => Some(v.sort_key(ignore_case)),
+                };
+                (k, k.sort_key(ignore_case), val)
+            })
+            .collect();
+        entries.sort_by(|(_, i1, v1), (_, i2, v2)| {
+            let ord = match (self.by, v1, v2) {
+                (IndStr, _, _) => i1.cmp_str(i2),
+                (IndNum, _, _) => i1.cmp_num(i2).then_with(|| i1.cmp_str(i2)),
+                (ValType, Some(v1), Some(v2)) => v1.cmp_type(v2),
+                (ValStr, Some(v1), Some(v2)) => v1.cmp_str(v2),
+                (ValNum, Some(v1), Some(v2)) => v1.cmp_num(v2),
+                _ => Ordering::Equal,
+            }
+            .then_with(|| i1.cmp_str(i2));
+            if self.desc {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+        entries.into_iter().map(|(k, _, _)| k.clone()).collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone + SortValue, V: SortValue> SharedMap<K, V> {
+    /// The keys of the map, in `order` if one is set and in hash order otherwise. `ignore_case`
+    /// folds ASCII case when comparing strings, for `IGNORECASE`.
+    pub(crate) fn sorted_keys(&self, order: Option<SortedIn>, ignore_case: bool) -> Vec<K> {
+        match order {
+            Some(order) => order.sort(self, ignore_case),
+            None => self.to_vec(),
+        }
+    }
+}
+
+// An index or value, prepared for comparison.
+pub(crate) struct SortKey {
+    num: Float,
+    bytes: Vec<u8>,
+    // Whether the value is a number, or a string that looks like one, for `@val_type`.
+    is_num: bool,
+}
+
+impl SortKey {
+    fn cmp_num(&self, other: &SortKey) -> Ordering {
+        self.num.partial_cmp(&other.num).unwrap_or(Ordering::Equal)
+    }
+    fn cmp_str(&self, other: &SortKey) -> Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
+    // Numbers come before strings.
+    fn cmp_type(&self, other: &SortKey) -> Ordering {
+        match (self.is_num, other.is_num) {
+            (true, true) => self.cmp_num(other),
+            (false, false) => self.cmp_str(other),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        }
+    }
+}
+
+/// Map indices and values that can be sorted by [SortedIn].
+pub(crate) trait SortValue {
+    fn sort_key(&self, ignore_case: bool) -> SortKey;
+}
+
+fn bytes_key(bs: &[u8], ignore_case: bool) -> Vec<u8> {
+    if ignore_case {
+        bs.to_ascii_lowercase()
+    } else {
+        bs.to_vec()
+    }
+}
+
+impl SortValue for Int {
+    fn sort_key(&self, _ignore_case: bool) -> SortKey {
+        SortKey {
+            num: *self as Float,
+            bytes: self.to_string().into_bytes(),
+            is_num: true,
+        }
+    }
+}
+
+impl SortValue for Float {
+    fn sort_key(&self, _ignore_case: bool) -> SortKey {
+        SortKey {
+            num: *self,
+            bytes: Str::from(*self).with_bytes(|bs| bs.to_vec()),
+            is_num: true,
+        }
+    }
+}
+
+impl<'a> SortValue for Str<'a> {
+    fn sort_key(&self, ignore_case: bool) -> SortKey {
+        self.with_bytes(|bs| {
+            let num = strnum(bs);
+            SortKey {
+                num: num.unwrap_or_else(|| strtod(bs)),
+                bytes: bytes_key(bs, ignore_case),
+                is_num: num.is_some(),
+            }
+        })
+    }
+}
+
+// The values of an array of arrays have no natural order; they compare equal, leaving them in
+// the order of their indices.
+impl<K, V> SortValue for SharedMap<K, V> {
+    fn sort_key(&self, _ignore_case: bool) -> SortKey {
+        SortKey {
+            num: 0.0,
+            bytes: Vec::new(),
+            is_num: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{IntMap, StrMap};
+
+    fn order(s: &str) -> Option<SortedIn> {
+        SortedIn::parse(s.as_bytes())
+    }
+
+    #[test]
+    fn parse_orders() {
+        assert_eq!(order("@unsorted"), None);
+        assert_eq!(order("cmp_fn"), None);
+        assert_eq!(
+            order("@val_num_desc"),
+            Some(SortedIn {
+                by: By::ValNum,
+                desc: true
+            })
+        );
+    }
+
+    #[test]
+    fn sort_indices() {
+        let m: IntMap<Int> = [(10, 1), (9, 2), (100, 3)].into_iter().collect();
+        assert_eq!(
+            m.sorted_keys(order("@ind_num_asc"), false),
+            vec![9, 10, 100]
+        );
+        assert_eq!(
+            m.sorted_keys(order("@ind_str_asc"), false),
+            vec![10, 100, 9]
+        );
+        assert_eq!(
+            m.sorted_keys(order("@ind_num_desc"), false),
+            vec![100, 10, 9]
+        );
+    }
+
+    #[test]
+    fn sort_values() {
+        let m: StrMap<Str> = [("a", "10"), ("b", "9"), ("c", "x"), ("d", "9")]
+            .into_iter()
+            .map(|(k, v)| (Str::from(k), Str::from(v)))
+            .collect();
+        let keys = |o, ignore_case| -> Vec<String> {
+            m.sorted_keys(order(o), ignore_case)
+                .into_iter()
+                .map(|k| k.as_str().to_string())
+                .collect()
+        };
+        assert_eq!(keys("@val_num_asc", false), vec!["c", "b", "d", "a"]);
+        assert_eq!(keys("@val_str_asc", false), vec!["a", "b", "d", "c"]);
+        assert_eq!(keys("@val_type_desc", false), vec!["c", "a", "d", "b"]);
+    }
+}
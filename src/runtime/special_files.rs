@@ -0,0 +1,61 @@
+//! File names that zawk handles itself instead of opening them: `-`, `/dev/stdin`,
+//! `/dev/stdout`, `/dev/stderr` and `/dev/fd/N`.
+//!
+//! Writes to standard output go to the same handle as `print` without a redirection, so the two
+//! stay in order. The other descriptors are duplicated rather than reopened by name, which also
+//! works on systems without a `/dev/fd` directory.
+use std::fs::File;
+use std::io;
+
+/// The file descriptor named by `name`, if it is a special file. `-` stands for standard input
+/// when reading and for standard output when writing.
+pub fn special_fd(name: &[u8], reading: bool) -> Option<u32> {
+    match name {
+        b"-" if reading => Some(0),
+        b"-" => Some(1),
+        b"/dev/stdin" => Some(0),
+        b"/dev/stdout" => Some(1),
+        b"/dev/stderr" => Some(2),
+        _ => std::str::from_utf8(name.strip_prefix(b"/dev/fd/")?)
+            .ok()?
+            .parse()
+            .ok(),
+    }
+}
+
+/// A new handle on the open file descriptor `fd`.
+#[cfg(unix)]
+pub fn dup_fd(fd: u32) -> io::Result<File> {
+    use std::os::unix::io::FromRawFd;
+    let fd = libc::c_int::try_from(fd).map_err(|_| io::Error::from_raw_os_error(libc::EBADF))?;
+    // SAFETY: dup either fails or returns a new descriptor, which the File then owns.
+    let new_fd = unsafe { libc::dup(fd) };
+    if new_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(new_fd) })
+}
+
+#[cfg(not(unix))]
+pub fn dup_fd(fd: u32) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("file descriptor {} cannot be opened on this platform", fd),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_names() {
+        assert_eq!(special_fd(b"-", true), Some(0));
+        assert_eq!(special_fd(b"-", false), Some(1));
+        assert_eq!(special_fd(b"/dev/stderr", false), Some(2));
+        assert_eq!(special_fd(b"/dev/fd/7", false), Some(7));
+        assert_eq!(special_fd(b"/dev/fd/x", false), None);
+        assert_eq!(special_fd(b"/dev/null", false), None);
+        assert_eq!(special_fd(b"out.txt", true), None);
+    }
+}
@@ -31,7 +31,7 @@ use crate::common::{CancelSignal, ExecutionStrategy, Result};
 use crate::pushdown::FieldSet;
 use crate::runtime::{
     str_impl::{Buf, Str, UniqueBuf},
-    Int, RegexCache,
+    Int, Regexes,
 };
 
 use super::{
@@ -94,7 +94,7 @@ impl LineReader for CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
         }
         res
     }
-    fn read_line(&mut self, _pat: &Str, _rc: &mut RegexCache) -> Result<(bool, Line)> {
+    fn read_line(&mut self, _pat: &Str, _rc: &mut Regexes) -> Result<(bool, Line)> {
         let mut line = Line::default();
         let changed = self.read_line_reuse(_pat, _rc, &mut line)?;
         Ok((changed, line))
@@ -102,7 +102,7 @@ impl LineReader for CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
     fn read_line_reuse<'a, 'b: 'a>(
         &'b mut self,
         _pat: &Str,
-        _rc: &mut RegexCache,
+        _rc: &mut Regexes,
         old: &'a mut Line,
     ) -> Result<bool> {
         self.read_line_inner(old)
@@ -332,7 +332,7 @@ impl<'a> super::Line<'a> for Line {
             .join(self.fields[start..end].iter().cloned().map(trans))
             .upcast())
     }
-    fn nf(&mut self, _pat: &Str, _rc: &mut super::RegexCache) -> Result<usize> {
+    fn nf(&mut self, _pat: &Str, _rc: &mut super::Regexes) -> Result<usize> {
         Ok(self.fields.len())
     }
 
@@ -341,7 +341,7 @@ impl<'a> super::Line<'a> for Line {
         col: super::Int,
         _pat: &Str,
         _ofs: &Str,
-        _rc: &mut super::RegexCache,
+        _rc: &mut super::Regexes,
     ) -> Result<Str<'a>> {
         if col == 0 {
             return Ok(self.raw.clone().upcast());
@@ -363,7 +363,7 @@ impl<'a> super::Line<'a> for Line {
         _col: super::Int,
         _s: &Str<'a>,
         _pat: &Str,
-        _rc: &mut super::RegexCache,
+        _rc: &mut super::Regexes,
     ) -> Result<()> {
         Ok(())
     }
@@ -1413,6 +1413,8 @@ pub struct ByteReader<P: ChunkProducer> {
     // Progress in the current buffer.
     progress: usize,
     record_sep: u8,
+    // Whether the last record read ended with `record_sep` (rather than the end of the input).
+    terminated: bool,
 
     last_len: usize,
     check_utf8: bool,
@@ -1508,6 +1510,7 @@ impl ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
             buf_len: 0,
             progress: 0,
             record_sep,
+            terminated: false,
             used_fields: FieldSet::all(),
             last_len: usize::max_value(),
             check_utf8,
@@ -1599,7 +1602,8 @@ impl ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk<WhitespaceOffsets>>>>
             cur_buf: UniqueBuf::new(0).into_buf(),
             buf_len: 0,
             progress: 0,
-            record_sep: 0, // unused
+            record_sep: b'\n',
+            terminated: false,
             used_fields: FieldSet::all(),
             last_len: usize::max_value(),
             check_utf8,
@@ -1635,6 +1639,7 @@ where
                 buf_len: 0,
                 progress: 0,
                 record_sep,
+                terminated: false,
                 last_len: usize::max_value(),
                 used_fields,
                 check_utf8,
@@ -1642,7 +1647,7 @@ where
         }
         res
     }
-    fn read_line(&mut self, _pat: &Str, _rc: &mut RegexCache) -> Result<(bool, DefaultLine)> {
+    fn read_line(&mut self, _pat: &Str, _rc: &mut Regexes) -> Result<(bool, DefaultLine)> {
         let mut line = DefaultLine::default();
         let changed = self.read_line_reuse(_pat, _rc, &mut line)?;
         Ok((changed, line))
@@ -1650,7 +1655,7 @@ where
     fn read_line_reuse<'a, 'b: 'a>(
         &'b mut self,
         _pat: &Str,
-        _rc: &mut RegexCache,
+        _rc: &mut Regexes,
         old: &'a mut DefaultLine,
     ) -> Result<bool> {
         let start = self.cur_chunk_version() == 0;
@@ -1684,6 +1689,14 @@ where
     fn set_used_fields(&mut self, field_set: &FieldSet) {
         self.used_fields = field_set.clone();
     }
+
+    fn record_terminator(&self) -> Str<'static> {
+        if self.terminated {
+            Str::from(&[self.record_sep][..]).unmoor()
+        } else {
+            Str::default()
+        }
+    }
 }
 
 // Most of the implementation for splitting by whitespace and splitting by a single byte is
@@ -1751,9 +1764,14 @@ where
             return Ok(false);
         }
     }
+    let line_start = br.progress;
     let (next_line, consumed) = unsafe { br.consume_line(fields) };
     *line = next_line;
     br.last_len = consumed;
+    // A record that runs to the end of the input has no terminator.
+    br.terminated = consumed > 0
+        && br.cur_buf.as_bytes()[..br.buf_len].get(line_start + consumed - 1)
+            == Some(&br.record_sep);
     Ok(changed)
 }
 
@@ -1973,6 +1991,7 @@ fn gallop(slice: &[u64], mut cmp: impl FnMut(u64) -> bool) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runtime::RegexCache;
     use std::io;
     use std::iter;
 
@@ -2048,7 +2067,7 @@ unquoted,commas,"as well, including some long ones", and there we have it.""#;
         );
         loop {
             let (_, line) = reader
-                .read_line(&_pat, &mut _cache)
+                .read_line(&_pat, &mut Regexes::new(&mut _cache))
                 .expect("failed to read line");
             if reader.read_state() != 1 {
                 break;
@@ -2121,7 +2140,7 @@ unquoted,commas,"as well, including some long ones", and there we have it.""#;
         let mut got = Vec::new();
         loop {
             let (_, line) = reader
-                .read_line(&_pat, &mut _cache)
+                .read_line(&_pat, &mut Regexes::new(&mut _cache))
                 .expect("failed to read line");
             if reader.read_state() != 1 {
                 break;
@@ -2245,7 +2264,7 @@ unquoted,commas,"as well, including some long ones", and there we have it.""#;
                 let mut reader = t();
                 loop {
                     let (_, _line) = reader
-                        .read_line(&_pat, &mut _cache)
+                        .read_line(&_pat, &mut Regexes::new(&mut _cache))
                         .expect("failed to read line");
                     if reader.read_state() != 1 {
                         break;
@@ -2339,7 +2358,7 @@ unquoted,commas,"as well, including some long ones", and there we have it.""#;
         let mut got = Vec::new();
         loop {
             let (_, line) = reader
-                .read_line(&_pat, &mut _cache)
+                .read_line(&_pat, &mut Regexes::new(&mut _cache))
                 .expect("failed to read line");
             if reader.read_state() != 1 {
                 break;
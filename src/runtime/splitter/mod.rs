@@ -8,13 +8,15 @@
 pub mod batch;
 pub mod chunk;
 pub mod regex;
+pub mod widths;
 
 use super::str_impl::{Buf, Str, UniqueBuf};
 use super::utf8::{is_utf8, validate_utf8_clipped};
-use super::{Int, RegexCache};
+use super::{Int, Regexes};
 use crate::common::Result;
 use crate::pushdown::FieldSet;
 
+use ::regex::bytes::Regex;
 use std::io::{ErrorKind, Read};
 
 // We have several implementations of "read and split a line"; they are governed by the LineReader
@@ -31,9 +33,9 @@ pub trait Line<'a>: Default {
     ) -> Result<Str<'a>>
     where
         F: FnMut(Str<'static>) -> Str<'static>;
-    fn nf(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<usize>;
-    fn get_col(&mut self, col: Int, pat: &Str, ofs: &Str, rc: &mut RegexCache) -> Result<Str<'a>>;
-    fn set_col(&mut self, col: Int, s: &Str<'a>, pat: &Str, rc: &mut RegexCache) -> Result<()>;
+    fn nf(&mut self, pat: &Str, rc: &mut Regexes) -> Result<usize>;
+    fn get_col(&mut self, col: Int, pat: &Str, ofs: &Str, rc: &mut Regexes) -> Result<Str<'a>>;
+    fn set_col(&mut self, col: Int, s: &Str<'a>, pat: &Str, rc: &mut Regexes) -> Result<()>;
 }
 
 pub trait LineReader: Sized {
@@ -49,12 +51,12 @@ pub trait LineReader: Sized {
     fn read_line(
         &mut self,
         pat: &Str,
-        rc: &mut RegexCache,
+        rc: &mut Regexes,
     ) -> Result<(/*file changed*/ bool, Self::Line)>;
     fn read_line_reuse<'a, 'b: 'a>(
         &'b mut self,
         pat: &Str,
-        rc: &mut RegexCache,
+        rc: &mut Regexes,
         old: &'a mut Self::Line,
     ) -> Result</* file changed */ bool> {
         let (changed, mut new) = self.read_line(pat, rc)?;
@@ -64,6 +66,11 @@ pub trait LineReader: Sized {
     fn read_state(&self) -> i64;
     fn next_file(&mut self) -> Result<bool>;
     fn set_used_fields(&mut self, used_fields: &FieldSet);
+    // The text that terminated the last record read, which is stored in `RT`. CSV and TSV readers
+    // do not track this and leave `RT` empty.
+    fn record_terminator(&self) -> Str<'static> {
+        Str::default()
+    }
     // Whether or not this LineReader is configured to check for valid UTF-8. This is used to
     // propagate consistent options across multiple LineReader instances.
     fn check_utf8(&self) -> bool;
@@ -112,7 +119,7 @@ impl Default for DefaultLine {
 }
 
 impl DefaultLine {
-    fn split_if_needed(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<()> {
+    fn split_if_needed(&mut self, pat: &Str, rc: &mut Regexes) -> Result<()> {
         if self.fields.is_empty() {
             rc.split_regex(pat, &self.line, &self.used_fields, &mut self.fields)?;
         }
@@ -142,11 +149,11 @@ impl<'a> Line<'a> for DefaultLine {
             .join(self.fields[start..end].iter().cloned().map(trans))
             .upcast())
     }
-    fn nf(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<usize> {
+    fn nf(&mut self, pat: &Str, rc: &mut Regexes) -> Result<usize> {
         self.split_if_needed(pat, rc)?;
         Ok(self.fields.len())
     }
-    fn get_col(&mut self, col: Int, pat: &Str, ofs: &Str, rc: &mut RegexCache) -> Result<Str<'a>> {
+    fn get_col(&mut self, col: Int, pat: &Str, ofs: &Str, rc: &mut Regexes) -> Result<Str<'a>> {
         if col < 0 {
             return err!("attempt to access field {}; field must be nonnegative", col);
         }
@@ -188,7 +195,7 @@ impl<'a> Line<'a> for DefaultLine {
         };
         Ok(res.upcast())
     }
-    fn set_col(&mut self, col: Int, s: &Str<'a>, pat: &Str, rc: &mut RegexCache) -> Result<()> {
+    fn set_col(&mut self, col: Int, s: &Str<'a>, pat: &Str, rc: &mut Regexes) -> Result<()> {
         if col == 0 {
             self.line = s.clone().unmoor();
             self.fields.clear();
@@ -237,7 +244,7 @@ where
             .map(LineReader::filename)
             .unwrap_or_else(Str::default)
     }
-    fn read_line(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<(bool, R::Line)> {
+    fn read_line(&mut self, pat: &Str, rc: &mut Regexes) -> Result<(bool, R::Line)> {
         let mut line = R::Line::default();
         let changed = self.read_line_reuse(pat, rc, &mut line)?;
         Ok((changed, line))
@@ -245,7 +252,7 @@ where
     fn read_line_reuse<'a, 'b: 'a>(
         &'b mut self,
         pat: &Str,
-        rc: &mut RegexCache,
+        rc: &mut Regexes,
         old: &'a mut Self::Line,
     ) -> Result<bool> {
         let cur = match self.0.last_mut() {
@@ -285,6 +292,119 @@ where
             i.set_used_fields(used_fields);
         }
     }
+    fn record_terminator(&self) -> Str<'static> {
+        self.0
+            .last()
+            .map(LineReader::record_terminator)
+            .unwrap_or_else(Str::default)
+    }
+}
+
+/// A LineReader that hands out whole records and leaves splitting to [DefaultLine], so that
+/// assignments to `FIELDWIDTHS`, `FPAT` or `FS` take effect on the next field access.
+///
+/// The inner reader should not split records into fields itself: a `ByteReader` whose field and
+/// record separators are both the record separator works, and keeps support for parallel
+/// execution.
+pub struct RecordReader<R> {
+    inner: R,
+    used_fields: FieldSet,
+    // Used to pick up the projected fields on the first read; see `RegexSplitter`.
+    start: bool,
+}
+
+impl<R> RecordReader<R> {
+    pub fn new(inner: R) -> Self {
+        RecordReader {
+            inner,
+            used_fields: FieldSet::all(),
+            start: true,
+        }
+    }
+}
+
+impl<R: LineReader<Line = DefaultLine> + 'static> LineReader for RecordReader<R> {
+    type Line = DefaultLine;
+    fn filename(&self) -> Str<'static> {
+        self.inner.filename()
+    }
+    fn check_utf8(&self) -> bool {
+        self.inner.check_utf8()
+    }
+    fn wait(&self) -> bool {
+        self.inner.wait()
+    }
+    fn request_handles(&self, size: usize) -> Vec<Box<dyn FnOnce() -> Self + Send>> {
+        let mut res = Vec::with_capacity(size);
+        for inner in self.inner.request_handles(size) {
+            let used_fields = self.used_fields.clone();
+            res.push(Box::new(move || RecordReader {
+                inner: inner(),
+                used_fields,
+                start: true,
+            }) as _)
+        }
+        res
+    }
+    fn read_line(&mut self, pat: &Str, rc: &mut Regexes) -> Result<(bool, DefaultLine)> {
+        let mut line = DefaultLine::default();
+        let changed = self.read_line_reuse(pat, rc, &mut line)?;
+        Ok((changed, line))
+    }
+    fn read_line_reuse<'a, 'b: 'a>(
+        &'b mut self,
+        pat: &Str,
+        rc: &mut Regexes,
+        old: &'a mut DefaultLine,
+    ) -> Result<bool> {
+        // The line may have widened its used fields after a write to a field; keep that for later
+        // records. The inner reader always reads the whole record, because the fields it would
+        // project are not the ones we split.
+        if !self.start && old.used_fields != self.used_fields {
+            self.used_fields = old.used_fields.clone();
+        }
+        self.start = false;
+        old.used_fields = FieldSet::all();
+        let changed = self.inner.read_line_reuse(pat, rc, old)?;
+        old.fields.clear();
+        old.used_fields = self.used_fields.clone();
+        Ok(changed)
+    }
+    fn read_state(&self) -> i64 {
+        self.inner.read_state()
+    }
+    fn next_file(&mut self) -> Result<bool> {
+        self.inner.next_file()
+    }
+    fn set_used_fields(&mut self, used_fields: &FieldSet) {
+        self.used_fields = used_fields.clone();
+    }
+    fn record_terminator(&self) -> Str<'static> {
+        self.inner.record_terminator()
+    }
+}
+
+/// A way of splitting records into fields that takes the place of `FS`: set by assigning
+/// `FIELDWIDTHS` or `FPAT`, and cleared by assigning `FS`.
+#[derive(Clone)]
+pub enum FieldSplit {
+    Widths(widths::FieldWidths),
+    // Fields are the matches of an `FPAT` regex.
+    Pattern(Regex),
+}
+
+impl FieldSplit {
+    pub(crate) fn split<'a>(
+        &self,
+        line: &Str<'a>,
+        used_fields: &FieldSet,
+        push: impl FnMut(Str<'a>),
+    ) {
+        match self {
+            FieldSplit::Widths(widths) => widths.split(line, used_fields, push),
+            FieldSplit::Pattern(re) => regex::split_fpat(re, line, used_fields, push),
+        }
+    }
 }
 
 // Buffer management and io
@@ -323,6 +443,10 @@ struct Reader<R> {
 
     // Validate input as UTF-8
     check_utf8: bool,
+
+    // Return whatever a single read yields rather than filling the buffer, for input that arrives
+    // a record at a time, like the output of a coprocess.
+    interactive: bool,
 }
 
 fn read_to_slice(r: &mut impl Read, mut buf: &mut [u8]) -> Result<usize> {
@@ -348,6 +472,20 @@ fn read_to_slice(r: &mut impl Read, mut buf: &mut [u8]) -> Result<usize> {
     Ok(read)
 }
 
+// Like `read_to_slice`, but returns after the first read that yields any data.
+fn read_some(r: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    loop {
+        match r.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) => match e.kind() {
+                ErrorKind::Interrupted => continue,
+                ErrorKind::UnexpectedEof => return Ok(0),
+                _ => return err!("read error {}", e),
+            },
+        }
+    }
+}
+
 impl<R: Read> Reader<R> {
     pub(crate) fn new(r: R, chunk_size: usize, padding: usize, check_utf8: bool) -> Self {
         Reader {
@@ -361,6 +499,7 @@ impl<R: Read> Reader<R> {
             state: ReaderState::OK,
             last_len: 0,
             check_utf8,
+            interactive: false,
         }
     }
 
@@ -432,11 +571,18 @@ impl<R: Read> Reader<R> {
             std::ptr::copy_nonoverlapping(self.buf.as_ptr().add(consume), data.as_mut_ptr(), plen);
         }
         let mut bytes = &mut data.as_mut_bytes()[..self.chunk_size];
-        let bytes_read = plen + read_to_slice(&mut self.inner, &mut bytes[plen..])?;
-        if bytes_read != self.chunk_size {
-            done = true;
-            bytes = &mut bytes[..bytes_read];
+        if self.interactive {
+            let n = read_some(&mut self.inner, &mut bytes[plen..])?;
+            done = n == 0;
+            bytes = &mut bytes[..plen + n];
+        } else {
+            let n = read_to_slice(&mut self.inner, &mut bytes[plen..])?;
+            if plen + n != self.chunk_size {
+                done = true;
+                bytes = &mut bytes[..plen + n];
+            }
         }
+        let bytes_read = bytes.len();
         let mut ulen = bytes.len();
         if self.check_utf8 {
             ulen = {
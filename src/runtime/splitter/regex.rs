@@ -15,6 +15,8 @@ pub struct RegexSplitter<R> {
     used_fields: FieldSet,
     // Used to trigger updating FILENAME on the first read.
     start: bool,
+    // The text that matched the record separator at the end of the last record, for `RT`.
+    rt: Str<'static>,
 }
 
 impl<R: Read> LineReader for RegexSplitter<R> {
@@ -25,6 +27,9 @@ impl<R: Read> LineReader for RegexSplitter<R> {
     fn check_utf8(&self) -> bool {
         self.reader.check_utf8()
     }
+    fn record_terminator(&self) -> Str<'static> {
+        self.rt.clone()
+    }
 
     // The _reuse variant not only allows us to reuse the memory in the `fields` vec, it also
     // allows us to reuse the old FieldSet, which may have been overwritten with all() if the more
@@ -32,7 +37,7 @@ impl<R: Read> LineReader for RegexSplitter<R> {
     fn read_line_reuse<'a, 'b: 'a>(
         &'b mut self,
         pat: &Str,
-        rc: &mut super::RegexCache,
+        rc: &mut super::Regexes,
         old: &'a mut Self::Line,
     ) -> Result<bool> {
         let start = self.start;
@@ -42,21 +47,19 @@ impl<R: Read> LineReader for RegexSplitter<R> {
         self.start = false;
         old.diverged = false;
         old.fields.clear();
-        rc.with_regex(pat, |re| {
-            old.line = self.read_line_regex(re);
-        })?;
+        old.line = self.read_record(pat, rc)?;
         Ok(/* file changed */ start)
     }
 
-    fn read_line(&mut self, pat: &Str, rc: &mut super::RegexCache) -> Result<(bool, Self::Line)> {
+    fn read_line(&mut self, pat: &Str, rc: &mut super::Regexes) -> Result<(bool, Self::Line)> {
         let start = self.start;
         self.start = false;
-        let line = rc.with_regex(pat, |re| DefaultLine {
-            line: self.read_line_regex(re),
+        let line = DefaultLine {
+            line: self.read_record(pat, rc)?,
             fields: Default::default(),
             used_fields: self.used_fields.clone(),
             diverged: false,
-        })?;
+        };
         Ok((/* file changed */ start, line))
     }
     fn read_state(&self) -> i64 {
@@ -79,9 +82,55 @@ impl<R: Read> RegexSplitter<R> {
             name: name.into(),
             used_fields: FieldSet::all(),
             start: true,
+            rt: Str::default(),
         }
     }
 
+    /// A splitter for input that arrives a record at a time, like the output of a coprocess.
+    /// Records are returned as soon as their terminator is read, rather than once a full chunk
+    /// is available or the input ends; a regex `RS` is not extended past the data read so far.
+    pub fn new_interactive(
+        r: R,
+        chunk_size: usize,
+        name: impl Into<Str<'static>>,
+        check_utf8: bool,
+    ) -> Self {
+        let mut res = Self::new(r, chunk_size, name, check_utf8);
+        res.reader.interactive = true;
+        res
+    }
+
+    /// Read a record ending with a match of the regex `rs`. An empty `rs` selects paragraph mode:
+    /// records are separated by runs of blank lines, and newlines at the start and end of the
+    /// input do not produce empty records.
+    pub fn read_record(&mut self, rs: &Str, rc: &mut super::Regexes) -> Result<Str<'static>> {
+        if !rs.is_empty() {
+            return rc.with_regex(rs, |re| self.read_line_regex(re));
+        }
+        rc.with_regex(&Str::from("\n\n+"), |re| loop {
+            let line = self.read_line_regex(re);
+            // Newlines can only be left at the start of the input, or at its end, where there is
+            // no blank line to match as a separator.
+            let (start, end, len) = line.with_bytes(|bs| {
+                let start = bs.iter().take_while(|b| **b == b'\n').count();
+                let trailing = bs[start..].iter().rev().take_while(|b| **b == b'\n');
+                (start, bs.len() - trailing.count(), bs.len())
+            });
+            if start == end {
+                if self.reader.is_eof() {
+                    self.reader.last_len = 0;
+                    self.rt = Str::default();
+                    return Str::default();
+                }
+                continue;
+            }
+            if end < len {
+                self.rt = line.slice(end, len);
+            }
+            return line.slice(start, end);
+        })
+    }
+
     pub fn read_line_regex(&mut self, pat: &Regex) -> Str<'static> {
         // We keep this as a separate method because it helps in writing tests.
         let (res, consumed) = self.read_line_inner(pat);
@@ -91,6 +140,7 @@ impl<R: Read> RegexSplitter<R> {
 
     fn read_line_inner(&mut self, pat: &Regex) -> (Str<'static>, usize) {
         if self.reader.is_eof() {
+            self.rt = Str::default();
             return (Str::default(), 0);
         }
         loop {
@@ -100,12 +150,18 @@ impl<R: Read> RegexSplitter<R> {
             // the start and end pointers.
             match pat.find(s).map(|m| (m.start(), m.end())) {
                 // We need this check in case the regex matches across a chunk boundary.
-                Some((start, end)) if end + self.reader.start < self.reader.end => {
+                Some((start, end))
+                    if end + self.reader.start < self.reader.end || self.reader.interactive =>
+                {
                     // Valid offsets guaranteed by correctness of regex `find`.
                     let res = self
                         .reader
                         .buf
                         .slice_to_str(self.reader.start, self.reader.start + start);
+                    self.rt = self
+                        .reader
+                        .buf
+                        .slice_to_str(self.reader.start + start, self.reader.start + end);
                     self.reader.start += end;
                     return (res, end);
                 }
@@ -119,6 +175,7 @@ impl<R: Read> RegexSplitter<R> {
                                 .buf
                                 .slice_to_str(self.reader.start, self.reader.end);
                             self.reader.start = self.reader.end;
+                            self.rt = Str::default();
                             (line, consumed)
                         }
                         Ok(false) => {
@@ -143,6 +200,7 @@ impl<R: Read> RegexSplitter<R> {
                         }
                         Err(_) => {
                             self.reader.state = ReaderState::Error;
+                            self.rt = Str::default();
                             (Str::default(), 0)
                         }
                     };
@@ -155,6 +213,10 @@ impl<R: Read> RegexSplitter<R> {
                                 .reader
                                 .buf
                                 .slice_to_str(self.reader.start, self.reader.start + start);
+                            self.rt = self
+                                .reader
+                                .buf
+                                .slice_to_str(self.reader.start + start, self.reader.start + end);
                             self.reader.start += end;
                             (res, end)
                         }
@@ -164,6 +226,7 @@ impl<R: Read> RegexSplitter<R> {
                         }
                         Err(_) => {
                             self.reader.state = ReaderState::Error;
+                            self.rt = Str::default();
                             (Str::default(), 0)
                         }
                     };
@@ -173,6 +236,56 @@ impl<R: Read> RegexSplitter<R> {
     }
 }
 
+/// Split `line` into the matches of `pat`, following gawk's rules for `FPAT`: an empty match is
+/// a field of its own unless it comes right after a non-empty field, in which case it is
+/// the separator between two fields and is skipped. Fields not in `used_fields` are passed as
+/// empty strings.
+pub(crate) fn split_fpat<'a>(
+    pat: &Regex,
+    line: &Str<'a>,
+    used_fields: &FieldSet,
+    mut push: impl FnMut(Str<'a>),
+) {
+    line.with_bytes(|bs| {
+        let mut pos = 0;
+        let mut field = 1;
+        let mut prev_end = None;
+        while pos <= bs.len() {
+            let (start, end) = match pat.find_at(bs, pos) {
+                Some(m) => (m.start(), m.end()),
+                None => break,
+            };
+            if start == end && prev_end == Some(start) {
+                prev_end = None;
+                pos = next_char(bs, start);
+                continue;
+            }
+            push(if used_fields.get(field) {
+                line.slice(start, end)
+            } else {
+                Str::default()
+            });
+            field += 1;
+            if start == end {
+                prev_end = None;
+                pos = next_char(bs, end);
+            } else {
+                prev_end = Some(end);
+                pos = end;
+            }
+        }
+    })
+}
+
+// The offset of the character after the one starting at `i`.
+fn next_char(bs: &[u8], i: usize) -> usize {
+    let mut i = i + 1;
+    while i < bs.len() && bs[i] & 0xC0 == 0x80 {
+        i += 1;
+    }
+    i
+}
+
 #[cfg(test)]
 mod tests {
     // need to benchmark batched splitting vs. regular splitting to get a feel for things.
@@ -193,6 +306,93 @@ mod tests {
         std::str::from_utf8(s).unwrap().into()
     }
 
+    fn fpat_fields(pat: &str, line: &str) -> Vec<String> {
+        let re = Regex::new(pat).unwrap();
+        let mut res = Vec::new();
+        split_fpat(&re, &Str::from(line), &FieldSet::all(), |s| {
+            res.push(s.as_str().to_string())
+        });
+        res
+    }
+
+    #[test]
+    fn fpat_split() {
+        // Alternatives are tried in order, so the quoted form comes first.
+        let csv = r#"("[^"]+")|([^,]*)"#;
+        assert_eq!(fpat_fields(csv, "a,,b"), vec!["a", "", "b"]);
+        assert_eq!(
+            fpat_fields(csv, r#"x,"y,z",w,"#),
+            vec!["x", r#""y,z""#, "w", ""]
+        );
+        assert_eq!(fpat_fields("[0-9]+", "ab 12 cd 345"), vec!["12", "345"]);
+        assert!(fpat_fields("[0-9]+", "abc").is_empty());
+    }
+
+    // Read every record from `inp`, along with its terminator.
+    fn records(inp: &str, rs: &str) -> Vec<(String, String)> {
+        let mut rdr = RegexSplitter::new(
+            Cursor::new(inp.to_string()),
+            /*chunk_size=*/ 512,
+            "",
+            /*check_utf8=*/ false,
+        );
+        let mut cache = crate::runtime::RegexCache::default();
+        let mut rc = crate::runtime::Regexes::new(&mut cache);
+        let mut res = Vec::new();
+        loop {
+            let rec = rdr.read_record(&Str::from(rs), &mut rc).unwrap();
+            if rdr.read_state() == 0 {
+                break;
+            }
+            let rt = rdr.record_terminator();
+            res.push((rec.as_str().to_string(), rt.as_str().to_string()));
+        }
+        res
+    }
+
+    #[test]
+    fn record_terminators() {
+        let rec = |r: &str, t: &str| (r.to_string(), t.to_string());
+        assert_eq!(
+            records("ab12cd345ef", "[0-9]+"),
+            vec![rec("ab", "12"), rec("cd", "345"), rec("ef", "")]
+        );
+        assert_eq!(
+            records("\n\na b\nc\n\n\nd\n", ""),
+            vec![rec("a b\nc", "\n\n\n"), rec("d", "\n")]
+        );
+        assert_eq!(
+            records("x\n\n\ny", ""),
+            vec![rec("x", "\n\n\n"), rec("y", "")]
+        );
+        assert!(records("\n\n\n", "").is_empty());
+    }
+
+    // Yields one chunk per read, like a pipe whose writer is waiting for a reply, and panics if
+    // read again before the next chunk is sent.
+    struct Chunks(Vec<&'static str>);
+
+    impl std::io::Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let chunk = self.0.pop().expect("read past the available input");
+            buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn interactive_records() {
+        let chunks = Chunks(vec!["", "c\nd", "a\nb"]);
+        let mut rdr = RegexSplitter::new_interactive(chunks, 512, "", /*check_utf8=*/ true);
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            lines.push(rdr.read_line_regex(&LINE).as_str().to_string());
+        }
+        assert_eq!(lines, vec!["a", "bc", "d"]);
+        assert_eq!(rdr.read_state(), 1);
+        assert!(rdr.reader.is_eof());
+    }
+
     #[test]
     fn test_line_split_big_sep() {
         let mut buf = Vec::new();
@@ -0,0 +1,175 @@
+//! Fixed-width field splitting, for `FIELDWIDTHS`.
+//!
+//! Fields are split lazily: [RecordReader](super::RecordReader) only reads records, and
+//! `DefaultLine` splits them the first time a field is requested, using the active
+//! [FieldWidths] if `FIELDWIDTHS` is set.
+use crate::common::Result;
+use crate::pushdown::FieldSet;
+use crate::runtime::utf8::is_utf8;
+use crate::runtime::Str;
+
+/// A parsed `FIELDWIDTHS` value.
+///
+/// The value is a space-separated list of widths. Each width may be preceded by `skip:` to ignore
+/// that many characters before the field, and the last entry may be `*` to take the rest of the
+/// record. Widths count characters; records that are not valid UTF-8 are measured in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldWidths {
+    // (characters to skip, field width), where a width of `None` is a trailing `*`.
+    cols: Vec<(usize, Option<usize>)>,
+}
+
+impl FieldWidths {
+    /// Parse a `FIELDWIDTHS` value. An empty (or all-blank) value yields `None`, which means
+    /// splitting should go back to using `FS`.
+    pub fn parse(spec: &[u8]) -> Result<Option<FieldWidths>> {
+        let spec = match std::str::from_utf8(spec) {
+            Ok(s) => s,
+            Err(_) => return err!("invalid FIELDWIDTHS value: not UTF-8"),
+        };
+        let mut cols = Vec::new();
+        let mut toks = spec.split_ascii_whitespace().peekable();
+        while let Some(tok) = toks.next() {
+            let (skip, width) = match tok.split_once(':') {
+                Some((skip, width)) => (skip, width),
+                None => ("0", tok),
+            };
+            let skip = match skip.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => return err!("invalid FIELDWIDTHS value {:?}: bad skip {:?}", spec, skip),
+            };
+            let width = if width == "*" {
+                if toks.peek().is_some() {
+                    return err!("invalid FIELDWIDTHS value {:?}: `*` must come last", spec);
+                }
+                None
+            } else {
+                match width.parse::<usize>() {
+                    Ok(n) => Some(n),
+                    Err(_) => {
+                        return err!(
+                            "invalid FIELDWIDTHS value {:?}: bad width {:?}",
+                            spec,
+                            width
+                        )
+                    }
+                }
+            };
+            cols.push((skip, width));
+        }
+        Ok(if cols.is_empty() {
+            None
+        } else {
+            Some(FieldWidths { cols })
+        })
+    }
+
+    /// Split `line` into fields, passing empty strings for fields not in `used_fields`. Splitting
+    /// stops at the end of the record, so a short record has fewer fields.
+    pub(crate) fn split<'a>(
+        &self,
+        line: &Str<'a>,
+        used_fields: &FieldSet,
+        mut push: impl FnMut(Str<'a>),
+    ) {
+        line.with_bytes(|bs| {
+            let chars = if is_utf8(bs) {
+                Some(unsafe { std::str::from_utf8_unchecked(bs) })
+            } else {
+                None
+            };
+            // Move `n` characters forward from byte offset `pos`.
+            let advance = |pos: usize, n: usize| match chars {
+                Some(s) => s[pos..]
+                    .char_indices()
+                    .nth(n)
+                    .map(|(i, _)| pos + i)
+                    .unwrap_or(bs.len()),
+                None => std::cmp::min(pos.saturating_add(n), bs.len()),
+            };
+            let mut pos = 0;
+            for (i, (skip, width)) in self.cols.iter().enumerate() {
+                pos = advance(pos, *skip);
+                if pos >= bs.len() {
+                    break;
+                }
+                let end = match width {
+                    Some(w) => advance(pos, *w),
+                    None => bs.len(),
+                };
+                push(if used_fields.get(i + 1) {
+                    line.slice(pos, end)
+                } else {
+                    Str::default()
+                });
+                pos = end;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{batch::ByteReader, FieldSplit, Line, LineReader, RecordReader};
+    use super::*;
+    use crate::common::ExecutionStrategy;
+    use crate::runtime::{RegexCache, Regexes};
+    use std::io::Cursor;
+    use std::iter::once;
+
+    fn split(spec: &str, line: &str) -> Vec<String> {
+        let widths = FieldWidths::parse(spec.as_bytes()).unwrap().unwrap();
+        let mut res = Vec::new();
+        widths.split(&Str::from(line), &FieldSet::all(), |s| {
+            res.push(s.as_str().to_string())
+        });
+        res
+    }
+
+    #[test]
+    fn parse_widths() {
+        assert_eq!(FieldWidths::parse(b"  ").unwrap(), None);
+        assert!(FieldWidths::parse(b"3 x").is_err());
+        assert!(FieldWidths::parse(b"* 3").is_err());
+        assert!(FieldWidths::parse(b"1:2 3 *").unwrap().is_some());
+    }
+
+    #[test]
+    fn split_widths() {
+        assert_eq!(split("3 2 4", "abcdefghijk"), vec!["abc", "de", "fghi"]);
+        assert_eq!(split("3 2 *", "abcdefghijk"), vec!["abc", "de", "fghijk"]);
+        assert_eq!(split("1:2 2:1", "abcdefg"), vec!["bc", "f"]);
+        assert_eq!(split("3 3 3", "abcd"), vec!["abc", "d"]);
+        assert_eq!(split("2 2", "日本語です"), vec!["日本", "語で"]);
+        assert!(split("2 2", "").is_empty());
+    }
+
+    #[test]
+    fn record_reader_widths() {
+        let inp = Cursor::new("abcdef\nghijkl\n");
+        let mut rdr = RecordReader::new(ByteReader::new(
+            once((inp, String::from("-"))),
+            b'\n',
+            b'\n',
+            /*chunk_size=*/ 512,
+            /*check_utf8=*/ false,
+            ExecutionStrategy::Serial,
+            Default::default(),
+        ));
+        let mut cache = RegexCache::default();
+        let widths = FieldWidths::parse(b"2 *").unwrap().unwrap();
+        let split = FieldSplit::Widths(widths);
+        let mut rc = Regexes::new(&mut cache).field_split(Some(&split));
+        let fs = Str::from(" ");
+        let mut cols = Vec::new();
+        loop {
+            let (_, mut line) = rdr.read_line(&fs, &mut rc).unwrap();
+            if rdr.read_state() == 0 {
+                break;
+            }
+            let col = line.get_col(2, &fs, &fs, &mut rc).unwrap();
+            cols.push(col.as_str().to_string());
+        }
+        assert_eq!(cols, vec!["cdef", "ijkl"]);
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::{Arc, Mutex};
 use lazy_static::lazy_static;
 use rusqlite::{params, Connection};
 use rusqlite::types::{Value};
+use crate::common::{CompileError, Result};
 use crate::runtime::{Int, IntMap, Str};
 use crate::runtime::csv::vec_to_csv;
 
@@ -10,21 +11,26 @@ lazy_static! {
     static ref SQLITE_CONNECTIONS: Arc<Mutex<HashMap<String, rusqlite::Connection>>> = Arc::new(Mutex::new(HashMap::new()));
 }
 
-pub(crate) fn sqlite_query<'a>(db_path: &str, sql: &str) -> IntMap<Str<'a>> {
+pub(crate) fn sqlite_query<'a>(db_path: &str, sql: &str) -> Result<IntMap<Str<'a>>> {
+    let fail = |e: rusqlite::Error| {
+        CompileError::new(format!("sqlite_query failed for {}: {}", db_path, e))
+    };
     let map: IntMap<Str> = IntMap::default();
     let mut pool = SQLITE_CONNECTIONS.lock().unwrap();
-    let conn = pool.entry(db_path.to_string()).or_insert_with(|| {
-        Connection::open(db_path).unwrap()
-    });
-    let mut stmt = conn.prepare(sql).unwrap();
+    if !pool.contains_key(db_path) {
+        let conn = Connection::open(db_path).map_err(fail)?;
+        pool.insert(db_path.to_string(), conn);
+    }
+    let conn = pool.get_mut(db_path).unwrap();
+    let mut stmt = conn.prepare(sql).map_err(fail)?;
     let colum_count = stmt.column_count();
     let mut index = 1;
-    let mut rows = stmt.query(params![]).unwrap();
-    while let Some(row) = rows.next().unwrap() {
+    let mut rows = stmt.query(params![]).map_err(fail)?;
+    while let Some(row) = rows.next().map_err(fail)? {
         let mut items: Vec<String> = vec![];
         let mut i = 0;
         while i < colum_count {
-            let value = row.get::<_, Value>(i).unwrap();
+            let value = row.get::<_, Value>(i).map_err(fail)?;
             let text_value = match value {
                 Value::Null => { "".to_owned() }
                 Value::Integer(num) => { num.to_string() }
@@ -39,7 +45,7 @@ pub(crate) fn sqlite_query<'a>(db_path: &str, sql: &str) -> IntMap<Str<'a>> {
         map.insert(index, Str::from(vec_to_csv(&v2)));
         index += 1;
     }
-    map
+    Ok(map)
 }
 
 pub(crate) fn sqlite_execute(db_path: &str, sql: &str) -> Int {
@@ -58,7 +64,7 @@ mod tests {
     fn test_query() {
         let sql = "SELECT nick, email, age FROM user";
         let db_path = "sqlite.db";
-        let rows = sqlite_query(db_path, sql);
+        let rows = sqlite_query(db_path, sql).unwrap();
         for key in rows.to_vec() {
             let value = rows.get(&key);
             println!("{}: {}", key, value.to_string());
@@ -60,8 +60,11 @@ impl StrTag {
 
 // Why the repr(C)? We may rely on the lengths coming first.
 
+// Strings are viewed as an `Inline` wherever they are stored, so it cannot be more aligned than
+// `StrRep`: u128 is 16-byte aligned on some targets, but a Str in a map entry (or in compiled
+// code's U128) is only 8-byte aligned.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
-#[repr(transparent)]
+#[repr(C, packed(8))]
 struct Inline(u128);
 
 const MAX_INLINE_SIZE: usize = 15;
@@ -315,6 +318,9 @@ impl<'a> UniqueStr<'a> {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+    pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        self.0.with_bytes(f)
+    }
 
     // TODO: is this safe for INLINE values?
     // Seems like we aren't guaranteed that inlines are valid for all of <'a>
@@ -1,21 +1,43 @@
 //! Implementation of substring searches.
 //!
 //! This is a tiny wrapper on top of `memmem::find` from the `memchr` crate.
-use super::{Int, Str};
+use super::{fold_case, Int, Str};
 use memchr::memmem;
 
-// 1-indexed, 0 on failure
-pub fn index_substr<'a>(needle: &Str<'a>, haystack: &Str<'a>) -> Int {
+// 1-indexed, 0 on failure. `ignore_case` folds case as `IGNORECASE` comparisons do.
+pub fn index_substr<'a>(needle: &Str<'a>, haystack: &Str<'a>, ignore_case: bool) -> Int {
     needle
-        .with_bytes(|n| haystack.with_bytes(|h| memmem::find(h, n)))
+        .with_bytes(|n| {
+            haystack.with_bytes(|h| {
+                if ignore_case {
+                    (0..=h.len()).find(|i| starts_with_folded(&h[*i..], n))
+                } else {
+                    memmem::find(h, n)
+                }
+            })
+        })
         .map(|x| x as Int + 1)
         .unwrap_or(0)
 }
 
-pub fn last_index_substr<'a>(needle: &Str<'a>, haystack: &Str<'a>) -> Int {
+pub fn last_index_substr<'a>(needle: &Str<'a>, haystack: &Str<'a>, ignore_case: bool) -> Int {
     needle
-        .with_bytes(|n| haystack.with_bytes(|h| memmem::rfind(h, n)))
+        .with_bytes(|n| {
+            haystack.with_bytes(|h| {
+                if ignore_case {
+                    (0..=h.len()).rev().find(|i| starts_with_folded(&h[*i..], n))
+                } else {
+                    memmem::rfind(h, n)
+                }
+            })
+        })
         .map(|x| x as Int + 1)
         .unwrap_or(0)
 }
 
+// Case-insensitive searches try each offset in turn, folding as they go rather than allocating
+// folded copies of the haystack and needle.
+fn starts_with_folded(h: &[u8], n: &[u8]) -> bool {
+    let mut h = fold_case(h);
+    fold_case(n).all(|c| h.next() == Some(c))
+}
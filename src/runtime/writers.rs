@@ -58,7 +58,12 @@ use crossbeam_channel::{bounded, Receiver, Sender};
 use hashbrown::HashMap;
 
 use crate::common::{CompileError, FileSpec, Notification, Result};
-use crate::runtime::{command::command_for_write, Str};
+use crate::runtime::{
+    command::command_for_write,
+    inet,
+    special_files::{dup_fd, special_fd},
+    Channels, Str,
+};
 
 /// The maximum number of pending requests in the per-file channels.
 const IO_CHAN_SIZE: usize = 8;
@@ -79,6 +84,14 @@ pub trait FileFactory: Clone + 'static + Send + Sync {
     fn cmd(&self, cmd: &[u8]) -> io::Result<ChildStdin> {
         command_for_write(cmd)
     }
+    // Standard error and other open file descriptors, for `/dev/stderr` and `/dev/fd/N`.
+    fn fd(&self, fd: u32) -> io::Result<Box<dyn io::Write + Send>> {
+        if fd == 2 {
+            Ok(Box::new(io::stderr()))
+        } else {
+            Ok(Box::new(dup_fd(fd)?))
+        }
+    }
     fn build(&self, path: &str, spec: FileSpec) -> io::Result<Self::Output>;
     // TODO maybe we should support this returning an error.
     fn stdout(&self) -> Self::Stdout;
@@ -133,7 +146,7 @@ pub fn factory_from_file(fname: &str) -> io::Result<impl FileFactory> {
 
 fn build_handle<W: io::Write, F: Fn(FileSpec) -> io::Result<W> + Send + 'static>(
     f: F,
-    is_stdout: bool,
+    line_buffer: bool,
 ) -> RawHandle {
     let (sender, receiver) = bounded(IO_CHAN_SIZE);
     let error = Arc::new(Mutex::new(None));
@@ -142,7 +155,7 @@ fn build_handle<W: io::Write, F: Fn(FileSpec) -> io::Result<W> + Send + 'static>
     RawHandle {
         error,
         sender,
-        line_buffer: is_stdout && io::stdout().is_terminal(),
+        line_buffer,
     }
 }
 
@@ -152,36 +165,70 @@ fn build_handle<W: io::Write, F: Fn(FileSpec) -> io::Result<W> + Send + 'static>
 /// to run and listen for new requests that might trigger a reopen.
 pub struct Registry {
     global: Arc<dyn Root>,
+    channels: Channels,
     files: HashMap<Str<'static>, FileHandle>,
     cmds: HashMap<Str<'static>, FileHandle>,
+    coprocs: HashMap<Str<'static>, FileHandle>,
+    sockets: HashMap<Str<'static>, FileHandle>,
+    fds: HashMap<u32, FileHandle>,
     stdout: FileHandle,
 }
 
 impl Registry {
     pub fn from_factory(f: impl FileFactory) -> Registry {
-        let root_impl = RootImpl::from_factory(f);
+        let channels = Channels::default();
+        let root_impl = RootImpl::from_factory(f, channels.clone());
         let stdout = root_impl.get_stdout().into_handle();
         Registry {
             global: Arc::new(root_impl),
+            channels,
             files: Default::default(),
             cmds: Default::default(),
+            coprocs: Default::default(),
+            sockets: Default::default(),
+            fds: Default::default(),
             stdout,
         }
     }
 
+    pub(crate) fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
     pub fn get_handle(&mut self, name: Option<&Str>, fspec: FileSpec) -> Result<&mut FileHandle> {
         let name = if let Some(s) = name {
             s
         } else {
             return self.get_file(None);
         };
+        if let FileSpec::Trunc | FileSpec::Append = fspec {
+            match name.with_bytes(|bs| special_fd(bs, /*reading=*/ false)) {
+                Some(1) => return self.get_file(None),
+                Some(fd) => return self.get_fd(fd),
+                None => {}
+            }
+        }
         match fspec {
             FileSpec::Cmd => self.get_cmd(name),
+            // Network files can be written with `>`, `>>` or `|&`.
+            _ if name.with_bytes(inet::is_inet) => self.get_socket(name),
+            FileSpec::Coproc => self.get_coproc(name),
             FileSpec::Trunc | FileSpec::Append => self.get_file(Some(name)),
         }
     }
 
     pub fn close(&mut self, path_or_cmd: &Str) -> Result<()> {
+        // Special files stay open; closing them only flushes pending output.
+        match path_or_cmd.with_bytes(|bs| special_fd(bs, /*reading=*/ false)) {
+            Some(1) => return self.stdout.flush(),
+            Some(fd) => {
+                return match self.fds.get_mut(&fd) {
+                    Some(fh) => fh.flush(),
+                    None => Ok(()),
+                }
+            }
+            None => {}
+        }
         // TODO: implement a newtype for heterogeneous lookup. We shouldn't have to do the clone or
         // the unmoor here, but we need to because we cannot implement Borrow<Str<'a>> for
         // Borrow<Str<'static>> (conflicts with the blanket impl for Borrow).
@@ -193,6 +240,16 @@ impl Registry {
             ch.close()?;
             return Ok(());
         }
+        // Coprocess handles are dropped, so that writing again starts a new process.
+        if let Some(mut ch) = self.coprocs.remove(&path_or_cmd.clone().unmoor()) {
+            ch.close()?;
+            return Ok(());
+        }
+        // As are the handles of network files, which reconnect when written again.
+        if let Some(mut sh) = self.sockets.remove(&path_or_cmd.clone().unmoor()) {
+            sh.close()?;
+            return Ok(());
+        }
         path_or_cmd.with_bytes(|bs| self.global.close(bs))
     }
 
@@ -208,6 +265,45 @@ impl Registry {
         }
     }
 
+    pub fn get_coproc(&mut self, cmd: &Str) -> Result<&mut FileHandle> {
+        use hashbrown::hash_map::Entry;
+        // borrowed by with_bytes closure.
+        let global = &self.global;
+        match self.coprocs.entry(cmd.clone().unmoor()) {
+            Entry::Occupied(o) => Ok(o.into_mut()),
+            Entry::Vacant(v) => {
+                let raw = cmd.with_bytes(|bs| global.get_coprocess(bs))?;
+                Ok(v.insert(raw.into_handle()))
+            }
+        }
+    }
+
+    pub fn get_socket(&mut self, name: &Str) -> Result<&mut FileHandle> {
+        use hashbrown::hash_map::Entry;
+        // borrowed by with_bytes closure.
+        let global = &self.global;
+        match self.sockets.entry(name.clone().unmoor()) {
+            Entry::Occupied(o) => Ok(o.into_mut()),
+            Entry::Vacant(v) => {
+                let raw = name.with_bytes(|bs| global.get_socket(bs))?;
+                Ok(v.insert(raw.into_handle()))
+            }
+        }
+    }
+
+    pub fn get_fd(&mut self, fd: u32) -> Result<&mut FileHandle> {
+        // Pending standard output is written first, so that it comes before the error message
+        // that follows it in the program.
+        if fd == 2 {
+            self.stdout.flush()?;
+        }
+        let global = &self.global;
+        Ok(self
+            .fds
+            .entry(fd)
+            .or_insert_with(|| global.get_fd(fd).into_handle()))
+    }
+
     pub fn get_file(&mut self, name: Option<&Str>) -> Result<&mut FileHandle> {
         match name {
             Some(path) => {
@@ -231,7 +327,10 @@ impl Registry {
 
     pub fn destroy_and_flush_all_files(&mut self) -> Result<()> {
         let mut last_error = Ok(());
-        for (_, mut fh) in self.files.drain().chain(self.cmds.drain()) {
+        let named = (self.files.drain().chain(self.cmds.drain()))
+            .chain(self.coprocs.drain().chain(self.sockets.drain()))
+            .map(|(_, fh)| fh);
+        for mut fh in named.chain(self.fds.drain().map(|(_, fh)| fh)) {
             let res = fh.flush();
             if res.is_err() {
                 last_error = res;
@@ -245,8 +344,12 @@ impl Clone for Registry {
     fn clone(&self) -> Registry {
         Registry {
             global: self.global.clone(),
+            channels: self.channels.clone(),
             files: Default::default(),
             cmds: Default::default(),
+            coprocs: Default::default(),
+            sockets: Default::default(),
+            fds: Default::default(),
             stdout: self.stdout.raw().into_handle(),
         }
     }
@@ -256,6 +359,9 @@ impl Clone for Registry {
 // receiver threads, while still avoiding an extra type parameter all the way up the stack.
 trait Root: 'static + Send + Sync {
     fn get_command(&self, cmd: &[u8]) -> RawHandle;
+    fn get_coprocess(&self, cmd: &[u8]) -> Result<RawHandle>;
+    fn get_socket(&self, name: &[u8]) -> Result<RawHandle>;
+    fn get_fd(&self, fd: u32) -> RawHandle;
     fn get_handle(&self, fname: &str) -> RawHandle;
     fn get_stdout(&self) -> RawHandle;
     // closes a file or command with name `fname`.
@@ -265,22 +371,30 @@ trait Root: 'static + Send + Sync {
 struct RootImpl<F> {
     handles: Mutex<HashMap<String, RawHandle>>,
     commands: Mutex<HashMap<Box<[u8]>, RawHandle>>,
+    coprocs: Mutex<HashMap<Box<[u8]>, RawHandle>>,
+    sockets: Mutex<HashMap<Box<[u8]>, RawHandle>>,
+    fds: Mutex<HashMap<u32, RawHandle>>,
     stdout_raw: RawHandle,
     file_factory: F,
+    channels: Channels,
 }
 
 impl<F: FileFactory> RootImpl<F> {
-    fn from_factory(file_factory: F) -> RootImpl<F> {
+    fn from_factory(file_factory: F, channels: Channels) -> RootImpl<F> {
         let local_factory = file_factory.clone();
         let stdout_raw = build_handle(
             move |_append| Ok(local_factory.stdout()),
-            /*is_stdout*/ true,
+            /*line_buffer=*/ io::stdout().is_terminal(),
         );
         RootImpl {
             handles: Default::default(),
             commands: Default::default(),
+            coprocs: Default::default(),
+            sockets: Default::default(),
+            fds: Default::default(),
             stdout_raw,
             file_factory,
+            channels,
         }
     }
 }
@@ -290,7 +404,13 @@ impl<F: FileFactory> Root for RootImpl<F> {
         let mut handle = None;
         {
             let cmds = self.commands.lock().unwrap();
-            if let Some(h) = cmds.get(fname) {
+            let coprocs = self.coprocs.lock().unwrap();
+            let sockets = self.sockets.lock().unwrap();
+            if let Some(h) = cmds
+                .get(fname)
+                .or_else(|| coprocs.get(fname))
+                .or_else(|| sockets.get(fname))
+            {
                 // We do this extra song and dance to avoid calling close with the lock held.
                 handle = Some(h.clone());
             }
@@ -328,7 +448,7 @@ impl<F: FileFactory> Root for RootImpl<F> {
         let global_name = local_name.clone();
         let handle = build_handle(
             move |_| local_factory.cmd(&local_name),
-            /*is_stdout=*/ false,
+            /*line_buffer=*/ false,
         );
         let _old = cmds.insert(global_name, handle.clone());
         debug_assert!(
@@ -338,6 +458,65 @@ impl<F: FileFactory> Root for RootImpl<F> {
         );
         handle
     }
+    fn get_coprocess(&self, cmd: &[u8]) -> Result<RawHandle> {
+        // Start the process now rather than on the first write: a `getline` from the coprocess
+        // may come before the writer thread gets to it, and must not start a second process.
+        if let Err(e) = self.channels.coprocs.start(cmd) {
+            return err!(
+                "failed to start coprocess '{}': {}",
+                String::from_utf8_lossy(cmd),
+                e
+            );
+        }
+        let mut coprocs = self.coprocs.lock().unwrap();
+        if let Some(h) = coprocs.get(cmd) {
+            return Ok(h.clone());
+        }
+        let local_coprocs = self.channels.coprocs.clone();
+        let local_name = Box::<[u8]>::from(cmd);
+        // Lines written to a coprocess are sent right away, as its replies are usually read
+        // before anything else is written.
+        let handle = build_handle(
+            move |_| local_coprocs.for_write(&local_name),
+            /*line_buffer=*/ true,
+        );
+        coprocs.insert(cmd.into(), handle.clone());
+        Ok(handle)
+    }
+    fn get_socket(&self, name: &[u8]) -> Result<RawHandle> {
+        // Connect now, as for coprocesses, which also reports connection errors where the file is
+        // first used.
+        if let Err(e) = self.channels.sockets.open(name) {
+            return err!(
+                "failed to open network file '{}': {}",
+                String::from_utf8_lossy(name),
+                e
+            );
+        }
+        let mut sockets = self.sockets.lock().unwrap();
+        if let Some(h) = sockets.get(name) {
+            return Ok(h.clone());
+        }
+        let local_sockets = self.channels.sockets.clone();
+        let local_name = Box::<[u8]>::from(name);
+        let handle = build_handle(
+            move |_| local_sockets.for_write(&local_name),
+            /*line_buffer=*/ true,
+        );
+        sockets.insert(name.into(), handle.clone());
+        Ok(handle)
+    }
+    fn get_fd(&self, fd: u32) -> RawHandle {
+        let mut fds = self.fds.lock().unwrap();
+        if let Some(h) = fds.get(&fd) {
+            return h.clone();
+        }
+        let local_factory = self.file_factory.clone();
+        // Like the standard error stream itself, writes to it are not held back.
+        let handle = build_handle(move |_| local_factory.fd(fd), /*line_buffer=*/ fd == 2);
+        fds.insert(fd, handle.clone());
+        handle
+    }
     fn get_handle(&self, fname: &str) -> RawHandle {
         let mut handles = self.handles.lock().unwrap();
         if let Some(h) = handles.get(fname) {
@@ -348,7 +527,7 @@ impl<F: FileFactory> Root for RootImpl<F> {
         let global_name = local_name.clone();
         let handle = build_handle(
             move |append| local_factory.build(local_name.as_str(), append),
-            /*is_stdout=*/ false,
+            /*line_buffer=*/ false,
         );
         handles.insert(global_name, handle.clone());
         handle
@@ -416,10 +595,10 @@ impl FileHandle {
         if let Ok(lock) = self.raw.error.lock() {
             match &*lock {
                 Some(err) => err.clone(),
-                None => CompileError(BAD_SHUTDOWN_MSG.into()),
+                None => CompileError::new(BAD_SHUTDOWN_MSG),
             }
         } else {
-            CompileError(BAD_SHUTDOWN_MSG.into())
+            CompileError::new(BAD_SHUTDOWN_MSG)
         }
     }
 
@@ -759,7 +938,7 @@ fn receive_thread<W: io::Write>(
         // We got an error! install it in the `error` mutex.
         {
             let mut err = error.lock().unwrap();
-            *err = Some(CompileError(format!("{}", e)));
+            *err = Some(CompileError::new(format!("{}", e)));
         }
         // Now signal an error on any pending requests.
         batch.clear_error();
@@ -853,6 +1032,10 @@ pub mod testing {
         fn stdout(&self) -> Self::Stdout {
             self.stdout.clone()
         }
+        fn fd(&self, fd: u32) -> io::Result<Box<dyn io::Write + Send>> {
+            let file = self.build(&format!("/dev/fd/{}", fd), FileSpec::Append)?;
+            Ok(Box::new(file))
+        }
     }
 
     #[derive(Default)]
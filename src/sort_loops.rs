@@ -0,0 +1,392 @@
+//! Desugaring for `for (k in m)` loops ordered by a user-defined comparison function.
+//!
+//! gawk lets `PROCINFO["sorted_in"]` name a function `cmp(i1, v1, i2, v2)` that returns a
+//! negative number if index `i1` (with value `v1`) comes before `i2`, a positive number if it
+//! comes after, and zero if they are equal. Not every backend can call an AWK function from the
+//! runtime, so these loops are sorted by AWK code instead. When the program assigns the name of a
+//! function to `PROCINFO["sorted_in"]` as a string constant, each `for (k in m)` loop over a
+//! named array is rewritten before it is converted to a CFG:
+//!
+//! ```text
+//! if (PROCINFO["sorted_in"] == "cmp") {
+//!     n = 0; delete keys
+//!     for (k in m) keys[++n] = k
+//!     # merge sort keys[1..n], calling cmp to compare elements
+//!     for (i = 1; i <= n; i++) { k = keys[i]; body }
+//! } else for (k in m) body
+//! ```
+//!
+//! with one branch per comparison function. The hidden variables (`keys`, `n`, `i`, ...) are
+//! globals with names that are not legal AWK identifiers, one set per loop; a recursive call that
+//! reenters the same loop while it is running will clobber them.
+//!
+//! The builtin orders, like `@ind_str_asc`, are applied at runtime; see
+//! [crate::runtime::sorted_in].
+use std::fmt::Display;
+
+use crate::arena::Arena;
+use crate::ast::{Binop, Expr, FunDec, Prog, Stmt};
+use crate::builtins::Function;
+use crate::common::{CompileError, Either, Result};
+use crate::lexer::Loc;
+use crate::runtime::sorted_in::SortedIn;
+
+pub(crate) struct SortLoops<'a, 'b, I> {
+    arena: &'a Arena,
+    // The comparison functions assigned to `PROCINFO["sorted_in"]`, along with the string
+    // constant naming them.
+    cmps: Vec<(I, &'b [u8])>,
+    loops: usize,
+}
+
+// Whether `e` is `PROCINFO["sorted_in"] = "name"`, returning the name if so.
+fn sorted_in_name<'a, 'b, I: Display>(e: &Expr<'a, 'b, I>) -> Option<&'b [u8]> {
+    match e {
+        Expr::Assign(Expr::Index(Expr::Var(v), Expr::StrLit(b"sorted_in")), Expr::StrLit(name))
+            if v.to_string() == "PROCINFO" =>
+        {
+            Some(*name)
+        }
+        _ => None,
+    }
+}
+
+// The names assigned to `PROCINFO["sorted_in"]` as string constants in `s`, along with the
+// location of the statement that assigns them.
+type Names<'b> = Vec<(&'b [u8], Loc)>;
+
+fn stmt_names<'a, 'b, I: Display>(s: &Stmt<'a, 'b, I>, loc: Loc, names: &mut Names<'b>) {
+    use Stmt::*;
+    match s {
+        StartCond(_) | EndCond(_) | LastCond(_) | Break | Continue | Next | NextFile => {}
+        Expr(e) | Return(Some(e)) => expr_names(e, loc, names),
+        Return(None) => {}
+        Block(stmts) => stmts.iter().for_each(|s| stmt_names(s, loc, names)),
+        Print(args, out) => {
+            args.iter().for_each(|e| expr_names(e, loc, names));
+            if let Some((e, _)) = out {
+                expr_names(e, loc, names);
+            }
+        }
+        Printf(fmt, args, out) => {
+            expr_names(fmt, loc, names);
+            args.iter().for_each(|e| expr_names(e, loc, names));
+            if let Some((e, _)) = out {
+                expr_names(e, loc, names);
+            }
+        }
+        If(c, t, f) => {
+            expr_names(c, loc, names);
+            stmt_names(t, loc, names);
+            if let Some(f) = f {
+                stmt_names(f, loc, names);
+            }
+        }
+        For(init, cond, update, body) => {
+            init.iter()
+                .chain(update)
+                .for_each(|s| stmt_names(s, loc, names));
+            cond.iter().for_each(|e| expr_names(e, loc, names));
+            stmt_names(body, loc, names);
+        }
+        DoWhile(c, body) | While(_, c, body) | ForEach(_, c, body) => {
+            expr_names(c, loc, names);
+            stmt_names(body, loc, names);
+        }
+        Switch(e, cases) => {
+            expr_names(e, loc, names);
+            for (_, body) in cases.iter() {
+                stmt_names(body, loc, names);
+            }
+        }
+        Located(loc, s) => stmt_names(s, *loc, names),
+    }
+}
+
+fn expr_names<'a, 'b, I: Display>(e: &Expr<'a, 'b, I>, loc: Loc, names: &mut Names<'b>) {
+    use Expr::*;
+    if let Some(name) = sorted_in_name(e) {
+        names.push((name, loc));
+    }
+    match e {
+        ILit(_) | FLit(_) | StrLit(_) | PatLit(_) | BigLit(_) | Var(_) | ReadStdin | Cond(_) => {}
+        Unop(_, x) | Inc { x, .. } | Located(_, x) => expr_names(x, loc, names),
+        Binop(_, x, y) | Index(x, y) | Assign(x, y) | AssignOp(x, _, y) | And(x, y) | Or(x, y) => {
+            expr_names(x, loc, names);
+            expr_names(y, loc, names);
+        }
+        ITE(c, t, f) => {
+            expr_names(c, loc, names);
+            expr_names(t, loc, names);
+            expr_names(f, loc, names);
+        }
+        Call(_, args) => args.iter().for_each(|e| expr_names(e, loc, names)),
+        Getline { into, from, .. } => {
+            into.iter()
+                .chain(from)
+                .for_each(|e| expr_names(e, loc, names));
+        }
+    }
+}
+
+impl<'a, 'b, I: From<&'a str> + Clone + Display> SortLoops<'a, 'b, I> {
+    /// Find the comparison functions named in `p`. Assigning a string constant to
+    /// `PROCINFO["sorted_in"]` that names neither a builtin order nor a function is an error.
+    pub(crate) fn new(arena: &'a Arena, p: &Prog<'a, 'b, I>) -> Result<Self> {
+        let mut names = Vec::new();
+        let pats = p.pats.iter().flat_map(|(_, _, body)| body.iter());
+        let blocks = p
+            .begin
+            .iter()
+            .chain(p.prepare.iter())
+            .chain(p.beginfile.iter());
+        for s in blocks
+            .chain(p.endfile.iter())
+            .chain(p.end.iter())
+            .chain(pats)
+            .chain(p.decs.iter().map(|d| &d.body))
+        {
+            stmt_names(s, Loc::default(), &mut names);
+        }
+        for (name, loc) in names.iter() {
+            let known = name.is_empty()
+                || *name == b"@unsorted"
+                || SortedIn::parse(name).is_some()
+                || p.decs
+                    .iter()
+                    .any(|d| d.name.to_string().as_bytes() == *name);
+            if !known {
+                return Err(CompileError::new(format!(
+                    "PROCINFO[\"sorted_in\"] is neither a builtin order nor a function: {:?}",
+                    String::from_utf8_lossy(name)
+                ))
+                .at(*loc));
+            }
+        }
+        let cmps = p
+            .decs
+            .iter()
+            .filter_map(|d| {
+                let name = d.name.to_string();
+                names
+                    .iter()
+                    .find(|(n, _)| *n == name.as_bytes())
+                    .map(|(n, _)| (d.name.clone(), *n))
+            })
+            .collect();
+        Ok(SortLoops {
+            arena,
+            cmps,
+            loops: 0,
+        })
+    }
+
+    /// The body of `dec`, with its loops rewritten. Loops in the comparison functions themselves
+    /// are left alone.
+    pub(crate) fn rewrite_function(&mut self, dec: &FunDec<'a, 'b, I>) -> &'a Stmt<'a, 'b, I> {
+        let name = dec.name.to_string();
+        if self.cmps.iter().any(|(f, _)| f.to_string() == name) {
+            dec.body
+        } else {
+            self.rewrite(dec.body)
+        }
+    }
+
+    pub(crate) fn rewrite(&mut self, s: &'a Stmt<'a, 'b, I>) -> &'a Stmt<'a, 'b, I> {
+        use Stmt::*;
+        if self.cmps.is_empty() {
+            return s;
+        }
+        let a = self.arena;
+        match s {
+            Block(stmts) => {
+                let mut block = a.vec_with_capacity(stmts.len());
+                for s in stmts.iter() {
+                    block.push(self.rewrite(*s));
+                }
+                a.alloc(Block(block))
+            }
+            If(c, t, f) => {
+                let t = self.rewrite(t);
+                let f = f.map(|f| self.rewrite(f));
+                a.alloc(If(*c, t, f))
+            }
+            For(init, cond, update, body) => {
+                a.alloc(For(*init, *cond, *update, self.rewrite(body)))
+            }
+            DoWhile(c, body) => a.alloc(DoWhile(*c, self.rewrite(body))),
+            While(is_toplevel, c, body) => a.alloc(While(*is_toplevel, *c, self.rewrite(body))),
+            Switch(e, cases) => {
+                let cases: Vec<_> = cases.iter().map(|(l, b)| (*l, self.rewrite(*b))).collect();
+                a.alloc(Switch(*e, a.alloc_slice(&cases)))
+            }
+            Located(loc, s) => a.alloc(Located(*loc, self.rewrite(s))),
+            ForEach(v, arr, body) => {
+                let body = self.rewrite(body);
+                self.sorted_loop(v, arr, body)
+            }
+            StartCond(_) | EndCond(_) | LastCond(_) | Expr(_) | Print(..) | Printf(..) | Break
+            | Continue | Next | NextFile | Return(_) => s,
+        }
+    }
+
+    fn sorted_loop(
+        &mut self,
+        v: &I,
+        arr: &'a Expr<'a, 'b, I>,
+        body: &'a Stmt<'a, 'b, I>,
+    ) -> &'a Stmt<'a, 'b, I> {
+        let a = self.arena;
+        let plain = a.alloc(Stmt::ForEach(v.clone(), arr, body));
+        if !matches!(arr, Expr::Var(_)) {
+            return plain;
+        }
+        let id = self.loops;
+        self.loops += 1;
+        let procinfo = a.alloc(Expr::Var("PROCINFO".into()));
+        let sorted_in = self.index(procinfo, a.alloc(Expr::StrLit(b"sorted_in")));
+        let mut res = plain;
+        for (cmp, name) in self.cmps.iter().rev() {
+            let is_cmp = self.binop(Binop::EQ, sorted_in, a.alloc(Expr::StrLit(name)));
+            let sorted = self.sorted_for_each(id, cmp, v, arr, body);
+            res = a.alloc(Stmt::If(is_cmp, sorted, Some(res)));
+        }
+        res
+    }
+
+    // Build the sorted version of `for (v in arr) body`; see the module comment.
+    fn sorted_for_each(
+        &self,
+        id: usize,
+        cmp: &I,
+        v: &I,
+        arr: &'a Expr<'a, 'b, I>,
+        body: &'a Stmt<'a, 'b, I>,
+    ) -> &'a Stmt<'a, 'b, I> {
+        use Binop::*;
+        let a = self.arena;
+        let hidden = |name: &str| -> &'a Expr<'a, 'b, I> {
+            let name = a.alloc_str(&format!("--sorted-in-{}-{}", id, name));
+            a.alloc(Expr::Var(name.into()))
+        };
+        let (keys, tmp, n) = (hidden("keys"), hidden("tmp"), hidden("n"));
+        let (width, lo, mid, hi) = (hidden("width"), hidden("lo"), hidden("mid"), hidden("hi"));
+        let (l, r, i) = (hidden("l"), hidden("r"), hidden("i"));
+        let var = a.alloc(Expr::Var(v.clone()));
+        let one = self.int(1);
+
+        // Copy the keys of `arr` into `keys[1..n]`.
+        let mut block = a.vec_with_capacity(5);
+        block.push(self.assign(n, self.int(0)));
+        block.push(a.alloc(Stmt::Expr(a.alloc(Expr::Call(
+            Either::Right(Function::Clear),
+            a.alloc_slice(&[keys]),
+        )))));
+        let next_key = self.index(keys, self.inc(n, /*is_post=*/ false));
+        block.push(a.alloc(Stmt::ForEach(v.clone(), arr, self.assign(next_key, var))));
+
+        // Merge runs of `width` keys into `tmp`, then copy them back, until one run is left:
+        //   for (width = 1; width < n; width += width)
+        //     for (lo = 1; lo + width <= n; lo += width + width) {
+        //       mid = lo + width; hi = mid + width; if (hi > n + 1) hi = n + 1
+        //       l = lo; r = mid
+        //       for (i = lo; i < hi; i++)
+        //         if (r >= hi || l < mid && cmp(keys[r], arr[keys[r]], keys[l], arr[keys[l]]) >= 0)
+        //           tmp[i] = keys[l++]
+        //         else
+        //           tmp[i] = keys[r++]
+        //       for (i = lo; i < hi; i++) keys[i] = tmp[i]
+        //     }
+        let (key_l, key_r) = (self.index(keys, l), self.index(keys, r));
+        let args = a.alloc_slice(&[key_r, self.index(arr, key_r), key_l, self.index(arr, key_l)]);
+        let in_order = self.binop(
+            GTE,
+            a.alloc(Expr::Call(Either::Left(cmp.clone()), args)),
+            self.int(0),
+        );
+        let take_left = a.alloc(Expr::Or(
+            self.binop(GTE, r, hi),
+            a.alloc(Expr::And(self.binop(LT, l, mid), in_order)),
+        ));
+        let tmp_i = self.index(tmp, i);
+        let merge = a.alloc(Stmt::If(
+            take_left,
+            self.assign(tmp_i, self.index(keys, self.inc(l, /*is_post=*/ true))),
+            Some(self.assign(tmp_i, self.index(keys, self.inc(r, /*is_post=*/ true)))),
+        ));
+        let over_run = |body| {
+            a.alloc(Stmt::For(
+                Some(self.assign(i, lo)),
+                Some(self.binop(LT, i, hi)),
+                Some(a.alloc(Stmt::Expr(self.inc(i, /*is_post=*/ false)))),
+                body,
+            ))
+        };
+        let n_plus_one = self.binop(Plus, n, one);
+        let run = a.new_vec_from_slice(&[
+            self.assign(mid, self.binop(Plus, lo, width)),
+            self.assign(hi, self.binop(Plus, mid, width)),
+            a.alloc(Stmt::If(
+                self.binop(GT, hi, n_plus_one),
+                self.assign(hi, n_plus_one),
+                None,
+            )),
+            self.assign(l, lo),
+            self.assign(r, mid),
+            over_run(merge),
+            over_run(self.assign(self.index(keys, i), tmp_i)),
+        ]);
+        let runs = a.alloc(Stmt::For(
+            Some(self.assign(lo, one)),
+            Some(self.binop(LTE, self.binop(Plus, lo, width), n)),
+            Some(self.assign(lo, self.binop(Plus, lo, self.binop(Plus, width, width)))),
+            a.alloc(Stmt::Block(run)),
+        ));
+        block.push(a.alloc(Stmt::For(
+            Some(self.assign(width, one)),
+            Some(self.binop(LT, width, n)),
+            Some(self.assign(width, self.binop(Plus, width, width))),
+            runs,
+        )));
+
+        // Run the original body for each key, in order.
+        let visit = a.new_vec_from_slice(&[self.assign(var, self.index(keys, i)), body]);
+        block.push(a.alloc(Stmt::For(
+            Some(self.assign(i, one)),
+            Some(self.binop(LTE, i, n)),
+            Some(a.alloc(Stmt::Expr(self.inc(i, /*is_post=*/ false)))),
+            a.alloc(Stmt::Block(visit)),
+        )));
+        a.alloc(Stmt::Block(block))
+    }
+
+    fn int(&self, i: i64) -> &'a Expr<'a, 'b, I> {
+        self.arena.alloc(Expr::ILit(i))
+    }
+
+    fn index(&self, m: &'a Expr<'a, 'b, I>, k: &'a Expr<'a, 'b, I>) -> &'a Expr<'a, 'b, I> {
+        self.arena.alloc(Expr::Index(m, k))
+    }
+
+    fn binop(
+        &self,
+        op: Binop,
+        x: &'a Expr<'a, 'b, I>,
+        y: &'a Expr<'a, 'b, I>,
+    ) -> &'a Expr<'a, 'b, I> {
+        self.arena.alloc(Expr::Binop(op, x, y))
+    }
+
+    fn inc(&self, x: &'a Expr<'a, 'b, I>, is_post: bool) -> &'a Expr<'a, 'b, I> {
+        self.arena.alloc(Expr::Inc {
+            is_inc: true,
+            is_post,
+            x,
+        })
+    }
+
+    fn assign(&self, x: &'a Expr<'a, 'b, I>, y: &'a Expr<'a, 'b, I>) -> &'a Stmt<'a, 'b, I> {
+        self.arena
+            .alloc(Stmt::Expr(self.arena.alloc(Expr::Assign(x, y))))
+    }
+}
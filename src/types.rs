@@ -16,7 +16,7 @@
 //! setting in some ways, and harder in others.
 //!
 //! Unlike an ML-like language, we don't have all that many types. The interpreter really only
-//! handles 11 or so types, and two of those (map iterators with integer and string key types) are
+//! handles 13 or so types, and two of those (map iterators with integer and string key types) are
 //! used in quite a limited way. This means that the types we infer are altogether simpler than
 //! ones that are inferred in a language like Rust, OCaml or Haskell.
 //!
@@ -277,8 +277,9 @@
 //! [`State`]: [crate::types::State]
 use crate::builtins;
 use crate::cfg::{self, Function, Ident, ProgramContext};
-use crate::common::{self, FileSpec, NodeIx, NumTy, Result};
+use crate::common::{self, CompileError, FileSpec, NodeIx, NumTy, Result};
 use crate::compile;
+use crate::lexer::Loc;
 use hashbrown::{HashMap, HashSet};
 
 use std::ops::{Deref, DerefMut};
@@ -291,6 +292,9 @@ pub(crate) enum BaseTy {
     Int,
     Float,
     Str,
+    // A string that compares as a number when it looks like one, such as a field read from input.
+    // These are only inferred under `--posix`; they are stored as strings.
+    StrNum,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
@@ -298,6 +302,8 @@ pub(crate) enum TVar<T> {
     Iter(T),
     Scalar(T),
     Map { key: T, val: T },
+    // An array of arrays. Values are always arrays of type Map{Str, Str}.
+    NestedMap { key: T },
 }
 
 impl<T> TVar<T> {
@@ -311,6 +317,7 @@ impl<T> TVar<T> {
                 key: f(key),
                 val: f(val),
             },
+            NestedMap { key } => NestedMap { key: f(key) },
         }
     }
 }
@@ -321,9 +328,33 @@ impl<T: Clone> TVar<T> {
     }
 }
 
+fn subarray_state() -> State {
+    TVar::Map {
+        key: BaseTy::Str,
+        val: BaseTy::Str,
+    }
+    .abs()
+}
+
+// Builtins returning text read from input, which POSIX awk treats as strnums.
+fn reads_input(f: &builtins::Function) -> bool {
+    use builtins::Function::*;
+    matches!(
+        f,
+        Unop(crate::ast::Unop::Column) | Nextline | NextlineCmd | NextlineCoproc | NextlineStdin
+    )
+}
+
+// Builtins doing arithmetic that overflows when both operands are integers; see `--checked-arith`.
+pub(crate) fn is_int_arith(f: &builtins::Function) -> bool {
+    use crate::ast::Binop::*;
+    matches!(f, builtins::Function::Binop(Plus | Minus | Mult | Mod))
+}
+
 pub(crate) fn val_of(s: &State) -> Result<State> {
     match s {
         Some(TVar::Map { val, .. }) => Ok(Some(TVar::Scalar(*val))),
+        Some(TVar::NestedMap { .. }) => Ok(subarray_state()),
         None => Ok(None),
         Some(TVar::Iter(_)) => err!("attempting to get value out of iterator state"),
         Some(TVar::Scalar(_)) => err!("attempting to get value out of iterator scalar"),
@@ -340,6 +371,8 @@ pub(crate) enum Constraint<T> {
     IterVal(T),
     IterValIn(T),
     Flows(T),
+    // The value of a variable that may be read before it is assigned. Only used under `--posix`.
+    Uninit(T),
     // TODO(ezr): have a shared Vec and just store a slice here?
     CallBuiltin(SmallVec<NodeIx>, builtins::Function),
     CallUDF(
@@ -359,6 +392,7 @@ impl<T> Constraint<T> {
             Constraint::IterValIn(_) => Constraint::IterValIn(s),
             Constraint::IterVal(_) => Constraint::IterVal(s),
             Constraint::Flows(_) => Constraint::Flows(s),
+            Constraint::Uninit(_) => Constraint::Uninit(s),
             Constraint::CallBuiltin(args, f) => Constraint::CallBuiltin(args.clone(), *f),
             Constraint::CallUDF(nix, args, f) => Constraint::CallUDF(*nix, args.clone(), *f),
         }
@@ -375,11 +409,16 @@ impl Constraint<State> {
                 key: None,
                 val: None,
             })),
-            Constraint::KeyIn(Some(TVar::Scalar(k))) => Ok(Some(TVar::Map { key: *k, val: None })),
+            Constraint::KeyIn(Some(TVar::Scalar(k))) => Ok(Some(TVar::Map {
+                // Map keys are always compared as strings.
+                key: k.map(|k| if k == BaseTy::StrNum { BaseTy::Str } else { k }),
+                val: None,
+            })),
             Constraint::KeyIn(op) => err!("Non-scalar KeyIn constraint: {:?}", op),
 
             Constraint::Key(None) => Ok(None),
-            Constraint::Key(Some(TVar::Map { key: s, .. })) => Ok(Some(TVar::Scalar(*s))),
+            Constraint::Key(Some(TVar::Map { key: s, .. }))
+            | Constraint::Key(Some(TVar::NestedMap { key: s })) => Ok(Some(TVar::Scalar(*s))),
             Constraint::Key(op) => {
                 err!("invalid operand for Key constraint: {:?} (must be map)", op)
             }
@@ -392,7 +431,11 @@ impl Constraint<State> {
             Constraint::ValIn(op) => err!("Non-scalar ValIn constraint: {:?}", op),
 
             Constraint::Val(None) => Ok(None),
+            // A map whose values we know nothing about may still turn out to be an array of
+            // arrays, so we cannot conclude that its values are scalars yet.
+            Constraint::Val(Some(TVar::Map { val: None, .. })) => Ok(None),
             Constraint::Val(Some(TVar::Map { val: s, .. })) => Ok(Some(TVar::Scalar(*s))),
+            Constraint::Val(Some(TVar::NestedMap { .. })) => Ok(subarray_state()),
             Constraint::Val(op) => {
                 err!("invalid operand for Val constraint: {:?} (must be map)", op)
             }
@@ -409,9 +452,21 @@ impl Constraint<State> {
             ),
 
             Constraint::Flows(s) => Ok(*s),
+            // Uninitialized scalars are both "" and 0, which is just how strnums behave.
+            Constraint::Uninit(Some(TVar::Scalar(_))) => {
+                Ok(Some(TVar::Scalar(Some(BaseTy::StrNum))))
+            }
+            Constraint::Uninit(_) => Ok(None),
             Constraint::CallBuiltin(args, f) => {
                 let arg_state: SmallVec<State> = args.iter().map(|ix| *tc.nw.read(*ix)).collect();
-                f.step(&arg_state[..])
+                let res = f.step(&arg_state[..])?;
+                if tc.posix && reads_input(f) {
+                    return Ok(Some(TVar::Scalar(Some(BaseTy::StrNum))));
+                }
+                if tc.float_arith && is_int_arith(f) {
+                    return Ok(Some(TVar::Scalar(Some(BaseTy::Float))));
+                }
+                Ok(res)
             }
             Constraint::CallUDF(nix, args, f) => {
                 let ret_ix = tc.get_function(&tc.func_table[*f as usize], args.clone(), *nix);
@@ -454,10 +509,37 @@ impl Rule {
             match (b1, b2) {
                 (Null, x) | (x, Null) => x,
                 (Str, _) | (_, Str) => Str,
+                // Numbers still look like numbers once they are converted to strings.
+                (StrNum, _) | (_, StrNum) => StrNum,
                 (Float, _) | (_, Float) => Float,
                 (Int, Int) => Int,
             }
         }
+        fn join_key(b1: BaseTy, b2: BaseTy) -> BaseTy {
+            use BaseTy::*;
+            match (b1, b2) {
+                (Float, _)
+                | (_, Float)
+                | (Str, _)
+                | (_, Str)
+                | (StrNum, _)
+                | (_, StrNum)
+                | (Null, _)
+                | (_, Null) => Str,
+                (Int, _) => Int,
+            }
+        }
+        fn lift(
+            f: impl Fn(BaseTy, BaseTy) -> BaseTy,
+            o1: Option<BaseTy>,
+            o2: Option<BaseTy>,
+        ) -> Option<BaseTy> {
+            match (o1, o2) {
+                (Some(x), Some(y)) => Some(f(x, y)),
+                (Some(x), None) | (None, Some(x)) => Some(x),
+                (None, None) => None,
+            }
+        }
         if let Rule::Const(tv) = self {
             return Ok((tv != prev, *tv));
         }
@@ -480,34 +562,23 @@ impl Rule {
                     }
                     (Scalar(x), Scalar(None)) | (Scalar(None), Scalar(x)) => Some(Scalar(x)),
                     (Scalar(Some(x)), Scalar(Some(y))) => Some(Scalar(Some(value_rule(x, y)))),
-                    (Map { key: k1, val: v1 }, Map { key: k2, val: v2 }) => {
-                        fn join_key(b1: BaseTy, b2: BaseTy) -> BaseTy {
-                            use BaseTy::*;
-                            match (b1, b2) {
-                                (Float, _)
-                                | (_, Float)
-                                | (Str, _)
-                                | (_, Str)
-                                | (Null, _)
-                                | (_, Null) => Str,
-                                (Int, _) => Int,
-                            }
-                        }
-                        fn lift(
-                            f: impl Fn(BaseTy, BaseTy) -> BaseTy,
-                            o1: Option<BaseTy>,
-                            o2: Option<BaseTy>,
-                        ) -> Option<BaseTy> {
-                            match (o1, o2) {
-                                (Some(x), Some(y)) => Some(f(x, y)),
-                                (Some(x), None) | (None, Some(x)) => Some(x),
-                                (None, None) => None,
-                            }
-                        }
-                        Some(Map {
-                            key: lift(join_key, k1, k2),
-                            val: lift(value_rule, v1, v2),
-                        })
+                    (Map { key: k1, val: v1 }, Map { key: k2, val: v2 }) => Some(Map {
+                        key: lift(join_key, k1, k2),
+                        val: lift(value_rule, v1, v2),
+                    }),
+                    (NestedMap { key: k1 }, NestedMap { key: k2 }) => Some(NestedMap {
+                        key: lift(join_key, k1, k2),
+                    }),
+                    // A map with no information about its values can still be an array of
+                    // arrays; anything else (i.e. scalar values) is a type error.
+                    (NestedMap { key: k1 }, Map { key: k2, val: None })
+                    | (Map { key: k2, val: None }, NestedMap { key: k1 }) => Some(NestedMap {
+                        key: lift(join_key, k1, k2),
+                    }),
+                    (NestedMap { .. }, Map { .. }) | (Map { .. }, NestedMap { .. }) => {
+                        return err!(
+                            "array is used both as an array of arrays and as an array of scalars"
+                        )
                     }
                     (t1, t2) => return err!("kinds do not match. {:?} vs {:?}", t1, t2),
                 },
@@ -534,14 +605,14 @@ fn flatten(tv: TVar<BaseTy>) -> Result<compile::Ty> {
         match b {
             Int => Ty::Int,
             Float => Ty::Float,
-            Str => Ty::Str,
+            Str | StrNum => Ty::Str,
             Null => Ty::Null,
         }
     }
     match tv {
         Scalar(b) => Ok(flatten_base(b)),
         Iter(Int) => Ok(Ty::IterInt),
-        Iter(Null) | Iter(Str) => Ok(Ty::IterStr),
+        Iter(Null) | Iter(Str) | Iter(StrNum) => Ok(Ty::IterStr),
         Iter(x) => err!("Iterator over an unsupported type: {:?}", x),
         Map { key, val } => {
             let f = |ty| {
@@ -561,6 +632,8 @@ fn flatten(tv: TVar<BaseTy>) -> Result<compile::Ty> {
                 (k, v) => err!("Map with unsupported type (key={:?} val={:?})", k, v),
             }
         }
+        NestedMap { key: Int } => Ok(Ty::MapIntMap),
+        NestedMap { .. } => Ok(Ty::MapStrMap),
     }
 }
 
@@ -586,6 +659,10 @@ pub(crate) struct Network {
     call_deps: HashMap<NodeIx, SmallVec<NodeIx>>,
     graph: common::Graph<Node, Edge>,
     iso: HashSet<(NumTy, NumTy)>,
+    // The location of the statement currently being constrained, and the locations of the
+    // statements that introduced each node. Used to report type errors.
+    loc: Option<Loc>,
+    locs: HashMap<NodeIx, Loc>,
 }
 
 impl Default for Network {
@@ -598,6 +675,8 @@ impl Default for Network {
             wl: Default::default(),
             call_deps: Default::default(),
             iso: Default::default(),
+            loc: None,
+            locs: Default::default(),
         }
     }
 }
@@ -620,9 +699,16 @@ impl Network {
     fn add_rule(&mut self, rule: Rule) -> NodeIx {
         let res = self.graph.add_node(Node::new(rule));
         self.wl.insert(res);
+        if let Some(loc) = self.loc {
+            self.locs.insert(res, loc);
+        }
         res
     }
 
+    fn locate(&self, ix: NodeIx, e: CompileError) -> CompileError {
+        e.at_opt(self.locs.get(&ix).cloned())
+    }
+
     fn read(&self, ix: NodeIx) -> &State {
         &self.graph.node_weight(ix).unwrap().cur_val
     }
@@ -642,6 +728,12 @@ pub(crate) struct TypeContext<'a, 'b> {
     func_table: &'a [Function<'b, &'b str>],
     local_globals: &'a HashSet<NumTy>,
     udf_nodes: Vec<NodeIx>,
+    // Infer strnums, under `--posix`.
+    pub(crate) posix: bool,
+    // Type `+`, `-`, `*` and `%` as floating point, under `--checked-arith=float`.
+    float_arith: bool,
+    // Assignments to variables, recorded for `zawk lint`.
+    asgns: Option<Vec<Asgn<NodeIx>>>,
 }
 
 struct View<'a, 'b, 'c> {
@@ -667,6 +759,38 @@ pub(crate) fn get_types<'a>(pc: &ProgramContext<'a, &'a str>) -> Result<TypeInfo
     TypeContext::from_prog(pc)
 }
 
+/// An assignment to a variable, with the types inferred for it (or, during inference, their
+/// nodes).
+pub(crate) struct Asgn<T> {
+    pub var: Ident,
+    pub loc: Option<Loc>,
+    /// The type of the variable once it is assigned.
+    pub ty: T,
+    /// The types of the values assigned: one for an ordinary assignment, or one per predecessor
+    /// for a phi node.
+    pub from: SmallVec<T>,
+    pub is_phi: bool,
+}
+
+/// Infer types for `pc`, returning every assignment in the program.
+pub(crate) fn assignments<'a>(pc: &ProgramContext<'a, &'a str>) -> Result<Vec<Asgn<compile::Ty>>> {
+    let mut tc = TypeContext::from_pc(pc);
+    tc.asgns = Some(Vec::new());
+    tc.solve_main(pc)?;
+    let ty = |ix: NodeIx| flatten(concrete(*tc.nw.read(ix)));
+    let mut res = Vec::new();
+    for asgn in tc.asgns.as_ref().unwrap().iter() {
+        res.push(Asgn {
+            var: asgn.var,
+            loc: asgn.loc,
+            ty: ty(asgn.ty)?,
+            from: asgn.from.iter().map(|ix| ty(*ix)).collect::<Result<_>>()?,
+            is_phi: asgn.is_phi,
+        });
+    }
+    Ok(res)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct Args<T> {
     id: T,
@@ -679,6 +803,8 @@ pub(crate) struct TypeInfo {
     pub var_tys: HashMap<(Ident, NumTy, SmallVec<compile::Ty>), compile::Ty>,
     // Map a particular function invocation to a return type.
     pub func_tys: HashMap<(NumTy, SmallVec<compile::Ty>), compile::Ty>,
+    // The identifiers (keyed as in var_tys) holding strnums. Only populated under `--posix`.
+    pub strnums: HashSet<(Ident, NumTy, SmallVec<compile::Ty>)>,
 }
 
 impl<'b, 'c> TypeContext<'b, 'c> {
@@ -692,25 +818,33 @@ impl<'b, 'c> TypeContext<'b, 'c> {
             func_table: &pc.funcs[..],
             local_globals: pc.local_globals_ref(),
             udf_nodes: Default::default(),
+            posix: pc.posix,
+            float_arith: pc.checked_arith == Some(common::CheckedArith::Float),
+            asgns: None,
         };
         tc.udf_nodes = (0..pc.funcs.len())
             .map(|_| tc.nw.add_rule(Rule::AlwaysNotify))
             .collect();
         tc
     }
-    pub(crate) fn from_prog<'a>(pc: &ProgramContext<'a, &'a str>) -> Result<TypeInfo> {
-        use hashbrown::hash_map::Entry;
-        let mut tc = TypeContext::from_pc(pc);
+    // Generate constraints for the main functions (and, transitively, their callees) and solve
+    // them.
+    fn solve_main(&mut self, pc: &ProgramContext<'c, &'c str>) -> Result<()> {
         // TODO: to migrate, simply iterate over the Stage variant of this and solve at the end?
         for offset in pc.main_offsets() {
             let main = &pc.funcs[offset];
-            let main_base = tc.udf_nodes[offset];
-            tc.get_function(main, /*arg_nodes=*/ Default::default(), main_base);
+            let main_base = self.udf_nodes[offset];
+            self.get_function(main, /*arg_nodes=*/ Default::default(), main_base);
         }
-
-        tc.solve()?;
+        self.solve()
+    }
+    pub(crate) fn from_prog<'a>(pc: &ProgramContext<'a, &'a str>) -> Result<TypeInfo> {
+        use hashbrown::hash_map::Entry;
+        let mut tc = TypeContext::from_pc(pc);
+        tc.solve_main(pc)?;
         let mut var_tys = HashMap::new();
         let mut func_tys = HashMap::new();
+        let mut strnums = HashMap::new();
         for (Args { id, args, .. }, ix) in tc.funcs.iter() {
             let mut flat_args = SmallVec::new();
             for a in args.iter().cloned() {
@@ -729,7 +863,14 @@ impl<'b, 'c> TypeContext<'b, 'c> {
 
             // We won't use the function id if id.global, so setting it to 0 should be fine.
             // TODO clean up some of this to make it less misleading
-            match var_tys.entry((*id, func_id.unwrap_or(0), flat_args)) {
+            let key = (*id, func_id.unwrap_or(0), flat_args);
+            if tc.posix {
+                // Functions are compiled once per set of flattened argument types, so only treat
+                // a variable as a strnum if it is one in every instance.
+                let is_strnum = matches!(tc.nw.read(*ix), Some(TVar::Scalar(Some(BaseTy::StrNum))));
+                *strnums.entry(key.clone()).or_insert(true) &= is_strnum;
+            }
+            match var_tys.entry(key) {
                 Entry::Vacant(vac) => {
                     vac.insert(v);
                 }
@@ -768,7 +909,15 @@ impl<'b, 'c> TypeContext<'b, 'c> {
                 }
             }
         }
-        Ok(TypeInfo { var_tys, func_tys })
+        let strnums = strnums
+            .into_iter()
+            .filter_map(|(key, is_strnum)| if is_strnum { Some(key) } else { None })
+            .collect();
+        Ok(TypeInfo {
+            var_tys,
+            func_tys,
+            strnums,
+        })
     }
     fn solve(&mut self) -> Result<()> {
         let mut dep_indices: SmallVec<NodeIx> = Default::default();
@@ -790,17 +939,22 @@ impl<'b, 'c> TypeContext<'b, 'c> {
             while let Some((e_ix, node_ix)) = walker.next(&self.nw.graph) {
                 let edge = self.nw.graph.edge_weight(e_ix).unwrap().clone();
                 let node_val = self.nw.graph.node_weight(node_ix).unwrap().cur_val;
-                deps.push(edge.constraint.sub(node_val).eval(self)?);
+                match edge.constraint.sub(node_val).eval(self) {
+                    Ok(dep) => deps.push(dep),
+                    Err(e) => return Err(self.nw.locate(ix, e)),
+                }
                 if edge.constraint.is_flow() {
                     dep_indices.push(node_ix);
                 }
             }
             // Compute an update value based on the newly-evaluated constraints.
-            let (changed, next) = rule.step(&cur_val, &deps[..])?;
+            let (changed, next) = rule
+                .step(&cur_val, &deps[..])
+                .map_err(|e| self.nw.locate(ix, e))?;
             if !changed {
                 continue;
             }
-            if let Some(TVar::Map { .. }) = next {
+            if let Some(TVar::Map { .. } | TVar::NestedMap { .. }) = next {
                 // If we have a map node, then we need to make sure that anything that assigns to
                 // it winds up with the same type. Assignments are marked as `Flows` constraints;
                 // and we recorded the nodes that flow into `ix` in `dep_indices`. Now we just add
@@ -910,11 +1064,16 @@ impl<'b, 'c> TypeContext<'b, 'c> {
             view.nw.add_dep(arg_node, ix, Constraint::Flows(()));
         }
         let nodes = cfg.raw_nodes();
+        // We may get here while constraining a call in another function; restore its location
+        // when we are done.
+        let outer_loc = view.nw.loc;
         for bb in nodes {
-            for stmt in bb.weight.q.iter() {
+            for (stmt, loc) in bb.weight.q.iter() {
+                view.nw.loc = loc.map(|l| l.error());
                 view.constrain_stmt(stmt);
             }
         }
+        view.nw.loc = outer_loc;
         res
     }
 }
@@ -970,7 +1129,29 @@ impl<'b, 'c, 'd> View<'b, 'c, 'd> {
             }
             AsgnVar(v, e) => {
                 let v_ix = self.ident_node(v);
-                self.constrain_expr(e, v_ix);
+                if self.asgns.is_none() {
+                    self.constrain_expr(e, v_ix);
+                    return;
+                }
+                // Route the value through its own node so that its type can be read back.
+                let e_ix = self.nw.add_rule(Rule::Var);
+                self.constrain_expr(e, e_ix);
+                self.nw.add_dep(e_ix, v_ix, Constraint::Flows(()));
+                let (from, is_phi) = match e {
+                    cfg::PrimExpr::Phi(preds) => (
+                        preds.iter().map(|(_, id)| self.ident_node(id)).collect(),
+                        true,
+                    ),
+                    _ => (smallvec::smallvec![e_ix], false),
+                };
+                let asgn = Asgn {
+                    var: *v,
+                    loc: self.nw.loc,
+                    ty: v_ix,
+                    from,
+                    is_phi,
+                };
+                self.tc.asgns.as_mut().unwrap().push(asgn);
             }
             Return(v) => {
                 let v_ix = self.val_node(v);
@@ -1031,9 +1212,16 @@ impl<'b, 'c, 'd> View<'b, 'c, 'd> {
                 self.nw.add_dep(pv_ix, to, Constraint::Flows(()));
             }
             Phi(preds) => {
+                let mut uninit = false;
                 for (_, id) in preds.iter() {
                     let id_ix = self.ident_node(id);
                     self.nw.add_dep(id_ix, to, Constraint::Flows(()));
+                    uninit |= self.is_unassigned(id);
+                }
+                if uninit && self.posix {
+                    // Otherwise `x` would print as 0 if it were unassigned in
+                    //   if (c) { x = 5 }; print x
+                    self.nw.add_dep(to, to, Constraint::Uninit(()));
                 }
             }
             CallBuiltin(f, args) => {
@@ -1087,7 +1275,16 @@ impl<'b, 'c, 'd> View<'b, 'c, 'd> {
                 self.nw.add_dep(iter_ix, to, Constraint::IterVal(()));
             }
             LoadBuiltin(bv) => {
-                let bv_ix = self.constant(bv.ty().abs());
+                let mut ty = bv.ty();
+                // Command-line arguments and environment variables are strnums too.
+                if self.posix
+                    && matches!(bv, builtins::Variable::ARGV | builtins::Variable::ENVIRON)
+                {
+                    if let TVar::Map { val, .. } = &mut ty {
+                        *val = BaseTy::StrNum;
+                    }
+                }
+                let bv_ix = self.constant(ty.abs());
                 self.nw.add_dep(bv_ix, to, Constraint::Flows(()));
             }
         };
@@ -1107,6 +1304,16 @@ impl<'b, 'c, 'd> View<'b, 'c, 'd> {
         id.is_global(self.local_globals)
     }
 
+    // Whether `id` is the value a local variable has before anything is assigned to it.
+    fn is_unassigned(&self, id: &Ident) -> bool {
+        id.sub == 0
+            && !self.is_global(id)
+            && !self.func_table[self.frame_id as usize]
+                .args
+                .iter()
+                .any(|arg| arg.id == *id)
+    }
+
     fn ident_node(&mut self, id: &Ident) -> NodeIx {
         let is_global = self.is_global(id);
         let key = Args {
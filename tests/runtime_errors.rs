@@ -0,0 +1,95 @@
+//! Runtime errors, checked by running the binary: the compiled backends end the process when a
+//! program fails, so these cannot be observed from within the unit tests.
+use assert_cmd::Command;
+
+#[cfg(feature = "llvm_backend")]
+const BACKEND_ARGS: &[&str] = &["-Binterp", "-Bllvm", "-Bcranelift"];
+#[cfg(not(feature = "llvm_backend"))]
+const BACKEND_ARGS: &[&str] = &["-Binterp", "-Bcranelift"];
+
+// Run `prog` on every backend, expecting it to fail with `msg` before printing anything.
+fn assert_fails(args: &[&str], prog: &str, msg: &str) {
+    for backend_arg in BACKEND_ARGS {
+        let out = Command::cargo_bin("zawk")
+            .unwrap()
+            .arg(backend_arg)
+            .args(args)
+            .arg(prog)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(!out.status.success(), "{}: {} succeeded", backend_arg, prog);
+        assert!(
+            stderr.contains(msg),
+            "{}: {} failed with {:?}, expected {:?}",
+            backend_arg,
+            prog,
+            stderr,
+            msg
+        );
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "", "{}", backend_arg);
+    }
+}
+
+#[test]
+fn refused_network_file() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    for print in ["print", "printf"] {
+        let prog = format!(
+            r#"BEGIN {{ {} "x" > "/inet/tcp/0/127.0.0.1/{}"; print "after" }}"#,
+            print, port
+        );
+        assert_fails(&[], &prog, "failed to open network file");
+    }
+}
+
+#[test]
+fn checked_arith_errors() {
+    for (prog, msg) in [
+        (
+            "BEGIN { x = 9223372036854775807; print x + 1 }",
+            "integer overflow in 9223372036854775807 + 1",
+        ),
+        (
+            "BEGIN { x = 3037000499; print x * x * 2 }",
+            "integer overflow in 9223372030926249001 * 2",
+        ),
+        (
+            "BEGIN { a[1] = 9223372036854775807; a[1] += 1; print a[1] }",
+            "integer overflow in 9223372036854775807 + 1",
+        ),
+        (
+            "BEGIN { x = -9223372036854775807; x--; print -x }",
+            "integer overflow in 0 - -9223372036854775808",
+        ),
+        ("BEGIN { x = 0; print 1 / x }", "division by zero in 1 / 0"),
+        ("BEGIN { x = 0; print 5 % x }", "division by zero in 5 % 0"),
+    ] {
+        assert_fails(&["--checked-arith"], prog, msg);
+    }
+}
+
+#[test]
+fn runtime_error_location() {
+    // The LLVM backend does not record source locations.
+    for backend_arg in ["-Binterp", "-Bcranelift"] {
+        let out = Command::cargo_bin("zawk")
+            .unwrap()
+            .arg(backend_arg)
+            .arg("BEGIN {\n  x = 1\n  print x,\n    sqlite_query(\"/nonexistent/x.db\", \"select 1\")[1]\n}")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(!out.status.success(), "{}", backend_arg);
+        assert!(
+            stderr.contains(":4:5: ") && stderr.contains("\n    sqlite_query("),
+            "{}: {:?}",
+            backend_arg,
+            stderr
+        );
+    }
+}